@@ -1,40 +1,142 @@
 use crate::{
-    Dependency, InstallResult, Installation, Package, PackageReference, RemovalResult,
-    SwitchResult, UhpmError,
+    AdoptOptions, AdoptResult, BuildRequirement, CancellationToken, Dependency, DependencyConflict,
+    DependencyKind, DoctorIssue, DoctorOptions, DoctorReport, ErrorContext, FileMetadata, FileType,
+    GlobDirectories, InstallMode, InstallOptions, InstallPlan, InstallResult, Installation,
+    Journal, JournalEntry, OperationKind, OperationRecord, Package, PackageEvent, PackageId,
+    PackageReference, PackageSpec, PlannedPackage, RecoveryResult, RemovalResult, RepairResult,
+    RequirementEdge, ResolutionResult, ResultExt, SwitchResult, Symlink, SymlinkAction, Target,
+    TargetPolicy, TrustLevel, UhpmError, UpdatePolicy, VersionConstraint,
+    clock::SystemClock,
+    compute_checksum,
     factories::{InstallationFactory, PackageFactory},
+    lock::{LockFile, LockGuard},
     ports::{
-        CacheManager, EventPublisher, FileSystemOperations, NetworkOperations, PackageRepository,
+        CacheManager, Clock, EventPublisher, FileSystemOperations, NetworkOperations,
+        PackageRepository, StateStore,
     },
+    repositories::{PackageFilesRepository, package_files::file_context},
+    services::{ResolutionContext, find_conflicts, install_order},
 };
+use futures_util::{StreamExt, TryStreamExt, future, stream};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Component, Path, PathBuf};
 use std::sync::Arc;
+use std::time::Instant;
+use tracing::{Instrument, debug, info_span, warn};
+
+/// Asked whether to install a package from a repository below the trust
+/// threshold, given the package and the repository name.
+type TrustConfirmation = Box<dyn Fn(&Package, &str) -> bool + Send + Sync>;
+
+/// A failed dependency resolution, with the version conflicts that caused it.
+struct FailedResolution {
+    error: UhpmError,
+    conflicts: Vec<DependencyConflict>,
+}
+
+impl From<UhpmError> for FailedResolution {
+    fn from(error: UhpmError) -> Self {
+        Self {
+            error,
+            conflicts: Vec::new(),
+        }
+    }
+}
+
+/// A package whose files are placed but which isn't recorded in the state
+/// store yet.
+struct StagedInstall {
+    /// The package as it is to be recorded.
+    package: Package,
+    /// Placed files, `None` for packages only extracted for a build.
+    installation: Option<Installation>,
+    /// Other versions deactivated to make room, active again if the install
+    /// is discarded.
+    deactivated: Vec<Package>,
+    result: InstallResult,
+}
 
 /// Main application service that orchestrates package management operations.
 ///
 /// This is the primary entry point for all package management functionality.
 /// It coordinates between repositories, services, and factories to perform
 /// complex operations like install, remove, and switch.
-pub struct PackageManager<FS, NET, REPO, CACHE, EVENTS>
+pub struct PackageManager<FS, NET, REPO, CACHE, EVENTS, STORE>
 where
     FS: FileSystemOperations,
     NET: NetworkOperations,
     REPO: PackageRepository,
     CACHE: CacheManager,
     EVENTS: EventPublisher,
+    STORE: StateStore,
 {
     file_system: Arc<FS>,
     network: Arc<NET>,
     repository: Arc<REPO>,
     cache: Arc<CACHE>,
     event_publisher: Arc<EVENTS>,
+    store: Arc<STORE>,
+    package_files: PackageFilesRepository<FS>,
+    install_mode: InstallMode,
+    max_concurrent_downloads: usize,
+    lock: Option<LockFile>,
+    journal: Option<PathBuf>,
+    clock: Arc<dyn Clock>,
+    installations: InstallationFactory,
+    default_prefix: Option<PathBuf>,
+    repository_trust: HashMap<String, TrustLevel>,
+    trust_threshold: TrustLevel,
+    trust_confirmation: Option<TrustConfirmation>,
+    update_policy: UpdatePolicy,
+}
+
+/// [`PackageManager`] over type-erased ports.
+///
+/// It names no port types, so it can be kept in application state as a
+/// plain field, and its ports can be picked at runtime, for example from
+/// configuration.
+pub type DynPackageManager = PackageManager<
+    Arc<dyn FileSystemOperations>,
+    Arc<dyn NetworkOperations>,
+    Arc<dyn PackageRepository>,
+    Arc<dyn CacheManager>,
+    Arc<dyn EventPublisher>,
+    Arc<dyn StateStore>,
+>;
+
+impl DynPackageManager {
+    /// Like [`PackageManager::new`], erasing the types of the ports.
+    pub fn from_ports(
+        file_system: impl FileSystemOperations + 'static,
+        network: impl NetworkOperations + 'static,
+        repository: impl PackageRepository + 'static,
+        cache: impl CacheManager + 'static,
+        event_publisher: impl EventPublisher + 'static,
+        store: impl StateStore + 'static,
+        packages_dir: PathBuf,
+    ) -> Self {
+        Self::new(
+            Arc::new(file_system),
+            Arc::new(network),
+            Arc::new(repository),
+            Arc::new(cache),
+            Arc::new(event_publisher),
+            Arc::new(store),
+            packages_dir,
+        )
+    }
 }
 
-impl<FS, NET, REPO, CACHE, EVENTS> PackageManager<FS, NET, REPO, CACHE, EVENTS>
+const DEFAULT_CONCURRENT_DOWNLOADS: usize = 4;
+
+impl<FS, NET, REPO, CACHE, EVENTS, STORE> PackageManager<FS, NET, REPO, CACHE, EVENTS, STORE>
 where
-    FS: FileSystemOperations + Send + Sync,
+    FS: FileSystemOperations + Clone + Send + Sync,
     NET: NetworkOperations + Send + Sync,
     REPO: PackageRepository + Send + Sync,
     CACHE: CacheManager + Send + Sync,
     EVENTS: EventPublisher + Send + Sync,
+    STORE: StateStore,
 {
     pub fn new(
         file_system: FS,
@@ -42,193 +144,5871 @@ where
         repository: REPO,
         cache: CACHE,
         event_publisher: EVENTS,
+        store: STORE,
+        packages_dir: PathBuf,
     ) -> Self {
         Self {
+            package_files: PackageFilesRepository::new(file_system.clone(), packages_dir),
             file_system: Arc::new(file_system),
             network: Arc::new(network),
             repository: Arc::new(repository),
             cache: Arc::new(cache),
             event_publisher: Arc::new(event_publisher),
+            store: Arc::new(store),
+            install_mode: InstallMode::default(),
+            max_concurrent_downloads: DEFAULT_CONCURRENT_DOWNLOADS,
+            lock: None,
+            journal: None,
+            clock: Arc::new(SystemClock),
+            installations: InstallationFactory::default(),
+            default_prefix: None,
+            repository_trust: HashMap::new(),
+            trust_threshold: TrustLevel::default(),
+            trust_confirmation: None,
+            update_policy: UpdatePolicy::default(),
         }
     }
 
+    /// Sets how package files are placed at their targets, `Auto` by default.
+    pub fn with_install_mode(mut self, install_mode: InstallMode) -> Self {
+        self.install_mode = install_mode;
+        self
+    }
+
+    /// Restricts where package instlists may place files.
+    ///
+    /// Targets are normalized with `policy` before they are placed and
+    /// recorded, so removal and ownership queries see the same paths.
+    pub fn with_target_policy(mut self, policy: TargetPolicy) -> Self {
+        self.package_files = self.package_files.with_target_policy(policy);
+        self
+    }
+
+    /// Sets what instlist glob patterns do with the directories they match,
+    /// [`GlobDirectories::Recurse`] by default.
+    pub fn with_glob_directories(mut self, glob_directories: GlobDirectories) -> Self {
+        self.package_files = self.package_files.with_glob_directories(glob_directories);
+        self
+    }
+
+    /// Sets the directory `${PREFIX}` in instlist targets stands for when an
+    /// install doesn't pass its own prefix.
+    ///
+    /// Without one, packages using the placeholder can only be installed
+    /// with an explicit prefix.
+    pub fn with_default_prefix(mut self, prefix: PathBuf) -> Self {
+        self.default_prefix = Some(prefix);
+        self
+    }
+
+    /// Sets the trust level of the repository named `name`; repositories
+    /// without one are [`TrustLevel::Normal`].
+    pub fn with_repository_trust(mut self, name: impl Into<String>, level: TrustLevel) -> Self {
+        self.repository_trust.insert(name.into(), level);
+        self
+    }
+
+    /// Sets the trust level below which packages need confirmation,
+    /// [`TrustLevel::Normal`] by default.
+    pub fn with_trust_threshold(mut self, threshold: TrustLevel) -> Self {
+        self.trust_threshold = threshold;
+        self
+    }
+
+    /// Asks `confirm` before installing a package, dependencies included,
+    /// whose repository is below the trust threshold. It receives the
+    /// package and the repository name and returns whether to go ahead.
+    ///
+    /// Without a callback, packages of untrusted repositories are refused.
+    pub fn with_trust_confirmation<F>(mut self, confirm: F) -> Self
+    where
+        F: Fn(&Package, &str) -> bool + Send + Sync + 'static,
+    {
+        self.trust_confirmation = Some(Box::new(confirm));
+        self
+    }
+
+    /// Sets which version bumps `update` makes for packages without a
+    /// policy of their own, [`UpdatePolicy::Major`] by default.
+    pub fn with_update_policy(mut self, update_policy: UpdatePolicy) -> Self {
+        self.update_policy = update_policy;
+        self
+    }
+
+    /// Sets how many packages are downloaded at the same time, 4 by default.
+    pub fn with_max_concurrent_downloads(mut self, max_concurrent_downloads: usize) -> Self {
+        self.max_concurrent_downloads = max_concurrent_downloads.max(1);
+        self
+    }
+
+    /// Serializes mutating operations with other processes through `lock`.
+    ///
+    /// Read-only operations such as `search`, `info` and `list_installed`
+    /// never take the lock.
+    pub fn with_lock(mut self, lock: LockFile) -> Self {
+        self.lock = Some(lock);
+        self
+    }
+
+    /// Journals installs at `path` while they run, so one the process dies
+    /// in can be cleaned up by [`recover`](Self::recover).
+    pub fn with_journal(mut self, path: PathBuf) -> Self {
+        self.journal = Some(path);
+        self
+    }
+
+    /// Sets the clock installations and history entries are stamped with,
+    /// the system clock by default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        let clock: Arc<dyn Clock> = Arc::new(clock);
+        self.installations = InstallationFactory::with_clock(Arc::clone(&clock));
+        self.clock = clock;
+        self
+    }
+
+    /// The publisher operations report their events to.
+    pub fn event_publisher(&self) -> &EVENTS {
+        &self.event_publisher
+    }
+
     pub async fn install(
         &self,
         package_ref: &PackageReference,
     ) -> Result<InstallResult, UhpmError> {
-        self.event_publisher
-            .publish(crate::PackageEvent::InstallationStarted {
-                package_ref: package_ref.clone(),
-            })
-            .await?;
+        self.install_with_options(package_ref, &InstallOptions::default())
+            .await
+    }
 
-        let package = self.repository.get_package(package_ref).await?;
-        let dependencies = self
-            .repository
-            .resolve_dependencies(package.dependencies())
-            .await?;
+    pub async fn install_with_options(
+        &self,
+        package_ref: &PackageReference,
+        options: &InstallOptions,
+    ) -> Result<InstallResult, UhpmError> {
+        let _lock = self.lock("install").await?;
+        self.install_recorded(package_ref, options, None).await
+    }
+
+    /// Installs `package_ref` and records it in the history, as undoing the
+    /// record `undoes` if given. The caller holds the lock.
+    async fn install_recorded(
+        &self,
+        package_ref: &PackageReference,
+        options: &InstallOptions,
+        undoes: Option<i64>,
+    ) -> Result<InstallResult, UhpmError> {
+        let started = Instant::now();
+        let outcome = self
+            .perform_install(package_ref, options)
+            .instrument(info_span!("install", package = %package_ref))
+            .await;
+
+        let mut record = self
+            .operation_record(OperationKind::Install, package_ref.name.clone())
+            .to_version(package_ref.version.clone());
+        record.undoes = undoes;
+        self.finish_operation(record, started, outcome).await
+    }
+
+    /// Works out what installing `package_ref` would do, without downloading
+    /// or placing anything.
+    ///
+    /// The plan lists the packages that would be installed with their sizes
+    /// and whether they are cached or installed already, along with the
+    /// conflicts and policy violations the install would fail on. Pass it to
+    /// `install_with_options` through [`InstallOptions::with_plan`] to install
+    /// exactly those packages.
+    pub async fn plan_install(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<InstallPlan, UhpmError> {
+        let mut plan = InstallPlan::new(package_ref.clone());
+        let context = ResolutionContext::new(self.repository.as_ref());
+        let package = context.get_package(package_ref).await?;
+        let resolution = match self
+            .resolve_install_order(&context, std::slice::from_ref(&package), false)
+            .await
+        {
+            Ok(resolution) => resolution,
+            Err(failure) if !failure.conflicts.is_empty() => {
+                plan.conflicts = failure.conflicts;
+                return Ok(plan);
+            }
+            Err(failure) => return Err(failure.error),
+        };
+        plan.warnings = upgrade_warnings(&resolution.packages_to_update);
+
+        let host = Target::current();
+        for package in &resolution.packages_to_install {
+            let planned_ref = PackageReference::from_package(package);
+            if !package.target().matches(&host) {
+                plan.blockers.push(format!(
+                    "{} is built for {}, host is {}",
+                    planned_ref,
+                    package.target(),
+                    host
+                ));
+            }
+            if let Some(repository) = package.source_repository() {
+                let level = self
+                    .repository_trust
+                    .get(repository)
+                    .copied()
+                    .unwrap_or_default();
+                if level < self.trust_threshold {
+                    match &self.trust_confirmation {
+                        Some(_) => plan.warnings.push(format!(
+                            "{} comes from {} repository `{}` and needs confirmation",
+                            planned_ref, level, repository
+                        )),
+                        None if level == TrustLevel::Untrusted => plan.blockers.push(format!(
+                            "{} comes from untrusted repository `{}`",
+                            planned_ref, repository
+                        )),
+                        None => {}
+                    }
+                }
+            }
+
+            let download_size = match package.download_size() {
+                Some(size) => Some(size),
+                None => self
+                    .repository
+                    .download_size(&planned_ref)
+                    .await
+                    .unwrap_or_else(|error| {
+                        warn!(package = %planned_ref, %error, "download size unknown");
+                        None
+                    }),
+            };
+            plan.packages.push(PlannedPackage {
+                repository: package.source_repository().map(str::to_string),
+                checksum: package.checksum().clone(),
+                download_size,
+                installed_size: package.installed_size(),
+                cached: self.cache.has_package(&planned_ref).await,
+                installed: self
+                    .store
+                    .get_package(package.id())
+                    .await?
+                    .is_some_and(|stored| stored.is_installed()),
+                package_ref: planned_ref,
+            });
+        }
+
+        Ok(plan)
+    }
+
+    /// Removes one version of a package, its package directory and its
+    /// database records.
+    ///
+    /// Inactive versions can be removed freely. The active version is
+    /// refused with `PackageIsActive`, and the last installed version of a
+    /// package that other installed packages depend on is kept; use
+    /// `remove_all` to remove either anyway.
+    pub async fn remove(&self, package_ref: &PackageReference) -> Result<RemovalResult, UhpmError> {
+        let _lock = self.lock("remove").await?;
+        self.remove_recorded(package_ref, None).await
+    }
+
+    /// Removes `package_ref` and records it in the history, as undoing the
+    /// record `undoes` if given. The caller holds the lock.
+    async fn remove_recorded(
+        &self,
+        package_ref: &PackageReference,
+        undoes: Option<i64>,
+    ) -> Result<RemovalResult, UhpmError> {
+        let started = Instant::now();
+        let outcome = self
+            .remove_version(package_ref)
+            .instrument(info_span!("remove", package = %package_ref))
+            .await;
+
+        let mut record = self
+            .operation_record(OperationKind::Remove, package_ref.name.clone())
+            .from_version(package_ref.version.clone());
+        record.undoes = undoes;
+        self.finish_operation(record, started, outcome).await
+    }
 
-        let all_packages = std::iter::once(&package)
-            .chain(&dependencies)
+    /// Removes every installed version of a package.
+    ///
+    /// Fails if one of them is active unless `force` is set, in which case it
+    /// is deactivated first. Versions are removed oldest first; the returned
+    /// result adds up all removals and carries the id of the newest version.
+    /// Installed packages left without the package are reported as warnings.
+    pub async fn remove_all(
+        &self,
+        package_name: &str,
+        force: bool,
+    ) -> Result<RemovalResult, UhpmError> {
+        let _lock = self.lock("remove").await?;
+        self.perform_remove_all(package_name, force)
+            .instrument(info_span!("remove_all", package = package_name, force))
+            .await
+    }
+
+    async fn perform_remove_all(
+        &self,
+        package_name: &str,
+        force: bool,
+    ) -> Result<RemovalResult, UhpmError> {
+        let mut versions = self
+            .installed_packages()
+            .await?
+            .into_iter()
+            .filter(|package| package.name() == package_name)
             .collect::<Vec<_>>();
-        for pkg in all_packages {
-            self.download_package_if_needed(pkg).await?;
+        versions.sort_by(|a, b| a.version().cmp(b.version()));
+        let newest = versions
+            .last()
+            .ok_or_else(|| UhpmError::InstallationNotFound(package_name.to_string()))?;
+
+        let mut total = RemovalResult {
+            package_id: newest.id().clone(),
+            removed_files: 0,
+            freed_space: 0,
+            warnings: Vec::new(),
+        };
+        if let Some(active) = versions.iter().find(|package| package.is_active()) {
+            if !force {
+                return Err(UhpmError::PackageIsActive);
+            }
+            let deactivated = self.deactivate_package(active).await?;
+            total.removed_files += deactivated.removed_files;
+            total.freed_space += deactivated.freed_space;
+            total.warnings.extend(deactivated.warnings);
         }
 
-        let mut installed_files = Vec::new();
-        let mut symlinks_created = 0;
+        for package in &versions {
+            let package_ref = PackageReference::from_package(package);
+            let started = Instant::now();
+            let outcome = async {
+                let result = self.perform_remove(&package_ref).await?;
+                self.purge_package(package.id()).await?;
+                Ok(result)
+            }
+            .await;
 
-        for pkg in dependencies {
-            let result = self.install_single_package(&pkg).await?;
-            installed_files.extend(result.installed_files);
-            symlinks_created += result.symlinks_created;
+            let record = self
+                .operation_record(OperationKind::Remove, package_name)
+                .from_version(package.version().clone());
+            let result = self.finish_operation(record, started, outcome).await?;
+            total.removed_files += result.removed_files;
+            total.freed_space += result.freed_space;
+            total.warnings.extend(result.warnings);
         }
 
-        let main_result = self.install_single_package(&package).await?;
-        installed_files.extend(main_result.installed_files);
-        symlinks_created += main_result.symlinks_created;
+        total
+            .warnings
+            .extend(self.orphaned_dependents(package_name).await?);
+        Ok(total)
+    }
 
-        let install_result = InstallResult {
-            package_id: package.id().clone(),
-            installed_files,
-            symlinks_created,
+    /// Switches a package to `target_version`.
+    ///
+    /// A pinned package is only switched with `allow_pinned`, and then keeps
+    /// its pin at the new version. The new version goes into the prefix the
+    /// current one was installed into; if it fails to install, the current
+    /// version is placed there again.
+    pub async fn switch(
+        &self,
+        package_name: &str,
+        target_version: &semver::Version,
+        allow_pinned: bool,
+    ) -> Result<SwitchResult, UhpmError> {
+        let _lock = self.lock("switch").await?;
+        self.switch_recorded(package_name, target_version, allow_pinned, None)
+            .await
+    }
+
+    /// Switches a package and records it in the history, as undoing the
+    /// record `undoes` if given. The caller holds the lock.
+    async fn switch_recorded(
+        &self,
+        package_name: &str,
+        target_version: &semver::Version,
+        allow_pinned: bool,
+        undoes: Option<i64>,
+    ) -> Result<SwitchResult, UhpmError> {
+        let started = Instant::now();
+        let current_version = self.get_current_version(package_name).await;
+        let outcome = match &current_version {
+            Ok(version) if !allow_pinned && self.is_pinned(package_name, version).await? => Err(
+                UhpmError::PackagePinned(format!("{}@{}", package_name, version)),
+            ),
+            Ok(version) => {
+                self.perform_switch(package_name, version, target_version)
+                    .instrument(info_span!(
+                        "switch",
+                        package = package_name,
+                        from = %version,
+                        to = %target_version
+                    ))
+                    .await
+            }
+            Err(_) => Err(UhpmError::PackageNotFound(package_name.to_string())),
         };
 
-        self.event_publisher
-            .publish(crate::PackageEvent::InstallationCompleted { package })
-            .await?;
+        let mut record = self
+            .operation_record(OperationKind::Switch, package_name)
+            .to_version(target_version.clone());
+        if let Ok(version) = current_version {
+            record = record.from_version(version);
+        }
+        record.undoes = undoes;
+        self.finish_operation(record, started, outcome).await
+    }
 
-        Ok(install_result)
+    /// Switches a package back to the version it had before its last switch,
+    /// update or install.
+    ///
+    /// If the previous version's package directory is still around it is
+    /// placed again without touching the repository or the cache; otherwise
+    /// its cached archive is reinstalled.
+    pub async fn rollback(&self, package_name: &str) -> Result<SwitchResult, UhpmError> {
+        let _lock = self.lock("rollback").await?;
+        let started = Instant::now();
+        let current = self.get_current_version(package_name).await?;
+        let previous = self.previous_version(package_name, &current).await?;
+        let outcome = self
+            .perform_rollback(package_name, &current, &previous)
+            .instrument(info_span!(
+                "rollback",
+                package = package_name,
+                from = %current,
+                to = %previous
+            ))
+            .await;
+
+        let record = self
+            .operation_record(OperationKind::Switch, package_name)
+            .from_version(current)
+            .to_version(previous);
+        self.finish_operation(record, started, outcome).await
     }
 
-    pub async fn remove(&self, package_ref: &PackageReference) -> Result<RemovalResult, UhpmError> {
-        self.event_publisher
-            .publish(crate::PackageEvent::RemoveStarted {
-                package_ref: package_ref.clone(),
-            })
-            .await?;
+    /// Pins the installed `package_name` at `version`, so updates skip it and
+    /// switches need to be explicitly allowed.
+    ///
+    /// A pin on another version of the package is moved.
+    pub async fn pin(
+        &self,
+        package_name: &str,
+        version: &semver::Version,
+    ) -> Result<(), UhpmError> {
+        let _lock = self.lock("pin").await?;
+        let installed = self.installed_versions(package_name).await?;
+        if !installed.iter().any(|package| package.version() == version) {
+            return Err(UhpmError::PackageNotFound(format!(
+                "{}@{}",
+                package_name, version
+            )));
+        }
 
-        let package = self.repository.get_package(package_ref).await?;
+        for mut package in installed {
+            let pinned = package.version() == version;
+            if package.is_pinned() != pinned {
+                package.set_pinned(pinned);
+                self.store.save_package(&package).await?;
+            }
+        }
+        Ok(())
+    }
 
-        if package.is_active() {
-            return Err(UhpmError::PackageIsActive);
+    /// Removes the pin of `package_name`, if it has one.
+    pub async fn unpin(&self, package_name: &str) -> Result<(), UhpmError> {
+        let _lock = self.lock("unpin").await?;
+        let installed = self.installed_versions(package_name).await?;
+        if installed.is_empty() {
+            return Err(UhpmError::PackageNotFound(package_name.to_string()));
+        }
+
+        for mut package in installed.into_iter().filter(Package::is_pinned) {
+            package.set_pinned(false);
+            self.store.save_package(&package).await?;
         }
+        Ok(())
+    }
 
-        let removal_result = self.remove_single_package(&package).await?;
+    /// Sets the update policy of `package_name`, or with `None` makes it
+    /// follow the manager's policy again.
+    pub async fn set_update_policy(
+        &self,
+        package_name: &str,
+        update_policy: Option<UpdatePolicy>,
+    ) -> Result<(), UhpmError> {
+        let _lock = self.lock("set update policy").await?;
+        let installed = self.installed_versions(package_name).await?;
+        if installed.is_empty() {
+            return Err(UhpmError::PackageNotFound(package_name.to_string()));
+        }
 
-        self.event_publisher
-            .publish(crate::PackageEvent::RemoveCompleted {
-                package_ref: package_ref.clone(),
-            })
-            .await?;
+        for mut package in installed {
+            if package.update_policy() != update_policy {
+                package.set_update_policy(update_policy);
+                self.store.save_package(&package).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists, for every installed package, the newest repository version its
+    /// update policy allows moving to from its current version.
+    ///
+    /// Pinned packages and packages the repository doesn't know are skipped.
+    /// Packages at a yanked version are offered the closest version that
+    /// isn't, even where their policy wouldn't allow it.
+    pub async fn check_updates(&self) -> Result<Vec<PackageReference>, UhpmError> {
+        let names = self
+            .installed_packages()
+            .await?
+            .into_iter()
+            .map(|package| package.name().to_string())
+            .collect::<BTreeSet<_>>();
 
-        Ok(removal_result)
+        let mut updates = Vec::new();
+        for name in names {
+            let current = self.get_current_version(&name).await?;
+            if self.is_pinned(&name, &current).await? {
+                debug!(package = %name, version = %current, "skipping pinned package");
+                continue;
+            }
+            if let Some(latest) = self.newer_version(&name, &current).await? {
+                updates.push(PackageReference::new(name, latest));
+            }
+        }
+        Ok(updates)
     }
 
-    pub async fn switch(
+    /// Switches `package_name` to the newest repository version its update
+    /// policy allows.
+    ///
+    /// Fails with `PackagePinned` if the package is pinned. If it is already
+    /// up to date nothing is changed.
+    pub async fn update(&self, package_name: &str) -> Result<SwitchResult, UhpmError> {
+        let _lock = self.lock("update").await?;
+        let current = self.unpinned_version(package_name).await?;
+        let latest = self.newer_version(package_name, &current).await?;
+        self.perform_update(package_name, current, latest).await
+    }
+
+    /// Switches `package_name` to `version`, regardless of its update policy.
+    ///
+    /// Fails with `PackagePinned` if the package is pinned. If it is already
+    /// at `version` nothing is changed.
+    pub async fn update_to(
         &self,
         package_name: &str,
-        target_version: &semver::Version,
+        version: &semver::Version,
     ) -> Result<SwitchResult, UhpmError> {
-        let current_ref = PackageReference::new(
-            package_name.to_string(),
-            self.get_current_version(package_name).await?,
-        );
+        let _lock = self.lock("update").await?;
+        let current = self.unpinned_version(package_name).await?;
+        let target = (*version != current).then(|| version.clone());
+        self.perform_update(package_name, current, target).await
+    }
 
-        let target_ref = PackageReference::new(package_name.to_string(), target_version.clone());
-        let target_package = self.repository.get_package(&target_ref).await?;
+    /// Current version of `package_name`, failing if it is pinned.
+    async fn unpinned_version(&self, package_name: &str) -> Result<semver::Version, UhpmError> {
+        let current = self.get_current_version(package_name).await?;
+        if self.is_pinned(package_name, &current).await? {
+            return Err(UhpmError::PackagePinned(format!(
+                "{}@{}",
+                package_name, current
+            )));
+        }
+        Ok(current)
+    }
 
-        let removal_result = self.remove(&current_ref).await?;
+    /// Switches `package_name` to `latest`, or leaves it at `current` when
+    /// there is nothing to update to.
+    async fn perform_update(
+        &self,
+        package_name: &str,
+        current: semver::Version,
+        latest: Option<semver::Version>,
+    ) -> Result<SwitchResult, UhpmError> {
+        let Some(latest) = latest else {
+            return Ok(SwitchResult {
+                package_name: package_name.to_string(),
+                from_version: Some(current.clone()),
+                to_version: current,
+                removed_files: 0,
+                installed_files: 0,
+                warnings: Vec::new(),
+            });
+        };
 
-        let install_result = self.install(&target_ref).await?;
+        let started = Instant::now();
+        let outcome = self
+            .perform_switch(package_name, &current, &latest)
+            .instrument(info_span!(
+                "update",
+                package = package_name,
+                from = %current,
+                to = %latest
+            ))
+            .await;
 
-        let switch_result = SwitchResult {
-            package_name: package_name.to_string(),
-            from_version: Some(current_ref.version),
-            to_version: target_version.clone(),
-            removed_files: removal_result.removed_files,
-            installed_files: install_result.installed_files.len(),
+        let record = self
+            .operation_record(OperationKind::Update, package_name)
+            .from_version(current)
+            .to_version(latest);
+        self.finish_operation(record, started, outcome).await
+    }
+
+    /// Re-creates missing or misdirected symlinks of the active installation.
+    ///
+    /// Links that can't be fixed without overwriting foreign files are
+    /// reported as warnings and left untouched.
+    pub async fn repair(&self, package_ref: &PackageReference) -> Result<RepairResult, UhpmError> {
+        let _lock = self.lock("repair").await?;
+        let package_id = PackageId::new(&package_ref.name, &package_ref.version);
+        let installation = self
+            .store
+            .get_active_installation(&package_id)
+            .await?
+            .ok_or_else(|| UhpmError::InstallationNotFound(package_ref.to_string()))?;
+
+        let mut result = RepairResult {
+            package_id,
+            repaired: Vec::new(),
             warnings: Vec::new(),
         };
 
-        Ok(switch_result)
+        for symlink in installation.symlinks() {
+            match self.package_files.ensure_symlink(symlink).await {
+                Ok(SymlinkAction::Unchanged) => {}
+                Ok(_) => result.repaired.push(symlink.target.clone()),
+                Err(e @ UhpmError::FileConflict { .. }) => {
+                    warn!(target = %symlink.target.display(), "{}", e);
+                    result.warnings.push(e.to_string())
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(result)
     }
 
-    pub async fn list_installed(&self) -> Result<Vec<Package>, UhpmError> {
-        let all_packages = self.repository.search_packages("").await?;
-        let installed = all_packages
-            .into_iter()
-            .filter(|pkg| pkg.is_installed())
+    /// Cross-checks the database, the package store, the placed files and
+    /// the cache.
+    ///
+    /// Looks for installed packages whose store directory is gone, recorded
+    /// files and symlinks that are missing or point elsewhere, recorded
+    /// executables that lost their execute bit, store directories and
+    /// symlinks into the store no package accounts for, and cached archives
+    /// of packages that aren't installed. A store full of packages next to a
+    /// database recording none suggests the database was lost; it is reported
+    /// so it can be rebuilt from the store. Symlinks are searched for under
+    /// the prefixes and the directories recorded symlinks live in.
+    ///
+    /// With `options.repair`, broken symlinks are re-created, drifted
+    /// permissions restored and orphan cache entries dropped. Everything else
+    /// is only reported, fixing it means deleting files that may still matter
+    /// or reinstalling.
+    pub async fn doctor(&self, options: &DoctorOptions) -> Result<DoctorReport, UhpmError> {
+        let _lock = if options.repair {
+            self.lock("doctor").await?
+        } else {
+            None
+        };
+        let packages_dir = self.package_files.packages_dir();
+        let installed = self.store.list_installed_packages().await?;
+        let mut issues = Vec::new();
+        let mut broken_links = HashMap::new();
+        let mut recorded_links = HashSet::new();
+        let mut roots: BTreeSet<PathBuf> = self.default_prefix.iter().cloned().collect();
+
+        for package in &installed {
+            let package_id = package.id();
+            let package_path = self.package_files.get_package_path(package_id);
+            if !self.file_system.exists(&package_path).await {
+                issues.push(DoctorIssue::MissingPackageDir {
+                    package_id: package_id.clone(),
+                    path: package_path,
+                });
+            }
+
+            for installation in self.store.list_installations(package_id).await? {
+                roots.extend(installation.prefix().map(Path::to_path_buf));
+                for symlink in installation.symlinks() {
+                    recorded_links.insert(symlink.target.clone());
+                    roots.extend(symlink.target.parent().map(Path::to_path_buf));
+                    if !installation.is_active()
+                        || self.points_to(&symlink.target, &symlink.source).await
+                    {
+                        continue;
+                    }
+                    issues.push(DoctorIssue::BrokenSymlink {
+                        package_id: package_id.clone(),
+                        target: symlink.target.clone(),
+                        source: symlink.source.clone(),
+                    });
+                    broken_links.insert(symlink.target.clone(), symlink.clone());
+                }
+
+                if !installation.is_active() {
+                    continue;
+                }
+                let files: BTreeMap<&PathBuf, &FileMetadata> =
+                    installation.installed_files().iter().collect();
+                for (path, recorded) in files {
+                    if !self.file_system.exists(path).await {
+                        issues.push(DoctorIssue::MissingFile {
+                            package_id: package_id.clone(),
+                            path: path.clone(),
+                        });
+                        continue;
+                    }
+                    let Ok(current) = self.file_system.metadata(path).await else {
+                        continue;
+                    };
+                    if recorded.is_executable() && !current.is_executable() {
+                        issues.push(DoctorIssue::PermissionDrift {
+                            package_id: package_id.clone(),
+                            path: path.clone(),
+                            expected: recorded.mode(),
+                            actual: current.mode(),
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut unrecorded = 0;
+        for package_ref in self.package_files.stored_packages().await? {
+            let package_id = PackageId::new(&package_ref.name, &package_ref.version);
+            if self.store.get_package(&package_id).await?.is_none() {
+                unrecorded += 1;
+                issues.push(DoctorIssue::OrphanPackageDir {
+                    path: self.package_files.get_package_path(&package_id),
+                });
+            }
+        }
+        if installed.is_empty() && unrecorded > 0 {
+            issues.push(DoctorIssue::UnrecordedStore {
+                packages: unrecorded,
+            });
+        }
+
+        let mut visited = HashSet::new();
+        let mut pending: Vec<PathBuf> = roots.into_iter().rev().collect();
+        while let Some(dir) = pending.pop() {
+            if dir.starts_with(packages_dir) || !visited.insert(dir.clone()) {
+                continue;
+            }
+            let Ok(entries) = self.file_system.read_dir(&dir).await else {
+                continue;
+            };
+            for entry in entries {
+                match self.file_system.metadata(&entry).await.map(|m| m.file_type) {
+                    Ok(FileType::Symlink) => {
+                        let points_to = self.file_system.read_symlink(&entry).await?;
+                        let points_to = dir.join(points_to);
+                        if points_to.starts_with(packages_dir) && !recorded_links.contains(&entry) {
+                            issues.push(DoctorIssue::OrphanSymlink {
+                                path: entry,
+                                points_to,
+                            });
+                        }
+                    }
+                    Ok(FileType::Directory) => pending.push(entry),
+                    _ => {}
+                }
+            }
+        }
+
+        let installed_refs: HashSet<PackageReference> = installed
+            .iter()
+            .map(PackageReference::from_package)
             .collect();
+        let mut cached = self.cache.list_packages().await?;
+        cached.sort();
+        for package in cached {
+            if !installed_refs.contains(&package) {
+                issues.push(DoctorIssue::OrphanCacheEntry { package });
+            }
+        }
 
-        Ok(installed)
-    }
+        let mut report = DoctorReport::default();
+        for issue in issues {
+            if options.repair && issue.is_repairable() {
+                let repaired = match &issue {
+                    DoctorIssue::BrokenSymlink { target, .. } => {
+                        self.repair_symlink(&broken_links[target]).await
+                    }
+                    DoctorIssue::OrphanCacheEntry { package } => {
+                        self.cache.remove_package(package).await
+                    }
+                    DoctorIssue::PermissionDrift { path, expected, .. } => {
+                        self.file_system.set_permissions(path, *expected).await
+                    }
+                    _ => Ok(()),
+                };
+                match repaired {
+                    Ok(()) => {
+                        report.repaired.push(issue);
+                        continue;
+                    }
+                    Err(e) => warn!(issue = %issue, "not repaired: {}", e),
+                }
+            }
+            report.push(issue);
+        }
 
-    pub async fn search(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
-        self.repository.search_packages(query).await
+        Ok(report)
     }
 
-    pub async fn info(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
-        self.repository.get_package(package_ref).await
+    /// Whether `path` is a symlink pointing at `source`.
+    async fn points_to(&self, path: &Path, source: &Path) -> bool {
+        self.file_system.is_symlink(path).await
+            && self
+                .file_system
+                .read_symlink(path)
+                .await
+                .is_ok_and(|current| current == source)
     }
 
-    async fn download_package_if_needed(&self, package: &Package) -> Result<(), UhpmError> {
-        if self
-            .cache
-            .has_package(&PackageReference::from_package(package))
-            .await
-        {
-            return Ok(());
+    /// Re-creates a recorded symlink, unless its source is gone too.
+    async fn repair_symlink(&self, symlink: &Symlink) -> Result<(), UhpmError> {
+        if !self.file_system.exists(&symlink.source).await {
+            return Err(UhpmError::InstallationError(format!(
+                "{} is missing",
+                symlink.source.display()
+            )));
         }
+        self.package_files.ensure_symlink(symlink).await.map(|_| ())
+    }
 
-        self.event_publisher
-            .publish(crate::PackageEvent::DownloadStarted {
-                package_ref: PackageReference::from_package(package),
-                size: None,
-            })
-            .await?;
+    /// Takes over files of `package_ref` that were installed by hand.
+    ///
+    /// The package is downloaded and extracted as usual, then every
+    /// `(source, target)` pair of `mapping` is checked: `source` is a path
+    /// inside the package and `target` the existing file, which must have
+    /// the same content. Matching files are recorded as a direct install,
+    /// or replaced by symlinks into the package store with
+    /// `options.replace`.
+    ///
+    /// Files that are missing or differ are reported in the result's
+    /// `divergent` and nothing is changed, unless `options.overwrite` is
+    /// set, in which case they are replaced by the package's copy.
+    pub async fn adopt(
+        &self,
+        package_ref: &PackageReference,
+        mapping: &[(PathBuf, PathBuf)],
+        options: &AdoptOptions,
+    ) -> Result<AdoptResult, UhpmError> {
+        let _lock = self.lock("adopt").await?;
+        let started = Instant::now();
+        let outcome = self
+            .perform_adopt(package_ref, mapping, options)
+            .instrument(info_span!("adopt", package = %package_ref))
+            .await;
 
-        let package_data = self
-            .repository
-            .download_package(&PackageReference::from_package(package))
+        let record = self
+            .operation_record(OperationKind::Install, &package_ref.name)
+            .to_version(package_ref.version.clone());
+        self.finish_operation(record, started, outcome).await
+    }
+
+    async fn perform_adopt(
+        &self,
+        package_ref: &PackageReference,
+        mapping: &[(PathBuf, PathBuf)],
+        options: &AdoptOptions,
+    ) -> Result<AdoptResult, UhpmError> {
+        let package = self.repository.get_package(package_ref).await?;
+        let package_id = package.id().clone();
+        let cancellation = CancellationToken::new();
+        let extracted_before = self.package_files.package_exists(&package_id).await;
+
+        self.download_package(&package, &cancellation).await?;
+        let data = self.cache.get_package(package_ref).await?.ok_or_else(|| {
+            UhpmError::InstallationError(format!("{} is not in the cache", package_ref))
+        })?;
+        let size = self
+            .package_files
+            .extract_package_with_events(
+                package_ref,
+                &data,
+                self.event_publisher.as_ref(),
+                &cancellation,
+            )
             .await?;
 
-        self.cache
-            .put_package(&PackageReference::from_package(package), &package_data)
+        let package_path = self.package_files.get_package_path(&package_id);
+        let mut result = AdoptResult {
+            package_id: package_id.clone(),
+            adopted: Vec::new(),
+            divergent: Vec::new(),
+            symlinks_created: 0,
+        };
+        let mut files = Vec::with_capacity(mapping.len());
+        for (source, target) in mapping {
+            let source = package_path.join(source);
+            // The store copy must be present before anything of the user's
+            // is touched.
+            let stored = self
+                .file_system
+                .read_file(&source)
+                .await
+                .with_context(|| file_context("adopt", &package_id, &source))?;
+            let matches = match self.file_system.read_file(target).await {
+                Ok(existing) => {
+                    compute_checksum("sha256", &existing)? == compute_checksum("sha256", &stored)?
+                }
+                Err(_) => false,
+            };
+            if !matches {
+                result.divergent.push(target.clone());
+            }
+            files.push((source, target.clone(), stored, matches));
+        }
+
+        if !result.divergent.is_empty() && !options.overwrite {
+            warn!(
+                package = %package_ref,
+                divergent = result.divergent.len(),
+                "not adopting, files differ from the package"
+            );
+            if !extracted_before {
+                self.package_files.remove_package_files(&package_id).await?;
+            }
+            return Ok(result);
+        }
+
+        self.deactivate_other_versions(&package).await?;
+        let mut installation = self.installations.create_installation(package_id.clone());
+        installation.set_size(size);
+        if options.replace {
+            installation.set_install_mode(InstallMode::Symlink);
+        } else {
+            installation.set_install_mode(InstallMode::Direct);
+        }
+
+        for (source, target, stored, matches) in files {
+            let context = || file_context("adopt", &package_id, &target);
+            if options.replace {
+                if self.file_system.exists(&target).await {
+                    self.file_system
+                        .remove(&target)
+                        .await
+                        .with_context(context)?;
+                }
+                let symlink = Symlink::file(&source, &target);
+                if let Err(error) = self.file_system.create_symlink(&symlink).await {
+                    // The store copy is what the file held, unless it was
+                    // being overwritten anyway.
+                    if matches {
+                        let _ = self.file_system.write_file(&target, &stored).await;
+                    }
+                    return Err(error.context(context()));
+                }
+                installation.add_symlink(symlink);
+                result.symlinks_created += 1;
+            } else {
+                if !matches {
+                    self.file_system
+                        .copy_file(&source, &target)
+                        .await
+                        .with_context(context)?;
+                }
+                let metadata = self
+                    .file_system
+                    .metadata(&target)
+                    .await
+                    .with_context(context)?;
+                installation.add_installed_file(target.clone(), metadata);
+            }
+            result.adopted.push(target);
+        }
+
+        installation.activate();
+        let mut installed = package.clone();
+        installed.set_installed(true);
+        installed.set_active(true);
+        installed.set_explicit(true);
+        self.store.save_package(&installed).await?;
+        self.store.save_installation(&installation).await?;
+
+        Ok(result)
+    }
+
+    /// Downloads and lays out an installed package again.
+    ///
+    /// The cached archive is bypassed and the previous files are replaced
+    /// even if the package is active. The package keeps its active flag.
+    pub async fn reinstall(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<InstallResult, UhpmError> {
+        let _lock = self.lock("reinstall").await?;
+        self.perform_reinstall(package_ref)
+            .instrument(info_span!("reinstall", package = %package_ref))
+            .await
+    }
+
+    async fn perform_reinstall(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<InstallResult, UhpmError> {
+        let package_id = PackageId::new(&package_ref.name, &package_ref.version);
+        let previous = self
+            .store
+            .get_package(&package_id)
+            .await?
+            .filter(Package::is_installed)
+            .ok_or_else(|| UhpmError::InstallationNotFound(package_ref.to_string()))?;
+
+        let package = self.repository.get_package(package_ref).await?;
+        self.download_package(&package, &CancellationToken::new())
             .await?;
+        let prefix = self.installed_prefix(package.id()).await?;
 
-        self.event_publisher
-            .publish(crate::PackageEvent::DownloadCompleted {
-                package_ref: PackageReference::from_package(package),
-            })
+        self.remove_single_package(&package, false).await?;
+        self.package_files
+            .remove_package_files(package.id())
+            .await?;
+        let result = self
+            .install_single_package(
+                &package,
+                previous.is_explicit(),
+                prefix.as_deref(),
+                &CancellationToken::new(),
+            )
             .await?;
 
-        Ok(())
+        if !previous.is_active() {
+            let mut installed = package;
+            installed.set_installed(true);
+            installed.set_explicit(previous.is_explicit());
+            self.deactivate_package(&installed).await?;
+        }
+
+        Ok(result)
     }
 
-    async fn install_single_package(&self, package: &Package) -> Result<InstallResult, UhpmError> {
-        Ok(InstallResult {
-            package_id: package.id().clone(),
-            installed_files: Vec::new(),
-            symlinks_created: 0,
-        })
+    /// Returns the persisted operation history, newest first.
+    pub async fn history(
+        &self,
+        limit: Option<usize>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<OperationRecord>, UhpmError> {
+        self.store.get_history(limit, package_name).await
     }
 
-    async fn remove_single_package(&self, package: &Package) -> Result<RemovalResult, UhpmError> {
-        Ok(RemovalResult {
-            package_id: package.id().clone(),
-            removed_files: 0,
-            freed_space: 0,
-        })
+    /// Compacts the state database, reclaiming space after many install/remove cycles.
+    pub async fn maintenance(&self) -> Result<(), UhpmError> {
+        let _lock = self.lock("maintenance").await?;
+        self.store.compact().await
     }
 
-    async fn get_current_version(&self, package_name: &str) -> Result<semver::Version, UhpmError> {
-        let installed = self.list_installed().await?;
-        let package = installed
+    /// Reverts the most recent successful operation.
+    ///
+    /// Installs are undone by removing the package, removals by reinstalling
+    /// from the cache, and switches/updates by switching back to the previous
+    /// version.
+    ///
+    /// The reverting operation is recorded as undoing the original. Neither
+    /// is picked again, so repeated calls walk further back through the
+    /// history instead of redoing the last undo.
+    pub async fn undo_last(&self) -> Result<OperationRecord, UhpmError> {
+        let _lock = self.lock("undo").await?;
+        let history = self.store.get_history(None, None).await?;
+        let undone: HashSet<i64> = history
+            .iter()
+            .filter(|record| record.success)
+            .filter_map(|record| record.undoes)
+            .collect();
+        let last = history
             .into_iter()
-            .find(|pkg| pkg.name() == package_name)
-            .ok_or_else(|| UhpmError::PackageNotFound(package_name.to_string()))?;
+            .find(|record| {
+                record.success
+                    && record.undoes.is_none()
+                    && !record.id.is_some_and(|id| undone.contains(&id))
+            })
+            .ok_or_else(|| UhpmError::UndoError("No operation to undo".to_string()))?;
 
-        Ok(package.version().clone())
+        match last.kind {
+            OperationKind::Install => {
+                let version = last.to_version.clone().ok_or_else(|| {
+                    UhpmError::UndoError(format!(
+                        "Install of `{}` has no recorded version",
+                        last.package_name
+                    ))
+                })?;
+                let package_ref = PackageReference::new(last.package_name.clone(), version);
+                // The installed version is usually the active one, which
+                // `remove` refuses.
+                if let Some(package) = self
+                    .store
+                    .get_package(&PackageId::new(&package_ref.name, &package_ref.version))
+                    .await?
+                    .filter(Package::is_active)
+                {
+                    self.deactivate_package(&package).await?;
+                }
+                self.remove_recorded(&package_ref, last.id).await?;
+            }
+            OperationKind::Remove => {
+                let version = last.from_version.clone().ok_or_else(|| {
+                    UhpmError::UndoError(format!(
+                        "Removal of `{}` has no recorded version",
+                        last.package_name
+                    ))
+                })?;
+                let package_ref = PackageReference::new(last.package_name.clone(), version);
+                if !self.cache.has_package(&package_ref).await {
+                    return Err(UhpmError::UndoError(format!(
+                        "`{}` is no longer in the cache and cannot be reinstalled",
+                        package_ref
+                    )));
+                }
+                self.install_recorded(&package_ref, &InstallOptions::default(), last.id)
+                    .await?;
+            }
+            OperationKind::Switch | OperationKind::Update => {
+                let version = last.from_version.clone().ok_or_else(|| {
+                    UhpmError::UndoError(format!(
+                        "No previous version of `{}` was recorded",
+                        last.package_name
+                    ))
+                })?;
+                self.switch_recorded(&last.package_name, &version, true, last.id)
+                    .await?;
+            }
+        }
+
+        Ok(last)
+    }
+
+    async fn lock(&self, operation: &str) -> Result<Option<LockGuard>, UhpmError> {
+        match &self.lock {
+            Some(lock) => lock.acquire(operation).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Starts a history entry for an operation happening now.
+    fn operation_record(
+        &self,
+        kind: OperationKind,
+        package_name: impl Into<String>,
+    ) -> OperationRecord {
+        OperationRecord::new(kind, package_name).at(self.clock.now())
+    }
+
+    /// Persists the outcome of an operation and hands the outcome back.
+    async fn finish_operation<T>(
+        &self,
+        record: OperationRecord,
+        started: Instant,
+        outcome: Result<T, UhpmError>,
+    ) -> Result<T, UhpmError> {
+        let record = match &outcome {
+            Ok(_) => record,
+            Err(error) => record.failed(error.to_string()),
+        }
+        .with_duration(started.elapsed());
+
+        let recorded = self.store.record_operation(&record).await;
+        let value = outcome?;
+        recorded?;
+        Ok(value)
+    }
+
+    /// Publishes the event built by `failed` if `outcome` is an error.
+    ///
+    /// The error is handed back unchanged; a publisher failing at this point
+    /// is ignored so it can't mask the original error.
+    async fn publish_failure<T>(
+        &self,
+        outcome: Result<T, UhpmError>,
+        failed: impl FnOnce(String) -> PackageEvent,
+    ) -> Result<T, UhpmError> {
+        if let Err(error) = &outcome {
+            let _ = self
+                .event_publisher
+                .publish(failed(error.to_string()))
+                .await;
+        }
+        outcome
+    }
+
+    async fn perform_install(
+        &self,
+        package_ref: &PackageReference,
+        options: &InstallOptions,
+    ) -> Result<InstallResult, UhpmError> {
+        let outcome = async {
+            self.event_publisher
+                .publish(PackageEvent::InstallationStarted {
+                    package_ref: package_ref.clone(),
+                })
+                .await?;
+            self.install_package(package_ref, options).await
+        }
+        .await;
+
+        self.publish_failure(outcome, |error| PackageEvent::InstallationFailed {
+            package_ref: package_ref.clone(),
+            error,
+        })
+        .await
+    }
+
+    async fn install_package(
+        &self,
+        package_ref: &PackageReference,
+        options: &InstallOptions,
+    ) -> Result<InstallResult, UhpmError> {
+        let context = ResolutionContext::new(self.repository.as_ref());
+        let package = context.get_package(package_ref).await?;
+        let resolution = match self
+            .resolve_install_order(
+                &context,
+                std::slice::from_ref(&package),
+                options.prefer_newest,
+            )
+            .await
+        {
+            Ok(resolution) => resolution,
+            Err(failure) => {
+                return Err(self
+                    .publish_resolution_failure(std::slice::from_ref(package_ref), failure)
+                    .await);
+            }
+        };
+        let packages = resolution.packages_to_install;
+        if let Some(plan) = &options.plan {
+            plan.check_matches(package_ref, &packages)?;
+        }
+
+        self.check_targets(packages.iter(), options)?;
+        self.check_trust(packages.iter())?;
+
+        let cancellation = options.cancellation.clone().unwrap_or_default();
+        let mut install_result = InstallResult {
+            package_id: package.id().clone(),
+            installed_files: Vec::new(),
+            symlinks_created: 0,
+            warnings: upgrade_warnings(&resolution.packages_to_update),
+        };
+        install_result.warnings.extend(release_warnings(&package));
+        let explicit = HashSet::from([package.id().clone()]);
+        for result in self
+            .install_all(
+                &packages,
+                &explicit,
+                options.prefix.as_deref(),
+                &cancellation,
+            )
+            .await?
+        {
+            install_result
+                .installed_files
+                .extend(result.installed_files);
+            install_result.symlinks_created += result.symlinks_created;
+            install_result.warnings.extend(result.warnings);
+        }
+
+        self.event_publisher
+            .publish(PackageEvent::InstallationCompleted { package })
+            .await?;
+
+        Ok(install_result)
+    }
+
+    /// Installs several packages with a single dependency resolution.
+    ///
+    /// Dependencies shared between the requested packages are downloaded and
+    /// installed once. Returns one result per installed package, dependencies
+    /// first, in the order they were installed.
+    pub async fn install_many(
+        &self,
+        refs: &[PackageReference],
+    ) -> Result<Vec<InstallResult>, UhpmError> {
+        let _lock = self.lock("install").await?;
+        let started = Instant::now();
+        let outcome = self
+            .perform_install_many(refs)
+            .instrument(info_span!("install_many", count = refs.len()))
+            .await;
+
+        if let Err(error) = &outcome {
+            for package_ref in refs {
+                let _ = self
+                    .event_publisher
+                    .publish(PackageEvent::InstallationFailed {
+                        package_ref: package_ref.clone(),
+                        error: error.to_string(),
+                    })
+                    .await;
+            }
+        }
+
+        let mut recorded = Ok(0);
+        for package_ref in refs {
+            let mut record = self
+                .operation_record(OperationKind::Install, package_ref.name.clone())
+                .to_version(package_ref.version.clone());
+            if let Err(error) = &outcome {
+                record = record.failed(error.to_string());
+            }
+            let record = record.with_duration(started.elapsed());
+            recorded = recorded.and(self.store.record_operation(&record).await);
+        }
+
+        let value = outcome?;
+        recorded?;
+        Ok(value)
+    }
+
+    async fn perform_install_many(
+        &self,
+        refs: &[PackageReference],
+    ) -> Result<Vec<InstallResult>, UhpmError> {
+        let context = ResolutionContext::new(self.repository.as_ref());
+        let mut roots: Vec<Package> = Vec::new();
+        for package_ref in refs {
+            self.event_publisher
+                .publish(PackageEvent::InstallationStarted {
+                    package_ref: package_ref.clone(),
+                })
+                .await?;
+
+            let package = context.get_package(package_ref).await?;
+            if roots.iter().all(|root| root.id() != package.id()) {
+                roots.push(package);
+            }
+        }
+
+        let packages = match self.resolve_install_order(&context, &roots, false).await {
+            Ok(resolution) => resolution.packages_to_install,
+            Err(failure) => return Err(self.publish_resolution_failure(refs, failure).await),
+        };
+
+        self.check_targets(packages.iter(), &InstallOptions::default())?;
+        self.check_trust(packages.iter())?;
+
+        let explicit = roots.iter().map(|root| root.id().clone()).collect();
+        let results = self
+            .install_all(&packages, &explicit, None, &CancellationToken::new())
+            .await?;
+
+        for root in roots {
+            self.event_publisher
+                .publish(PackageEvent::InstallationCompleted { package: root })
+                .await?;
+        }
+
+        Ok(results)
+    }
+
+    /// Resolves the transitive dependencies of `roots`.
+    ///
+    /// `packages_to_install` holds every package to install exactly once,
+    /// roots included, with dependencies ordered before the packages that
+    /// need them.
+    ///
+    /// Packages only needed through a `Build` or `Dev` dependency are tagged
+    /// with their [`BuildRequirement`].
+    ///
+    /// A dependency already satisfied by an installed version reuses it and
+    /// is not installed again, unless `prefer_newest` is set. Otherwise one
+    /// version is selected per package name, the highest one requested; one
+    /// replacing an installed version is reported in `packages_to_update`. If
+    /// a selected version doesn't satisfy every package depending on it,
+    /// resolution fails with the conflicting constraints.
+    ///
+    /// Repository lookups go through `context`, so the returned packages are
+    /// the ones the caller's later lookups of the same references see.
+    async fn resolve_install_order(
+        &self,
+        context: &ResolutionContext<'_, REPO>,
+        roots: &[Package],
+        prefer_newest: bool,
+    ) -> Result<ResolutionResult, FailedResolution> {
+        let installed = self.installed_packages().await?;
+        let installed_ids = installed
+            .iter()
+            .map(|package| package.id().clone())
+            .collect::<HashSet<_>>();
+
+        let mut known = roots
+            .iter()
+            .map(|root| root.name().to_string())
+            .collect::<HashSet<_>>();
+        let mut packages = Vec::new();
+        let mut packages_to_update = Vec::new();
+        let mut pending = roots
+            .iter()
+            .flat_map(|root| root.dependencies().iter().cloned())
+            .collect::<HashSet<_>>();
+        // First package requiring each name, to explain failures.
+        let mut required_by = HashMap::new();
+        for root in roots {
+            note_requirements(&mut required_by, root);
+        }
+
+        while !pending.is_empty() {
+            let mut by_name = BTreeMap::<String, Vec<Dependency>>::new();
+            for dependency in pending.drain() {
+                if !known.contains(&dependency.name) {
+                    by_name
+                        .entry(dependency.name.clone())
+                        .or_default()
+                        .push(dependency);
+                }
+            }
+
+            let mut requested = HashSet::new();
+            let mut resolved = Vec::new();
+            let mut next = HashSet::new();
+            for (name, dependencies) in by_name {
+                match installed_match(&installed, &name, &dependencies) {
+                    Some(package) if !prefer_newest => {
+                        debug!(package = %package.id().as_str(), "reusing installed dependency");
+                        known.insert(name);
+                        note_requirements(&mut required_by, package);
+                        next.extend(package.dependencies().iter().cloned());
+                        packages.push(package.clone());
+                    }
+                    _ if dependencies.len() > 1 => {
+                        resolved.push(self.resolve_shared(context, &name, &dependencies).await?)
+                    }
+                    _ => requested.extend(dependencies),
+                }
+            }
+
+            if !requested.is_empty() {
+                match context.resolve_dependencies(&requested).await {
+                    Ok(packages) => resolved.extend(packages),
+                    Err(UhpmError::ResolutionError(mut failure)) => {
+                        failure.chain = requirement_chain(&required_by, &failure.package);
+                        return Err(UhpmError::ResolutionError(failure).into());
+                    }
+                    Err(error) => return Err(error.into()),
+                }
+            }
+            resolved.sort_by(|a, b| a.name().cmp(b.name()).then(b.version().cmp(a.version())));
+            for package in resolved {
+                if known.insert(package.name().to_string()) {
+                    debug!(package = %package.id().as_str(), "resolved dependency");
+                    let replaces_installed = !installed_ids.contains(package.id())
+                        && installed.iter().any(|other| other.name() == package.name());
+                    if replaces_installed {
+                        packages_to_update.push(PackageReference::from_package(&package));
+                    }
+                    note_requirements(&mut required_by, &package);
+                    next.extend(package.dependencies().iter().cloned());
+                    packages.push(package);
+                }
+            }
+            pending = next;
+        }
+
+        packages.extend(roots.iter().cloned());
+        let conflicts = find_conflicts(&packages);
+        if !conflicts.is_empty() {
+            let messages = conflicts
+                .iter()
+                .map(|conflict| conflict.message.as_str())
+                .collect::<Vec<_>>();
+            return Err(FailedResolution {
+                error: UhpmError::DependencyConflict(messages.join("; ")),
+                conflicts,
+            });
+        }
+
+        tag_build_only(roots, &mut packages);
+
+        let root_ids = roots
+            .iter()
+            .map(|root| root.id().clone())
+            .collect::<HashSet<_>>();
+        let packages_to_install = install_order(packages)?
+            .into_iter()
+            .filter(|package| {
+                root_ids.contains(package.id()) || !installed_ids.contains(package.id())
+            })
+            .collect();
+        Ok(ResolutionResult {
+            packages_to_install,
+            packages_to_update,
+            packages_to_remove: Vec::new(),
+            conflicts: Vec::new(),
+        })
+    }
+
+    /// Picks the highest available version of `name` satisfying every one of
+    /// `dependencies`. Yanked versions are only picked when a dependency
+    /// pins them exactly.
+    async fn resolve_shared(
+        &self,
+        context: &ResolutionContext<'_, REPO>,
+        name: &str,
+        dependencies: &[Dependency],
+    ) -> Result<Package, FailedResolution> {
+        let yanked: Vec<semver::Version> = context
+            .get_yanked_versions(name)
+            .await?
+            .iter()
+            .filter_map(|version| semver::Version::parse(version).ok())
+            .collect();
+        let candidates = context
+            .get_package_versions(name)
+            .await?
+            .iter()
+            .filter_map(|version| semver::Version::parse(version).ok())
+            .filter(|version| {
+                !yanked.contains(version)
+                    || dependencies
+                        .iter()
+                        .any(|dependency| dependency.pins_exactly(version))
+            })
+            .collect::<Vec<_>>();
+        let mut requirements = dependencies
+            .iter()
+            .map(|dependency| dependency.constraint.requirement.clone())
+            .collect::<Vec<_>>();
+        requirements.sort_by_cached_key(ToString::to_string);
+
+        match VersionConstraint::intersect(name, &requirements, &candidates) {
+            Ok(satisfying) => {
+                let package_ref = PackageReference::new(name.to_string(), satisfying[0].clone());
+                Ok(context.get_package(&package_ref).await?)
+            }
+            Err(UhpmError::DependencyConflict(message)) => {
+                let required = requirements
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>();
+                Err(FailedResolution {
+                    conflicts: vec![DependencyConflict {
+                        package: name.to_string(),
+                        required: required.join(", "),
+                        installed: String::new(),
+                        message: message.clone(),
+                    }],
+                    error: UhpmError::DependencyConflict(message),
+                })
+            }
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    /// Publishes `ResolutionFailed` for every requested package and returns
+    /// the underlying error.
+    async fn publish_resolution_failure(
+        &self,
+        refs: &[PackageReference],
+        failure: FailedResolution,
+    ) -> UhpmError {
+        for package_ref in refs {
+            let _ = self
+                .event_publisher
+                .publish(PackageEvent::ResolutionFailed {
+                    package_ref: package_ref.clone(),
+                    error: failure.error.to_string(),
+                    conflicts: failure.conflicts.clone(),
+                })
+                .await;
+        }
+        failure.error
+    }
+
+    /// Rejects packages built for another platform unless `options` allow it.
+    fn check_targets<'a>(
+        &self,
+        packages: impl Iterator<Item = &'a Package>,
+        options: &InstallOptions,
+    ) -> Result<(), UhpmError> {
+        if options.allow_target_mismatch {
+            return Ok(());
+        }
+
+        let host = Target::current();
+        for package in packages {
+            if !package.target().matches(&host) {
+                return Err(UhpmError::UnsupportedTarget(format!(
+                    "{} is built for {}, host is {}",
+                    PackageReference::from_package(package),
+                    package.target(),
+                    host
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the trust policy to packages about to be installed.
+    fn check_trust<'a>(
+        &self,
+        packages: impl Iterator<Item = &'a Package>,
+    ) -> Result<(), UhpmError> {
+        for package in packages {
+            let Some(repository) = package.source_repository() else {
+                continue;
+            };
+            let level = self
+                .repository_trust
+                .get(repository)
+                .copied()
+                .unwrap_or_default();
+            if level >= self.trust_threshold {
+                continue;
+            }
+
+            let package_ref = PackageReference::from_package(package);
+            match &self.trust_confirmation {
+                Some(confirm) if !confirm(package, repository) => {
+                    return Err(UhpmError::PermissionError(format!(
+                        "Installing {} from {} repository `{}` was not confirmed",
+                        package_ref, level, repository
+                    )));
+                }
+                None if level == TrustLevel::Untrusted => {
+                    return Err(UhpmError::PermissionError(format!(
+                        "{} comes from untrusted repository `{}`; raise its trust_level to install it",
+                        package_ref, repository
+                    )));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Downloads `packages` and installs them in the given order.
+    ///
+    /// Every package stays pinned in the cache until all of them are placed.
+    /// Packages in `explicit` are recorded as requested by the user, the rest
+    /// as dependencies.
+    ///
+    /// Installing is a two-phase commit: every package is placed on disk
+    /// first, and only then are all of them recorded in the state store. If
+    /// placing, recording or `cancellation` interrupts it, the packages this
+    /// call started to place are removed again, the versions they replaced
+    /// are made active again and the error is returned.
+    /// The targets each package creates are journaled before it is placed,
+    /// in case the process dies.
+    async fn install_all(
+        &self,
+        packages: &[Package],
+        explicit: &HashSet<PackageId>,
+        prefix: Option<&Path>,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<InstallResult>, UhpmError> {
+        let pinned = packages
+            .iter()
+            .map(PackageReference::from_package)
+            .collect::<Vec<_>>();
+        for package_ref in &pinned {
+            self.cache.pin_package(package_ref);
+        }
+
+        let mut installed_before = HashSet::new();
+        for package in packages {
+            if self
+                .store
+                .get_package(package.id())
+                .await?
+                .is_some_and(|stored| stored.is_installed())
+            {
+                installed_before.insert(package.id().clone());
+            }
+        }
+
+        let mut journal = Journal {
+            entries: packages
+                .iter()
+                .filter(|package| !installed_before.contains(package.id()))
+                .map(|package| {
+                    JournalEntry::new(
+                        OperationKind::Install,
+                        PackageReference::from_package(package),
+                        self.extraction_dir(package),
+                        self.clock.now(),
+                    )
+                })
+                .collect(),
+        };
+        let mut started = Vec::new();
+        let mut staged = Vec::new();
+        let outcome = async {
+            self.write_journal(&journal).await?;
+            self.download_packages(
+                &packages.iter().collect::<Vec<_>>(),
+                self.max_concurrent_downloads,
+                cancellation,
+            )
+            .await?;
+
+            for package in packages {
+                cancellation.check()?;
+                started.push(package);
+                let pending = self
+                    .stage_single_package(
+                        package,
+                        explicit.contains(package.id()),
+                        prefix,
+                        Some(&mut journal),
+                        cancellation,
+                    )
+                    .await?;
+                staged.push(pending);
+            }
+
+            self.record_staged(&staged).await?;
+            Ok(staged
+                .iter()
+                .map(|pending| pending.result.clone())
+                .collect())
+        }
+        .await;
+
+        if outcome.is_err() {
+            self.roll_back_install(&started, &staged, &installed_before)
+                .await;
+        }
+        if let Err(error) = self.clear_journal().await {
+            warn!(%error, "failed to clear the journal");
+        }
+
+        for package_ref in &pinned {
+            self.cache.unpin_package(package_ref);
+        }
+        outcome
+    }
+
+    /// Cleans up after an install the process died in, as told by the
+    /// journal it left behind.
+    ///
+    /// Packages the install had already recorded are kept, rolling it
+    /// forward; for the others, the targets the install created are removed
+    /// along with their extracted package directories. Symlinks pointing
+    /// outside the package are left alone. The journal is removed afterwards.
+    /// Without a journal nothing is done.
+    pub async fn recover(&self) -> Result<RecoveryResult, UhpmError> {
+        let _lock = self.lock("recover").await?;
+        let mut result = RecoveryResult::default();
+        let Some(journal) = self.read_journal().await? else {
+            return Ok(result);
+        };
+
+        for entry in journal.entries {
+            let package_id = PackageId::new(&entry.package.name, &entry.package.version);
+            if self
+                .store
+                .get_package(&package_id)
+                .await?
+                .is_some_and(|stored| stored.is_installed())
+            {
+                result.completed.push(entry.package);
+                continue;
+            }
+
+            for path in &entry.placed {
+                let context = || file_context("recover", &package_id, path);
+                if self.file_system.is_symlink(path).await {
+                    // Only links into the package are ours to remove.
+                    let link = self.file_system.read_symlink(path).await?;
+                    let link = match path.parent() {
+                        Some(parent) => parent.join(link),
+                        None => link,
+                    };
+                    if !link.starts_with(&entry.extracted) {
+                        warn!(path = %path.display(), "keeping a symlink that leaves the package");
+                        continue;
+                    }
+                    self.file_system
+                        .remove_symlink(path)
+                        .await
+                        .with_context(context)?;
+                } else if !self.file_system.exists(path).await {
+                    continue;
+                } else if self.file_system.metadata(path).await?.is_directory() {
+                    self.file_system
+                        .remove_dir_all(path)
+                        .await
+                        .with_context(context)?;
+                } else {
+                    self.file_system.remove(path).await.with_context(context)?;
+                }
+                result.removed_files += 1;
+            }
+            if self.file_system.exists(&entry.extracted).await {
+                self.file_system
+                    .remove_dir_all(&entry.extracted)
+                    .await
+                    .with_context(|| file_context("recover", &package_id, &entry.extracted))?;
+            }
+            debug!(package = %package_id.as_str(), "rolled back interrupted install");
+            result.rolled_back.push(entry.package);
+        }
+
+        self.clear_journal().await?;
+        Ok(result)
+    }
+
+    /// Directory `package` is extracted into: its build directory for
+    /// build-only packages, the packages directory otherwise.
+    fn extraction_dir(&self, package: &Package) -> PathBuf {
+        match package.build_requirement() {
+            Some(requirement) => self
+                .package_files
+                .for_build_of(&requirement.consumer)
+                .get_package_path(package.id()),
+            None => self.package_files.get_package_path(package.id()),
+        }
+    }
+
+    async fn read_journal(&self) -> Result<Option<Journal>, UhpmError> {
+        let Some(path) = &self.journal else {
+            return Ok(None);
+        };
+        if !self.file_system.exists(path).await {
+            return Ok(None);
+        }
+        let content = self.file_system.read_file(path).await?;
+        Journal::from_toml(&String::from_utf8_lossy(&content)).map(Some)
+    }
+
+    async fn write_journal(&self, journal: &Journal) -> Result<(), UhpmError> {
+        match &self.journal {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    self.file_system.create_dir_all(parent).await?;
+                }
+                self.file_system
+                    .atomic_write(path, journal.to_toml()?.as_bytes())
+                    .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    async fn clear_journal(&self) -> Result<(), UhpmError> {
+        match &self.journal {
+            Some(path) if self.file_system.exists(path).await => {
+                self.file_system.remove(path).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the packages a failed install started to place, newest first,
+    /// along with their extracted files, whether they were recorded yet or
+    /// not.
+    ///
+    /// Packages that were already installed before are left alone. Rolling
+    /// back is best effort; failures are logged so the remaining packages
+    /// are still removed.
+    async fn roll_back_install(
+        &self,
+        started: &[&Package],
+        staged: &[StagedInstall],
+        installed_before: &HashSet<PackageId>,
+    ) {
+        for package in started.iter().rev() {
+            if installed_before.contains(package.id()) {
+                continue;
+            }
+
+            let outcome = async {
+                if let Some(staged) = staged
+                    .iter()
+                    .find(|staged| staged.package.id() == package.id())
+                {
+                    self.discard_staged(staged).await?;
+                }
+                self.remove_single_package(package, false).await?;
+                match package.build_requirement() {
+                    Some(requirement) => {
+                        self.package_files
+                            .for_build_of(&requirement.consumer)
+                            .remove_package_files(package.id())
+                            .await
+                    }
+                    None => self.package_files.remove_package_files(package.id()).await,
+                }
+            }
+            .await;
+            match outcome {
+                Ok(()) => debug!(package = %package.id().as_str(), "rolled back install"),
+                Err(error) => warn!(
+                    package = %package.id().as_str(),
+                    %error,
+                    "failed to roll back install"
+                ),
+            }
+        }
+    }
+
+    /// Computes what removing a package would do without touching anything.
+    ///
+    /// The returned result reports the same file counts and freed space a real
+    /// `remove` would, so callers can show a confirmation prompt first. It
+    /// fails wherever `remove` would: for versions that aren't installed, for
+    /// the active version and for the last installed version of a package
+    /// others depend on.
+    pub async fn remove_dry_run(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<RemovalResult, UhpmError> {
+        let package = self.removable_package(package_ref).await?;
+        self.remove_single_package(&package, true).await
+    }
+
+    /// Removes installed dependencies no other installed package needs anymore.
+    ///
+    /// Packages installed explicitly are always kept. Removing an orphan can
+    /// orphan its own dependencies, so this repeats until nothing is left to
+    /// remove. Returns one result per removed package.
+    pub async fn autoremove(&self) -> Result<Vec<RemovalResult>, UhpmError> {
+        let _lock = self.lock("autoremove").await?;
+        self.perform_autoremove()
+            .instrument(info_span!("autoremove"))
+            .await
+    }
+
+    async fn perform_autoremove(&self) -> Result<Vec<RemovalResult>, UhpmError> {
+        let mut results = Vec::new();
+
+        loop {
+            let installed = self.installed_packages().await?;
+            let needed = installed
+                .iter()
+                .flat_map(|package| package.dependencies())
+                .map(|dependency| dependency.name.as_str())
+                .collect::<HashSet<_>>();
+            let orphans = installed
+                .iter()
+                .filter(|package| !package.is_explicit() && !needed.contains(package.name()))
+                .collect::<Vec<_>>();
+            if orphans.is_empty() {
+                return Ok(results);
+            }
+
+            for orphan in orphans {
+                let package_ref = PackageReference::from_package(orphan);
+                let started = Instant::now();
+                let outcome = async {
+                    self.event_publisher
+                        .publish(PackageEvent::RemoveStarted {
+                            package_ref: package_ref.clone(),
+                        })
+                        .await?;
+                    let result = self.remove_single_package(orphan, false).await?;
+                    self.event_publisher
+                        .publish(PackageEvent::RemoveCompleted {
+                            package_ref: package_ref.clone(),
+                        })
+                        .await?;
+                    Ok(result)
+                }
+                .await;
+                let outcome = self
+                    .publish_failure(outcome, |error| PackageEvent::RemovalFailed {
+                        package_ref: package_ref.clone(),
+                        error,
+                    })
+                    .await;
+
+                let record = self
+                    .operation_record(OperationKind::Remove, orphan.name())
+                    .from_version(orphan.version().clone());
+                results.push(self.finish_operation(record, started, outcome).await?);
+            }
+        }
+    }
+
+    /// Removes a single version for `remove`, refusing to remove a package
+    /// still needed by others.
+    async fn remove_version(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<RemovalResult, UhpmError> {
+        let checked = self.removable_package(package_ref).await;
+        self.publish_failure(checked, |error| PackageEvent::RemovalFailed {
+            package_ref: package_ref.clone(),
+            error,
+        })
+        .await?;
+
+        let result = self.perform_remove(package_ref).await?;
+        self.purge_package(&PackageId::new(&package_ref.name, &package_ref.version))
+            .await?;
+        Ok(result)
+    }
+
+    /// Loads the installed version `remove` is asked for, rejecting the
+    /// active version and the last installed version of a package other
+    /// installed packages depend on.
+    async fn removable_package(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Package, UhpmError> {
+        self.check_removable(package_ref).await?;
+        let package = self.installed_package(package_ref).await?;
+        if package.is_active() {
+            return Err(UhpmError::PackageIsActive);
+        }
+        Ok(package)
+    }
+
+    /// Rejects removing the last installed version of a package that other
+    /// installed packages depend on.
+    async fn check_removable(&self, package_ref: &PackageReference) -> Result<(), UhpmError> {
+        let other_version_installed = self.installed_packages().await?.iter().any(|package| {
+            package.name() == package_ref.name && package.version() != &package_ref.version
+        });
+        if other_version_installed {
+            return Ok(());
+        }
+
+        let dependents = self
+            .rdepends(&package_ref.name)
+            .await?
+            .into_iter()
+            .filter(|dependent| dependent.name != package_ref.name)
+            .map(|dependent| dependent.to_string())
+            .collect::<Vec<_>>();
+        if dependents.is_empty() {
+            return Ok(());
+        }
+
+        Err(UhpmError::DependencyConflict(format!(
+            "{} is the last installed version of {} and is required by {}",
+            package_ref,
+            package_ref.name,
+            dependents.join(", ")
+        )))
+    }
+
+    /// Loads an installed version from the state store.
+    async fn installed_package(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Package, UhpmError> {
+        self.store
+            .get_package(&PackageId::new(&package_ref.name, &package_ref.version))
+            .await?
+            .filter(Package::is_installed)
+            .ok_or_else(|| UhpmError::InstallationNotFound(package_ref.to_string()))
+    }
+
+    /// Deletes the package directory and database record of a removed version.
+    async fn purge_package(&self, package_id: &PackageId) -> Result<(), UhpmError> {
+        self.package_files.remove_package_files(package_id).await?;
+        self.store.delete_package(package_id).await
+    }
+
+    async fn perform_remove(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<RemovalResult, UhpmError> {
+        let outcome = async {
+            self.event_publisher
+                .publish(PackageEvent::RemoveStarted {
+                    package_ref: package_ref.clone(),
+                })
+                .await?;
+
+            let package = self.installed_package(package_ref).await?;
+            let removal_result = self.remove_single_package(&package, false).await?;
+
+            self.event_publisher
+                .publish(PackageEvent::RemoveCompleted {
+                    package_ref: package_ref.clone(),
+                })
+                .await?;
+
+            Ok(removal_result)
+        }
+        .await;
+
+        self.publish_failure(outcome, |error| PackageEvent::RemovalFailed {
+            package_ref: package_ref.clone(),
+            error,
+        })
+        .await
+    }
+
+    async fn perform_switch(
+        &self,
+        package_name: &str,
+        current_version: &semver::Version,
+        target_version: &semver::Version,
+    ) -> Result<SwitchResult, UhpmError> {
+        let target_ref = PackageReference::new(package_name.to_string(), target_version.clone());
+        let outcome = async {
+            self.event_publisher
+                .publish(PackageEvent::UpdateStarted {
+                    package_ref: target_ref.clone(),
+                })
+                .await?;
+
+            let package = self.repository.get_package(&target_ref).await?;
+            let switch_result = self
+                .switch_package(package_name, current_version, target_version)
+                .await?;
+
+            self.event_publisher
+                .publish(PackageEvent::UpdateCompleted { package })
+                .await?;
+
+            Ok(switch_result)
+        }
+        .await;
+
+        self.publish_failure(outcome, |error| PackageEvent::UpdateFailed {
+            package_ref: target_ref.clone(),
+            error,
+        })
+        .await
+    }
+
+    async fn perform_rollback(
+        &self,
+        package_name: &str,
+        current_version: &semver::Version,
+        previous_version: &semver::Version,
+    ) -> Result<SwitchResult, UhpmError> {
+        let previous_ref =
+            PackageReference::new(package_name.to_string(), previous_version.clone());
+        let previous_id = PackageId::new(package_name, previous_version);
+
+        if !self.package_files.package_exists(&previous_id).await {
+            if self.cache.has_package(&previous_ref).await {
+                warn!(
+                    package = %previous_ref,
+                    "package directory is gone, reinstalling from the cache"
+                );
+                return self
+                    .perform_switch(package_name, current_version, previous_version)
+                    .await;
+            }
+            return Err(UhpmError::SwitchError(format!(
+                "Cannot roll back `{}` to {}: its files have been pruned",
+                package_name, previous_version
+            )));
+        }
+
+        let outcome = async {
+            self.event_publisher
+                .publish(PackageEvent::UpdateStarted {
+                    package_ref: previous_ref.clone(),
+                })
+                .await?;
+
+            let current_id = PackageId::new(package_name, current_version);
+            let current =
+                self.store.get_package(&current_id).await?.ok_or_else(|| {
+                    UhpmError::InstallationNotFound(current_id.as_str().to_string())
+                })?;
+            let previous = match self.store.get_package(&previous_id).await? {
+                Some(previous) => previous,
+                None => self.repository.get_package_by_id(&previous_id).await?,
+            };
+
+            let prefix = self.installed_prefix(&current_id).await?;
+            debug!(package = %current_id.as_str(), "removing current version");
+            let removal_result = self.remove_single_package(&current, false).await?;
+            debug!(package = %previous_id.as_str(), "placing previous version");
+            let size = self.package_files.package_size(&previous_id).await?;
+            let install_result = self
+                .place_package(
+                    &previous,
+                    current.is_explicit(),
+                    prefix.as_deref(),
+                    size,
+                    &CancellationToken::new(),
+                )
+                .await?;
+
+            self.event_publisher
+                .publish(PackageEvent::UpdateCompleted { package: previous })
+                .await?;
+
+            Ok(SwitchResult {
+                package_name: package_name.to_string(),
+                from_version: Some(current_version.clone()),
+                to_version: previous_version.clone(),
+                removed_files: removal_result.removed_files,
+                installed_files: install_result.installed_files.len(),
+                warnings: removal_result.warnings,
+            })
+        }
+        .await;
+
+        self.publish_failure(outcome, |error| PackageEvent::UpdateFailed {
+            package_ref: previous_ref.clone(),
+            error,
+        })
+        .await
+    }
+
+    async fn switch_package(
+        &self,
+        package_name: &str,
+        current_version: &semver::Version,
+        target_version: &semver::Version,
+    ) -> Result<SwitchResult, UhpmError> {
+        let current_ref = PackageReference::new(package_name.to_string(), current_version.clone());
+        let target_ref = PackageReference::new(package_name.to_string(), target_version.clone());
+
+        let current_id = PackageId::new(package_name, current_version);
+        let current = self.store.get_package(&current_id).await?;
+        let prefix = self.installed_prefix(&current_id).await?;
+        let options = match &prefix {
+            Some(prefix) => InstallOptions::default().with_prefix(prefix),
+            None => InstallOptions::default(),
+        };
+        let removal_result = self.perform_remove(&current_ref).await?;
+
+        let install_result = match self.perform_install(&target_ref, &options).await {
+            Ok(result) => result,
+            Err(error) => {
+                // Put the current version back rather than leave neither.
+                if let Some(current) = &current
+                    && let Err(restore_error) =
+                        self.restore_version(current, prefix.as_deref()).await
+                {
+                    warn!(
+                        package = %current_id.as_str(),
+                        error = %restore_error,
+                        "failed to restore the version switched away from"
+                    );
+                }
+                return Err(error);
+            }
+        };
+
+        if let Some(current) = current {
+            self.carry_over_holds(&current, target_version).await?;
+        }
+
+        let switch_result = SwitchResult {
+            package_name: package_name.to_string(),
+            from_version: Some(current_ref.version),
+            to_version: target_version.clone(),
+            removed_files: removal_result.removed_files,
+            installed_files: install_result.installed_files.len(),
+            warnings: removal_result.warnings,
+        };
+
+        Ok(switch_result)
+    }
+
+    /// Places a removed version again from its package directory, under
+    /// `prefix`, as it was recorded before.
+    async fn restore_version(
+        &self,
+        package: &Package,
+        prefix: Option<&Path>,
+    ) -> Result<(), UhpmError> {
+        let size = self.package_files.package_size(package.id()).await?;
+        self.place_package(
+            package,
+            package.is_explicit(),
+            prefix,
+            size,
+            &CancellationToken::new(),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Lists the installed packages, leaving out those only installed to
+    /// build another package unless `include_build` is set.
+    pub async fn list_installed(&self, include_build: bool) -> Result<Vec<Package>, UhpmError> {
+        if include_build {
+            self.store.list_installed_packages().await
+        } else {
+            self.installed_packages().await
+        }
+    }
+
+    /// Removes the build-time-only dependencies installed to build
+    /// `package_ref`, together with its build directory.
+    ///
+    /// Returns the removed packages.
+    pub async fn clean_build_deps(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Vec<PackageReference>, UhpmError> {
+        let _lock = self.lock("clean_build_deps").await?;
+        let consumer = PackageId::new(&package_ref.name, &package_ref.version);
+
+        let mut removed = Vec::new();
+        for package in self.store.list_installed_packages().await? {
+            if package
+                .build_requirement()
+                .is_some_and(|requirement| requirement.consumer == consumer)
+            {
+                debug!(package = %package.id().as_str(), "removing build dependency");
+                self.store.delete_package(package.id()).await?;
+                removed.push(PackageReference::from_package(&package));
+            }
+        }
+
+        let build_dir = self.package_files.get_build_dir(&consumer);
+        if self.file_system.exists(&build_dir).await {
+            self.file_system.remove_dir_all(&build_dir).await?;
+        }
+        Ok(removed)
+    }
+
+    pub async fn search(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
+        self.repository.search_packages(query).await
+    }
+
+    pub async fn info(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
+        self.repository.get_package(package_ref).await
+    }
+
+    /// Lists the installed packages that depend on `package_name`.
+    pub async fn rdepends(&self, package_name: &str) -> Result<Vec<PackageReference>, UhpmError> {
+        let mut dependents = Vec::new();
+        for package_id in self.store.get_dependents(package_name).await? {
+            if let Some(package) = self.store.get_package(&package_id).await? {
+                dependents.push(PackageReference::from_package(&package));
+            }
+        }
+        Ok(dependents)
+    }
+
+    /// Warns about installed packages left without `package_name` once no
+    /// version of it is installed anymore.
+    async fn orphaned_dependents(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        if self.get_current_version(package_name).await.is_ok() {
+            return Ok(Vec::new());
+        }
+
+        Ok(self
+            .rdepends(package_name)
+            .await?
+            .into_iter()
+            .map(|dependent| format!("{} still depends on {}", dependent, package_name))
+            .collect())
+    }
+
+    /// Finds the installed package that placed `path`.
+    ///
+    /// The path is normalized first. If nothing owns it and it is a symlink,
+    /// the link target is looked up as well.
+    pub async fn owner_of(&self, path: &Path) -> Result<Option<PackageReference>, UhpmError> {
+        let path = normalize_path(path);
+        let mut owner = self.store.find_owner(&path).await?;
+
+        if owner.is_none() && self.file_system.is_symlink(&path).await {
+            let link = self.file_system.read_symlink(&path).await?;
+            let resolved = match path.parent() {
+                Some(parent) => normalize_path(&parent.join(link)),
+                None => normalize_path(&link),
+            };
+            owner = self.store.find_owner(&resolved).await?;
+        }
+
+        Ok(match owner {
+            Some(package_id) => self
+                .store
+                .get_package(&package_id)
+                .await?
+                .map(|package| PackageReference::from_package(&package)),
+            None => None,
+        })
+    }
+
+    /// Returns the files recorded for the active installation of a package.
+    ///
+    /// Symlinked installs report their links as `FileType::Symlink` entries.
+    pub async fn files_of(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Vec<FileMetadata>, UhpmError> {
+        let package_id = PackageId::new(&package_ref.name, &package_ref.version);
+        let installation = self
+            .store
+            .get_active_installation(&package_id)
+            .await?
+            .ok_or_else(|| UhpmError::InstallationNotFound(package_ref.to_string()))?;
+
+        let mut files = installation
+            .installed_files()
+            .values()
+            .cloned()
+            .chain(installation.symlinks().iter().map(|symlink| {
+                FileMetadata::new(symlink.target.clone(), 0).with_file_type(FileType::Symlink)
+            }))
+            .collect::<Vec<_>>();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        Ok(files)
+    }
+
+    /// Reports the disk space used by each installed package, largest first.
+    ///
+    /// Each entry holds the bytes taken in the package store and by the
+    /// cached archive. Files placed by copying count on top of the store;
+    /// symlinks and hard links share the store's data and don't.
+    pub async fn disk_usage(&self) -> Result<Vec<(PackageReference, u64, u64)>, UhpmError> {
+        let mut usage = Vec::new();
+
+        for package in self.store.list_installed_packages().await? {
+            let package_ref = PackageReference::from_package(&package);
+            let mut store_bytes = 0;
+            for installation in self.store.list_installations(package.id()).await? {
+                store_bytes += installation.size();
+                if installation.install_mode() == InstallMode::Direct {
+                    store_bytes += installation
+                        .installed_files()
+                        .values()
+                        .map(|metadata| metadata.size)
+                        .sum::<u64>();
+                }
+            }
+            let cache_bytes = self
+                .cache
+                .get_package_size(&package_ref)
+                .await?
+                .unwrap_or(0);
+            usage.push((package_ref, store_bytes, cache_bytes));
+        }
+
+        usage.sort_by(|a, b| (b.1 + b.2).cmp(&(a.1 + a.2)).then(a.0.name.cmp(&b.0.name)));
+        Ok(usage)
+    }
+
+    /// Total package store and cache bytes over all installed packages.
+    pub async fn disk_usage_total(&self) -> Result<(u64, u64), UhpmError> {
+        Ok(self.disk_usage().await?.iter().fold(
+            (0, 0),
+            |(store, cache), (_, store_bytes, cache_bytes)| {
+                (store + store_bytes, cache + cache_bytes)
+            },
+        ))
+    }
+
+    /// Installed packages using more than `threshold` bytes in total,
+    /// largest first.
+    pub async fn packages_larger_than(
+        &self,
+        threshold: u64,
+    ) -> Result<Vec<(PackageReference, u64, u64)>, UhpmError> {
+        let mut usage = self.disk_usage().await?;
+        usage.retain(|(_, store_bytes, cache_bytes)| store_bytes + cache_bytes > threshold);
+        Ok(usage)
+    }
+
+    /// Installs the newest repository version matching `spec`, e.g. `"ripgrep@^14"`.
+    pub async fn install_spec(&self, spec: &str) -> Result<InstallResult, UhpmError> {
+        let package_ref = PackageSpec::parse(spec)?
+            .resolve(self.repository.as_ref())
+            .await?;
+        self.install(&package_ref).await
+    }
+
+    /// Removes the newest installed version matching `spec`.
+    pub async fn remove_spec(&self, spec: &str) -> Result<RemovalResult, UhpmError> {
+        let spec = PackageSpec::parse(spec)?;
+        let installed = self
+            .installed_packages()
+            .await?
+            .into_iter()
+            .filter(|package| package.name() == spec.name)
+            .map(|package| package.version().clone());
+
+        let version = spec
+            .select(installed)
+            .ok_or_else(|| UhpmError::PackageNotFound(format!("{} is not installed", spec)))?;
+        self.remove(&PackageReference::new(spec.name.clone(), version))
+            .await
+    }
+
+    pub async fn info_spec(&self, spec: &str) -> Result<Package, UhpmError> {
+        let package_ref = PackageSpec::parse(spec)?
+            .resolve(self.repository.as_ref())
+            .await?;
+        self.info(&package_ref).await
+    }
+
+    /// Downloads the packages missing from the cache, at most
+    /// `max_concurrency` at a time.
+    ///
+    /// Stops at the first failed download; downloads still in flight at that
+    /// point are dropped.
+    pub async fn download_all(
+        &self,
+        packages: &[&Package],
+        max_concurrency: usize,
+    ) -> Result<(), UhpmError> {
+        self.download_packages(packages, max_concurrency, &CancellationToken::new())
+            .await
+    }
+
+    async fn download_packages(
+        &self,
+        packages: &[&Package],
+        max_concurrency: usize,
+        cancellation: &CancellationToken,
+    ) -> Result<(), UhpmError> {
+        // Collected up front: a lazily mapped iterator would keep its closure
+        // in the future, which then can't be proven `Send`.
+        let downloads: Vec<_> = packages
+            .iter()
+            .map(|package| self.download_package_if_needed(package, cancellation))
+            .collect();
+        stream::iter(downloads)
+            .buffer_unordered(max_concurrency.max(1))
+            .try_for_each(|()| async { Ok(()) })
+            .await
+    }
+
+    async fn download_package_if_needed(
+        &self,
+        package: &Package,
+        cancellation: &CancellationToken,
+    ) -> Result<(), UhpmError> {
+        if self
+            .cache
+            .has_package(&PackageReference::from_package(package))
+            .await
+        {
+            return Ok(());
+        }
+
+        self.download_package(package, cancellation).await
+    }
+
+    /// Downloads a package, verifies its checksum and stores it in the cache.
+    ///
+    /// The request is dropped as soon as `cancellation` is cancelled.
+    async fn download_package(
+        &self,
+        package: &Package,
+        cancellation: &CancellationToken,
+    ) -> Result<(), UhpmError> {
+        let package_ref = PackageReference::from_package(package);
+        let outcome = async {
+            cancellation.check()?;
+            self.event_publisher
+                .publish(PackageEvent::DownloadStarted {
+                    package_ref: package_ref.clone(),
+                    size: None,
+                })
+                .await?;
+
+            // Progress is reported synchronously by the repository and
+            // published from here while the download runs.
+            let (progress, mut updates) = tokio::sync::mpsc::unbounded_channel();
+            let on_progress = Box::new(move |downloaded, total| {
+                let _ = progress.send((downloaded, total));
+            });
+            let download = cancellation.run(async {
+                self.repository
+                    .download_package_with_progress(&package_ref, on_progress)
+                    .await
+                    .with_context(|| {
+                        ErrorContext::new("download").with_package(package_ref.clone())
+                    })
+            });
+            let forward = async {
+                while let Some((downloaded, total)) = updates.recv().await {
+                    self.event_publisher
+                        .publish(PackageEvent::DownloadProgress {
+                            package_ref: package_ref.clone(),
+                            downloaded,
+                            total,
+                        })
+                        .await?;
+                }
+                Ok::<_, UhpmError>(())
+            };
+            let (downloaded, forwarded) = future::join(download, forward).await;
+            let (package_data, source_url) = downloaded?;
+            forwarded?;
+
+            if let Some(checksum) = package.checksum() {
+                let actual = compute_checksum(&checksum.algorithm, &package_data)?;
+                if actual != checksum.hash {
+                    return Err(UhpmError::ChecksumMismatch(format!(
+                        "{}: expected {}, got {}",
+                        package_ref, checksum.hash, actual
+                    )));
+                }
+            }
+
+            self.cache
+                .put_package(&package_ref, &package_data)
+                .await
+                .with_context(|| ErrorContext::new("cache").with_package(package_ref.clone()))?;
+
+            self.event_publisher
+                .publish(PackageEvent::DownloadCompleted {
+                    package_ref: package_ref.clone(),
+                    source_url,
+                })
+                .await
+        }
+        .await;
+
+        self.publish_failure(outcome, |error| PackageEvent::DownloadFailed {
+            package_ref: package_ref.clone(),
+            error,
+        })
+        .await
+    }
+
+    /// Extracts a cached package, places its files according to the install
+    /// mode and records it.
+    async fn install_single_package(
+        &self,
+        package: &Package,
+        explicit: bool,
+        prefix: Option<&Path>,
+        cancellation: &CancellationToken,
+    ) -> Result<InstallResult, UhpmError> {
+        let staged = self
+            .stage_single_package(package, explicit, prefix, None, cancellation)
+            .await?;
+        self.commit_staged(staged).await
+    }
+
+    /// Extracts a cached package and places its files according to the
+    /// install mode, without recording it.
+    async fn stage_single_package(
+        &self,
+        package: &Package,
+        explicit: bool,
+        prefix: Option<&Path>,
+        journal: Option<&mut Journal>,
+        cancellation: &CancellationToken,
+    ) -> Result<StagedInstall, UhpmError> {
+        let package_ref = PackageReference::from_package(package);
+        let data = self.cache.get_package(&package_ref).await?.ok_or_else(|| {
+            UhpmError::InstallationError(format!("{} is not in the cache", package_ref))
+        })?;
+        if let Some(requirement) = package.build_requirement() {
+            return self
+                .stage_build_only(package, requirement, &data, cancellation)
+                .await;
+        }
+
+        let size = self
+            .package_files
+            .extract_package_with_events(
+                &package_ref,
+                &data,
+                self.event_publisher.as_ref(),
+                cancellation,
+            )
+            .await?;
+
+        self.stage_placement(package, explicit, prefix, size, journal, cancellation)
+            .await
+    }
+
+    /// Extracts a build-time-only package into the build directory of the
+    /// package needing it, without placing any of its files.
+    async fn stage_build_only(
+        &self,
+        package: &Package,
+        requirement: &BuildRequirement,
+        data: &[u8],
+        cancellation: &CancellationToken,
+    ) -> Result<StagedInstall, UhpmError> {
+        self.package_files
+            .for_build_of(&requirement.consumer)
+            .extract_package_with_events(
+                &PackageReference::from_package(package),
+                data,
+                self.event_publisher.as_ref(),
+                cancellation,
+            )
+            .await?;
+
+        let mut installed = package.clone();
+        installed.set_installed(true);
+        installed.set_active(false);
+        installed.set_explicit(false);
+
+        Ok(StagedInstall {
+            package: installed,
+            installation: None,
+            deactivated: Vec::new(),
+            result: InstallResult {
+                package_id: package.id().clone(),
+                installed_files: Vec::new(),
+                symlinks_created: 0,
+                warnings: Vec::new(),
+            },
+        })
+    }
+
+    /// Places an already extracted package of `size` unpacked bytes and
+    /// records it as the active version.
+    async fn place_package(
+        &self,
+        package: &Package,
+        explicit: bool,
+        prefix: Option<&Path>,
+        size: u64,
+        cancellation: &CancellationToken,
+    ) -> Result<InstallResult, UhpmError> {
+        let staged = self
+            .stage_placement(package, explicit, prefix, size, None, cancellation)
+            .await?;
+        self.commit_staged(staged).await
+    }
+
+    /// Places an already extracted package of `size` unpacked bytes as the
+    /// active version, without recording it.
+    ///
+    /// A package already installed explicitly stays explicit when it is
+    /// installed again as a dependency. With a `journal`, the targets about
+    /// to be created are written to it before anything is placed.
+    async fn stage_placement(
+        &self,
+        package: &Package,
+        explicit: bool,
+        prefix: Option<&Path>,
+        size: u64,
+        journal: Option<&mut Journal>,
+        cancellation: &CancellationToken,
+    ) -> Result<StagedInstall, UhpmError> {
+        let explicit = explicit
+            || self
+                .store
+                .get_package(package.id())
+                .await?
+                .is_some_and(|stored| stored.is_installed() && stored.is_explicit());
+        let deactivated = self.deactivate_other_versions(package).await?;
+
+        let mode = match self.install_mode {
+            InstallMode::Auto if InstallMode::Auto.should_use_symlinks(cfg!(unix)) => {
+                InstallMode::Symlink
+            }
+            InstallMode::Auto => InstallMode::Direct,
+            mode => mode,
+        };
+
+        let mut installation = self.installations.create_installation(package.id().clone());
+        installation.set_install_mode(mode);
+        installation.set_size(size);
+        installation.set_prefix(
+            prefix
+                .map(Path::to_path_buf)
+                .or_else(|| self.default_prefix.clone()),
+        );
+        let placed = async {
+            if let Some(journal) = journal {
+                self.journal_targets(journal, package, &installation)
+                    .await?;
+            }
+            self.place_files(&mut installation, cancellation).await
+        }
+        .await;
+        let result = match placed {
+            Ok(result) => result,
+            Err(error) => {
+                if let Err(restore_error) = self.reactivate(&deactivated).await {
+                    warn!(
+                        package = %package.id().as_str(),
+                        error = %restore_error,
+                        "failed to reactivate the previous version"
+                    );
+                }
+                return Err(error);
+            }
+        };
+
+        installation.activate();
+        let mut installed = package.clone();
+        installed.set_installed(true);
+        installed.set_active(true);
+        installed.set_explicit(explicit);
+
+        Ok(StagedInstall {
+            package: installed,
+            installation: Some(installation),
+            deactivated,
+            result,
+        })
+    }
+
+    /// Journals the instlist targets of `installation` that don't exist yet,
+    /// so `recover` knows which paths the install created even if it dies
+    /// halfway through placing them.
+    async fn journal_targets(
+        &self,
+        journal: &mut Journal,
+        package: &Package,
+        installation: &Installation,
+    ) -> Result<(), UhpmError> {
+        let Some(entry) = journal.entry_mut(&PackageReference::from_package(package)) else {
+            return Ok(());
+        };
+        let mut created = Vec::new();
+        for symlink in self
+            .files_for(installation.prefix())
+            .load_package_instlist(package.id())
+            .await?
+        {
+            let target = symlink.target;
+            if !self.file_system.is_symlink(&target).await
+                && !self.file_system.exists(&target).await
+            {
+                created.push(target);
+            }
+        }
+        created.sort();
+        entry.placed = created;
+        self.write_journal(journal).await
+    }
+
+    /// Records a staged package, removing its placed files again if the
+    /// state store fails to record it.
+    async fn commit_staged(&self, staged: StagedInstall) -> Result<InstallResult, UhpmError> {
+        if let Err(error) = self.record_staged(std::slice::from_ref(&staged)).await {
+            if let Err(discard_error) = self.discard_staged(&staged).await {
+                warn!(
+                    package = %staged.package.id().as_str(),
+                    error = %discard_error,
+                    "failed to remove files of an unrecorded install"
+                );
+            }
+            return Err(error);
+        }
+        Ok(staged.result)
+    }
+
+    /// Records staged packages and their installations in one go, so a
+    /// failing store leaves none of them half recorded.
+    async fn record_staged(&self, staged: &[StagedInstall]) -> Result<(), UhpmError> {
+        let packages = staged
+            .iter()
+            .map(|pending| pending.package.clone())
+            .collect::<Vec<_>>();
+        let installations = staged
+            .iter()
+            .filter_map(|pending| pending.installation.clone())
+            .collect::<Vec<_>>();
+        self.store.save_installs(&packages, &installations).await
+    }
+
+    /// Removes the files a staged package placed, and its installation if it
+    /// got recorded, then reactivates the versions it replaced.
+    async fn discard_staged(&self, staged: &StagedInstall) -> Result<(), UhpmError> {
+        let Some(installation) = &staged.installation else {
+            return Ok(());
+        };
+        let mut removed = RemovalResult {
+            package_id: staged.package.id().clone(),
+            removed_files: 0,
+            freed_space: 0,
+            warnings: Vec::new(),
+        };
+        self.remove_placed_files(installation, false, &mut removed)
+            .await?;
+        self.store.delete_installation(installation.id()).await?;
+        self.reactivate(&staged.deactivated).await
+    }
+
+    /// Makes versions deactivated for an install that didn't go through
+    /// active again, placing their files anew.
+    async fn reactivate(&self, packages: &[Package]) -> Result<(), UhpmError> {
+        for package in packages {
+            self.activate_package(package.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Package files placing `${PREFIX}` targets under `prefix`.
+    fn files_for(&self, prefix: Option<&Path>) -> PackageFilesRepository<FS> {
+        match prefix {
+            Some(prefix) => self.package_files.clone().with_prefix(prefix.to_path_buf()),
+            None => self.package_files.clone(),
+        }
+    }
+
+    /// Places the extracted files of an installation at their instlist
+    /// targets according to its install mode and records what was placed.
+    async fn place_files(
+        &self,
+        installation: &mut Installation,
+        cancellation: &CancellationToken,
+    ) -> Result<InstallResult, UhpmError> {
+        let package_id = installation.package_id().clone();
+        let mode = installation.install_mode();
+        let package_files = self.files_for(installation.prefix());
+        let mut result = InstallResult {
+            package_id: package_id.clone(),
+            installed_files: Vec::new(),
+            symlinks_created: 0,
+            warnings: Vec::new(),
+        };
+
+        if mode.is_symlink() {
+            for symlink in package_files
+                .create_symlinks_from_instlist(&package_id, cancellation)
+                .await?
+            {
+                installation.add_symlink(symlink);
+                result.symlinks_created += 1;
+            }
+        } else {
+            if mode.is_hardlink() {
+                result.warnings = package_files
+                    .hard_link_files(&package_id, cancellation)
+                    .await?;
+            } else {
+                package_files
+                    .copy_files_direct(&package_id, cancellation)
+                    .await?;
+            }
+
+            for symlink in package_files.load_package_instlist(&package_id).await? {
+                let metadata = self
+                    .file_system
+                    .metadata(&symlink.target)
+                    .await
+                    .with_context(|| file_context("install", &package_id, &symlink.target))?;
+                installation.add_installed_file(symlink.target.clone(), metadata);
+                result.installed_files.push(symlink.target);
+            }
+        }
+
+        Ok(result)
+    }
+
+    /// Removes the files and symlinks recorded for every installation of a package.
+    ///
+    /// Freed space is summed from the recorded file sizes. Files that are
+    /// already missing or still hard linked elsewhere don't free anything and
+    /// are reported as warnings instead. With `dry_run` nothing is deleted.
+    async fn remove_single_package(
+        &self,
+        package: &Package,
+        dry_run: bool,
+    ) -> Result<RemovalResult, UhpmError> {
+        let installations = self.store.list_installations(package.id()).await?;
+        let mut result = RemovalResult {
+            package_id: package.id().clone(),
+            removed_files: 0,
+            freed_space: 0,
+            warnings: Vec::new(),
+        };
+
+        for installation in &installations {
+            self.remove_placed_files(installation, dry_run, &mut result)
+                .await?;
+
+            if !dry_run {
+                self.store.delete_installation(installation.id()).await?;
+            }
+        }
+
+        if !dry_run && let Some(mut removed) = self.store.get_package(package.id()).await? {
+            removed.set_installed(false);
+            removed.set_active(false);
+            removed.set_explicit(false);
+            self.store.save_package(&removed).await?;
+        }
+
+        Ok(result)
+    }
+
+    /// Removes what `place_files` recorded on an installation, tallying the
+    /// outcome into `result`.
+    async fn remove_placed_files(
+        &self,
+        installation: &Installation,
+        dry_run: bool,
+        result: &mut RemovalResult,
+    ) -> Result<(), UhpmError> {
+        let context = |path: &Path| file_context("remove", installation.package_id(), path);
+        for symlink in installation.symlinks() {
+            if !self.file_system.is_symlink(&symlink.target).await {
+                warn!(target = %symlink.target.display(), "symlink already missing");
+                result.warnings.push(format!(
+                    "Symlink already missing: {}",
+                    symlink.target.display()
+                ));
+                continue;
+            }
+
+            if !dry_run {
+                self.file_system
+                    .remove_symlink(&symlink.target)
+                    .await
+                    .with_context(|| context(&symlink.target))?;
+            }
+            result.removed_files += 1;
+        }
+
+        for (path, recorded) in installation.installed_files() {
+            let current = match self.file_system.metadata(path).await {
+                Ok(metadata) => metadata,
+                Err(_) => {
+                    warn!(path = %path.display(), "file already missing");
+                    result
+                        .warnings
+                        .push(format!("File already missing: {}", path.display()));
+                    continue;
+                }
+            };
+
+            // Hard link installs share their data with the package store,
+            // so removing them frees nothing but isn't worth a warning.
+            if !current.is_hard_linked() {
+                result.freed_space += recorded.size;
+            } else if !installation.install_mode().is_hardlink() {
+                result.warnings.push(format!(
+                    "File is hard linked elsewhere, no space freed: {}",
+                    path.display()
+                ));
+            }
+
+            if !dry_run {
+                if current.is_directory() {
+                    self.file_system
+                        .remove_dir_all(path)
+                        .await
+                        .with_context(|| context(path))?;
+                } else {
+                    self.file_system
+                        .remove(path)
+                        .await
+                        .with_context(|| context(path))?;
+                }
+            }
+            result.removed_files += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Makes an installed version the active one.
+    ///
+    /// Any other active version of the same package is deactivated first, then
+    /// the instlist entries are placed again using the installation's mode,
+    /// which also restores them if the version was already active.
+    pub async fn activate(&self, package_ref: &PackageReference) -> Result<(), UhpmError> {
+        let _lock = self.lock("activate").await?;
+        let package_id = PackageId::new(&package_ref.name, &package_ref.version);
+        let package = self
+            .store
+            .get_package(&package_id)
+            .await?
+            .filter(Package::is_installed)
+            .ok_or_else(|| UhpmError::InstallationNotFound(package_ref.to_string()))?;
+
+        self.activate_package(package).await
+    }
+
+    /// Places the files of an installed version again and records it as the
+    /// active one, deactivating any other active version first.
+    async fn activate_package(&self, package: Package) -> Result<(), UhpmError> {
+        self.deactivate_other_versions(&package).await?;
+
+        let mut installation = self
+            .store
+            .list_installations(package.id())
+            .await?
+            .pop()
+            .ok_or_else(|| UhpmError::InstallationNotFound(package.id().as_str().to_string()))?;
+        installation.clear_files();
+        self.place_files(&mut installation, &CancellationToken::new())
+            .await?;
+        installation.activate();
+
+        let mut active = package;
+        active.set_active(true);
+        self.store.save_installation(&installation).await?;
+        self.store.save_package(&active).await?;
+
+        Ok(())
+    }
+
+    /// Removes the placed files of the active version and clears its flag.
+    ///
+    /// The package stays installed and can be activated again later.
+    pub async fn deactivate(&self, package_ref: &PackageReference) -> Result<(), UhpmError> {
+        let _lock = self.lock("deactivate").await?;
+        let package_id = PackageId::new(&package_ref.name, &package_ref.version);
+        let package = self
+            .store
+            .get_package(&package_id)
+            .await?
+            .filter(Package::is_installed)
+            .ok_or_else(|| UhpmError::InstallationNotFound(package_ref.to_string()))?;
+
+        self.deactivate_package(&package).await?;
+        Ok(())
+    }
+
+    /// Deactivates every other active version of `package`, removing their
+    /// placed files so the new version can take their place. Returns the
+    /// versions deactivated.
+    async fn deactivate_other_versions(
+        &self,
+        package: &Package,
+    ) -> Result<Vec<Package>, UhpmError> {
+        let others = self
+            .store
+            .list_installed_packages()
+            .await?
+            .into_iter()
+            .filter(|other| {
+                other.name() == package.name() && other.id() != package.id() && other.is_active()
+            })
+            .collect::<Vec<_>>();
+        for other in &others {
+            self.deactivate_package(other).await?;
+        }
+        Ok(others)
+    }
+
+    /// Removes the placed files of a package's active installation and marks
+    /// it inactive, returning what was removed.
+    async fn deactivate_package(&self, package: &Package) -> Result<RemovalResult, UhpmError> {
+        let mut removed = RemovalResult {
+            package_id: package.id().clone(),
+            removed_files: 0,
+            freed_space: 0,
+            warnings: Vec::new(),
+        };
+        let installation = self.store.get_active_installation(package.id()).await?;
+        if let Some(mut installation) = installation {
+            self.remove_placed_files(&installation, false, &mut removed)
+                .await?;
+            installation.clear_files();
+            installation.deactivate();
+            self.store.save_installation(&installation).await?;
+        }
+
+        let mut inactive = package.clone();
+        inactive.set_active(false);
+        self.store.save_package(&inactive).await?;
+        Ok(removed)
+    }
+
+    /// Installed packages, leaving out those only installed to build another
+    /// package; `clean_build_deps` takes care of those.
+    async fn installed_packages(&self) -> Result<Vec<Package>, UhpmError> {
+        Ok(self
+            .store
+            .list_installed_packages()
+            .await?
+            .into_iter()
+            .filter(|package| !package.is_build_only())
+            .collect())
+    }
+
+    /// Prefix of the latest installation of a package, to place it there
+    /// again.
+    async fn installed_prefix(&self, package_id: &PackageId) -> Result<Option<PathBuf>, UhpmError> {
+        Ok(self
+            .store
+            .list_installations(package_id)
+            .await?
+            .pop()
+            .and_then(|installation| installation.prefix().map(Path::to_path_buf)))
+    }
+
+    /// Installed versions of `package_name`.
+    async fn installed_versions(&self, package_name: &str) -> Result<Vec<Package>, UhpmError> {
+        Ok(self
+            .installed_packages()
+            .await?
+            .into_iter()
+            .filter(|package| package.name() == package_name)
+            .collect())
+    }
+
+    async fn is_pinned(
+        &self,
+        package_name: &str,
+        version: &semver::Version,
+    ) -> Result<bool, UhpmError> {
+        Ok(self
+            .store
+            .get_package(&PackageId::new(package_name, version))
+            .await?
+            .is_some_and(|package| package.is_pinned()))
+    }
+
+    /// Moves the pin and the update policy of `from` to version `to` of the
+    /// same package.
+    async fn carry_over_holds(
+        &self,
+        from: &Package,
+        to: &semver::Version,
+    ) -> Result<(), UhpmError> {
+        if !from.is_pinned() && from.update_policy().is_none() {
+            return Ok(());
+        }
+        if from.is_pinned()
+            && let Some(mut previous) = self.store.get_package(from.id()).await?
+        {
+            previous.set_pinned(false);
+            self.store.save_package(&previous).await?;
+        }
+        if let Some(mut package) = self
+            .store
+            .get_package(&PackageId::new(from.name(), to))
+            .await?
+        {
+            package.set_pinned(from.is_pinned());
+            package.set_update_policy(from.update_policy());
+            self.store.save_package(&package).await?;
+        }
+        Ok(())
+    }
+
+    /// The newest repository version of `package_name` the package's update
+    /// policy allows moving to from `current`, `None` if there is none or
+    /// the repository doesn't know the package.
+    ///
+    /// Yanked versions are never proposed. When `current` itself is yanked
+    /// and the policy allows nothing newer, the lowest version above it
+    /// that isn't yanked is proposed instead.
+    ///
+    /// Versions the repository lists that aren't valid semver are ignored.
+    async fn newer_version(
+        &self,
+        package_name: &str,
+        current: &semver::Version,
+    ) -> Result<Option<semver::Version>, UhpmError> {
+        let versions = match self.repository.get_package_versions(package_name).await {
+            Ok(versions) => versions,
+            Err(UhpmError::PackageNotFound(_)) => return Ok(None),
+            Err(error) => return Err(error),
+        };
+        let yanked: Vec<semver::Version> = self
+            .repository
+            .get_yanked_versions(package_name)
+            .await?
+            .iter()
+            .filter_map(|version| semver::Version::parse(version).ok())
+            .collect();
+        let versions: Vec<semver::Version> = versions
+            .iter()
+            .filter_map(|version| semver::Version::parse(version).ok())
+            .filter(|version| !yanked.contains(version))
+            .collect();
+        let policy = self
+            .store
+            .get_package(&PackageId::new(package_name, current))
+            .await?
+            .and_then(|package| package.update_policy())
+            .unwrap_or(self.update_policy);
+        let newest = policy.newest(current, &versions);
+        if newest.is_none() && yanked.contains(current) {
+            // Leaving a yanked version beats staying within the policy.
+            return Ok(versions.into_iter().filter(|v| v > current).min());
+        }
+        Ok(newest.cloned())
+    }
+
+    /// Returns the active version of a package, or its newest installed one.
+    async fn get_current_version(&self, package_name: &str) -> Result<semver::Version, UhpmError> {
+        let installed = self
+            .installed_packages()
+            .await?
+            .into_iter()
+            .filter(|pkg| pkg.name() == package_name)
+            .collect::<Vec<_>>();
+        let package = installed
+            .iter()
+            .find(|pkg| pkg.is_active())
+            .or_else(|| installed.iter().max_by(|a, b| a.version().cmp(b.version())))
+            .ok_or_else(|| UhpmError::PackageNotFound(package_name.to_string()))?;
+
+        Ok(package.version().clone())
+    }
+
+    /// Finds the version that was current before `current` became current.
+    ///
+    /// This is the source of the switch or update that led to `current`, or
+    /// else the version installed before it.
+    async fn previous_version(
+        &self,
+        package_name: &str,
+        current: &semver::Version,
+    ) -> Result<semver::Version, UhpmError> {
+        let history = self.store.get_history(None, Some(package_name)).await?;
+        let mut records = history
+            .iter()
+            .filter(|record| record.success && record.kind != OperationKind::Remove);
+
+        let previous = records
+            .find(|record| record.to_version.as_ref() == Some(current))
+            .and_then(|record| {
+                record.from_version.clone().or_else(|| {
+                    records.find_map(|record| {
+                        record
+                            .to_version
+                            .clone()
+                            .filter(|version| version != current)
+                    })
+                })
+            });
+
+        previous.ok_or_else(|| {
+            UhpmError::SwitchError(format!(
+                "No previous version of `{}` was recorded",
+                package_name
+            ))
+        })
+    }
+}
+
+/// The installed version of `name` to reuse for `dependencies`: the active
+/// one if it satisfies all of them, the highest satisfying one otherwise.
+fn installed_match<'a>(
+    installed: &'a [Package],
+    name: &str,
+    dependencies: &[Dependency],
+) -> Option<&'a Package> {
+    installed
+        .iter()
+        .filter(|package| {
+            package.name() == name
+                && dependencies
+                    .iter()
+                    .all(|dependency| dependency.matches_version(package.version()))
+        })
+        .max_by(|a, b| (a.is_active(), a.version()).cmp(&(b.is_active(), b.version())))
+}
+
+/// Tags the packages only reachable from `roots` through a `Build` or `Dev`
+/// dependency with the requirement of that dependency.
+///
+/// Packages reachable through runtime dependencies alone stay untagged, even
+/// when a build dependency leads to them too. Everything a build-time-only
+/// package depends on shares its requirement.
+fn tag_build_only(roots: &[Package], packages: &mut [Package]) {
+    let by_name = packages
+        .iter()
+        .enumerate()
+        .map(|(position, package)| (package.name().to_string(), position))
+        .collect::<HashMap<_, _>>();
+    let dependencies_of = |name: &str| {
+        let mut dependencies = by_name
+            .get(name)
+            .map(|&position| packages[position].dependencies().iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+        dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+        dependencies
+    };
+    let is_build =
+        |kind: &DependencyKind| matches!(kind, DependencyKind::Build | DependencyKind::Dev);
+
+    let mut runtime = BTreeSet::new();
+    let mut pending = roots
+        .iter()
+        .map(|root| root.name().to_string())
+        .collect::<Vec<_>>();
+    while let Some(name) = pending.pop() {
+        if runtime.insert(name.clone()) {
+            pending.extend(
+                dependencies_of(&name)
+                    .into_iter()
+                    .filter(|dependency| !is_build(&dependency.kind))
+                    .map(|dependency| dependency.name.clone()),
+            );
+        }
+    }
+
+    let mut queue = VecDeque::new();
+    for name in &runtime {
+        let Some(&position) = by_name.get(name) else {
+            continue;
+        };
+        for dependency in dependencies_of(name) {
+            if is_build(&dependency.kind) {
+                queue.push_back((
+                    dependency.name.clone(),
+                    BuildRequirement {
+                        kind: dependency.kind.clone(),
+                        consumer: packages[position].id().clone(),
+                    },
+                ));
+            }
+        }
+    }
+
+    let mut requirements = HashMap::new();
+    while let Some((name, requirement)) = queue.pop_front() {
+        if runtime.contains(&name) || requirements.contains_key(&name) {
+            continue;
+        }
+        for dependency in dependencies_of(&name) {
+            queue.push_back((dependency.name.clone(), requirement.clone()));
+        }
+        requirements.insert(name, requirement);
+    }
+
+    for package in packages {
+        package.set_build_requirement(requirements.remove(package.name()));
+    }
+}
+
+/// Warnings for dependencies replaced because no installed version
+/// satisfied them.
+fn upgrade_warnings(packages_to_update: &[PackageReference]) -> Vec<String> {
+    packages_to_update
+        .iter()
+        .map(|package_ref| {
+            format!(
+                "{} is upgraded to {}: no installed version satisfies its dependents",
+                package_ref.name, package_ref.version
+            )
+        })
+        .collect()
+}
+
+/// Records `package` as requiring each of its dependencies, unless another
+/// package required them first.
+fn note_requirements(required_by: &mut HashMap<String, RequirementEdge>, package: &Package) {
+    for dependency in package.dependencies() {
+        required_by
+            .entry(dependency.name.clone())
+            .or_insert_with(|| RequirementEdge {
+                package: PackageReference::from_package(package),
+                dependency: dependency.name.clone(),
+                requirement: dependency.constraint.requirement.clone(),
+            });
+    }
+}
+
+/// Requirements leading from a requested package down to `package_name`,
+/// outermost first.
+fn requirement_chain(
+    required_by: &HashMap<String, RequirementEdge>,
+    package_name: &str,
+) -> Vec<RequirementEdge> {
+    let mut chain = Vec::new();
+    let mut seen = HashSet::new();
+    let mut name = package_name;
+    while seen.insert(name)
+        && let Some(edge) = required_by.get(name)
+    {
+        chain.push(edge.clone());
+        name = &edge.package.name;
+    }
+    chain.reverse();
+    chain
+}
+
+/// Warnings for installing a version its publisher yanked or deprecated.
+fn release_warnings(package: &Package) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if package.is_yanked() {
+        warnings.push(format!(
+            "{} is yanked by its publisher and installed only because it was requested explicitly",
+            package.id().as_str()
+        ));
+    }
+    if let Some(message) = package.deprecation() {
+        warnings.push(format!(
+            "{} is deprecated: {}",
+            package.id().as_str(),
+            message
+        ));
+    }
+    warnings
+}
+
+/// Makes `path` absolute and removes `.` and `..` components without
+/// touching the file system.
+fn normalize_path(path: &Path) -> PathBuf {
+    let path = if path.is_relative() {
+        std::env::current_dir().unwrap_or_default().join(path)
+    } else {
+        path.to_path_buf()
+    };
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{
+        InMemoryStateStore, MemoryCache, MemoryRepository, MockNetwork, RecordingEventPublisher,
+        RecordingSubscriber, block_on, package_archive,
+    };
+    use crate::{
+        Architecture, Checksum, DependencyKind, OperatingSystem, PackageSource, VersionConstraint,
+        fs::TokioFileSystem,
+    };
+    use semver::Version;
+
+    type TestManager = PackageManager<
+        TokioFileSystem,
+        MockNetwork,
+        MemoryRepository,
+        MemoryCache,
+        RecordingEventPublisher,
+        InMemoryStateStore,
+    >;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("uhpm-manager-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn package(
+        name: &str,
+        target: Target,
+        checksum: Option<Checksum>,
+        dependencies: Vec<Dependency>,
+    ) -> Package {
+        PackageFactory::create(
+            name.to_string(),
+            Version::new(1, 0, 0),
+            "tester".to_string(),
+            PackageSource::Local {
+                path: PathBuf::from("/memory").join(name),
+            },
+            target,
+            checksum,
+            dependencies,
+        )
+        .unwrap()
+    }
+
+    fn dependency(name: &str) -> Dependency {
+        Dependency {
+            name: name.to_string(),
+            constraint: VersionConstraint {
+                requirement: semver::VersionReq::STAR,
+            },
+            kind: DependencyKind::Required,
+            provides: None,
+            features: vec![],
+        }
+    }
+
+    fn archive(dir: &std::path::Path, name: &str) -> Vec<u8> {
+        let file = format!("bin/{}", name);
+        let instlist = format!("{} {}\n", file, dir.join(&file).display());
+        package_archive(&[
+            ("instlist", instlist.as_bytes()),
+            (file.as_str(), b"#!/bin/sh\n"),
+        ])
+    }
+
+    fn manager_with(dir: &std::path::Path, repository: MemoryRepository) -> TestManager {
+        PackageManager::new(
+            TokioFileSystem::new(),
+            MockNetwork::new(),
+            repository,
+            MemoryCache::new(),
+            RecordingEventPublisher::new(),
+            InMemoryStateStore::new(),
+            dir.join("packages"),
+        )
+        .with_install_mode(InstallMode::Direct)
+    }
+
+    fn manager(dir: &std::path::Path, target: Target) -> TestManager {
+        let repository = MemoryRepository::new();
+        repository.add(package("tool", target, None, vec![]), archive(dir, "tool"));
+        manager_with(dir, repository)
+    }
+
+    fn failures(manager: &TestManager) -> Vec<&'static str> {
+        manager
+            .event_publisher
+            .events()
+            .into_iter()
+            .filter_map(|event| match event {
+                PackageEvent::InstallationFailed { .. } => Some("install"),
+                PackageEvent::RemovalFailed { .. } => Some("remove"),
+                PackageEvent::UpdateFailed { .. } => Some("update"),
+                PackageEvent::DownloadFailed { .. } => Some("download"),
+                PackageEvent::ResolutionFailed { .. } => Some("resolution"),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn foreign_target() -> Target {
+        let host = Target::current();
+        Target {
+            os: host.os,
+            arch: match host.arch {
+                Architecture::Aarch64 => Architecture::X86_64,
+                _ => Architecture::Aarch64,
+            },
+        }
+    }
+
+    fn tool_ref() -> PackageReference {
+        PackageReference::new("tool".to_string(), Version::new(1, 0, 0))
+    }
+
+    #[test]
+    fn test_install_matching_target() {
+        let dir = temp_dir();
+        let manager = manager(&dir, Target::current());
+
+        block_on(async {
+            let result = manager.install(&tool_ref()).await.unwrap();
+
+            assert_eq!(result.installed_files, vec![dir.join("bin/tool")]);
+            assert!(dir.join("bin/tool").exists());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_is_traced() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("lib", Target::current(), None, vec![]),
+            archive(&dir, "lib"),
+        );
+        repository.add(
+            package("app", Target::current(), None, vec![dependency("lib")]),
+            archive(&dir, "app"),
+        );
+        let manager = manager_with(&dir, repository);
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+        let subscriber = RecordingSubscriber::new();
+
+        tracing::subscriber::with_default(subscriber.clone(), || {
+            block_on(async {
+                manager.install(&app).await.unwrap();
+                manager.deactivate(&app).await.unwrap();
+                manager.remove(&app).await.unwrap();
+            })
+        });
+
+        assert_eq!(subscriber.spans(), ["install", "remove"]);
+        assert!(subscriber.events().contains(&tracing::Level::DEBUG));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_undo_walks_back_through_the_history() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        for name in ["lib", "tool"] {
+            repository.add(
+                package(name, Target::current(), None, vec![]),
+                archive(&dir, name),
+            );
+        }
+        let manager =
+            manager_with(&dir, repository).with_lock(LockFile::new(dir.join("uhpm.lock")));
+        let installed = |manager: &TestManager| {
+            block_on(manager.list_installed(false))
+                .unwrap()
+                .iter()
+                .map(|package| package.name().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        block_on(async {
+            for name in ["lib", "tool"] {
+                let package_ref = PackageReference::new(name.to_string(), Version::new(1, 0, 0));
+                manager.install(&package_ref).await.unwrap();
+            }
+        });
+
+        let undone = block_on(manager.undo_last()).unwrap();
+        assert_eq!(undone.package_name, "tool");
+        assert_eq!(installed(&manager), ["lib"]);
+
+        // The removal the first undo made is not undone again.
+        let undone = block_on(manager.undo_last()).unwrap();
+        assert_eq!(undone.package_name, "lib");
+        assert!(installed(&manager).is_empty());
+
+        assert!(matches!(
+            block_on(manager.undo_last()),
+            Err(UhpmError::UndoError(_))
+        ));
+        let history = block_on(manager.history(None, None)).unwrap();
+        assert_eq!(history[0].undoes, history[3].id);
+        assert_eq!(history[1].undoes, history[2].id);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_target_policy_normalizes_and_restricts_targets() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        for (name, target) in [("tool", "~/bin/../bin/tool"), ("sneaky", "~/.ssh/sneaky")] {
+            let instlist = format!("bin/{} {}\n", name, target);
+            repository.add(
+                package(name, Target::current(), None, vec![]),
+                package_archive(&[
+                    ("instlist", instlist.as_bytes()),
+                    (format!("bin/{}", name).as_str(), b"#!/bin/sh\n"),
+                ]),
+            );
+        }
+        let manager = manager_with(&dir, repository)
+            .with_target_policy(TargetPolicy::new(&dir, [dir.join("bin")]));
+        let sneaky = PackageReference::new("sneaky".to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            let result = manager.install(&tool_ref()).await.unwrap();
+            assert_eq!(result.installed_files, [dir.join("bin/tool")]);
+            assert_eq!(
+                manager.owner_of(&dir.join("bin/tool")).await.unwrap(),
+                Some(tool_ref())
+            );
+
+            let err = manager.install(&sneaky).await.unwrap_err();
+            assert!(matches!(err, UhpmError::ValidationError(_)), "{}", err);
+            assert!(err.to_string().contains(".ssh/sneaky"), "{}", err);
+        });
+        assert!(!dir.join(".ssh").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_rejects_mismatching_target() {
+        let dir = temp_dir();
+
+        for target in [
+            foreign_target(),
+            Target {
+                os: OperatingSystem::Custom("plan9".to_string()),
+                arch: Target::current().arch,
+            },
+        ] {
+            let manager = manager(&dir, target);
+            let err = block_on(manager.install(&tool_ref())).unwrap_err();
+
+            assert!(matches!(err, UhpmError::UnsupportedTarget(_)));
+            assert!(!dir.join("bin/tool").exists());
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_install_allows_mismatch_when_requested() {
+        let dir = temp_dir();
+        let manager = manager(&dir, foreign_target());
+        let options = InstallOptions::default().allow_target_mismatch();
+
+        block_on(async {
+            manager
+                .install_with_options(&tool_ref(), &options)
+                .await
+                .unwrap();
+        });
+
+        assert!(dir.join("bin/tool").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reinstall_restores_corrupted_files() {
+        let dir = temp_dir();
+        let manager = manager(&dir, Target::current());
+        let tool = dir.join("bin/tool");
+
+        block_on(async {
+            manager.install(&tool_ref()).await.unwrap();
+            std::fs::write(&tool, b"corrupted").unwrap();
+            // A broken cached archive must not be reused.
+            manager
+                .cache
+                .put_package(&tool_ref(), b"garbage")
+                .await
+                .unwrap();
+
+            manager.reinstall(&tool_ref()).await.unwrap();
+
+            let id = PackageId::new("tool", &Version::new(1, 0, 0));
+            let package = manager.store.get_package(&id).await.unwrap();
+            assert!(package.unwrap().is_active());
+        });
+
+        assert_eq!(std::fs::read(&tool).unwrap(), b"#!/bin/sh\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unresolvable_dependency_publishes_failures() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("tool", Target::current(), None, vec![dependency("missing")]),
+            archive(&dir, "tool"),
+        );
+        let manager = manager_with(&dir, repository);
+
+        assert!(block_on(manager.install(&tool_ref())).is_err());
+        assert_eq!(failures(&manager), ["resolution", "install"]);
+    }
+
+    #[test]
+    fn test_checksum_mismatch_publishes_download_failure() {
+        let dir = temp_dir();
+        let checksum = Checksum {
+            algorithm: "sha256".to_string(),
+            hash: "0".repeat(64),
+        };
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("tool", Target::current(), Some(checksum), vec![]),
+            archive(&dir, "tool"),
+        );
+        let manager = manager_with(&dir, repository);
+
+        let err = block_on(manager.install(&tool_ref())).unwrap_err();
+
+        assert!(matches!(err, UhpmError::ChecksumMismatch(_)));
+        assert_eq!(failures(&manager), ["download", "install"]);
+    }
+
+    #[test]
+    fn test_remove_and_switch_publish_failures() {
+        let dir = temp_dir();
+        let manager = manager(&dir, Target::current());
+        let missing = PackageReference::new("missing".to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            assert!(manager.remove(&missing).await.is_err());
+            assert!(
+                manager
+                    .perform_switch("tool", &Version::new(1, 0, 0), &Version::new(2, 0, 0))
+                    .await
+                    .is_err()
+            );
+        });
+
+        assert_eq!(failures(&manager), ["remove", "update"]);
+    }
+
+    #[test]
+    fn test_failure_events_carry_package_and_error() {
+        let dir = temp_dir();
+        let checksum = Checksum {
+            algorithm: "sha256".to_string(),
+            hash: "0".repeat(64),
+        };
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("tool", Target::current(), Some(checksum), vec![]),
+            archive(&dir, "tool"),
+        );
+        let manager = manager_with(&dir, repository);
+        let missing = PackageReference::new("missing".to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            assert!(manager.install(&tool_ref()).await.is_err());
+            assert!(manager.remove(&missing).await.is_err());
+        });
+
+        let events = manager.event_publisher.events();
+        assert!(events.iter().any(|event| matches!(
+            event,
+            PackageEvent::DownloadFailed { package_ref, error }
+                if *package_ref == tool_ref() && error.contains("Checksum")
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            PackageEvent::RemovalFailed { package_ref, error }
+                if *package_ref == missing && error.contains("missing")
+        )));
+    }
+
+    fn manager_with_app_and_lib(dir: &std::path::Path) -> (TestManager, usize, usize) {
+        let repository = MemoryRepository::new();
+        let lib_archive = archive(dir, "lib");
+        let app_archive = archive(dir, "app");
+        let sizes = (lib_archive.len(), app_archive.len());
+        repository.add(package("lib", Target::current(), None, vec![]), lib_archive);
+        repository.add(
+            package("app", Target::current(), None, vec![dependency("lib")]),
+            app_archive,
+        );
+        (manager_with(dir, repository), sizes.0, sizes.1)
+    }
+
+    #[test]
+    fn test_plan_install_changes_nothing() {
+        let dir = temp_dir();
+        let (manager, lib_size, app_size) = manager_with_app_and_lib(&dir);
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+        let lib = PackageReference::new("lib".to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            manager
+                .cache
+                .put_package(&lib, &archive(&dir, "lib"))
+                .await
+                .unwrap();
+            let plan = manager.plan_install(&app).await.unwrap();
+
+            let planned: Vec<_> = plan.packages.iter().map(|p| &p.package_ref).collect();
+            assert_eq!(planned, [&lib, &app]);
+            assert_eq!(plan.packages[0].download_size, Some(lib_size as u64));
+            assert!(plan.packages[0].cached);
+            assert!(!plan.packages[1].cached && !plan.packages[1].installed);
+            assert_eq!(plan.downloads(), 1);
+            assert_eq!(plan.download_size(), app_size as u64);
+            assert_eq!(plan.installed_size(), (lib_size + app_size) as u64);
+            assert!(!plan.is_blocked());
+
+            assert!(manager.list_installed(true).await.unwrap().is_empty());
+            assert!(!dir.join("bin/app").exists());
+
+            manager
+                .install_with_options(&app, &InstallOptions::default().with_plan(plan))
+                .await
+                .unwrap();
+            assert!(dir.join("bin/app").exists());
+            assert!(dir.join("bin/lib").exists());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_refuses_an_outdated_plan() {
+        let dir = temp_dir();
+        let (manager, _, _) = manager_with_app_and_lib(&dir);
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            let plan = manager.plan_install(&app).await.unwrap();
+            let newer = PackageFactory::create(
+                "lib".to_string(),
+                Version::new(1, 1, 0),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory/lib"),
+                },
+                Target::current(),
+                None,
+                vec![],
+            )
+            .unwrap();
+            manager.repository.add(newer, archive(&dir, "lib"));
+
+            let error = manager
+                .install_with_options(&app, &InstallOptions::default().with_plan(plan))
+                .await
+                .unwrap_err();
+            assert!(matches!(error, UhpmError::PlanOutdated(_)), "{}", error);
+            assert!(manager.list_installed(true).await.unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_install_many_shares_dependencies() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("common", Target::current(), None, vec![]),
+            archive(&dir, "common"),
+        );
+        for name in ["first", "second"] {
+            repository.add(
+                package(name, Target::current(), None, vec![dependency("common")]),
+                archive(&dir, name),
+            );
+        }
+        let manager = manager_with(&dir, repository);
+        let refs = ["first", "second"]
+            .map(|name| PackageReference::new(name.to_string(), Version::new(1, 0, 0)));
+
+        let results = block_on(manager.install_many(&refs)).unwrap();
+
+        let installed = results
+            .iter()
+            .map(|result| result.package_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(installed, ["common@1.0.0", "first@1.0.0", "second@1.0.0"]);
+
+        let common_downloads = manager
+            .event_publisher
+            .events()
+            .into_iter()
+            .filter(|event| {
+                matches!(event, PackageEvent::DownloadStarted { package_ref, .. }
+                    if package_ref.name == "common")
+            })
+            .count();
+        assert_eq!(common_downloads, 1);
+
+        let common = PackageId::new("common", &Version::new(1, 0, 0));
+        let installations = block_on(manager.store.list_installations(&common));
+        assert_eq!(installations.unwrap().len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_dependencies_are_not_explicit() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("lib", Target::current(), None, vec![]),
+            archive(&dir, "lib"),
+        );
+        repository.add(
+            package("app", Target::current(), None, vec![dependency("lib")]),
+            archive(&dir, "app"),
+        );
+        let manager = manager_with(&dir, repository);
+        let explicit = async |name: &str| {
+            let id = PackageId::new(name, &Version::new(1, 0, 0));
+            let package = manager.store.get_package(&id).await.unwrap().unwrap();
+            package.is_explicit()
+        };
+
+        block_on(async {
+            manager
+                .install(&PackageReference::new(
+                    "app".to_string(),
+                    Version::new(1, 0, 0),
+                ))
+                .await
+                .unwrap();
+            assert!(explicit("app").await);
+            assert!(!explicit("lib").await);
+
+            // Asking for the dependency itself makes it explicit.
+            manager
+                .install(&PackageReference::new(
+                    "lib".to_string(),
+                    Version::new(1, 0, 0),
+                ))
+                .await
+                .unwrap();
+            assert!(explicit("lib").await);
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_autoremove_keeps_shared_dependencies() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        for (name, dependencies) in [
+            ("common", vec![]),
+            ("lib", vec![]),
+            ("app", vec![dependency("lib"), dependency("common")]),
+            ("other", vec![dependency("common")]),
+        ] {
+            repository.add(
+                package(name, Target::current(), None, dependencies),
+                archive(&dir, name),
+            );
+        }
+        let manager = manager_with(&dir, repository);
+        let reference = |name: &str| PackageReference::new(name.to_string(), Version::new(1, 0, 0));
+        let installed = async |name: &str| {
+            let id = PackageId::new(name, &Version::new(1, 0, 0));
+            let package = manager.store.get_package(&id).await.unwrap();
+            package.filter(Package::is_installed)
+        };
+
+        block_on(async {
+            manager.install(&reference("app")).await.unwrap();
+            manager.install(&reference("other")).await.unwrap();
+            assert!(installed("app").await.unwrap().is_explicit());
+            assert!(!installed("lib").await.unwrap().is_explicit());
+
+            manager.remove_all("app", true).await.unwrap();
+            let removed = manager.autoremove().await.unwrap();
+
+            let removed = removed
+                .iter()
+                .map(|result| result.package_id.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(removed, ["lib@1.0.0"]);
+            assert!(installed("lib").await.is_none());
+            assert!(installed("common").await.is_some());
+            assert!(installed("other").await.is_some());
+            assert!(manager.autoremove().await.unwrap().is_empty());
+        });
+
+        assert!(!dir.join("bin/lib").exists());
+        assert!(dir.join("bin/common").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rollback_to_previous_version() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let versions = [Version::new(1, 0, 0), Version::new(2, 0, 0)];
+        for version in &versions {
+            let tool = PackageFactory::create(
+                "tool".to_string(),
+                version.clone(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory/tool"),
+                },
+                Target::current(),
+                None,
+                vec![],
+            )
+            .unwrap();
+            repository.add(tool, archive(&dir, "tool"));
+        }
+        let manager = manager_with(&dir, repository);
+        let refs = versions
+            .clone()
+            .map(|version| PackageReference::new("tool".to_string(), version));
+
+        block_on(async {
+            manager.install(&refs[0]).await.unwrap();
+            let err = manager.rollback("tool").await.unwrap_err();
+            assert!(matches!(err, UhpmError::SwitchError(_)), "{}", err);
+
+            manager.switch("tool", &versions[1], false).await.unwrap();
+            // The old package directory is reused, the cache isn't needed.
+            manager.cache.remove_package(&refs[0]).await.unwrap();
+
+            let result = manager.rollback("tool").await.unwrap();
+            assert_eq!(result.from_version, Some(versions[1].clone()));
+            assert_eq!(result.to_version, versions[0]);
+            assert_eq!(
+                manager.get_current_version("tool").await.unwrap(),
+                versions[0]
+            );
+            assert!(dir.join("bin/tool").exists());
+
+            // Rolling back again returns to the version rolled back from.
+            std::fs::remove_dir_all(dir.join("packages/tool@2.0.0")).unwrap();
+            manager.cache.remove_package(&refs[1]).await.unwrap();
+            let err = manager.rollback("tool").await.unwrap_err();
+            assert!(err.to_string().contains("pruned"), "{}", err);
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_into_prefixes() {
+        let dir = temp_dir();
+        let repository = || {
+            let repository = MemoryRepository::new();
+            repository.add(
+                package("tool", Target::current(), None, vec![]),
+                package_archive(&[
+                    ("instlist", b"bin/tool ${PREFIX}/bin/tool\n"),
+                    ("bin/tool", b"#!/bin/sh\n"),
+                ]),
+            );
+            repository
+        };
+        let manager = manager_with(&dir, repository());
+        let tool = tool_ref();
+        let id = PackageId::new(&tool.name, &tool.version);
+        let prefixes = [dir.join("vendor"), dir.join("other user/my prefix")];
+
+        block_on(async {
+            let err = manager.install(&tool).await.unwrap_err();
+            assert!(matches!(err, UhpmError::ValidationError(_)), "{}", err);
+
+            for prefix in &prefixes {
+                let options = InstallOptions::default().with_prefix(prefix);
+                let result = manager.install_with_options(&tool, &options).await.unwrap();
+                assert_eq!(result.installed_files, [prefix.join("bin/tool")]);
+            }
+
+            let installations = manager.store.list_installations(&id).await.unwrap();
+            assert_eq!(installations.len(), 2);
+            for (installation, prefix) in installations.iter().zip(&prefixes) {
+                assert_eq!(installation.prefix(), Some(prefix.as_path()));
+                let files = installation.installed_files();
+                assert!(files.contains_key(&prefix.join("bin/tool")));
+                assert!(prefix.join("bin/tool").exists());
+            }
+
+            manager.remove_all(&tool.name, true).await.unwrap();
+        });
+
+        for prefix in &prefixes {
+            assert!(!prefix.join("bin/tool").exists());
+        }
+
+        // Without a prefix of its own, an install uses the default one.
+        let manager = manager_with(&dir, repository()).with_default_prefix(prefixes[1].clone());
+        block_on(manager.install(&tool)).unwrap();
+        assert!(prefixes[1].join("bin/tool").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_switch_keeps_the_prefix() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let broken = Checksum {
+            algorithm: "sha256".to_string(),
+            hash: "0".repeat(64),
+        };
+        for (version, checksum) in [("1.0.0", None), ("2.0.0", None), ("3.0.0", Some(broken))] {
+            let package = PackageFactory::create(
+                "tool".to_string(),
+                Version::parse(version).unwrap(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory/tool"),
+                },
+                Target::current(),
+                checksum,
+                vec![],
+            )
+            .unwrap();
+            repository.add(
+                package,
+                package_archive(&[
+                    ("instlist", b"bin/tool ${PREFIX}/bin/tool\n"),
+                    ("bin/tool", version.as_bytes()),
+                ]),
+            );
+        }
+        let manager = manager_with(&dir, repository);
+        let prefix = dir.join("vendor");
+        let id = |version: &str| PackageId::new("tool", &Version::parse(version).unwrap());
+        let active_prefix = |version: &'static str| {
+            let manager = &manager;
+            async move {
+                manager
+                    .store
+                    .get_active_installation(&id(version))
+                    .await
+                    .unwrap()
+                    .and_then(|installation| installation.prefix().map(Path::to_path_buf))
+            }
+        };
+
+        block_on(async {
+            let options = InstallOptions::default().with_prefix(&prefix);
+            manager
+                .install_with_options(&tool_ref(), &options)
+                .await
+                .unwrap();
+
+            manager
+                .switch("tool", &Version::new(2, 0, 0), false)
+                .await
+                .unwrap();
+            assert_eq!(active_prefix("2.0.0").await, Some(prefix.clone()));
+            assert_eq!(std::fs::read(prefix.join("bin/tool")).unwrap(), b"2.0.0");
+
+            // A failed switch puts the current version back where it was.
+            let err = manager
+                .switch("tool", &Version::new(3, 0, 0), false)
+                .await
+                .unwrap_err();
+            assert!(err.to_string().contains("Checksum"), "{}", err);
+            assert_eq!(active_prefix("2.0.0").await, Some(prefix.clone()));
+            assert!(
+                manager
+                    .store
+                    .get_package(&id("2.0.0"))
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .is_active()
+            );
+            assert_eq!(std::fs::read(prefix.join("bin/tool")).unwrap(), b"2.0.0");
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_adopt_takes_over_matching_files_only() {
+        let dir = temp_dir();
+        let manager = manager(&dir, Target::current());
+        let tool = tool_ref();
+        let id = PackageId::new(&tool.name, &tool.version);
+        let existing = dir.join("local/bin/tool");
+        std::fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        let mapping = [(PathBuf::from("bin/tool"), existing.clone())];
+
+        block_on(async {
+            // A file that differs is reported and left alone.
+            std::fs::write(&existing, b"modified").unwrap();
+            let result = manager
+                .adopt(&tool, &mapping, &AdoptOptions::default().replace())
+                .await
+                .unwrap();
+            assert_eq!(result.divergent, std::slice::from_ref(&existing));
+            assert!(result.adopted.is_empty());
+            assert_eq!(std::fs::read(&existing).unwrap(), b"modified");
+            assert!(manager.store.get_package(&id).await.unwrap().is_none());
+            assert!(!manager.package_files.package_exists(&id).await);
+
+            // A matching file is replaced by a link into the store.
+            std::fs::write(&existing, b"#!/bin/sh\n").unwrap();
+            let result = manager
+                .adopt(&tool, &mapping, &AdoptOptions::default().replace())
+                .await
+                .unwrap();
+            assert!(result.divergent.is_empty());
+            assert_eq!(result.adopted, std::slice::from_ref(&existing));
+            assert_eq!(result.symlinks_created, 1);
+            assert!(std::fs::symlink_metadata(&existing).unwrap().is_symlink());
+            assert_eq!(std::fs::read(&existing).unwrap(), b"#!/bin/sh\n");
+
+            let package = manager.store.get_package(&id).await.unwrap().unwrap();
+            assert!(package.is_installed() && package.is_active());
+            let installations = manager.store.list_installations(&id).await.unwrap();
+            assert_eq!(installations.len(), 1);
+            assert_eq!(installations[0].symlinks().len(), 1);
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_untrusted_dependencies_need_confirmation() {
+        let dir = temp_dir();
+        let repository = || {
+            let repository = MemoryRepository::new();
+            let mut app = package("app", Target::current(), None, vec![dependency("lib")]);
+            app.set_source_repository(Some("official".to_string()));
+            let mut lib = package("lib", Target::current(), None, vec![]);
+            lib.set_source_repository(Some("third-party".to_string()));
+            repository.add(app, archive(&dir, "app"));
+            repository.add(lib, archive(&dir, "lib"));
+            repository
+        };
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            let manager = manager_with(&dir, repository())
+                .with_repository_trust("official", TrustLevel::Trusted)
+                .with_repository_trust("third-party", TrustLevel::Untrusted);
+            let error = manager.install(&app).await.unwrap_err();
+            assert!(matches!(error.root(), UhpmError::PermissionError(message)
+                if message.contains("lib@1.0.0") && message.contains("third-party")));
+            assert!(!dir.join("bin/app").exists());
+
+            let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let recorded = Arc::clone(&asked);
+            let manager = manager_with(&dir, repository())
+                .with_repository_trust("third-party", TrustLevel::Untrusted)
+                .with_trust_confirmation(move |package, repository| {
+                    recorded
+                        .lock()
+                        .unwrap()
+                        .push(format!("{}@{}", package.name(), repository));
+                    true
+                });
+            manager.install(&app).await.unwrap();
+            assert_eq!(*asked.lock().unwrap(), ["lib@third-party"]);
+            assert!(dir.join("bin/lib").exists());
+        });
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_pinned_packages_are_held_back() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let versions = [Version::new(1, 0, 0), Version::new(2, 0, 0)];
+        for version in &versions {
+            let tool = PackageFactory::create(
+                "tool".to_string(),
+                version.clone(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory/tool"),
+                },
+                Target::current(),
+                None,
+                vec![],
+            )
+            .unwrap();
+            repository.add(tool, archive(&dir, "tool"));
+        }
+        let manager = manager_with(&dir, repository);
+        let old = PackageReference::new("tool".to_string(), versions[0].clone());
+        let new = PackageReference::new("tool".to_string(), versions[1].clone());
+
+        block_on(async {
+            manager.install(&old).await.unwrap();
+            assert_eq!(manager.check_updates().await.unwrap(), [new]);
+
+            manager.pin("tool", &versions[0]).await.unwrap();
+            assert!(manager.list_installed(false).await.unwrap()[0].is_pinned());
+            assert!(manager.check_updates().await.unwrap().is_empty());
+
+            let err = manager.update("tool").await.unwrap_err();
+            assert!(matches!(err, UhpmError::PackagePinned(_)), "{}", err);
+            assert!(err.to_string().contains("tool@1.0.0"), "{}", err);
+            let err = manager
+                .switch("tool", &versions[1], false)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, UhpmError::PackagePinned(_)), "{}", err);
+            assert_eq!(
+                manager.get_current_version("tool").await.unwrap(),
+                versions[0]
+            );
+
+            // An allowed switch keeps the pin at the new version.
+            manager.switch("tool", &versions[1], true).await.unwrap();
+            assert!(manager.is_pinned("tool", &versions[1]).await.unwrap());
+            assert!(!manager.is_pinned("tool", &versions[0]).await.unwrap());
+
+            manager.unpin("tool").await.unwrap();
+            manager.switch("tool", &versions[0], false).await.unwrap();
+            let result = manager.update("tool").await.unwrap();
+            assert_eq!(result.to_version, versions[1]);
+            assert!(manager.check_updates().await.unwrap().is_empty());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_disk_usage_counts_store_copies_and_cache() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let tool_archive = archive(&dir, "tool");
+        repository.add(
+            package("tool", Target::current(), None, vec![]),
+            tool_archive.clone(),
+        );
+        let big_instlist = format!("data/big {}\n", dir.join("share/big").display());
+        let big_archive = package_archive(&[
+            ("instlist", big_instlist.as_bytes()),
+            ("data/big", &[0; 4096]),
+        ]);
+        repository.add(
+            package("big", Target::current(), None, vec![]),
+            big_archive.clone(),
+        );
+        let manager = manager_with(&dir, repository);
+        let tool_instlist = format!("bin/tool {}\n", dir.join("bin/tool").display());
+
+        block_on(async {
+            manager.install(&tool_ref()).await.unwrap();
+            manager
+                .install(&PackageReference::new(
+                    "big".to_string(),
+                    Version::new(1, 0, 0),
+                ))
+                .await
+                .unwrap();
+
+            let usage = manager.disk_usage().await.unwrap();
+            let names = usage
+                .iter()
+                .map(|(package_ref, _, _)| package_ref.name.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(names, ["big", "tool"]);
+
+            // Unpacked instlist and binary, plus the direct-mode copy.
+            let tool_store = tool_instlist.len() as u64 + 10 + 10;
+            assert_eq!(usage[1].1, tool_store);
+            assert_eq!(usage[1].2, tool_archive.len() as u64);
+
+            let (store, cache) = manager.disk_usage_total().await.unwrap();
+            assert_eq!(store, usage[0].1 + tool_store);
+            assert_eq!(cache, (tool_archive.len() + big_archive.len()) as u64);
+
+            let larger = manager.packages_larger_than(4096).await.unwrap();
+            assert_eq!(larger.len(), 1);
+            assert_eq!(larger[0].0.name, "big");
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rdepends_and_remove_guard() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("lib", Target::current(), None, vec![]),
+            archive(&dir, "lib"),
+        );
+        for name in ["app", "tool"] {
+            repository.add(
+                package(name, Target::current(), None, vec![dependency("lib")]),
+                archive(&dir, name),
+            );
+        }
+        let manager = manager_with(&dir, repository);
+        let reference = |name: &str| PackageReference::new(name.to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            manager.install(&reference("app")).await.unwrap();
+            manager.install(&reference("tool")).await.unwrap();
+
+            let dependents = manager.rdepends("lib").await.unwrap();
+            assert_eq!(dependents, [reference("app"), reference("tool")]);
+            assert!(manager.rdepends("app").await.unwrap().is_empty());
+
+            let err = manager.remove_dry_run(&reference("lib")).await.unwrap_err();
+            assert!(matches!(err, UhpmError::DependencyConflict(_)), "{}", err);
+            let err = manager.remove(&reference("lib")).await.unwrap_err();
+            assert!(matches!(err, UhpmError::DependencyConflict(_)), "{}", err);
+            assert!(
+                err.to_string()
+                    .contains("required by app@1.0.0, tool@1.0.0"),
+                "{}",
+                err
+            );
+            assert!(dir.join("bin/lib").exists());
+
+            let err = manager
+                .remove_dry_run(&reference("tool"))
+                .await
+                .unwrap_err();
+            assert!(matches!(err, UhpmError::PackageIsActive), "{}", err);
+            let err = manager.remove(&reference("tool")).await.unwrap_err();
+            assert!(matches!(err, UhpmError::PackageIsActive), "{}", err);
+            assert!(dir.join("bin/tool").exists());
+
+            let err = manager.remove_all("lib", false).await.unwrap_err();
+            assert!(matches!(err, UhpmError::PackageIsActive), "{}", err);
+
+            let result = manager.remove_all("lib", true).await.unwrap();
+            let warnings = result
+                .warnings
+                .iter()
+                .filter(|warning| warning.contains("depends on"))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                warnings,
+                [
+                    "app@1.0.0 still depends on lib",
+                    "tool@1.0.0 still depends on lib"
+                ]
+            );
+        });
+
+        assert!(!dir.join("bin/lib").exists());
+        assert!(!dir.join("packages/lib@1.0.0").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_remove_inactive_version_and_remove_all() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let versions = [Version::new(1, 0, 0), Version::new(2, 0, 0)];
+        for version in &versions {
+            let tool = PackageFactory::create(
+                "tool".to_string(),
+                version.clone(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory/tool"),
+                },
+                Target::current(),
+                None,
+                vec![],
+            )
+            .unwrap();
+            repository.add(tool, archive(&dir, "tool"));
+        }
+        let manager = manager_with(&dir, repository);
+        let refs = versions
+            .clone()
+            .map(|version| PackageReference::new("tool".to_string(), version));
+        let ids = versions
+            .clone()
+            .map(|version| PackageId::new("tool", &version));
+
+        block_on(async {
+            manager.install(&refs[0]).await.unwrap();
+            manager.install(&refs[1]).await.unwrap();
+
+            // The older version is inactive and goes away with its records.
+            manager.remove(&refs[0]).await.unwrap();
+            assert!(!dir.join("packages/tool@1.0.0").exists());
+            assert!(manager.store.get_package(&ids[0]).await.unwrap().is_none());
+            assert!(dir.join("bin/tool").exists());
+
+            manager.install(&refs[0]).await.unwrap();
+            let result = manager.remove_all("tool", true).await.unwrap();
+            assert_eq!(result.package_id, ids[1]);
+            assert_eq!(result.removed_files, 1);
+            for id in &ids {
+                assert!(manager.store.get_package(id).await.unwrap().is_none());
+            }
+            assert!(
+                manager
+                    .store
+                    .list_installed_packages()
+                    .await
+                    .unwrap()
+                    .is_empty()
+            );
+            assert!(matches!(
+                manager.remove_all("tool", true).await,
+                Err(UhpmError::InstallationNotFound(_))
+            ));
+        });
+
+        assert!(!dir.join("bin/tool").exists());
+        assert_eq!(std::fs::read_dir(dir.join("packages")).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_owner_of_and_files_of() {
+        let dir = temp_dir();
+        let manager = manager(&dir, Target::current());
+        let tool = dir.join("bin/tool");
+
+        block_on(async {
+            manager.install(&tool_ref()).await.unwrap();
+
+            let indirect = dir.join("bin/../bin/./tool");
+            assert_eq!(manager.owner_of(&indirect).await.unwrap(), Some(tool_ref()));
+            assert_eq!(manager.owner_of(&dir.join("bin")).await.unwrap(), None);
+
+            let files = manager.files_of(&tool_ref()).await.unwrap();
+            assert_eq!(files.len(), 1);
+            assert_eq!(files[0].path, tool);
+            assert_eq!(files[0].size, 10);
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_glob_instlist_entries_are_recorded_per_file() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let instlist = format!("share/man/*.1 {}\n", dir.join("man").display());
+        repository.add(
+            package("docs", Target::current(), None, vec![]),
+            package_archive(&[
+                ("instlist", instlist.as_bytes()),
+                ("share/man/docs.1", b"docs"),
+                ("share/man/docs-index.1", b"index"),
+            ]),
+        );
+        let manager = manager_with(&dir, repository);
+        let docs = PackageReference::new("docs".to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            manager.install(&docs).await.unwrap();
+
+            for page in ["docs.1", "docs-index.1"] {
+                let path = dir.join("man").join(page);
+                assert_eq!(manager.owner_of(&path).await.unwrap(), Some(docs.clone()));
+            }
+            assert_eq!(manager.files_of(&docs).await.unwrap().len(), 2);
+
+            let removal = manager.remove_all(&docs.name, true).await.unwrap();
+            assert_eq!(removal.removed_files, 2);
+        });
+        assert!(!dir.join("man/docs.1").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_install_places_dependencies_first() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        for (name, dependencies) in [
+            ("app", vec!["lib"]),
+            ("lib", vec!["base"]),
+            ("base", vec![]),
+        ] {
+            repository.add(
+                package(
+                    name,
+                    Target::current(),
+                    None,
+                    dependencies.into_iter().map(dependency).collect(),
+                ),
+                archive(&dir, name),
+            );
+        }
+        let manager = manager_with(&dir, repository);
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+
+        let result = block_on(manager.install(&app)).unwrap();
+
+        let installed = result
+            .installed_files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(installed, ["base", "lib", "app"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_build_dependencies_are_kept_apart() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let kind_of = |name: &str, kind| Dependency {
+            kind,
+            ..dependency(name)
+        };
+        for (name, dependencies) in [
+            (
+                "app",
+                vec![
+                    dependency("lib"),
+                    kind_of("compiler", DependencyKind::Build),
+                    kind_of("testkit", DependencyKind::Dev),
+                ],
+            ),
+            ("lib", vec![dependency("base")]),
+            ("compiler", vec![dependency("base"), dependency("helper")]),
+            ("helper", vec![]),
+            ("testkit", vec![]),
+            ("base", vec![]),
+        ] {
+            repository.add(
+                package(name, Target::current(), None, dependencies),
+                archive(&dir, name),
+            );
+        }
+        let manager = manager_with(&dir, repository);
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+        let names = |packages: Vec<Package>| {
+            packages
+                .iter()
+                .map(|package| package.name().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let result = block_on(manager.install(&app)).unwrap();
+
+        let placed = result
+            .installed_files
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(placed, ["base", "lib", "app"]);
+        let build_dir = dir.join("packages/_build/app@1.0.0");
+        assert!(build_dir.join("compiler@1.0.0/instlist").exists());
+        assert!(build_dir.join("helper@1.0.0/instlist").exists());
+        assert!(!dir.join("packages/compiler@1.0.0").exists());
+        assert!(!dir.join("bin/compiler").exists());
+
+        let requirement = |name: &str| {
+            block_on(
+                manager
+                    .store
+                    .get_package(&PackageId::new(name, &Version::new(1, 0, 0))),
+            )
+            .unwrap()
+            .unwrap()
+            .build_requirement()
+            .map(|requirement| (requirement.kind.clone(), requirement.consumer.clone()))
+        };
+        let app_id = PackageId::new("app", &Version::new(1, 0, 0));
+        assert_eq!(
+            requirement("helper"),
+            Some((DependencyKind::Build, app_id.clone()))
+        );
+        assert_eq!(requirement("testkit"), Some((DependencyKind::Dev, app_id)));
+        assert_eq!(requirement("base"), None);
+
+        assert_eq!(
+            names(block_on(manager.list_installed(false)).unwrap()),
+            ["app", "base", "lib"]
+        );
+        assert_eq!(
+            names(block_on(manager.list_installed(true)).unwrap()).len(),
+            6
+        );
+
+        let removed = block_on(manager.clean_build_deps(&app)).unwrap();
+        assert_eq!(
+            removed
+                .iter()
+                .map(|package_ref| package_ref.name.as_str())
+                .collect::<Vec<_>>(),
+            ["compiler", "helper", "testkit"]
+        );
+        assert!(!build_dir.exists());
+        assert_eq!(
+            names(block_on(manager.list_installed(true)).unwrap()),
+            ["app", "base", "lib"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cancelled_install_rolls_back() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        for (name, dependencies) in [
+            ("app", vec!["lib"]),
+            ("lib", vec!["base"]),
+            ("base", vec![]),
+        ] {
+            repository.add(
+                package(
+                    name,
+                    Target::current(),
+                    None,
+                    dependencies.into_iter().map(dependency).collect(),
+                ),
+                archive(&dir, name),
+            );
+        }
+        let manager = manager_with(&dir, repository);
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+
+        // Cancel while `lib` is extracted, after `base` has been placed.
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        manager.event_publisher.on_publish(move |event| {
+            if let PackageEvent::ExtractionStarted { package_ref } = event
+                && package_ref.name == "lib"
+            {
+                canceller.cancel();
+            }
+        });
+
+        let outcome = block_on(
+            manager.install_with_options(&app, &InstallOptions::default().with_cancellation(token)),
+        );
+
+        assert!(matches!(outcome, Err(UhpmError::Cancelled)));
+        assert!(!dir.join("bin").join("base").exists());
+        assert!(!dir.join("bin").join("lib").exists());
+        assert_eq!(std::fs::read_dir(dir.join("packages")).unwrap().count(), 0);
+        assert!(
+            block_on(manager.store.list_installed_packages())
+                .unwrap()
+                .is_empty()
+        );
+        assert!(manager.event_publisher.events().iter().any(|event| matches!(
+            event,
+            PackageEvent::InstallationFailed { error, .. } if error.contains("cancelled by user")
+        )));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_database_save_rolls_back_placed_files() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("app", Target::current(), None, vec![dependency("lib")]),
+            archive(&dir, "app"),
+        );
+        repository.add(
+            package("lib", Target::current(), None, vec![]),
+            archive(&dir, "lib"),
+        );
+        let manager = manager_with(&dir, repository);
+        manager.store.fail_install_records();
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+
+        let outcome = block_on(manager.install(&app));
+
+        assert!(matches!(outcome, Err(UhpmError::DatabaseError(_))));
+        assert!(!dir.join("bin").join("app").exists());
+        assert!(!dir.join("bin").join("lib").exists());
+        assert_eq!(std::fs::read_dir(dir.join("packages")).unwrap().count(), 0);
+        assert!(
+            block_on(manager.store.list_installed_packages())
+                .unwrap()
+                .is_empty()
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_failed_database_save_keeps_the_previous_version_active() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        for version in ["1.0.0", "2.0.0"] {
+            let package = PackageFactory::create(
+                "tool".to_string(),
+                Version::parse(version).unwrap(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory/tool"),
+                },
+                Target::current(),
+                None,
+                vec![],
+            )
+            .unwrap();
+            repository.add(package, archive(&dir, "tool"));
+        }
+        let manager = manager_with(&dir, repository).with_install_mode(InstallMode::Symlink);
+        let old = PackageReference::new("tool".to_string(), Version::new(1, 0, 0));
+        let new = PackageReference::new("tool".to_string(), Version::new(2, 0, 0));
+        let old_id = PackageId::new(&old.name, &old.version);
+        let new_id = PackageId::new(&new.name, &new.version);
+
+        block_on(async {
+            manager.install(&old).await.unwrap();
+            manager.store.fail_install_records();
+
+            let outcome = manager.install(&new).await;
+
+            assert!(matches!(outcome, Err(UhpmError::DatabaseError(_))));
+            assert!(
+                manager
+                    .store
+                    .get_package(&old_id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .is_active()
+            );
+            let installation = manager
+                .store
+                .get_active_installation(&old_id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(installation.symlinks().len(), 1);
+            assert_eq!(
+                std::fs::read_link(dir.join("bin/tool")).unwrap(),
+                dir.join("packages/tool@1.0.0/bin/tool")
+            );
+            assert!(
+                !manager
+                    .store
+                    .get_package(&new_id)
+                    .await
+                    .unwrap()
+                    .is_some_and(|package| package.is_installed())
+            );
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_recover_removes_files_of_an_interrupted_install() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("lib", Target::current(), None, vec![]),
+            archive(&dir, "lib"),
+        );
+        let journal_path = dir.join("journal");
+        let manager = manager_with(&dir, repository).with_journal(journal_path.clone());
+        let reference = |name: &str| PackageReference::new(name.to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            manager.install(&reference("lib")).await.unwrap();
+            assert!(!journal_path.exists());
+
+            // `app` was placed but the process died before recording it.
+            let extracted = dir.join("packages").join("app@1.0.0");
+            std::fs::create_dir_all(extracted.join("bin")).unwrap();
+            std::fs::write(extracted.join("bin").join("app"), b"app").unwrap();
+            let placed = dir.join("bin").join("app");
+            std::fs::write(&placed, b"app").unwrap();
+            let mut app = JournalEntry::new(
+                OperationKind::Install,
+                reference("app"),
+                extracted.clone(),
+                chrono::Utc::now(),
+            );
+            app.placed.push(placed.clone());
+            let lib = JournalEntry::new(
+                OperationKind::Install,
+                reference("lib"),
+                dir.join("packages").join("lib@1.0.0"),
+                chrono::Utc::now(),
+            );
+            let journal = Journal {
+                entries: vec![lib, app],
+            };
+            std::fs::write(&journal_path, journal.to_toml().unwrap()).unwrap();
+
+            let result = manager.recover().await.unwrap();
+
+            assert_eq!(result.completed, [reference("lib")]);
+            assert_eq!(result.rolled_back, [reference("app")]);
+            assert_eq!(result.removed_files, 1);
+            assert!(!placed.exists());
+            assert!(!extracted.exists());
+            assert!(dir.join("bin").join("lib").exists());
+            assert!(!journal_path.exists());
+            assert_eq!(manager.recover().await.unwrap(), RecoveryResult::default());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_recover_after_dying_halfway_through_placing_a_package() {
+        let dir = temp_dir();
+        let journal_path = dir.join("journal");
+        let manager = manager_with(&dir, MemoryRepository::new())
+            .with_install_mode(InstallMode::Symlink)
+            .with_journal(journal_path.clone());
+        let app = package("app", Target::current(), None, vec![]);
+        let app_ref = PackageReference::from_package(&app);
+        let instlist = format!(
+            "bin/app {}\nbin/helper {}\n",
+            dir.join("bin/app").display(),
+            dir.join("bin/helper").display()
+        );
+        let data = package_archive(&[
+            ("instlist", instlist.as_bytes()),
+            ("bin/app", b"#!/bin/sh\n"),
+            ("bin/helper", b"#!/bin/sh\n"),
+        ]);
+        // A file of the user's stops placement after `bin/app` is linked.
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::write(dir.join("bin/helper"), b"mine").unwrap();
+
+        block_on(async {
+            manager.cache.put_package(&app_ref, &data).await.unwrap();
+            let mut journal = Journal {
+                entries: vec![JournalEntry::new(
+                    OperationKind::Install,
+                    app_ref.clone(),
+                    manager.extraction_dir(&app),
+                    chrono::Utc::now(),
+                )],
+            };
+            // Without the rollback of `install_all`, this is what the process
+            // leaves behind when it dies while placing.
+            let staged = manager
+                .stage_single_package(
+                    &app,
+                    true,
+                    None,
+                    Some(&mut journal),
+                    &CancellationToken::new(),
+                )
+                .await;
+            assert!(staged.is_err());
+            assert!(dir.join("bin/app").is_symlink());
+            let written =
+                Journal::from_toml(&std::fs::read_to_string(&journal_path).unwrap()).unwrap();
+            assert_eq!(written.entries[0].placed, [dir.join("bin/app")]);
+
+            // A journaled path that now links elsewhere isn't the install's.
+            let mut written = written;
+            let other = dir.join("bin/other");
+            std::os::unix::fs::symlink(dir.join("bin/helper"), &other).unwrap();
+            written.entries[0].placed.push(other.clone());
+            std::fs::write(&journal_path, written.to_toml().unwrap()).unwrap();
+
+            let result = manager.recover().await.unwrap();
+
+            assert_eq!(result.rolled_back, [app_ref]);
+            assert_eq!(result.removed_files, 1);
+            assert!(!dir.join("bin/app").is_symlink());
+            assert_eq!(std::fs::read(dir.join("bin/helper")).unwrap(), b"mine");
+            assert!(other.is_symlink());
+            assert!(!manager.extraction_dir(&app).exists());
+            assert!(!journal_path.exists());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// `lib` 1.0.0 installed, 1.5.0 available; `app` accepts either while
+    /// `new-app` needs 1.5.
+    fn manager_with_installed_lib(dir: &std::path::Path) -> TestManager {
+        let repository = MemoryRepository::new();
+        let requiring = |requirement: &str| Dependency {
+            constraint: VersionConstraint {
+                requirement: semver::VersionReq::parse(requirement).unwrap(),
+            },
+            ..dependency("lib")
+        };
+        for (name, version, dependencies) in [
+            ("lib", "1.0.0", vec![]),
+            ("lib", "1.5.0", vec![]),
+            ("app", "1.0.0", vec![requiring("^1")]),
+            ("new-app", "1.0.0", vec![requiring("^1.5")]),
+        ] {
+            let package = PackageFactory::create(
+                name.to_string(),
+                Version::parse(version).unwrap(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory").join(name),
+                },
+                Target::current(),
+                None,
+                dependencies,
+            )
+            .unwrap();
+            repository.add(package, archive(dir, name));
+        }
+        let manager = manager_with(dir, repository);
+        block_on(manager.install(&PackageReference::new(
+            "lib".to_string(),
+            Version::new(1, 0, 0),
+        )))
+        .unwrap();
+        manager
+    }
+
+    #[test]
+    fn test_resolution_error_explains_the_requirement_chain() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let requiring = |name: &str, requirement: &str| Dependency {
+            constraint: VersionConstraint {
+                requirement: semver::VersionReq::parse(requirement).unwrap(),
+            },
+            ..dependency(name)
+        };
+        for (name, version, dependencies) in [
+            ("app", "1.0.0", vec![requiring("lib", "^1.2")]),
+            ("lib", "1.3.0", vec![requiring("foo", "^2.0")]),
+            ("foo", "1.4.0", vec![]),
+            ("foo", "3.0.0", vec![]),
+        ] {
+            let package = PackageFactory::create(
+                name.to_string(),
+                Version::parse(version).unwrap(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory").join(name),
+                },
+                Target::current(),
+                None,
+                dependencies,
+            )
+            .unwrap();
+            repository.add(package, archive(&dir, name));
+        }
+        let manager = manager_with(&dir, repository);
+
+        let error = block_on(manager.install(&PackageReference::new(
+            "app".to_string(),
+            Version::new(1, 0, 0),
+        )))
+        .unwrap_err();
+
+        let UhpmError::ResolutionError(failure) = &error else {
+            panic!("unexpected error: {}", error);
+        };
+        assert_eq!(failure.package, "foo");
+        let chain = failure
+            .chain
+            .iter()
+            .map(|edge| format!("{} -> {}", edge.package, edge.dependency))
+            .collect::<Vec<_>>();
+        assert_eq!(chain, ["app@1.0.0 -> lib", "lib@1.3.0 -> foo"]);
+        let message = error.to_string();
+        assert!(
+            message.contains("lib@1.3.0 requires foo ^2.0"),
+            "{}",
+            message
+        );
+        assert!(message.contains("1.4.0: excluded by ^2.0"), "{}", message);
+        assert!(message.contains("3.0.0: excluded by ^2.0"), "{}", message);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_installed_dependency_is_reused() {
+        let dir = temp_dir();
+        let manager = manager_with_installed_lib(&dir);
+        let reference = |name: &str| PackageReference::new(name.to_string(), Version::new(1, 0, 0));
+        let extracted = |manager: &TestManager| {
+            manager
+                .event_publisher
+                .events()
+                .into_iter()
+                .filter_map(|event| match event {
+                    PackageEvent::ExtractionStarted { package_ref } => {
+                        Some(package_ref.to_string())
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        block_on(async {
+            let result = manager.install(&reference("app")).await.unwrap();
+            assert_eq!(result.installed_files, [dir.join("bin/app")]);
+            assert!(result.warnings.is_empty());
+            assert_eq!(extracted(&manager), ["lib@1.0.0", "app@1.0.0"]);
+            assert_eq!(
+                manager.get_current_version("lib").await.unwrap(),
+                Version::new(1, 0, 0)
+            );
+
+            // No installed version satisfies ^1.5, so lib is upgraded.
+            let result = manager.install(&reference("new-app")).await.unwrap();
+            assert_eq!(
+                result.warnings,
+                ["lib is upgraded to 1.5.0: no installed version satisfies its dependents"]
+            );
+            assert_eq!(
+                manager.get_current_version("lib").await.unwrap(),
+                Version::new(1, 5, 0)
+            );
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_prefer_newest_upgrades_installed_dependency() {
+        let dir = temp_dir();
+        let manager = manager_with_installed_lib(&dir);
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+
+        block_on(async {
+            let result = manager
+                .install_with_options(&app, &InstallOptions::default().prefer_newest())
+                .await
+                .unwrap();
+
+            assert_eq!(
+                result.installed_files,
+                [dir.join("bin/lib"), dir.join("bin/app")]
+            );
+            assert_eq!(result.warnings.len(), 1);
+            assert_eq!(
+                manager.get_current_version("lib").await.unwrap(),
+                Version::new(1, 5, 0)
+            );
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// `app` depends on `old` and `new`, which require `lib` with
+    /// `old_requirement` and `new_requirement`; `lib` is available in
+    /// `lib_versions`.
+    fn manager_with_shared_lib(
+        dir: &std::path::Path,
+        old_requirement: &str,
+        new_requirement: &str,
+        lib_versions: &[Version],
+    ) -> TestManager {
+        let repository = MemoryRepository::new();
+        let requiring = |requirement: &str| Dependency {
+            constraint: VersionConstraint {
+                requirement: semver::VersionReq::parse(requirement).unwrap(),
+            },
+            ..dependency("lib")
+        };
+        repository.add(
+            package(
+                "app",
+                Target::current(),
+                None,
+                vec![dependency("old"), dependency("new")],
+            ),
+            archive(dir, "app"),
+        );
+        repository.add(
+            package(
+                "old",
+                Target::current(),
+                None,
+                vec![requiring(old_requirement)],
+            ),
+            archive(dir, "old"),
+        );
+        repository.add(
+            package(
+                "new",
+                Target::current(),
+                None,
+                vec![requiring(new_requirement)],
+            ),
+            archive(dir, "new"),
+        );
+        for version in lib_versions {
+            let lib = PackageFactory::create(
+                "lib".to_string(),
+                version.clone(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory/lib"),
+                },
+                Target::current(),
+                None,
+                vec![],
+            )
+            .unwrap();
+            repository.add(lib, archive(dir, "lib"));
+        }
+        manager_with(dir, repository)
+    }
+
+    #[test]
+    fn test_shared_dependency_satisfies_every_dependent() {
+        let dir = temp_dir();
+        let lib_versions = ["1.0.0", "1.2.0", "1.4.0"].map(|v| Version::parse(v).unwrap());
+        let manager = manager_with_shared_lib(&dir, ">=1.1", "<1.3", &lib_versions);
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+
+        block_on(manager.install(&app)).unwrap();
+
+        let lib = block_on(manager.store.list_installed_packages())
+            .unwrap()
+            .into_iter()
+            .find(|package| package.name() == "lib")
+            .unwrap();
+        assert_eq!(lib.version(), &Version::new(1, 2, 0));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_diamond_resolution_fetches_each_package_once() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let requiring = |name: &str, requirement: &str| Dependency {
+            constraint: VersionConstraint {
+                requirement: semver::VersionReq::parse(requirement).unwrap(),
+            },
+            ..dependency(name)
+        };
+        let graph = [
+            ("a", vec![dependency("b"), dependency("c")]),
+            ("b", vec![requiring("d", "^1")]),
+            ("c", vec![requiring("d", ">=1.0")]),
+            ("d", vec![]),
+        ];
+        for (name, dependencies) in graph.clone() {
+            repository.add(
+                package(name, Target::current(), None, dependencies),
+                archive(&dir, name),
+            );
+        }
+        let manager = manager_with(&dir, repository);
+
+        block_on(manager.install(&PackageReference::new(
+            "a".to_string(),
+            Version::new(1, 0, 0),
+        )))
+        .unwrap();
+
+        assert_eq!(
+            block_on(manager.store.list_installed_packages())
+                .unwrap()
+                .len(),
+            4
+        );
+        for (name, _) in graph {
+            let package_ref = PackageReference::new(name.to_string(), Version::new(1, 0, 0));
+            let calls = manager.repository.get_package_calls(&package_ref);
+            assert!(calls <= 1, "{} was fetched {} times", name, calls);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_incompatible_dependencies_publish_conflicts() {
+        let dir = temp_dir();
+        let lib_versions = [Version::new(1, 4, 0), Version::new(2, 1, 0)];
+        let manager = manager_with_shared_lib(&dir, "^1", "^2", &lib_versions);
+        let app = PackageReference::new("app".to_string(), Version::new(1, 0, 0));
+
+        let err = block_on(manager.install(&app)).unwrap_err();
+        assert!(matches!(err, UhpmError::DependencyConflict(_)), "{}", err);
+
+        let conflicts =
+            manager
+                .event_publisher
+                .events()
+                .into_iter()
+                .find_map(|event| match event {
+                    PackageEvent::ResolutionFailed {
+                        package_ref,
+                        conflicts,
+                        ..
+                    } => Some((package_ref, conflicts)),
+                    _ => None,
+                });
+        let (package_ref, conflicts) = conflicts.expect("ResolutionFailed was not published");
+        assert_eq!(package_ref, app);
+        assert_eq!(
+            conflicts,
+            [DependencyConflict {
+                package: "lib".to_string(),
+                required: "^1, ^2".to_string(),
+                installed: String::new(),
+                message: "no version of lib satisfies ^1, ^2 (available: 1.4.0, 2.1.0)".to_string(),
+            }]
+        );
+        assert!(!dir.join("bin/lib").exists());
+    }
+
+    #[test]
+    fn test_download_all_respects_concurrency_limit() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let packages = (0..6)
+            .map(|i| package(&format!("pkg{}", i), Target::current(), None, vec![]))
+            .collect::<Vec<_>>();
+        for package in &packages {
+            repository.add(package.clone(), archive(&dir, package.name()));
+        }
+        let manager = manager_with(&dir, repository);
+
+        block_on(manager.download_all(&packages.iter().collect::<Vec<_>>(), 2)).unwrap();
+
+        assert_eq!(manager.repository.max_in_flight(), 2);
+        let completed = manager
+            .event_publisher
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, PackageEvent::DownloadCompleted { .. }))
+            .count();
+        assert_eq!(completed, packages.len());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_only_one_version_is_active() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        let versions = [Version::new(1, 0, 0), Version::new(2, 0, 0)];
+        for version in &versions {
+            let tool = PackageFactory::create(
+                "tool".to_string(),
+                version.clone(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory/tool"),
+                },
+                Target::current(),
+                None,
+                vec![],
+            )
+            .unwrap();
+            repository.add(tool, archive(&dir, "tool"));
+        }
+        let manager = manager_with(&dir, repository).with_install_mode(InstallMode::Symlink);
+        let refs = versions
+            .clone()
+            .map(|version| PackageReference::new("tool".to_string(), version));
+        let link = dir.join("bin/tool");
+        let is_active = async |package_ref: &PackageReference| {
+            let id = PackageId::new(&package_ref.name, &package_ref.version);
+            let package = manager.store.get_package(&id).await.unwrap();
+            package.unwrap().is_active()
+        };
+
+        block_on(async {
+            manager.install(&refs[0]).await.unwrap();
+            manager.install(&refs[1]).await.unwrap();
+            assert!(!is_active(&refs[0]).await);
+            assert!(is_active(&refs[1]).await);
+            let source = std::fs::read_link(&link).unwrap();
+            assert!(source.starts_with(dir.join("packages/tool@2.0.0")));
+
+            manager.activate(&refs[0]).await.unwrap();
+            assert!(is_active(&refs[0]).await);
+            assert!(!is_active(&refs[1]).await);
+            let source = std::fs::read_link(&link).unwrap();
+            assert!(source.starts_with(dir.join("packages/tool@1.0.0")));
+
+            manager.activate(&refs[1]).await.unwrap();
+            assert!(!is_active(&refs[0]).await);
+            assert!(is_active(&refs[1]).await);
+            let source = std::fs::read_link(&link).unwrap();
+            assert!(source.starts_with(dir.join("packages/tool@2.0.0")));
+
+            manager.deactivate(&refs[1]).await.unwrap();
+            assert!(!is_active(&refs[1]).await);
+            assert!(std::fs::symlink_metadata(&link).is_err());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_doctor_reports_and_repairs_drift() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        for name in ["tool", "other"] {
+            repository.add(
+                package(name, Target::current(), None, vec![]),
+                archive(&dir, name),
+            );
+        }
+        let manager = manager_with(&dir, repository).with_install_mode(InstallMode::Symlink);
+        let tool = tool_ref();
+        let other = PackageReference::new("other".to_string(), Version::new(1, 0, 0));
+        let ghost = PackageReference::new("ghost".to_string(), Version::new(1, 0, 0));
+        let packages = dir.join("packages");
+
+        block_on(async {
+            manager.install(&tool).await.unwrap();
+            manager.install(&other).await.unwrap();
+            let report = manager.doctor(&DoctorOptions::default()).await.unwrap();
+            assert!(report.is_healthy(), "{:?}", report);
+
+            std::fs::remove_file(dir.join("bin/tool")).unwrap();
+            std::fs::remove_dir_all(packages.join("other@1.0.0")).unwrap();
+            std::fs::create_dir_all(packages.join("ghost@1.0.0/bin")).unwrap();
+            std::os::unix::fs::symlink(
+                packages.join("ghost@1.0.0/bin/ghost"),
+                dir.join("bin/ghost"),
+            )
+            .unwrap();
+            manager.cache.put_package(&ghost, b"ghost").await.unwrap();
+
+            let report = manager.doctor(&DoctorOptions::default()).await.unwrap();
+            assert_eq!(
+                report.errors,
+                [DoctorIssue::MissingPackageDir {
+                    package_id: PackageId::new("other", &other.version),
+                    path: packages.join("other@1.0.0"),
+                }]
+            );
+            assert_eq!(report.warnings.len(), 3, "{:?}", report.warnings);
+            assert!(report.warnings.contains(&DoctorIssue::BrokenSymlink {
+                package_id: PackageId::new("tool", &tool.version),
+                target: dir.join("bin/tool"),
+                source: packages.join("tool@1.0.0/bin/tool"),
+            }));
+            assert!(report.warnings.contains(&DoctorIssue::OrphanPackageDir {
+                path: packages.join("ghost@1.0.0"),
+            }));
+            assert!(report.warnings.contains(&DoctorIssue::OrphanSymlink {
+                path: dir.join("bin/ghost"),
+                points_to: packages.join("ghost@1.0.0/bin/ghost"),
+            }));
+            assert_eq!(
+                report.info,
+                [DoctorIssue::OrphanCacheEntry {
+                    package: ghost.clone()
+                }]
+            );
+            assert!(report.repaired.is_empty());
+
+            let report = manager
+                .doctor(&DoctorOptions::default().repair())
+                .await
+                .unwrap();
+            assert_eq!(report.repaired.len(), 2, "{:?}", report.repaired);
+            assert_eq!(report.errors.len(), 1);
+            assert_eq!(report.warnings.len(), 2, "{:?}", report.warnings);
+            assert!(report.info.is_empty());
+            assert_eq!(
+                std::fs::read_link(dir.join("bin/tool")).unwrap(),
+                packages.join("tool@1.0.0/bin/tool")
+            );
+            assert!(!manager.cache.has_package(&ghost).await);
+            assert!(packages.join("ghost@1.0.0").exists());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_doctor_suggests_rebuilding_a_lost_database() {
+        let dir = temp_dir();
+
+        block_on(async {
+            manager(&dir, Target::current())
+                .install(&tool_ref())
+                .await
+                .unwrap();
+
+            // A manager over the same store whose database starts empty.
+            let fresh = manager(&dir, Target::current());
+            let report = fresh.doctor(&DoctorOptions::default()).await.unwrap();
+            assert!(
+                report
+                    .errors
+                    .contains(&DoctorIssue::UnrecordedStore { packages: 1 }),
+                "{:?}",
+                report
+            );
+            assert!(report.warnings.contains(&DoctorIssue::OrphanPackageDir {
+                path: dir.join("packages/tool@1.0.0"),
+            }));
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_direct_install_keeps_executables_executable() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = temp_dir();
+        let target = dir.join("bin/tool");
+        let instlist = format!("bin/tool {}\n", target.display());
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("tool", Target::current(), None, vec![]),
+            package_archive(&[
+                ("instlist", instlist.as_bytes()),
+                ("bin/tool", b"#!/bin/sh\n"),
+            ]),
+        );
+        let manager = manager_with(&dir, repository);
+        let mode =
+            |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        block_on(async {
+            manager.install(&tool_ref()).await.unwrap();
+            assert_eq!(mode(&target), 0o755);
+            let package_id = PackageId::new("tool", &Version::new(1, 0, 0));
+            let installation = manager
+                .store
+                .get_active_installation(&package_id)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(installation.installed_files()[&target].is_executable());
+
+            std::fs::set_permissions(&target, std::fs::Permissions::from_mode(0o644)).unwrap();
+            let report = manager.doctor(&DoctorOptions::default()).await.unwrap();
+            assert_eq!(
+                report.warnings,
+                [DoctorIssue::PermissionDrift {
+                    package_id,
+                    path: target.clone(),
+                    expected: 0o755,
+                    actual: 0o644,
+                }]
+            );
+
+            let report = manager
+                .doctor(&DoctorOptions::default().repair())
+                .await
+                .unwrap();
+            assert_eq!(report.repaired.len(), 1);
+            assert!(report.is_healthy(), "{:?}", report);
+            assert_eq!(mode(&target), 0o755);
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn manager_with_versions(dir: &std::path::Path, versions: &[&str]) -> TestManager {
+        manager_with_yanked_versions(dir, versions, &[])
+    }
+
+    fn manager_with_yanked_versions(
+        dir: &std::path::Path,
+        versions: &[&str],
+        yanked: &[&str],
+    ) -> TestManager {
+        let repository = MemoryRepository::new();
+        for version in versions {
+            let mut tool = PackageFactory::create(
+                "tool".to_string(),
+                Version::parse(version).unwrap(),
+                "tester".to_string(),
+                PackageSource::Local {
+                    path: PathBuf::from("/memory/tool"),
+                },
+                Target::current(),
+                None,
+                vec![],
+            )
+            .unwrap();
+            tool.set_yanked(yanked.contains(version));
+            repository.add(tool, archive(dir, "tool"));
+        }
+        manager_with(dir, repository)
+    }
+
+    #[test]
+    fn test_update_policies_limit_version_bumps() {
+        let versions = [
+            "1.4.2",
+            "1.4.5",
+            "1.5.0-rc.1",
+            "1.5.0",
+            "2.0.0",
+            "2.1.0-rc.1",
+        ];
+        let installed = PackageReference::new("tool".to_string(), Version::new(1, 4, 2));
+        let cases = [
+            (UpdatePolicy::Patch, "1.4.5"),
+            (UpdatePolicy::Minor, "1.5.0"),
+            (UpdatePolicy::Major, "2.0.0"),
+        ];
+
+        for (policy, expected) in cases {
+            let dir = temp_dir();
+            let manager = manager_with_versions(&dir, &versions).with_update_policy(policy);
+            let expected = Version::parse(expected).unwrap();
+
+            block_on(async {
+                manager.install(&installed).await.unwrap();
+                let updates = manager.check_updates().await.unwrap();
+                assert_eq!(
+                    updates,
+                    [PackageReference::new("tool".to_string(), expected.clone())],
+                    "{} policy",
+                    policy
+                );
+                let result = manager.update("tool").await.unwrap();
+                assert_eq!(result.to_version, expected, "{} policy", policy);
+            });
+
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_package_update_policy_overrides_and_update_to_bypasses() {
+        let dir = temp_dir();
+        let manager = manager_with_versions(&dir, &["1.4.2", "1.4.5", "1.5.0", "2.0.0"]);
+        let installed = PackageReference::new("tool".to_string(), Version::new(1, 4, 2));
+
+        block_on(async {
+            manager.install(&installed).await.unwrap();
+            manager
+                .set_update_policy("tool", Some(UpdatePolicy::Patch))
+                .await
+                .unwrap();
+
+            let result = manager.update("tool").await.unwrap();
+            assert_eq!(result.to_version, Version::new(1, 4, 5));
+            assert!(manager.check_updates().await.unwrap().is_empty());
+
+            let result = manager
+                .update_to("tool", &Version::new(2, 0, 0))
+                .await
+                .unwrap();
+            assert_eq!(result.to_version, Version::new(2, 0, 0));
+            let current = manager
+                .store
+                .get_package(&PackageId::new("tool", &Version::new(2, 0, 0)))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(current.update_policy(), Some(UpdatePolicy::Patch));
+
+            manager.set_update_policy("tool", None).await.unwrap();
+            assert!(manager.check_updates().await.unwrap().is_empty());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_yanked_versions_install_with_a_warning_and_are_updated_away_from() {
+        let dir = temp_dir();
+        let manager = manager_with_yanked_versions(
+            &dir,
+            &["1.4.2", "1.4.3", "1.5.0", "1.6.0", "1.7.0"],
+            &["1.4.2", "1.4.3", "1.7.0"],
+        )
+        .with_update_policy(UpdatePolicy::Patch);
+        let yanked = PackageReference::new("tool".to_string(), Version::new(1, 4, 2));
+
+        block_on(async {
+            let result = manager.install(&yanked).await.unwrap();
+            assert_eq!(result.warnings.len(), 1, "{:?}", result.warnings);
+            assert!(result.warnings[0].contains("yanked"));
+
+            assert_eq!(
+                manager.check_updates().await.unwrap(),
+                [PackageReference::new(
+                    "tool".to_string(),
+                    Version::new(1, 5, 0)
+                )]
+            );
+            manager.update("tool").await.unwrap();
+            assert!(manager.check_updates().await.unwrap().is_empty());
+
+            manager
+                .set_update_policy("tool", Some(UpdatePolicy::Major))
+                .await
+                .unwrap();
+            assert_eq!(
+                manager.check_updates().await.unwrap(),
+                [PackageReference::new(
+                    "tool".to_string(),
+                    Version::new(1, 6, 0)
+                )]
+            );
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Application state holding a manager without naming its ports.
+    struct AppState {
+        manager: DynPackageManager,
+    }
+
+    #[test]
+    fn test_dyn_manager_is_stored_without_generics() {
+        let dir = temp_dir();
+        let repository = MemoryRepository::new();
+        repository.add(
+            package("tool", Target::current(), None, vec![]),
+            archive(&dir, "tool"),
+        );
+        let states = [
+            AppState {
+                manager: DynPackageManager::from_ports(
+                    TokioFileSystem::new(),
+                    MockNetwork::new(),
+                    repository,
+                    MemoryCache::new(),
+                    RecordingEventPublisher::new(),
+                    InMemoryStateStore::new(),
+                    dir.join("packages"),
+                )
+                .with_install_mode(InstallMode::Direct),
+            },
+            AppState {
+                manager: DynPackageManager::from_ports(
+                    crate::test_utils::MemoryFileSystem::new(),
+                    MockNetwork::new(),
+                    MemoryRepository::new(),
+                    Arc::new(MemoryCache::new()),
+                    crate::events::InMemoryEventPublisher::new(),
+                    InMemoryStateStore::new(),
+                    PathBuf::from("/uhpm/packages"),
+                ),
+            },
+        ];
+
+        block_on(async {
+            states[0].manager.install(&tool_ref()).await.unwrap();
+
+            let installed = states[0].manager.list_installed(false).await.unwrap();
+            assert_eq!(installed.len(), 1);
+            let installed = states[1].manager.list_installed(false).await.unwrap();
+            assert!(installed.is_empty());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
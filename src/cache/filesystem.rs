@@ -0,0 +1,674 @@
+use crate::{
+    PackageReference, UhpmError,
+    clock::SystemClock,
+    compute_checksum,
+    ports::{CacheManager, Clock, FileSystemOperations},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+const STATE_FILE: &str = "cache.toml";
+const DEFAULT_INDEX_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Package and index cache stored in a directory.
+///
+/// Entry sizes and access times are tracked in a `cache.toml` sidecar, so the
+/// total size is known without walking the directory. When a maximum size is
+/// configured, least recently used entries are evicted after every write.
+/// Pinned package archives and index entries younger than the index TTL are
+/// never evicted.
+pub struct FileSystemCache<FS>
+where
+    FS: FileSystemOperations,
+{
+    file_system: FS,
+    cache_dir: PathBuf,
+    max_cache_size: Option<u64>,
+    index_ttl: Duration,
+    clock: Arc<dyn Clock>,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct CacheState {
+    #[serde(default)]
+    sequence: u64,
+    #[serde(default)]
+    entries: BTreeMap<String, CacheEntry>,
+    #[serde(skip)]
+    total_size: u64,
+    #[serde(skip)]
+    pinned: HashSet<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum EntryKind {
+    Package,
+    Index,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CacheEntry {
+    kind: EntryKind,
+    size: u64,
+    created_at: DateTime<Utc>,
+    last_access: DateTime<Utc>,
+    /// Monotonic access counter, breaks ties between equal timestamps.
+    access: u64,
+    /// SHA-256 of the entry when it was written, checked on every read.
+    /// Entries written before it was recorded aren't checked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+}
+
+impl CacheState {
+    fn touch(&mut self, key: &str, now: DateTime<Utc>) -> bool {
+        self.sequence += 1;
+        let sequence = self.sequence;
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                entry.last_access = now;
+                entry.access = sequence;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: String,
+        kind: EntryKind,
+        size: u64,
+        sha256: String,
+        now: DateTime<Utc>,
+    ) {
+        self.sequence += 1;
+        let entry = CacheEntry {
+            kind,
+            size,
+            created_at: now,
+            last_access: now,
+            access: self.sequence,
+            sha256: Some(sha256),
+        };
+
+        if let Some(previous) = self.entries.insert(key, entry) {
+            self.total_size -= previous.size;
+        }
+        self.total_size += size;
+    }
+
+    fn remove(&mut self, key: &str) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.total_size -= entry.size;
+        Some(entry)
+    }
+}
+
+impl<FS> FileSystemCache<FS>
+where
+    FS: FileSystemOperations,
+{
+    /// Opens the cache in `cache_dir`, loading the sidecar if there is one.
+    ///
+    /// `max_cache_size` is in bytes, `None` means unlimited.
+    pub async fn open<P: Into<PathBuf>>(
+        file_system: FS,
+        cache_dir: P,
+        max_cache_size: Option<u64>,
+    ) -> Result<Self, UhpmError> {
+        let cache_dir = cache_dir.into();
+        file_system.create_dir_all(&cache_dir).await?;
+
+        let state_path = cache_dir.join(STATE_FILE);
+        let mut state = if file_system.exists(&state_path).await {
+            let data = file_system.read_file(&state_path).await?;
+            let content = String::from_utf8(data)
+                .map_err(|e| UhpmError::CacheError(format!("Invalid cache state: {}", e)))?;
+            toml::from_str::<CacheState>(&content)
+                .map_err(|e| UhpmError::CacheError(format!("Invalid cache state: {}", e)))?
+        } else {
+            CacheState::default()
+        };
+        state.total_size = state.entries.values().map(|entry| entry.size).sum();
+
+        Ok(Self {
+            file_system,
+            cache_dir,
+            max_cache_size,
+            index_ttl: DEFAULT_INDEX_TTL,
+            clock: Arc::new(SystemClock),
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Sets how long index entries are protected from eviction.
+    pub fn with_index_ttl(mut self, index_ttl: Duration) -> Self {
+        self.index_ttl = index_ttl;
+        self
+    }
+
+    /// Sets the clock entry ages are measured with, the system clock by
+    /// default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn max_cache_size(&self) -> Option<u64> {
+        self.max_cache_size
+    }
+
+    /// Removes least recently used entries until the cache fits the size limit.
+    ///
+    /// Returns the number of bytes freed. Without a limit this does nothing.
+    pub async fn evict_to_fit(&self) -> Result<u64, UhpmError> {
+        let Some(max_size) = self.max_cache_size else {
+            return Ok(0);
+        };
+
+        let victims = {
+            let state = self.lock_state()?;
+            if state.total_size <= max_size {
+                return Ok(0);
+            }
+
+            let now = self.clock.now();
+            let mut candidates = state
+                .entries
+                .iter()
+                .filter(|(key, entry)| match entry.kind {
+                    EntryKind::Package => !state.pinned.contains(*key),
+                    EntryKind::Index => now
+                        .signed_duration_since(entry.created_at)
+                        .to_std()
+                        .is_ok_and(|age| age >= self.index_ttl),
+                })
+                .collect::<Vec<_>>();
+            candidates.sort_by_key(|(_, entry)| entry.access);
+
+            let mut excess = state.total_size - max_size;
+            let mut victims = Vec::new();
+            for (key, entry) in candidates {
+                if excess == 0 {
+                    break;
+                }
+                excess = excess.saturating_sub(entry.size);
+                victims.push(key.clone());
+            }
+            victims
+        };
+
+        let mut freed = 0;
+        for key in &victims {
+            self.delete_file(key).await?;
+            if let Some(entry) = self.lock_state()?.remove(key) {
+                freed += entry.size;
+            }
+        }
+
+        if !victims.is_empty() {
+            self.save_state().await?;
+        }
+        Ok(freed)
+    }
+
+    fn package_key(package_ref: &PackageReference) -> String {
+        format!(
+            "packages/{}-{}.tar.gz",
+            package_ref.name, package_ref.version
+        )
+    }
+
+    /// Reverses `package_key`. Names may contain dashes, so the version is
+    /// the first dash-separated suffix that parses.
+    fn package_from_key(key: &str) -> Option<PackageReference> {
+        let stem = key.strip_prefix("packages/")?.strip_suffix(".tar.gz")?;
+        stem.match_indices('-').find_map(|(at, _)| {
+            let version = Version::parse(&stem[at + 1..]).ok()?;
+            Some(PackageReference::new(stem[..at].to_string(), version))
+        })
+    }
+
+    fn index_key(repository_url: &str) -> Result<String, UhpmError> {
+        let hash = compute_checksum("sha256", repository_url.as_bytes())?;
+        Ok(format!("indexes/{}.toml", hash))
+    }
+
+    fn entry_path(&self, key: &str) -> PathBuf {
+        self.cache_dir.join(key)
+    }
+
+    fn lock_state(&self) -> Result<std::sync::MutexGuard<'_, CacheState>, UhpmError> {
+        self.state
+            .lock()
+            .map_err(|_| UhpmError::CacheError("Cache state lock poisoned".into()))
+    }
+
+    async fn save_state(&self) -> Result<(), UhpmError> {
+        let content = toml::to_string(&*self.lock_state()?)
+            .map_err(|e| UhpmError::SerializationError(e.to_string()))?;
+        self.file_system
+            .atomic_write(&self.cache_dir.join(STATE_FILE), content.as_bytes())
+            .await
+    }
+
+    async fn delete_file(&self, key: &str) -> Result<(), UhpmError> {
+        let path = self.entry_path(key);
+        if self.file_system.exists(&path).await {
+            self.file_system.remove(&path).await?;
+        }
+        Ok(())
+    }
+
+    /// Reads an entry, treating it as missing when the file is gone or no
+    /// longer matches the checksum recorded when it was written.
+    async fn read_entry(&self, key: &str) -> Result<Option<Vec<u8>>, UhpmError> {
+        let Some(expected) = self
+            .lock_state()?
+            .entries
+            .get(key)
+            .map(|entry| entry.sha256.clone())
+        else {
+            return Ok(None);
+        };
+
+        let path = self.entry_path(key);
+        if !self.file_system.exists(&path).await {
+            self.lock_state()?.remove(key);
+            self.save_state().await?;
+            return Ok(None);
+        }
+
+        let data = self.file_system.read_file(&path).await?;
+        if let Some(expected) = expected
+            && compute_checksum("sha256", &data)? != expected
+        {
+            warn!(entry = key, "dropping corrupted cache entry");
+            self.delete_file(key).await?;
+            self.lock_state()?.remove(key);
+            self.save_state().await?;
+            return Ok(None);
+        }
+        self.lock_state()?.touch(key, self.clock.now());
+        self.save_state().await?;
+        Ok(Some(data))
+    }
+
+    async fn write_entry(
+        &self,
+        key: String,
+        kind: EntryKind,
+        data: &[u8],
+    ) -> Result<(), UhpmError> {
+        let path = self.entry_path(&key);
+        if let Some(parent) = path.parent() {
+            self.file_system.create_dir_all(parent).await?;
+        }
+        self.file_system.atomic_write(&path, data).await?;
+
+        let sha256 = compute_checksum("sha256", data)?;
+        self.lock_state()?
+            .insert(key, kind, data.len() as u64, sha256, self.clock.now());
+        self.save_state().await?;
+        self.evict_to_fit().await?;
+        Ok(())
+    }
+
+    async fn remove_entries<F>(&self, predicate: F) -> Result<(), UhpmError>
+    where
+        F: Fn(&str, &CacheEntry, &HashSet<String>) -> bool,
+    {
+        let keys = {
+            let state = self.lock_state()?;
+            state
+                .entries
+                .iter()
+                .filter(|(key, entry)| predicate(key, entry, &state.pinned))
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>()
+        };
+
+        for key in &keys {
+            self.delete_file(key).await?;
+            self.lock_state()?.remove(key);
+        }
+
+        if !keys.is_empty() {
+            self.save_state().await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<FS> CacheManager for FileSystemCache<FS>
+where
+    FS: FileSystemOperations,
+{
+    async fn get_package(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<Vec<u8>>, UhpmError> {
+        self.read_entry(&Self::package_key(package_ref)).await
+    }
+
+    async fn put_package(
+        &self,
+        package_ref: &PackageReference,
+        data: &[u8],
+    ) -> Result<(), UhpmError> {
+        self.write_entry(Self::package_key(package_ref), EntryKind::Package, data)
+            .await
+    }
+
+    async fn remove_package(&self, package_ref: &PackageReference) -> Result<(), UhpmError> {
+        let key = Self::package_key(package_ref);
+        self.delete_file(&key).await?;
+        if self.lock_state()?.remove(&key).is_some() {
+            self.save_state().await?;
+        }
+        Ok(())
+    }
+
+    async fn clear_packages(&self) -> Result<(), UhpmError> {
+        self.remove_entries(|key, entry, pinned| {
+            entry.kind == EntryKind::Package && !pinned.contains(key)
+        })
+        .await
+    }
+
+    async fn list_packages(&self) -> Result<Vec<PackageReference>, UhpmError> {
+        Ok(self
+            .lock_state()?
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.kind == EntryKind::Package)
+            .filter_map(|(key, _)| Self::package_from_key(key))
+            .collect())
+    }
+
+    async fn get_index(&self, repository_url: &str) -> Result<Option<Vec<u8>>, UhpmError> {
+        self.read_entry(&Self::index_key(repository_url)?).await
+    }
+
+    async fn put_index(&self, repository_url: &str, data: &[u8]) -> Result<(), UhpmError> {
+        self.write_entry(Self::index_key(repository_url)?, EntryKind::Index, data)
+            .await
+    }
+
+    async fn invalidate_index(&self, repository_url: &str) -> Result<(), UhpmError> {
+        let key = Self::index_key(repository_url)?;
+        self.delete_file(&key).await?;
+        if self.lock_state()?.remove(&key).is_some() {
+            self.save_state().await?;
+        }
+        Ok(())
+    }
+
+    async fn get_cache_size(&self) -> Result<u64, UhpmError> {
+        Ok(self.lock_state()?.total_size)
+    }
+
+    async fn cleanup_old_entries(&self, max_age: Duration) -> Result<(), UhpmError> {
+        let now = self.clock.now();
+        self.remove_entries(|key, entry, pinned| {
+            !pinned.contains(key)
+                && now
+                    .signed_duration_since(entry.last_access)
+                    .to_std()
+                    .is_ok_and(|age| age > max_age)
+        })
+        .await
+    }
+
+    fn get_cache_path(&self) -> &PathBuf {
+        &self.cache_dir
+    }
+
+    async fn has_package(&self, package_ref: &PackageReference) -> bool {
+        let key = Self::package_key(package_ref);
+        let known = self
+            .lock_state()
+            .map(|state| state.entries.contains_key(&key))
+            .unwrap_or(false);
+        known && self.file_system.exists(&self.entry_path(&key)).await
+    }
+
+    async fn get_package_size(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<u64>, UhpmError> {
+        Ok(self
+            .lock_state()?
+            .entries
+            .get(&Self::package_key(package_ref))
+            .map(|entry| entry.size))
+    }
+
+    fn pin_package(&self, package_ref: &PackageReference) {
+        if let Ok(mut state) = self.lock_state() {
+            state.pinned.insert(Self::package_key(package_ref));
+        }
+    }
+
+    fn unpin_package(&self, package_ref: &PackageReference) {
+        if let Ok(mut state) = self.lock_state() {
+            state.pinned.remove(&Self::package_key(package_ref));
+        }
+    }
+
+    async fn evict_to_fit(&self) -> Result<u64, UhpmError> {
+        FileSystemCache::evict_to_fit(self).await
+    }
+}
+
+impl<FS> std::fmt::Debug for FileSystemCache<FS>
+where
+    FS: FileSystemOperations,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FileSystemCache")
+            .field("cache_dir", &self.cache_dir)
+            .field("max_cache_size", &self.max_cache_size)
+            .field("index_ttl", &self.index_ttl)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::test_utils::{MemoryFileSystem, block_on};
+    use std::path::Path;
+
+    fn package(name: &str) -> PackageReference {
+        PackageReference::new(name.to_string(), Version::new(1, 0, 0))
+    }
+
+    async fn cache(
+        fs: &MemoryFileSystem,
+        max_size: Option<u64>,
+    ) -> FileSystemCache<MemoryFileSystem> {
+        FileSystemCache::open(fs.clone(), "/cache", max_size)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_lists_cached_packages() {
+        block_on(async {
+            let cache = cache(&MemoryFileSystem::new(), None).await;
+            let prerelease = PackageReference::new(
+                "dash-name".to_string(),
+                Version::parse("1.0.0-rc.1").unwrap(),
+            );
+            cache.put_package(&package("tool"), b"tool").await.unwrap();
+            cache.put_package(&prerelease, b"pre").await.unwrap();
+            cache.put_index("https://repo", b"index").await.unwrap();
+
+            let mut listed = cache.list_packages().await.unwrap();
+            listed.sort();
+            assert_eq!(listed, [prerelease, package("tool")]);
+        });
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used_package_first() {
+        block_on(async {
+            let fs = MemoryFileSystem::new();
+            let cache = cache(&fs, Some(300)).await;
+
+            cache.put_package(&package("a"), &[0; 100]).await.unwrap();
+            cache.put_package(&package("b"), &[0; 100]).await.unwrap();
+            cache.put_package(&package("c"), &[0; 100]).await.unwrap();
+
+            // Reading `a` makes `b` the least recently used entry.
+            cache.get_package(&package("a")).await.unwrap();
+            cache.put_package(&package("d"), &[0; 100]).await.unwrap();
+
+            assert!(!cache.has_package(&package("b")).await);
+            assert!(cache.has_package(&package("a")).await);
+            assert!(cache.has_package(&package("c")).await);
+            assert!(cache.has_package(&package("d")).await);
+            assert_eq!(cache.get_cache_size().await.unwrap(), 300);
+        });
+    }
+
+    #[test]
+    fn test_pinned_packages_and_fresh_indexes_are_kept() {
+        block_on(async {
+            let fs = MemoryFileSystem::new();
+            let cache = cache(&fs, Some(250)).await;
+
+            cache.put_index("https://repo", &[0; 50]).await.unwrap();
+            cache.put_package(&package("a"), &[0; 100]).await.unwrap();
+            cache.pin_package(&package("a"));
+            cache.put_package(&package("b"), &[0; 100]).await.unwrap();
+            cache.put_package(&package("c"), &[0; 100]).await.unwrap();
+
+            assert!(cache.has_package(&package("a")).await);
+            assert!(!cache.has_package(&package("b")).await);
+            assert!(cache.get_index("https://repo").await.unwrap().is_some());
+
+            cache.unpin_package(&package("a"));
+            cache.put_package(&package("d"), &[0; 100]).await.unwrap();
+            assert!(!cache.has_package(&package("a")).await);
+        });
+    }
+
+    #[test]
+    fn test_invalidate_index_drops_the_entry() {
+        block_on(async {
+            let fs = MemoryFileSystem::new();
+            let cache = cache(&fs, None).await;
+
+            cache.put_index("https://repo", &[0; 50]).await.unwrap();
+            cache.invalidate_index("https://repo").await.unwrap();
+            cache.invalidate_index("https://other").await.unwrap();
+
+            assert!(cache.get_index("https://repo").await.unwrap().is_none());
+            assert_eq!(cache.get_cache_size().await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_indexes_become_evictable_once_the_ttl_expires() {
+        block_on(async {
+            let fs = MemoryFileSystem::new();
+            let clock = MockClock::default();
+            let cache = cache(&fs, Some(150))
+                .await
+                .with_index_ttl(Duration::from_secs(60))
+                .with_clock(clock.clone());
+
+            cache.put_index("https://repo", &[0; 100]).await.unwrap();
+            cache.put_package(&package("a"), &[0; 100]).await.unwrap();
+            assert!(cache.get_index("https://repo").await.unwrap().is_some());
+            assert!(!cache.has_package(&package("a")).await);
+
+            clock.advance(Duration::from_secs(60));
+            cache.put_package(&package("b"), &[0; 100]).await.unwrap();
+            assert!(cache.get_index("https://repo").await.unwrap().is_none());
+            assert!(cache.has_package(&package("b")).await);
+        });
+    }
+
+    #[test]
+    fn test_cleanup_removes_entries_not_accessed_recently() {
+        block_on(async {
+            let fs = MemoryFileSystem::new();
+            let clock = MockClock::default();
+            let cache = cache(&fs, None).await.with_clock(clock.clone());
+
+            cache.put_package(&package("a"), &[0; 10]).await.unwrap();
+            cache.put_package(&package("b"), &[0; 10]).await.unwrap();
+            cache.put_package(&package("c"), &[0; 10]).await.unwrap();
+            cache.pin_package(&package("c"));
+
+            clock.advance(Duration::from_secs(30));
+            cache.get_package(&package("b")).await.unwrap();
+            clock.advance(Duration::from_secs(31));
+
+            cache
+                .cleanup_old_entries(Duration::from_secs(60))
+                .await
+                .unwrap();
+            assert!(!cache.has_package(&package("a")).await);
+            assert!(cache.has_package(&package("b")).await);
+            assert!(cache.has_package(&package("c")).await);
+            assert_eq!(cache.get_cache_size().await.unwrap(), 20);
+        });
+    }
+
+    #[test]
+    fn test_torn_write_is_a_cache_miss() {
+        block_on(async {
+            let fs = MemoryFileSystem::new();
+            let cache = cache(&fs, None).await;
+            cache
+                .put_package(&package("tool"), b"complete archive")
+                .await
+                .unwrap();
+            let path = Path::new("/cache/packages/tool-1.0.0.tar.gz");
+            assert_eq!(
+                fs.read_dir(Path::new("/cache/packages")).await.unwrap(),
+                [path]
+            );
+
+            fs.add_file(path, b"complete");
+
+            assert!(cache.get_package(&package("tool")).await.unwrap().is_none());
+            assert!(!fs.exists(path).await);
+            assert!(!cache.has_package(&package("tool")).await);
+            assert_eq!(cache.get_cache_size().await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_size_is_restored_from_sidecar() {
+        block_on(async {
+            let fs = MemoryFileSystem::new();
+            {
+                let cache = cache(&fs, None).await;
+                cache.put_package(&package("a"), &[0; 10]).await.unwrap();
+                cache.put_package(&package("b"), &[0; 20]).await.unwrap();
+                cache.remove_package(&package("a")).await.unwrap();
+            }
+
+            let reopened = cache(&fs, None).await;
+            assert_eq!(reopened.get_cache_size().await.unwrap(), 20);
+            assert!(reopened.has_package(&package("b")).await);
+        });
+    }
+}
@@ -0,0 +1,73 @@
+use crate::ports::Clock;
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// [`Clock`] reading the system time.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// [`Clock`] standing still until it is advanced by hand.
+///
+/// Clones share the same time, so a test can keep one and advance the
+/// clock it handed to the code under test.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<DateTime<Utc>>>,
+}
+
+impl MockClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::MAX);
+    }
+}
+
+impl Default for MockClock {
+    /// Starts at the Unix epoch.
+    fn default() -> Self {
+        Self::new(DateTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock_is_shared_between_clones() {
+        let clock = MockClock::default();
+        let handed_out = clock.clone();
+
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(
+            handed_out.now(),
+            DateTime::UNIX_EPOCH + chrono::Duration::seconds(90)
+        );
+
+        handed_out.set(DateTime::UNIX_EPOCH);
+        assert_eq!(clock.now(), DateTime::UNIX_EPOCH);
+    }
+}
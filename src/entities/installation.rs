@@ -1,10 +1,12 @@
-use crate::{FileMetadata, PackageId, Symlink, UhpmError};
-use std::collections::HashMap;
+use crate::{FileMetadata, InstallMode, PackageId, Symlink, UhpmError};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
 pub struct InstallationId(Uuid);
 
 impl InstallationId {
@@ -53,13 +55,21 @@ impl TryFrom<&str> for InstallationId {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Installation {
     id: InstallationId,
     package_id: PackageId,
+    /// Keyed by path; serialized in path order so the output is stable.
+    #[serde(serialize_with = "serialize_sorted")]
     installed_files: HashMap<PathBuf, FileMetadata>,
     symlinks: Vec<Symlink>,
     installed_at: chrono::DateTime<chrono::Utc>,
     active: bool,
+    install_mode: InstallMode,
+    size: u64,
+    /// Directory `${PREFIX}` instlist targets were placed under.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    prefix: Option<PathBuf>,
 }
 
 impl Installation {
@@ -78,6 +88,9 @@ impl Installation {
             symlinks: symlinks,
             installed_at: installed_at,
             active: active,
+            install_mode: InstallMode::Symlink,
+            size: 0,
+            prefix: None,
         }
     }
 
@@ -89,6 +102,12 @@ impl Installation {
         self.symlinks.push(symlink);
     }
 
+    /// Forgets the recorded files and symlinks, e.g. after they were removed.
+    pub fn clear_files(&mut self) {
+        self.installed_files.clear();
+        self.symlinks.clear();
+    }
+
     pub fn activate(&mut self) {
         self.active = true;
     }
@@ -133,6 +152,11 @@ impl Installation {
         &self.symlinks
     }
 
+    /// How the package files were placed at their targets.
+    pub fn install_mode(&self) -> InstallMode {
+        self.install_mode
+    }
+
     pub fn set_id(&mut self, id: InstallationId) {
         self.id = id;
     }
@@ -140,4 +164,110 @@ impl Installation {
     pub fn set_installed_at(&mut self, installed_at: chrono::DateTime<chrono::Utc>) {
         self.installed_at = installed_at;
     }
+
+    pub fn set_install_mode(&mut self, install_mode: InstallMode) {
+        self.install_mode = install_mode;
+    }
+
+    /// Unpacked size of the package in the package store, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        self.size = size;
+    }
+
+    /// Directory `${PREFIX}` instlist targets were placed under, if any.
+    pub fn prefix(&self) -> Option<&Path> {
+        self.prefix.as_deref()
+    }
+
+    pub fn set_prefix(&mut self, prefix: Option<PathBuf>) {
+        self.prefix = prefix;
+    }
+}
+
+fn serialize_sorted<S>(
+    files: &HashMap<PathBuf, FileMetadata>,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    files
+        .iter()
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    #[test]
+    fn test_json_shape() {
+        let epoch = DateTime::from_timestamp(0, 0).unwrap();
+        let path = PathBuf::from("/home/user/.local/bin/tool");
+        let mut metadata = FileMetadata::new(path.clone(), 4);
+        metadata.created_at = epoch;
+        metadata.modified_at = epoch;
+        let mut symlink = Symlink::file("/uhpm/packages/tool@1.0.0/bin/tool", path.clone());
+        symlink.metadata.created_at = epoch;
+        let mut installation = Installation::new(
+            InstallationId::try_from("6f1c1b9e-3f5d-4c1e-9a55-0d2b8d7c4e21").unwrap(),
+            PackageId::new("tool", &semver::Version::new(1, 0, 0)),
+            HashMap::from([(path.clone(), metadata)]),
+            vec![symlink],
+            epoch,
+            true,
+        );
+        installation.set_size(4);
+
+        let json = serde_json::to_string_pretty(&installation).unwrap();
+        assert_eq!(json, SNAPSHOT);
+
+        let parsed: Installation = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string_pretty(&parsed).unwrap(), SNAPSHOT);
+    }
+
+    const SNAPSHOT: &str = r#"{
+  "id": "6f1c1b9e-3f5d-4c1e-9a55-0d2b8d7c4e21",
+  "package_id": "tool@1.0.0",
+  "installed_files": {
+    "/home/user/.local/bin/tool": {
+      "path": "/home/user/.local/bin/tool",
+      "size": 4,
+      "checksum": null,
+      "permissions": {
+        "read": true,
+        "write": false,
+        "execute": false
+      },
+      "created_at": "1970-01-01T00:00:00Z",
+      "modified_at": "1970-01-01T00:00:00Z",
+      "file_type": "regular",
+      "hard_links": 1,
+      "mode": null
+    }
+  },
+  "symlinks": [
+    {
+      "source": "/uhpm/packages/tool@1.0.0/bin/tool",
+      "target": "/home/user/.local/bin/tool",
+      "link_type": "file",
+      "metadata": {
+        "created_at": "1970-01-01T00:00:00Z",
+        "owner": null,
+        "group": null,
+        "description": null
+      }
+    }
+  ],
+  "installed_at": "1970-01-01T00:00:00Z",
+  "active": true,
+  "install_mode": "symlink",
+  "size": 4
+}"#;
 }
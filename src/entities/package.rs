@@ -1,6 +1,7 @@
-use crate::{Dependency, Target};
+use crate::{Dependency, DependencyKind, Target, UpdatePolicy};
 use semver::Version;
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::collections::HashSet;
 use std::fmt;
 use std::path::PathBuf;
@@ -9,7 +10,7 @@ use std::path::PathBuf;
 ///
 /// This is a pure data structure with no business logic.
 /// All validation and business rules are handled by factories and services.
-#[derive(Debug, Clone, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, Eq)]
 pub struct Package {
     id: PackageId,
     name: String,
@@ -21,6 +22,26 @@ pub struct Package {
     dependencies: HashSet<Dependency>,
     installed: bool,
     active: bool,
+    #[serde(default)]
+    explicit: bool,
+    #[serde(default)]
+    build_requirement: Option<BuildRequirement>,
+    #[serde(default)]
+    pinned: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    source_repository: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    update_policy: Option<UpdatePolicy>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    download_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    installed_size: Option<u64>,
+    #[serde(default)]
+    yanked: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    deprecation: Option<String>,
 }
 
 impl Package {
@@ -47,6 +68,16 @@ impl Package {
             dependencies: dependencies,
             installed: installed,
             active: active,
+            explicit: false,
+            build_requirement: None,
+            pinned: false,
+            source_repository: None,
+            update_policy: None,
+            description: None,
+            download_size: None,
+            installed_size: None,
+            yanked: false,
+            deprecation: None,
         }
     }
 
@@ -109,6 +140,142 @@ impl Package {
     pub fn set_active(&mut self, active: bool) {
         self.active = active;
     }
+
+    /// Checks if the package was requested by the user rather than pulled
+    /// in as a dependency.
+    pub fn is_explicit(&self) -> bool {
+        self.explicit
+    }
+
+    /// Sets whether the package was requested by the user.
+    pub fn set_explicit(&mut self, explicit: bool) {
+        self.explicit = explicit;
+    }
+
+    /// Returns why the package is installed when it is only needed to
+    /// build another package.
+    pub fn build_requirement(&self) -> Option<&BuildRequirement> {
+        self.build_requirement.as_ref()
+    }
+
+    /// Checks if the package is only installed to build another package.
+    pub fn is_build_only(&self) -> bool {
+        self.build_requirement.is_some()
+    }
+
+    /// Sets whether the package is only needed to build another package.
+    pub fn set_build_requirement(&mut self, build_requirement: Option<BuildRequirement>) {
+        self.build_requirement = build_requirement;
+    }
+
+    /// Checks if the package is held at its version, excluded from updates
+    /// and switches.
+    pub fn is_pinned(&self) -> bool {
+        self.pinned
+    }
+
+    /// Sets whether the package is held at its version.
+    pub fn set_pinned(&mut self, pinned: bool) {
+        self.pinned = pinned;
+    }
+
+    /// Update policy set for this package, overriding the global one.
+    pub fn update_policy(&self) -> Option<UpdatePolicy> {
+        self.update_policy
+    }
+
+    /// Sets the update policy of the package, `None` to follow the global one.
+    pub fn set_update_policy(&mut self, update_policy: Option<UpdatePolicy>) {
+        self.update_policy = update_policy;
+    }
+
+    /// Name of the configured repository the package was resolved from,
+    /// when known.
+    pub fn source_repository(&self) -> Option<&str> {
+        self.source_repository.as_deref()
+    }
+
+    /// Sets the repository the package was resolved from.
+    pub fn set_source_repository(&mut self, repository: Option<String>) {
+        self.source_repository = repository;
+    }
+
+    /// Short description from the package meta, when it has one.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn set_description(&mut self, description: Option<String>) {
+        self.description = description;
+    }
+
+    /// Size of the package archive in bytes, when the repository publishes it.
+    pub fn download_size(&self) -> Option<u64> {
+        self.download_size
+    }
+
+    pub fn set_download_size(&mut self, size: Option<u64>) {
+        self.download_size = size;
+    }
+
+    /// Size of the unpacked package in bytes, when the repository publishes it.
+    pub fn installed_size(&self) -> Option<u64> {
+        self.installed_size
+    }
+
+    pub fn set_installed_size(&mut self, size: Option<u64>) {
+        self.installed_size = size;
+    }
+
+    /// Whether the publisher withdrew this version.
+    pub fn is_yanked(&self) -> bool {
+        self.yanked
+    }
+
+    pub fn set_yanked(&mut self, yanked: bool) {
+        self.yanked = yanked;
+    }
+
+    /// Deprecation message the publisher attached to this version.
+    pub fn deprecation(&self) -> Option<&str> {
+        self.deprecation.as_deref()
+    }
+
+    pub fn set_deprecation(&mut self, message: Option<String>) {
+        self.deprecation = message;
+    }
+
+    /// One-line listing with aligned name, version and status columns.
+    pub fn summary(&self) -> String {
+        format!(
+            "{:<24} {:<12} {}",
+            self.name,
+            self.version.to_string(),
+            self.status().unwrap_or("available")
+        )
+    }
+
+    fn status(&self) -> Option<&'static str> {
+        if self.active {
+            Some("active")
+        } else if self.installed {
+            Some("installed")
+        } else {
+            None
+        }
+    }
+}
+
+/// Renders `name@version (author)`, followed by `(active)` or `(installed)`
+/// when the package is.
+impl fmt::Display for Package {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{} ({})", self.name, self.version, self.author)?;
+        if let Some(status) = self.status() {
+            write!(f, " ({})", status)?;
+        }
+        Ok(())
+    }
 }
 
 impl PartialEq for Package {
@@ -117,6 +284,17 @@ impl PartialEq for Package {
     }
 }
 
+/// Build-time-only installation of a package: the `Build` or `Dev`
+/// dependency edge that pulled it in and the package it is needed to build.
+///
+/// Runtime dependencies of a build-time-only package are build-time-only
+/// themselves and share its requirement.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct BuildRequirement {
+    pub kind: DependencyKind,
+    pub consumer: PackageId,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
 pub enum PackageSource {
     Git {
@@ -131,7 +309,8 @@ pub enum PackageSource {
     },
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(transparent)]
 pub struct PackageId(String);
 
 impl PackageId {
@@ -142,6 +321,19 @@ impl PackageId {
     pub fn as_str(&self) -> &str {
         &self.0
     }
+
+    /// The reference the id was made from, `None` if the version part
+    /// doesn't parse.
+    pub fn reference(&self) -> Option<PackageReference> {
+        let (name, version) = self.0.rsplit_once('@')?;
+        let version = Version::parse(version).ok()?;
+        Some(PackageReference::new(name.to_string(), version))
+    }
+
+    /// Name part of the id, without the version.
+    pub fn name(&self) -> &str {
+        self.0.split_once('@').map_or(&self.0, |(name, _)| name)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -177,6 +369,21 @@ impl PackageReference {
     }
 }
 
+/// Orders references by name, then by version.
+impl Ord for PackageReference {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.name
+            .cmp(&other.name)
+            .then_with(|| self.version.cmp(&other.version))
+    }
+}
+
+impl PartialOrd for PackageReference {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl fmt::Display for PackageReference {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}@{}", self.name, self.version)
@@ -205,3 +412,81 @@ impl TryFrom<&str> for PackageReference {
         Ok(PackageReference::new(name, version))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::factories::PackageFactory;
+
+    fn package(installed: bool, active: bool) -> Package {
+        let mut package = PackageFactory::create(
+            "tool".to_string(),
+            Version::new(1, 2, 0),
+            "John Doe".to_string(),
+            PackageSource::Local {
+                path: "/tmp".into(),
+            },
+            Target::current(),
+            None,
+            vec![],
+        )
+        .unwrap();
+        package.set_installed(installed);
+        package.set_active(active);
+        package
+    }
+
+    #[test]
+    fn test_display_reflects_flags() {
+        assert_eq!(package(false, false).to_string(), "tool@1.2.0 (John Doe)");
+        assert_eq!(
+            package(true, false).to_string(),
+            "tool@1.2.0 (John Doe) (installed)"
+        );
+        assert_eq!(
+            package(true, true).to_string(),
+            "tool@1.2.0 (John Doe) (active)"
+        );
+    }
+
+    #[test]
+    fn test_summary() {
+        assert_eq!(
+            package(false, false).summary(),
+            "tool                     1.2.0        available"
+        );
+        assert_eq!(
+            package(true, true).summary(),
+            "tool                     1.2.0        active"
+        );
+    }
+
+    #[test]
+    fn test_references_sort_by_name_then_version() {
+        let reference = |name: &str, major, minor| {
+            PackageReference::new(name.to_string(), Version::new(major, minor, 0))
+        };
+        let mut references = vec![
+            reference("tool", 1, 10),
+            reference("lib", 2, 0),
+            reference("tool", 1, 2),
+            reference("app", 3, 0),
+            reference("lib", 0, 9),
+            reference("tool", 0, 1),
+        ];
+
+        references.sort();
+
+        assert_eq!(
+            references,
+            [
+                reference("app", 3, 0),
+                reference("lib", 0, 9),
+                reference("lib", 2, 0),
+                reference("tool", 0, 1),
+                reference("tool", 1, 2),
+                reference("tool", 1, 10),
+            ]
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use crate::PackageReference;
 use std::path::PathBuf;
 use thiserror::Error;
 
@@ -16,7 +17,7 @@ pub enum UhpmError {
     },
 
     #[error("Dependency resolution failed: {0}")]
-    ResolutionError(String),
+    ResolutionError(Box<crate::ResolutionFailure>),
 
     #[error("Dependency conflict: {0}")]
     DependencyConflict(String),
@@ -33,11 +34,14 @@ pub enum UhpmError {
     #[error("Package is currently active and cannot be removed")]
     PackageIsActive,
 
+    #[error("Package `{0}` is pinned")]
+    PackagePinned(String),
+
     #[error("Validation error: {0}")]
     ValidationError(String),
 
-    #[error("Invalid package format in `{0}`")]
-    InvalidPackage(PathBuf),
+    #[error("Invalid package in `{}`: {reason}", path.display())]
+    InvalidPackage { path: PathBuf, reason: String },
 
     #[error("Checksum verification failed for package: {0}")]
     ChecksumMismatch(String),
@@ -57,6 +61,24 @@ pub enum UhpmError {
     #[error("Version switch failed: {0}")]
     SwitchError(String),
 
+    #[error("Cannot undo operation: {0}")]
+    UndoError(String),
+
+    #[error("Install plan is out of date: {0}")]
+    PlanOutdated(String),
+
+    #[error("Timed out waiting for lock held by {0}")]
+    LockTimeout(String),
+
+    #[error("Operation cancelled by user")]
+    Cancelled,
+
+    #[error("File conflict at {}: {reason}", path.display())]
+    FileConflict {
+        path: std::path::PathBuf,
+        reason: String,
+    },
+
     #[error("Network error: {0}")]
     NetworkError(String),
 
@@ -69,6 +91,9 @@ pub enum UhpmError {
     #[error("Database error: {0}")]
     DatabaseError(String),
 
+    #[error("Database corrupted: {0}")]
+    DatabaseCorrupted(String),
+
     #[error("Storage error: {0}")]
     StorageError(String),
 
@@ -101,6 +126,84 @@ pub enum UhpmError {
 
     #[error("Database operation failed: {0}")]
     RusqliteError(#[from] rusqlite::Error),
+
+    #[error("{}", context.describe(source))]
+    WithContext {
+        context: ErrorContext,
+        #[source]
+        source: Box<UhpmError>,
+    },
+}
+
+/// What was being done when an error happened: the operation, and the
+/// package and path involved when known.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub operation: &'static str,
+    pub package: Option<PackageReference>,
+    pub path: Option<PathBuf>,
+}
+
+impl ErrorContext {
+    /// Context for `operation`, a verb such as `"extract"`.
+    pub fn new(operation: &'static str) -> Self {
+        Self {
+            operation,
+            package: None,
+            path: None,
+        }
+    }
+
+    pub fn with_package(mut self, package: PackageReference) -> Self {
+        self.package = Some(package);
+        self
+    }
+
+    pub fn with_path<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Renders `failed to <operation> <package>: <source>`.
+    ///
+    /// File system errors are shown without their category prefix, and the
+    /// path is only added when the source doesn't mention it already.
+    fn describe(&self, source: &UhpmError) -> String {
+        let message = match source {
+            UhpmError::FileSystemError(error) => error.to_string(),
+            other => other.to_string(),
+        };
+        let path = self
+            .path
+            .as_ref()
+            .map(|path| path.display().to_string())
+            .filter(|path| !message.contains(path.as_str()));
+
+        let mut described = format!("failed to {}", self.operation);
+        if let Some(package) = &self.package {
+            described.push_str(&format!(" {}", package));
+        }
+        if let Some(path) = path {
+            described.push_str(&format!(" at {}", path));
+        }
+        format!("{}: {}", described, message)
+    }
+}
+
+/// Attaches an [`ErrorContext`] to the error of a result.
+pub trait ResultExt<T> {
+    fn with_context<F>(self, context: F) -> Result<T, UhpmError>
+    where
+        F: FnOnce() -> ErrorContext;
+}
+
+impl<T, E: Into<UhpmError>> ResultExt<T> for Result<T, E> {
+    fn with_context<F>(self, context: F) -> Result<T, UhpmError>
+    where
+        F: FnOnce() -> ErrorContext,
+    {
+        self.map_err(|error| error.into().context(context()))
+    }
 }
 
 impl UhpmError {
@@ -115,4 +218,28 @@ impl UhpmError {
     pub fn network<S: Into<String>>(msg: S) -> Self {
         Self::NetworkError(msg.into())
     }
+
+    /// Wraps the error with what was being done when it happened.
+    pub fn context(self, context: ErrorContext) -> Self {
+        Self::WithContext {
+            context,
+            source: Box::new(self),
+        }
+    }
+
+    /// The error with any context wrapped around it removed.
+    pub fn root(&self) -> &UhpmError {
+        match self {
+            Self::WithContext { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// The outermost context attached to the error, if any.
+    pub fn error_context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::WithContext { context, .. } => Some(context),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,267 @@
+use super::subscribers::Subscribers;
+use crate::{
+    EventEnvelope, EventReceiver, OverflowPolicy, PackageEvent, UhpmError,
+    clock::SystemClock,
+    paths::UhpmPaths,
+    ports::{Clock, EventCallback, EventFilter, EventPublisher, FileSystemOperations},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const LOG_FILE_NAME: &str = "events.jsonl";
+
+/// Event publisher that keeps an audit log of every event.
+///
+/// Each event envelope is appended to `events.jsonl` in the log directory as
+/// one JSON object per line, so the history survives restarts and can be
+/// inspected with ordinary line-based tools. Sequence numbers continue from
+/// the last one in the log. Subscribers are notified after the line has been
+/// written.
+pub struct FileEventPublisher<FS: FileSystemOperations> {
+    file_system: FS,
+    log_path: PathBuf,
+    subscribers: Subscribers,
+    /// Last sequence number written, read from the log on first publish.
+    last_sequence: Mutex<Option<u64>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<FS: FileSystemOperations> FileEventPublisher<FS> {
+    pub fn new(file_system: FS, paths: &impl UhpmPaths) -> Self {
+        Self::with_log_path(file_system, paths.log_dir().join(LOG_FILE_NAME))
+    }
+
+    pub fn with_log_path(file_system: FS, log_path: impl Into<PathBuf>) -> Self {
+        Self {
+            file_system,
+            log_path: log_path.into(),
+            subscribers: Subscribers::default(),
+            last_sequence: Mutex::new(None),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the clock events are stamped with, the system clock by default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    pub fn log_path(&self) -> &Path {
+        &self.log_path
+    }
+
+    async fn read_log(&self) -> Result<Vec<EventEnvelope>, UhpmError> {
+        if !self.file_system.exists(&self.log_path).await {
+            return Ok(Vec::new());
+        }
+
+        let data = self.file_system.read_file(&self.log_path).await?;
+        let contents = String::from_utf8(data).map_err(|e| {
+            UhpmError::SerializationError(format!(
+                "Event log {} is not valid UTF-8: {}",
+                self.log_path.display(),
+                e
+            ))
+        })?;
+
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str::<EventEnvelope>(line).map_err(|e| {
+                    UhpmError::SerializationError(format!(
+                        "Invalid event on line {} of {}: {}",
+                        index + 1,
+                        self.log_path.display(),
+                        e
+                    ))
+                })
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl<FS: FileSystemOperations> EventPublisher for FileEventPublisher<FS> {
+    async fn publish(&self, event: PackageEvent) -> Result<(), UhpmError> {
+        // Held until the line is written so the log stays in sequence order.
+        let mut last_sequence = self.last_sequence.lock().await;
+        let sequence = match *last_sequence {
+            Some(sequence) => sequence,
+            None => self
+                .read_log()
+                .await?
+                .last()
+                .map_or(0, |envelope| envelope.sequence),
+        } + 1;
+
+        let envelope = EventEnvelope::new(sequence, self.clock.now(), event);
+        let mut line = envelope.to_json()?;
+        line.push('\n');
+
+        if let Some(parent) = self.log_path.parent() {
+            self.file_system.create_dir_all(parent).await?;
+        }
+        self.file_system
+            .append_file(&self.log_path, line.as_bytes())
+            .await?;
+        *last_sequence = Some(sequence);
+        drop(last_sequence);
+
+        self.subscribers.notify(&envelope).await;
+        Ok(())
+    }
+
+    async fn subscribe(&self, callback: EventCallback) -> Result<String, UhpmError> {
+        Ok(self.subscribers.add(None, callback))
+    }
+
+    async fn subscribe_filtered(
+        &self,
+        predicate: EventFilter,
+        callback: EventCallback,
+    ) -> Result<String, UhpmError> {
+        Ok(self.subscribers.add(Some(predicate), callback))
+    }
+
+    async fn subscribe_channel(
+        &self,
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> Result<EventReceiver, UhpmError> {
+        self.subscribers.add_channel(capacity, overflow)
+    }
+
+    async fn unsubscribe(&self, subscription_id: &str) -> Result<(), UhpmError> {
+        self.subscribers.remove(subscription_id);
+        Ok(())
+    }
+
+    async fn get_event_history(
+        &self,
+        limit: Option<usize>,
+        since: Option<DateTime<Utc>>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<EventEnvelope>, UhpmError> {
+        let mut envelopes = self.read_log().await?;
+        envelopes.retain(|envelope| envelope.matches(since, package_name));
+        if let Some(limit) = limit {
+            envelopes.drain(..envelopes.len().saturating_sub(limit));
+        }
+        Ok(envelopes)
+    }
+
+    /// Truncates the log. Sequence numbers keep counting from where they were.
+    async fn clear_event_history(&self) -> Result<(), UhpmError> {
+        let mut last_sequence = self.last_sequence.lock().await;
+        if last_sequence.is_none() {
+            *last_sequence = self
+                .read_log()
+                .await?
+                .last()
+                .map(|envelope| envelope.sequence);
+        }
+        if self.file_system.exists(&self.log_path).await {
+            self.file_system.write_file(&self.log_path, &[]).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        PackageReference,
+        test_utils::{MemoryFileSystem, TestPaths, block_on},
+    };
+    use semver::Version;
+    use std::sync::{Arc, Mutex};
+
+    fn events_of(envelopes: Vec<EventEnvelope>) -> Vec<PackageEvent> {
+        envelopes
+            .into_iter()
+            .map(|envelope| envelope.event)
+            .collect()
+    }
+
+    fn sequences_of(envelopes: &[EventEnvelope]) -> Vec<u64> {
+        envelopes.iter().map(|envelope| envelope.sequence).collect()
+    }
+
+    fn tool_ref() -> PackageReference {
+        PackageReference::new("tool".to_string(), Version::new(1, 0, 0))
+    }
+
+    #[test]
+    fn test_events_are_appended_and_read_back_in_order() {
+        let fs = MemoryFileSystem::new();
+        let publisher = FileEventPublisher::new(fs.clone(), &TestPaths::new("/uhpm"));
+        let received = Arc::new(Mutex::new(0));
+        let events = vec![
+            PackageEvent::InstallationStarted {
+                package_ref: tool_ref(),
+            },
+            PackageEvent::DownloadStarted {
+                package_ref: tool_ref(),
+                size: Some(42),
+            },
+            PackageEvent::DownloadCompleted {
+                package_ref: tool_ref(),
+                source_url: None,
+            },
+        ];
+
+        block_on(async {
+            let counter = Arc::clone(&received);
+            publisher
+                .subscribe(Box::new(move |_| *counter.lock().unwrap() += 1))
+                .await
+                .unwrap();
+            for event in &events {
+                publisher.publish(event.clone()).await.unwrap();
+            }
+
+            let log = fs.file(Path::new("/uhpm/logs/events.jsonl")).unwrap();
+            let log = String::from_utf8(log).unwrap();
+            assert_eq!(log.lines().count(), 3);
+            assert!(log.lines().next().unwrap().contains("installation_started"));
+
+            let history = publisher.get_event_history(None, None, None).await.unwrap();
+            assert_eq!(sequences_of(&history), [1, 2, 3]);
+            assert_eq!(events_of(history), events);
+            assert_eq!(
+                events_of(
+                    publisher
+                        .get_event_history(Some(2), None, Some("tool"))
+                        .await
+                        .unwrap()
+                ),
+                events[1..]
+            );
+
+            // A new publisher on the same log continues the sequence.
+            let reopened = FileEventPublisher::new(fs.clone(), &TestPaths::new("/uhpm"));
+            reopened.publish(events[0].clone()).await.unwrap();
+            reopened.clear_event_history().await.unwrap();
+            assert!(
+                reopened
+                    .get_event_history(None, None, None)
+                    .await
+                    .unwrap()
+                    .is_empty()
+            );
+            reopened.publish(events[0].clone()).await.unwrap();
+            let history = reopened.get_event_history(None, None, None).await.unwrap();
+            assert_eq!(sequences_of(&history), [5]);
+            assert_eq!(events_of(history), events[..1]);
+        });
+
+        assert_eq!(*received.lock().unwrap(), 3);
+    }
+}
@@ -0,0 +1,376 @@
+use super::subscribers::Subscribers;
+use crate::{
+    EventEnvelope, EventReceiver, OverflowPolicy, PackageEvent, UhpmError,
+    clock::SystemClock,
+    ports::{Clock, EventCallback, EventFilter, EventPublisher},
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+const DEFAULT_HISTORY_LIMIT: usize = 1000;
+
+#[derive(Default)]
+struct History {
+    envelopes: VecDeque<EventEnvelope>,
+    last_sequence: u64,
+}
+
+/// Event publisher that delivers events to in-process subscribers.
+///
+/// Callbacks run synchronously on the publishing task, in subscription
+/// order. The most recent events are kept for `get_event_history`.
+pub struct InMemoryEventPublisher {
+    subscribers: Subscribers,
+    history: Mutex<History>,
+    history_limit: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl InMemoryEventPublisher {
+    pub fn new() -> Self {
+        Self::with_history_limit(DEFAULT_HISTORY_LIMIT)
+    }
+
+    /// Keeps at most `limit` events in the history, dropping the oldest.
+    pub fn with_history_limit(limit: usize) -> Self {
+        Self {
+            subscribers: Subscribers::default(),
+            history: Mutex::new(History::default()),
+            history_limit: limit,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the clock events are stamped with, the system clock by default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
+
+impl Default for InMemoryEventPublisher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl EventPublisher for InMemoryEventPublisher {
+    async fn publish(&self, event: PackageEvent) -> Result<(), UhpmError> {
+        // The sequence is assigned under the history lock so the history
+        // stays in sequence order.
+        let envelope = {
+            let mut history = self.history.lock().unwrap();
+            history.last_sequence += 1;
+            let envelope = EventEnvelope::new(history.last_sequence, self.clock.now(), event);
+            history.envelopes.push_back(envelope.clone());
+            while history.envelopes.len() > self.history_limit {
+                history.envelopes.pop_front();
+            }
+            envelope
+        };
+
+        self.subscribers.notify(&envelope).await;
+        Ok(())
+    }
+
+    async fn subscribe(&self, callback: EventCallback) -> Result<String, UhpmError> {
+        Ok(self.subscribers.add(None, callback))
+    }
+
+    async fn subscribe_filtered(
+        &self,
+        predicate: EventFilter,
+        callback: EventCallback,
+    ) -> Result<String, UhpmError> {
+        Ok(self.subscribers.add(Some(predicate), callback))
+    }
+
+    async fn subscribe_channel(
+        &self,
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> Result<EventReceiver, UhpmError> {
+        self.subscribers.add_channel(capacity, overflow)
+    }
+
+    async fn unsubscribe(&self, subscription_id: &str) -> Result<(), UhpmError> {
+        self.subscribers.remove(subscription_id);
+        Ok(())
+    }
+
+    async fn get_event_history(
+        &self,
+        limit: Option<usize>,
+        since: Option<DateTime<Utc>>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<EventEnvelope>, UhpmError> {
+        let history = self.history.lock().unwrap();
+        let mut envelopes = history
+            .envelopes
+            .iter()
+            .filter(|envelope| envelope.matches(since, package_name))
+            .cloned()
+            .collect::<Vec<_>>();
+        if let Some(limit) = limit {
+            envelopes.drain(..envelopes.len().saturating_sub(limit));
+        }
+        Ok(envelopes)
+    }
+
+    async fn clear_event_history(&self) -> Result<(), UhpmError> {
+        self.history.lock().unwrap().envelopes.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackageReference, clock::MockClock, test_utils::block_on};
+    use semver::Version;
+    use std::sync::Arc;
+
+    fn tool_ref() -> PackageReference {
+        PackageReference::new("tool".to_string(), Version::new(1, 0, 0))
+    }
+
+    #[test]
+    fn test_filtered_subscription_only_receives_matching_events() {
+        let clock = MockClock::default();
+        let publisher = InMemoryEventPublisher::new().with_clock(clock.clone());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let all = Arc::new(Mutex::new(0));
+
+        block_on(async {
+            let sink = Arc::clone(&received);
+            publisher
+                .subscribe_filtered(
+                    Box::new(|event| matches!(event, PackageEvent::DownloadStarted { .. })),
+                    Box::new(move |envelope| sink.lock().unwrap().push(envelope.event)),
+                )
+                .await
+                .unwrap();
+            let counter = Arc::clone(&all);
+            let id = publisher
+                .subscribe(Box::new(move |_| *counter.lock().unwrap() += 1))
+                .await
+                .unwrap();
+
+            publisher
+                .publish(PackageEvent::InstallationStarted {
+                    package_ref: tool_ref(),
+                })
+                .await
+                .unwrap();
+            publisher
+                .publish(PackageEvent::DownloadStarted {
+                    package_ref: tool_ref(),
+                    size: None,
+                })
+                .await
+                .unwrap();
+            publisher.unsubscribe(&id).await.unwrap();
+            clock.advance(std::time::Duration::from_secs(1));
+            publisher
+                .publish(PackageEvent::DownloadCompleted {
+                    package_ref: tool_ref(),
+                    source_url: None,
+                })
+                .await
+                .unwrap();
+
+            let history = publisher
+                .get_event_history(Some(2), None, None)
+                .await
+                .unwrap();
+            assert_eq!(
+                history
+                    .iter()
+                    .map(|envelope| envelope.sequence)
+                    .collect::<Vec<_>>(),
+                [2, 3]
+            );
+            assert!(
+                publisher
+                    .get_event_history(None, None, Some("other"))
+                    .await
+                    .unwrap()
+                    .is_empty()
+            );
+            let recent = publisher
+                .get_event_history(None, Some(clock.now()), None)
+                .await
+                .unwrap();
+            assert_eq!(
+                recent
+                    .iter()
+                    .map(|envelope| envelope.sequence)
+                    .collect::<Vec<_>>(),
+                [3]
+            );
+        });
+
+        assert_eq!(
+            *received.lock().unwrap(),
+            [PackageEvent::DownloadStarted {
+                package_ref: tool_ref(),
+                size: None,
+            }]
+        );
+        assert_eq!(*all.lock().unwrap(), 2);
+    }
+
+    async fn publish_burst(publisher: &InMemoryEventPublisher, count: usize) {
+        for _ in 0..count {
+            publisher
+                .publish(PackageEvent::InstallationStarted {
+                    package_ref: tool_ref(),
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    fn drain(receiver: &mut EventReceiver) -> Vec<u64> {
+        std::iter::from_fn(|| receiver.try_recv())
+            .map(|envelope| envelope.sequence)
+            .collect()
+    }
+
+    #[test]
+    fn test_channel_overflow_policies_keep_the_right_events() {
+        for (overflow, survivors) in [
+            (OverflowPolicy::DropOldest, [9, 10]),
+            (OverflowPolicy::DropNewest, [1, 2]),
+        ] {
+            let publisher = InMemoryEventPublisher::new();
+            block_on(async {
+                let mut receiver = publisher.subscribe_channel(2, overflow).await.unwrap();
+                publish_burst(&publisher, 10).await;
+
+                assert_eq!(drain(&mut receiver), survivors, "{:?}", overflow);
+                assert_eq!(receiver.dropped(), 8);
+            });
+        }
+    }
+
+    #[test]
+    fn test_blocking_channel_holds_up_only_the_publisher() {
+        use futures_util::FutureExt;
+
+        let publisher = InMemoryEventPublisher::new();
+        block_on(async {
+            let mut blocking = publisher
+                .subscribe_channel(2, OverflowPolicy::Block)
+                .await
+                .unwrap();
+            let mut lossy = publisher
+                .subscribe_channel(2, OverflowPolicy::DropOldest)
+                .await
+                .unwrap();
+            publish_burst(&publisher, 2).await;
+
+            // The third event reaches the other channel while publish waits
+            // for room in the full one.
+            let mut third = Box::pin(publish_burst(&publisher, 1));
+            assert!((&mut third).now_or_never().is_none());
+            assert_eq!(drain(&mut lossy), [2, 3]);
+
+            let consumer = async {
+                let mut received = Vec::new();
+                while received.len() < 10 {
+                    received.push(blocking.recv().await.unwrap().sequence);
+                }
+                received
+            };
+            let producer = async {
+                third.await;
+                publish_burst(&publisher, 7).await;
+            };
+            let (received, ()) = futures_util::future::join(consumer, producer).await;
+            assert_eq!(received, (1..=10).collect::<Vec<_>>());
+            assert_eq!(blocking.dropped(), 0);
+        });
+    }
+
+    #[test]
+    fn test_dropped_receiver_no_longer_holds_up_the_publisher() {
+        use futures_util::FutureExt;
+
+        let publisher = InMemoryEventPublisher::new();
+        block_on(async {
+            let receiver = publisher
+                .subscribe_channel(1, OverflowPolicy::Block)
+                .await
+                .unwrap();
+            publish_burst(&publisher, 1).await;
+            assert!(publish_burst(&publisher, 1).now_or_never().is_none());
+
+            drop(receiver);
+            assert!(publish_burst(&publisher, 1).now_or_never().is_some());
+        });
+    }
+
+    #[test]
+    fn test_sequence_numbers_are_unique_across_concurrent_publishers() {
+        const THREADS: u64 = 4;
+        const TASKS: u64 = 4;
+        const EVENTS: u64 = 25;
+
+        let publisher = Arc::new(InMemoryEventPublisher::new());
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&received);
+        block_on(publisher.subscribe(Box::new(move |envelope| {
+            sink.lock().unwrap().push(envelope.sequence)
+        })))
+        .unwrap();
+
+        let threads = (0..THREADS)
+            .map(|_| {
+                let publisher = Arc::clone(&publisher);
+                std::thread::spawn(move || {
+                    block_on(async {
+                        let tasks = (0..TASKS)
+                            .map(|_| {
+                                let publisher = Arc::clone(&publisher);
+                                tokio::spawn(async move {
+                                    for _ in 0..EVENTS {
+                                        publisher
+                                            .publish(PackageEvent::InstallationStarted {
+                                                package_ref: tool_ref(),
+                                            })
+                                            .await
+                                            .unwrap();
+                                        tokio::task::yield_now().await;
+                                    }
+                                })
+                            })
+                            .collect::<Vec<_>>();
+                        for task in tasks {
+                            task.await.unwrap();
+                        }
+                    })
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let expected = (1..=THREADS * TASKS * EVENTS).collect::<Vec<_>>();
+        let history = block_on(publisher.get_event_history(None, None, None)).unwrap();
+        assert_eq!(
+            history
+                .iter()
+                .map(|envelope| envelope.sequence)
+                .collect::<Vec<_>>(),
+            expected
+        );
+        let mut received = received.lock().unwrap().clone();
+        received.sort_unstable();
+        assert_eq!(received, expected);
+    }
+}
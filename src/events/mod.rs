@@ -0,0 +1,6 @@
+mod file;
+mod in_memory;
+mod subscribers;
+
+pub use file::FileEventPublisher;
+pub use in_memory::InMemoryEventPublisher;
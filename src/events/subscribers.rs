@@ -0,0 +1,101 @@
+use crate::{
+    EventEnvelope, EventReceiver, EventSender, OverflowPolicy, PackageEvent, UhpmError,
+    event_channel,
+    ports::{EventCallback, EventFilter},
+};
+use futures_util::future;
+use std::sync::{Arc, Mutex};
+
+type Callback = Arc<dyn Fn(EventEnvelope) + Send + Sync>;
+type Predicate = Arc<dyn Fn(&PackageEvent) -> bool + Send + Sync>;
+
+enum Delivery {
+    Callback(Callback),
+    Channel(Arc<EventSender>),
+}
+
+struct Subscription {
+    id: String,
+    predicate: Option<Predicate>,
+    delivery: Delivery,
+}
+
+/// In-process subscriber list shared by the event publishers.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    subscriptions: Mutex<Vec<Subscription>>,
+}
+
+impl Subscribers {
+    pub(crate) fn add(&self, predicate: Option<EventFilter>, callback: EventCallback) -> String {
+        self.push(predicate, Delivery::Callback(Arc::from(callback)))
+    }
+
+    /// Subscribes a channel of `capacity` events, which must not be zero.
+    pub(crate) fn add_channel(
+        &self,
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> Result<EventReceiver, UhpmError> {
+        if capacity == 0 {
+            return Err(UhpmError::ValidationError(
+                "Event channel capacity must be at least 1".to_string(),
+            ));
+        }
+        let (sender, receiver) = event_channel(capacity, overflow);
+        self.push(None, Delivery::Channel(Arc::new(sender)));
+        Ok(receiver)
+    }
+
+    fn push(&self, predicate: Option<EventFilter>, delivery: Delivery) -> String {
+        let id = uuid::Uuid::new_v4().to_string();
+        self.subscriptions.lock().unwrap().push(Subscription {
+            id: id.clone(),
+            predicate: predicate.map(Arc::from),
+            delivery,
+        });
+        id
+    }
+
+    pub(crate) fn remove(&self, subscription_id: &str) {
+        self.subscriptions
+            .lock()
+            .unwrap()
+            .retain(|subscription| subscription.id != subscription_id);
+    }
+
+    /// Invokes every matching callback in subscription order, then queues
+    /// the event on every channel.
+    ///
+    /// Channels whose receiver was dropped are unsubscribed. Channels that
+    /// block are waited on together, once everything else has been
+    /// delivered, so a slow one holds up only the publisher.
+    pub(crate) async fn notify(&self, envelope: &EventEnvelope) {
+        // Callbacks run without the lock held so they may (un)subscribe.
+        let mut callbacks = Vec::new();
+        let mut channels = Vec::new();
+        {
+            let mut subscriptions = self.subscriptions.lock().unwrap();
+            subscriptions.retain(|subscription| match &subscription.delivery {
+                Delivery::Channel(sender) => !sender.is_closed(),
+                Delivery::Callback(_) => true,
+            });
+            for subscription in subscriptions.iter().filter(|subscription| {
+                subscription
+                    .predicate
+                    .as_ref()
+                    .is_none_or(|predicate| predicate(&envelope.event))
+            }) {
+                match &subscription.delivery {
+                    Delivery::Callback(callback) => callbacks.push(Arc::clone(callback)),
+                    Delivery::Channel(sender) => channels.push(Arc::clone(sender)),
+                }
+            }
+        }
+
+        for callback in callbacks {
+            callback(envelope.clone());
+        }
+        future::join_all(channels.iter().map(|sender| sender.send(envelope.clone()))).await;
+    }
+}
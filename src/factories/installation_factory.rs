@@ -1,18 +1,48 @@
 // src/factories/installation_factory.rs
 
-use crate::{FileMetadata, Installation, InstallationId, PackageId, Symlink, UhpmError};
+use crate::{
+    FileMetadata, Installation, InstallationId, PackageId, Symlink, UhpmError, clock::SystemClock,
+    ports::Clock,
+};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Factory for creating Installation entities with validation.
 ///
 /// Ensures that installations are created with proper metadata and
-/// validates the installation state.
-#[derive(Debug, Clone)]
-pub struct InstallationFactory;
+/// validates the installation state. New installations are stamped with the
+/// factory's clock, the system clock by default.
+#[derive(Clone)]
+pub struct InstallationFactory {
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for InstallationFactory {
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(SystemClock),
+        }
+    }
+}
+
+impl fmt::Debug for InstallationFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InstallationFactory")
+            .finish_non_exhaustive()
+    }
+}
 
 impl InstallationFactory {
-    /// Creates a new Installation for a package.
+    /// Creates a factory stamping installations with `clock`.
+    pub fn with_clock<C: Clock + 'static>(clock: C) -> Self {
+        Self {
+            clock: Arc::new(clock),
+        }
+    }
+
+    /// Creates a new Installation for a package, installed now.
     ///
     /// # Arguments
     /// * `package_id` - ID of the package being installed
@@ -20,43 +50,65 @@ impl InstallationFactory {
     /// # Returns
     /// * `Installation` - New installation instance
     ///
-    pub fn create(package_id: PackageId) -> Installation {
+    pub fn create_installation(&self, package_id: PackageId) -> Installation {
         Installation::new(
             InstallationId::new(),
             package_id,
             HashMap::new(),
             Vec::new(),
-            chrono::Utc::now(),
+            self.clock.now(),
             false,
         )
     }
 
+    /// Creates a new Installation with the default factory.
+    pub fn create(package_id: PackageId) -> Installation {
+        Self::default().create_installation(package_id)
+    }
+
     /// Creates an installation from database data (for reconstruction).
     ///
     /// # Arguments
     /// * `installation_id` - Existing installation ID
     /// * `package_id` - Package ID
+    /// * `installed_files` - Files recorded for the installation
+    /// * `symlinks` - Symlinks recorded for the installation
     /// * `installed_at` - Installation timestamp
     /// * `active` - Whether installation is active
     ///
     /// # Returns
-    /// * `Installation` - Reconstructed installation
+    /// * `Ok(Installation)` - Reconstructed installation
+    /// * `Err(UhpmError)` - A file path is empty or a symlink is invalid
     pub fn from_existing(
         installation_id: InstallationId,
         package_id: PackageId,
+        installed_files: HashMap<PathBuf, FileMetadata>,
+        symlinks: Vec<Symlink>,
         installed_at: chrono::DateTime<chrono::Utc>,
         active: bool,
-    ) -> Installation {
-        let mut installation = Installation::new(
+    ) -> Result<Installation, UhpmError> {
+        if installed_files
+            .keys()
+            .any(|path| path.as_os_str().is_empty())
+        {
+            return Err(UhpmError::ValidationError(format!(
+                "Installation {} has a file with an empty path",
+                installation_id
+            )));
+        }
+
+        for symlink in &symlinks {
+            Self::default().validate_symlink(symlink)?;
+        }
+
+        Ok(Installation::new(
             installation_id,
             package_id,
-            HashMap::new(),
-            Vec::new(),
+            installed_files,
+            symlinks,
             installed_at,
-            active, // Will set properly below
-        );
-
-        installation
+            active,
+        ))
     }
 
     /// Validates if an installation can be activated.
@@ -157,7 +209,7 @@ impl InstallationFactory {
     }
 
     /// Checks if a path is a system directory (for safety).
-    fn is_system_directory(path: &PathBuf) -> bool {
+    pub(crate) fn is_system_directory(path: &Path) -> bool {
         let system_dirs = [
             "/bin",
             "/sbin",
@@ -178,6 +230,7 @@ mod tests {
     use semver::Version;
 
     use super::*;
+    use crate::clock::MockClock;
 
     #[test]
     fn test_create_installation() {
@@ -189,6 +242,18 @@ mod tests {
         assert!(installation.symlinks().is_empty());
     }
 
+    #[test]
+    fn test_installations_are_stamped_by_the_clock() {
+        let clock = MockClock::default();
+        let factory = InstallationFactory::with_clock(clock.clone());
+        let package_id = PackageId::new("test-pkg", &Version::parse("1.0.0").unwrap());
+
+        clock.advance(std::time::Duration::from_secs(5));
+        let installation = factory.create_installation(package_id);
+
+        assert_eq!(*installation.installed_at(), clock.now());
+    }
+
     #[test]
     fn test_validate_activation_empty_installation() {
         let package_id = PackageId::new("test-pkg", &Version::parse("1.0.0").unwrap());
@@ -197,4 +262,51 @@ mod tests {
         let result = InstallationFactory::validate_activation(&installation);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_from_existing_keeps_state() {
+        let package_id = PackageId::new("test-pkg", &Version::parse("1.0.0").unwrap());
+        let path = PathBuf::from("/store/bin/tool");
+        let files = HashMap::from([(path.clone(), FileMetadata::new(path.clone(), 4))]);
+        let symlinks = vec![Symlink::file(path, "/home/user/.local/bin/tool")];
+
+        let installation = InstallationFactory::from_existing(
+            InstallationId::new(),
+            package_id,
+            files,
+            symlinks,
+            chrono::Utc::now(),
+            true,
+        )
+        .unwrap();
+
+        assert!(installation.is_active());
+        assert_eq!(installation.installed_files().len(), 1);
+        assert_eq!(installation.symlinks().len(), 1);
+    }
+
+    #[test]
+    fn test_from_existing_rejects_invalid_entries() {
+        let package_id = PackageId::new("test-pkg", &Version::parse("1.0.0").unwrap());
+
+        let empty_path = InstallationFactory::from_existing(
+            InstallationId::new(),
+            package_id.clone(),
+            HashMap::from([(PathBuf::new(), FileMetadata::new(PathBuf::new(), 0))]),
+            vec![],
+            chrono::Utc::now(),
+            false,
+        );
+        assert!(matches!(empty_path, Err(UhpmError::ValidationError(_))));
+
+        let self_link = InstallationFactory::from_existing(
+            InstallationId::new(),
+            package_id,
+            HashMap::new(),
+            vec![Symlink::file("/store/tool", "/store/tool")],
+            chrono::Utc::now(),
+            false,
+        );
+        assert!(matches!(self_link, Err(UhpmError::ValidationError(_))));
+    }
 }
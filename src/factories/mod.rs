@@ -1,11 +1,15 @@
 // src/factories/mod.rs
 
 mod installation_factory;
+mod package_builder;
 mod package_factory;
 
 pub use installation_factory::InstallationFactory;
+pub use package_builder::PackageBuilder;
 pub use package_factory::PackageFactory;
 
+use crate::{ports::Clock, validation::NamePolicy};
+
 /// Collection of factories for creating domain entities.
 ///
 /// Factories encapsulate complex creation logic and ensure
@@ -19,11 +23,25 @@ impl Factories {
     /// Creates a new collection of factories.
     pub fn new() -> Self {
         Self {
-            package: PackageFactory,
-            installation: InstallationFactory,
+            package: PackageFactory::default(),
+            installation: InstallationFactory::default(),
         }
     }
 
+    /// Creates the factories with a package factory enforcing `name_policy`.
+    pub fn with_name_policy(name_policy: NamePolicy) -> Self {
+        Self {
+            package: PackageFactory::with_name_policy(name_policy),
+            installation: InstallationFactory::default(),
+        }
+    }
+
+    /// Stamps the installations created by the factories with `clock`.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.installation = InstallationFactory::with_clock(clock);
+        self
+    }
+
     /// Returns the package factory.
     pub fn package(&self) -> &PackageFactory {
         &self.package
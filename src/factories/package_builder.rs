@@ -0,0 +1,190 @@
+// src/factories/package_builder.rs
+
+use crate::{
+    Checksum, Dependency, Package, PackageSource, Target, UhpmError, factories::PackageFactory,
+    validation::NamePolicy,
+};
+use semver::Version;
+
+/// Step-by-step construction of a [`Package`].
+///
+/// `name`, `version`, `author` and `source` are required; the target
+/// defaults to the host platform. `build` applies the same validation as
+/// [`PackageFactory::create`], with the default [`NamePolicy`] unless another
+/// one is set.
+///
+/// # Examples
+/// ```
+/// use semver::Version;
+/// use uhpm_core::{PackageSource, factories::PackageBuilder};
+///
+/// let package = PackageBuilder::new()
+///     .name("my-package")
+///     .version(Version::new(1, 0, 0))
+///     .author("author")
+///     .source(PackageSource::Local { path: "/path".into() })
+///     .installed(true)
+///     .build()
+///     .unwrap();
+/// assert!(package.is_installed());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PackageBuilder {
+    name: Option<String>,
+    version: Option<Version>,
+    author: Option<String>,
+    source: Option<PackageSource>,
+    target: Option<Target>,
+    checksum: Option<Checksum>,
+    dependencies: Vec<Dependency>,
+    installed: bool,
+    active: bool,
+    name_policy: NamePolicy,
+}
+
+impl PackageBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn version(mut self, version: Version) -> Self {
+        self.version = Some(version);
+        self
+    }
+
+    pub fn author(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    pub fn source(mut self, source: PackageSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// Adds a dependency; call once per dependency.
+    pub fn dependency(mut self, dependency: Dependency) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    pub fn installed(mut self, installed: bool) -> Self {
+        self.installed = installed;
+        self
+    }
+
+    pub fn active(mut self, active: bool) -> Self {
+        self.active = active;
+        self
+    }
+
+    /// Checks the name against `name_policy` instead of the default one.
+    pub fn name_policy(mut self, name_policy: NamePolicy) -> Self {
+        self.name_policy = name_policy;
+        self
+    }
+
+    pub fn build(self) -> Result<Package, UhpmError> {
+        let factory = PackageFactory::with_name_policy(self.name_policy);
+        let mut package = factory.create_package(
+            required(self.name, "name")?,
+            required(self.version, "version")?,
+            required(self.author, "author")?,
+            required(self.source, "source")?,
+            self.target.unwrap_or_else(Target::current),
+            self.checksum,
+            self.dependencies,
+        )?;
+        package.set_installed(self.installed);
+        package.set_active(self.active);
+        Ok(package)
+    }
+}
+
+fn required<T>(value: Option<T>, field: &str) -> Result<T, UhpmError> {
+    value.ok_or_else(|| UhpmError::ValidationError(format!("Package {} is required", field)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Architecture, DependencyKind, OperatingSystem, VersionConstraint};
+    use semver::VersionReq;
+
+    fn minimal() -> PackageBuilder {
+        PackageBuilder::new()
+            .name("my-package")
+            .version(Version::new(1, 0, 0))
+            .author("John Doe")
+            .source(PackageSource::Local {
+                path: "/tmp".into(),
+            })
+    }
+
+    #[test]
+    fn test_build_minimal_package() {
+        let package = minimal().build().unwrap();
+
+        assert_eq!(package.id().as_str(), "my-package@1.0.0");
+        assert_eq!(package.target(), &Target::current());
+        assert!(package.checksum().is_none());
+        assert!(package.dependencies().is_empty());
+        assert!(!package.is_installed());
+        assert!(!package.is_active());
+    }
+
+    #[test]
+    fn test_build_fully_specified_package() {
+        let target = Target {
+            os: OperatingSystem::Linux,
+            arch: Architecture::Aarch64,
+        };
+        let package = minimal()
+            .target(target.clone())
+            .checksum(Checksum {
+                algorithm: "sha256".to_string(),
+                hash: "abc".to_string(),
+            })
+            .dependency(Dependency {
+                name: "lib".to_string(),
+                constraint: VersionConstraint {
+                    requirement: VersionReq::parse("^1").unwrap(),
+                },
+                kind: DependencyKind::Required,
+                provides: None,
+                features: vec![],
+            })
+            .installed(true)
+            .active(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(package.target(), &target);
+        assert_eq!(package.checksum().as_ref().unwrap().hash, "abc");
+        assert_eq!(package.dependencies().len(), 1);
+        assert!(package.is_installed());
+        assert!(package.is_active());
+    }
+
+    #[test]
+    fn test_build_validates_like_the_factory() {
+        assert!(PackageBuilder::new().build().is_err());
+        assert!(minimal().name("bad name").build().is_err());
+        assert!(minimal().author(" ").build().is_err());
+    }
+}
@@ -1,16 +1,50 @@
 // src/factories/package_factory.rs
 
-use crate::{Checksum, Dependency, Package, PackageId, PackageSource, Target, UhpmError};
+use crate::{
+    Checksum, Dependency, Package, PackageId, PackageSource, Target, UhpmError,
+    validation::{self, NamePolicy},
+};
 use semver::Version;
 
 /// Factory for creating Package entities with validation and business rules.
 ///
 /// The PackageFactory ensures that all Package instances are created in a valid state
-/// and enforces business rules during creation.
-#[derive(Debug, Clone)]
-pub struct PackageFactory;
+/// and enforces business rules during creation. The associated functions apply
+/// the default [`NamePolicy`]; a factory built with `with_name_policy` applies
+/// its own.
+#[derive(Debug, Clone, Default)]
+pub struct PackageFactory {
+    name_policy: NamePolicy,
+}
 
 impl PackageFactory {
+    /// A factory accepting the package names allowed by `name_policy`.
+    ///
+    /// # Examples
+    /// ```
+    /// use semver::Version;
+    /// use uhpm_core::{Target, PackageSource, factories::PackageFactory, validation::NamePolicy};
+    ///
+    /// let factory = PackageFactory::with_name_policy(NamePolicy::default().allow_leading_digit());
+    /// let package = factory.create_package(
+    ///     "7zip".to_string(),
+    ///     Version::new(23, 1, 0),
+    ///     "author".to_string(),
+    ///     PackageSource::Local { path: "/path".into() },
+    ///     Target::current(),
+    ///     None,
+    ///     vec![],
+    /// );
+    /// assert!(package.is_ok());
+    /// ```
+    pub fn with_name_policy(name_policy: NamePolicy) -> Self {
+        Self { name_policy }
+    }
+
+    pub fn name_policy(&self) -> &NamePolicy {
+        &self.name_policy
+    }
+
     /// Creates a new Package with validation.
     ///
     /// # Arguments
@@ -50,20 +84,32 @@ impl PackageFactory {
         checksum: Option<Checksum>,
         dependencies: Vec<Dependency>,
     ) -> Result<Package, UhpmError> {
-        // Validate name
-        if name.trim().is_empty() {
-            return Err(UhpmError::ValidationError(
-                "Package name cannot be empty or whitespace".to_string(),
-            ));
-        }
+        Self::default().create_package(
+            name,
+            version,
+            author,
+            source,
+            target,
+            checksum,
+            dependencies,
+        )
+    }
 
-        // Validate name format (alphanumeric, hyphens, underscores)
-        if !Self::is_valid_package_name(&name) {
-            return Err(UhpmError::ValidationError(format!(
-                "Invalid package name '{}'. Must contain only alphanumeric characters, hyphens, and underscores",
-                name
-            )));
-        }
+    /// Creates a new Package like [`PackageFactory::create`], checking the
+    /// name against this factory's [`NamePolicy`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_package(
+        &self,
+        name: String,
+        version: Version,
+        author: String,
+        source: PackageSource,
+        target: Target,
+        checksum: Option<Checksum>,
+        dependencies: Vec<Dependency>,
+    ) -> Result<Package, UhpmError> {
+        // Validate name
+        self.name_policy.validate(&name)?;
 
         // Validate author
         if author.trim().is_empty() {
@@ -80,7 +126,7 @@ impl PackageFactory {
         }
 
         // Validate source
-        Self::validate_source(&source)?;
+        validation::validate_source(&source)?;
 
         // Create package ID
         let id = PackageId::new(&name, &version);
@@ -143,62 +189,6 @@ impl PackageFactory {
     ) -> Result<Package, UhpmError> {
         Self::create(name, version, author, source, target, None, dependencies)
     }
-
-    /// Validates package name format
-    fn is_valid_package_name(name: &str) -> bool {
-        !name.is_empty()
-            && name.len() <= 50
-            && name
-                .chars()
-                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
-            && name
-                .chars()
-                .next()
-                .map(|c| c.is_ascii_alphabetic())
-                .unwrap_or(false)
-    }
-
-    /// Validates package source
-    fn validate_source(source: &PackageSource) -> Result<(), UhpmError> {
-        match source {
-            PackageSource::Git { url, release: _ } => {
-                if url.trim().is_empty() {
-                    return Err(UhpmError::ValidationError(
-                        "Git URL cannot be empty".to_string(),
-                    ));
-                }
-                // Basic URL validation
-                if !url.starts_with("http://")
-                    && !url.starts_with("https://")
-                    && !url.starts_with("git@")
-                {
-                    return Err(UhpmError::ValidationError(
-                        "Git URL must be http, https, or git@ format".to_string(),
-                    ));
-                }
-            }
-            PackageSource::Http { url } => {
-                if url.trim().is_empty() {
-                    return Err(UhpmError::ValidationError(
-                        "HTTP URL cannot be empty".to_string(),
-                    ));
-                }
-                if !url.starts_with("http://") && !url.starts_with("https://") {
-                    return Err(UhpmError::ValidationError(
-                        "HTTP URL must start with http:// or https://".to_string(),
-                    ));
-                }
-            }
-            PackageSource::Local { path } => {
-                if path.as_os_str().is_empty() {
-                    return Err(UhpmError::ValidationError(
-                        "Local path cannot be empty".to_string(),
-                    ));
-                }
-            }
-        }
-        Ok(())
-    }
 }
 
 #[cfg(test)]
@@ -241,4 +231,25 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_name_policy() {
+        let create = |factory: &PackageFactory| {
+            factory.create_package(
+                "7zip".to_string(),
+                Version::parse("23.1.0").unwrap(),
+                "Igor Pavlov".to_string(),
+                PackageSource::Local {
+                    path: "/tmp".into(),
+                },
+                Target::current(),
+                None,
+                vec![],
+            )
+        };
+
+        assert!(create(&PackageFactory::default()).is_err());
+        let relaxed = PackageFactory::with_name_policy(NamePolicy::default().allow_leading_digit());
+        assert_eq!(create(&relaxed).unwrap().name(), "7zip");
+    }
 }
@@ -0,0 +1,3 @@
+mod tokio_fs;
+
+pub use tokio_fs::TokioFileSystem;
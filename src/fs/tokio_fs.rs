@@ -0,0 +1,323 @@
+use crate::{FileMetadata, FileType, FsError, Symlink, UhpmError, ports::FileSystemOperations};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncRead;
+
+/// [`FileSystemOperations`] on the local disk using `tokio::fs`.
+#[derive(Debug, Clone, Default)]
+pub struct TokioFileSystem;
+
+impl TokioFileSystem {
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+fn fs_error(path: &Path, error: std::io::Error) -> UhpmError {
+    let path = path.display().to_string();
+    match error.kind() {
+        ErrorKind::NotFound => FsError::NotFound(path),
+        ErrorKind::PermissionDenied => FsError::PermissionDenied(path),
+        ErrorKind::NotADirectory => FsError::NotADirectory(path),
+        ErrorKind::CrossesDevices => FsError::CrossDevice(path),
+        _ => FsError::Io(format!("{}: {}", path, error)),
+    }
+    .into()
+}
+
+fn timestamp(time: std::io::Result<std::time::SystemTime>) -> Option<DateTime<Utc>> {
+    time.ok().map(DateTime::<Utc>::from)
+}
+
+#[cfg(unix)]
+fn mode_and_links(metadata: &std::fs::Metadata) -> (u32, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (metadata.mode() & 0o7777, metadata.nlink())
+}
+
+#[cfg(not(unix))]
+fn mode_and_links(metadata: &std::fs::Metadata) -> (u32, u64) {
+    let mode = if metadata.permissions().readonly() {
+        0o444
+    } else {
+        0o644
+    };
+    (mode, 1)
+}
+
+#[async_trait]
+impl FileSystemOperations for TokioFileSystem {
+    async fn read_file(&self, path: &Path) -> Result<Vec<u8>, UhpmError> {
+        tokio::fs::read(path).await.map_err(|e| fs_error(path, e))
+    }
+
+    async fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), UhpmError> {
+        tokio::fs::write(path, data)
+            .await
+            .map_err(|e| fs_error(path, e))
+    }
+
+    async fn open_read(&self, path: &Path) -> Result<Box<dyn AsyncRead + Send + Unpin>, UhpmError> {
+        let file = tokio::fs::File::open(path)
+            .await
+            .map_err(|e| fs_error(path, e))?;
+        Ok(Box::new(file))
+    }
+
+    async fn append_file(&self, path: &Path, data: &[u8]) -> Result<(), UhpmError> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await
+            .map_err(|e| fs_error(path, e))?;
+        file.write_all(data).await.map_err(|e| fs_error(path, e))?;
+        file.flush().await.map_err(|e| fs_error(path, e))
+    }
+
+    async fn create_dir(&self, path: &Path) -> Result<(), UhpmError> {
+        tokio::fs::create_dir(path)
+            .await
+            .map_err(|e| fs_error(path, e))
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> Result<(), UhpmError> {
+        tokio::fs::create_dir_all(path)
+            .await
+            .map_err(|e| fs_error(path, e))
+    }
+
+    async fn remove(&self, path: &Path) -> Result<(), UhpmError> {
+        tokio::fs::remove_file(path)
+            .await
+            .map_err(|e| fs_error(path, e))
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> Result<(), UhpmError> {
+        tokio::fs::remove_dir_all(path)
+            .await
+            .map_err(|e| fs_error(path, e))
+    }
+
+    async fn copy_file(&self, from: &Path, to: &Path) -> Result<(), UhpmError> {
+        tokio::fs::copy(from, to)
+            .await
+            .map(|_| ())
+            .map_err(|e| fs_error(from, e))
+    }
+
+    async fn move_file(&self, from: &Path, to: &Path) -> Result<(), UhpmError> {
+        match tokio::fs::rename(from, to).await {
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                self.copy_file(from, to).await?;
+                self.remove(from).await
+            }
+            result => result.map_err(|e| fs_error(from, e)),
+        }
+    }
+
+    async fn exists(&self, path: &Path) -> bool {
+        tokio::fs::symlink_metadata(path).await.is_ok()
+    }
+
+    async fn metadata(&self, path: &Path) -> Result<FileMetadata, UhpmError> {
+        let metadata = tokio::fs::symlink_metadata(path)
+            .await
+            .map_err(|e| fs_error(path, e))?;
+
+        let file_type = if metadata.file_type().is_symlink() {
+            FileType::Symlink
+        } else if metadata.is_dir() {
+            FileType::Directory
+        } else {
+            FileType::Regular
+        };
+        let (mode, hard_links) = mode_and_links(&metadata);
+
+        let mut result = FileMetadata::new(path.to_path_buf(), metadata.len())
+            .with_file_type(file_type)
+            .with_mode(mode)
+            .with_hard_links(hard_links);
+        // Not every file system records creation times; the modification
+        // time is the closest thing to it then.
+        let modified = timestamp(metadata.modified());
+        if let Some(created) = timestamp(metadata.created()).or(modified) {
+            result.created_at = created;
+        }
+        if let Some(modified) = modified {
+            result.modified_at = modified;
+        }
+
+        Ok(result)
+    }
+
+    async fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, UhpmError> {
+        let mut entries = tokio::fs::read_dir(path)
+            .await
+            .map_err(|e| fs_error(path, e))?;
+        let mut paths = Vec::new();
+
+        while let Some(entry) = entries.next_entry().await.map_err(|e| fs_error(path, e))? {
+            paths.push(entry.path());
+        }
+
+        paths.sort();
+        Ok(paths)
+    }
+
+    async fn create_symlink(&self, symlink: &Symlink) -> Result<(), UhpmError> {
+        #[cfg(unix)]
+        let result = tokio::fs::symlink(&symlink.source, &symlink.target).await;
+
+        #[cfg(windows)]
+        let result = match symlink.link_type {
+            crate::SymlinkType::File => {
+                tokio::fs::symlink_file(&symlink.source, &symlink.target).await
+            }
+            crate::SymlinkType::Directory => {
+                tokio::fs::symlink_dir(&symlink.source, &symlink.target).await
+            }
+        };
+
+        result.map_err(|e| fs_error(&symlink.target, e))
+    }
+
+    async fn remove_symlink(&self, path: &Path) -> Result<(), UhpmError> {
+        match tokio::fs::remove_file(path).await {
+            // Directory symlinks on Windows have to be removed as directories.
+            Err(e) if cfg!(windows) && e.kind() != ErrorKind::NotFound => {
+                tokio::fs::remove_dir(path)
+                    .await
+                    .map_err(|e| fs_error(path, e))
+            }
+            result => result.map_err(|e| fs_error(path, e)),
+        }
+    }
+
+    async fn read_symlink(&self, path: &Path) -> Result<PathBuf, UhpmError> {
+        tokio::fs::read_link(path)
+            .await
+            .map_err(|e| fs_error(path, e))
+    }
+
+    async fn is_symlink(&self, path: &Path) -> bool {
+        tokio::fs::symlink_metadata(path)
+            .await
+            .map(|metadata| metadata.file_type().is_symlink())
+            .unwrap_or(false)
+    }
+
+    async fn set_permissions(&self, path: &Path, permissions: u32) -> Result<(), UhpmError> {
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::Permissions::from_mode(permissions)
+        };
+
+        #[cfg(not(unix))]
+        let permissions = {
+            let mut current = tokio::fs::metadata(path)
+                .await
+                .map_err(|e| fs_error(path, e))?
+                .permissions();
+            current.set_readonly(permissions & 0o200 == 0);
+            current
+        };
+
+        tokio::fs::set_permissions(path, permissions)
+            .await
+            .map_err(|e| fs_error(path, e))
+    }
+
+    async fn create_hard_link(&self, from: &Path, to: &Path) -> Result<(), UhpmError> {
+        tokio::fs::hard_link(from, to)
+            .await
+            .map_err(|e| fs_error(to, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::block_on;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("uhpm-fs-{}", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_metadata_reports_the_modification_time() {
+        let dir = temp_dir();
+        let fs = TokioFileSystem::new();
+        let path = dir.join("file");
+        let modified =
+            std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+
+        block_on(async {
+            fs.create_dir_all(&dir).await.unwrap();
+            fs.write_file(&path, b"data").await.unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&path)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+
+            let metadata = fs.metadata(&path).await.unwrap();
+            assert_eq!(metadata.modified_at, DateTime::<Utc>::from(modified));
+        });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hard_link_shares_data() {
+        let dir = temp_dir();
+        let fs = TokioFileSystem::new();
+
+        block_on(async {
+            fs.create_dir_all(&dir).await.unwrap();
+            fs.write_file(&dir.join("a"), b"data").await.unwrap();
+            fs.create_hard_link(&dir.join("a"), &dir.join("b"))
+                .await
+                .unwrap();
+
+            assert_eq!(fs.read_file(&dir.join("b")).await.unwrap(), b"data");
+            if cfg!(unix) {
+                assert!(fs.metadata(&dir.join("a")).await.unwrap().is_hard_linked());
+            }
+
+            fs.remove_dir_all(&dir).await.unwrap();
+        });
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_round_trip() {
+        let dir = temp_dir();
+        let fs = TokioFileSystem::new();
+        let symlink = Symlink::file(dir.join("missing"), dir.join("link"));
+
+        block_on(async {
+            fs.create_dir_all(&dir).await.unwrap();
+            fs.create_symlink(&symlink).await.unwrap();
+
+            // A dangling link still counts as existing so it can be replaced.
+            assert!(fs.exists(&symlink.target).await);
+            assert!(fs.is_symlink(&symlink.target).await);
+            assert_eq!(
+                fs.read_symlink(&symlink.target).await.unwrap(),
+                symlink.source
+            );
+            assert!(fs.metadata(&symlink.target).await.unwrap().is_symlink());
+
+            fs.remove_symlink(&symlink.target).await.unwrap();
+            assert!(!fs.exists(&symlink.target).await);
+
+            fs.remove_dir_all(&dir).await.unwrap();
+        });
+    }
+}
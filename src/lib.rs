@@ -1,14 +1,28 @@
 pub mod application;
+pub mod cache;
+pub mod clock;
 pub mod entities;
 pub mod errors;
+pub mod events;
 pub mod factories;
+pub mod fs;
+pub mod lock;
 pub mod models;
+pub mod network;
 pub mod paths;
 pub mod ports;
 pub mod repositories;
 pub mod services;
+#[cfg(feature = "facade")]
+mod uhpm;
+pub mod validation;
+
+#[cfg(test)]
+pub(crate) mod test_utils;
 
 pub use entities::*;
 pub use errors::*;
 pub use models::*;
 pub use ports::*;
+#[cfg(feature = "facade")]
+pub use uhpm::Uhpm;
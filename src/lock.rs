@@ -0,0 +1,270 @@
+use crate::UhpmError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Advisory lock serializing mutating operations between processes.
+///
+/// The lock is a file created exclusively, holding the PID and operation of
+/// the owner. Locks left behind by processes that are no longer running are
+/// broken automatically.
+///
+/// Within a process, callers sharing a `LockFile` (or clones of it) queue
+/// for it in turn rather than waiting on their own lock file.
+#[derive(Debug, Clone)]
+pub struct LockFile {
+    path: PathBuf,
+    timeout: Duration,
+    in_process: Arc<Mutex<()>>,
+}
+
+/// Owner information stored in the lock file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub operation: String,
+    pub acquired_at: DateTime<Utc>,
+}
+
+/// Held lock, released when dropped.
+#[derive(Debug)]
+pub struct LockGuard {
+    path: PathBuf,
+    /// Released after the lock file is removed.
+    _in_process: Option<OwnedMutexGuard<()>>,
+}
+
+impl LockFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            timeout: DEFAULT_TIMEOUT,
+            in_process: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Sets how long `acquire` waits for another holder before giving up.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Takes the lock for `operation`.
+    ///
+    /// Waits for earlier callers in this process first, then up to the
+    /// configured timeout for another process holding the lock file. The
+    /// file is polled on the blocking thread pool.
+    pub async fn acquire(&self, operation: &str) -> Result<LockGuard, UhpmError> {
+        let in_process = Arc::clone(&self.in_process).lock_owned().await;
+        let lock = self.clone();
+        let operation = operation.to_string();
+        let mut guard = tokio::task::spawn_blocking(move || lock.acquire_file(&operation))
+            .await
+            .map_err(|e| UhpmError::IoError(std::io::Error::other(e)))??;
+        guard._in_process = Some(in_process);
+        Ok(guard)
+    }
+
+    fn acquire_file(&self, operation: &str) -> Result<LockGuard, UhpmError> {
+        let started = Instant::now();
+
+        loop {
+            match self.try_create(operation) {
+                Ok(guard) => return Ok(guard),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e.into()),
+            }
+
+            let holder = self.holder();
+            if holder
+                .as_ref()
+                .is_some_and(|holder| !is_process_alive(holder.pid))
+            {
+                self.break_lock()?;
+                continue;
+            }
+
+            if started.elapsed() >= self.timeout {
+                return Err(UhpmError::LockTimeout(match holder {
+                    Some(holder) => holder.to_string(),
+                    None => format!("unknown process ({})", self.path.display()),
+                }));
+            }
+
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Returns the current holder, if the lock is taken and readable.
+    pub fn holder(&self) -> Option<LockHolder> {
+        let content = std::fs::read_to_string(&self.path).ok()?;
+        toml::from_str(&content).ok()
+    }
+
+    fn try_create(&self, operation: &str) -> std::io::Result<LockGuard> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&self.path)?;
+        let guard = LockGuard {
+            path: self.path.clone(),
+            _in_process: None,
+        };
+
+        let holder = LockHolder {
+            pid: std::process::id(),
+            operation: operation.to_string(),
+            acquired_at: Utc::now(),
+        };
+        let content = toml::to_string(&holder).map_err(std::io::Error::other)?;
+        file.write_all(content.as_bytes())?;
+
+        Ok(guard)
+    }
+
+    fn break_lock(&self) -> Result<(), UhpmError> {
+        match std::fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "process {} ({}) since {}",
+            self.pid,
+            self.operation,
+            self.acquired_at.to_rfc3339()
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn is_process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn is_process_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(true)
+}
+
+#[cfg(not(unix))]
+fn is_process_alive(_pid: u32) -> bool {
+    // Without a reliable liveness check the lock is never considered stale.
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::block_on;
+
+    fn lock_path() -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("uhpm-lock-{}", uuid::Uuid::new_v4()))
+            .join("uhpm.lock")
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_acquire_times_out_on_another_live_holder() {
+        let path = lock_path();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        // PID 1 always runs and is never this test process.
+        let holder = LockHolder {
+            pid: 1,
+            operation: "install".to_string(),
+            acquired_at: Utc::now(),
+        };
+        std::fs::write(&path, toml::to_string(&holder).unwrap()).unwrap();
+        let lock = LockFile::new(&path).with_timeout(Duration::from_millis(100));
+
+        match block_on(lock.acquire("remove")).unwrap_err() {
+            UhpmError::LockTimeout(holder) => {
+                assert!(holder.contains("process 1 "), "{}", holder);
+                assert!(holder.contains("install"));
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(block_on(lock.acquire("remove")).is_ok());
+    }
+
+    #[test]
+    fn test_callers_in_one_process_take_turns() {
+        // Without queueing, the second caller would find the lock file taken
+        // by a live process and give up at once.
+        let lock = LockFile::new(lock_path()).with_timeout(Duration::ZERO);
+        let order = std::sync::Mutex::new(Vec::new());
+
+        block_on(async {
+            let first = lock.acquire("install").await.unwrap();
+            let waiting = async {
+                let _guard = lock.acquire("remove").await.unwrap();
+                order.lock().unwrap().push("remove");
+            };
+            let releasing = async {
+                // Give the second caller a chance to queue up first.
+                for _ in 0..10 {
+                    tokio::task::yield_now().await;
+                }
+                order.lock().unwrap().push("install");
+                drop(first);
+            };
+            futures_util::future::join(waiting, releasing).await;
+        });
+
+        assert_eq!(*order.lock().unwrap(), ["install", "remove"]);
+        assert!(!lock.path().exists());
+    }
+
+    #[test]
+    fn test_stale_lock_is_broken() {
+        let path = lock_path();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let stale = LockHolder {
+            pid: u32::MAX,
+            operation: "install".to_string(),
+            acquired_at: Utc::now(),
+        };
+        std::fs::write(&path, toml::to_string(&stale).unwrap()).unwrap();
+
+        let lock = LockFile::new(&path).with_timeout(Duration::ZERO);
+        let _guard = block_on(lock.acquire("remove")).unwrap();
+
+        assert_eq!(lock.holder().unwrap().operation, "remove");
+    }
+}
@@ -0,0 +1,101 @@
+use crate::UhpmError;
+use futures_util::future::{Either, select};
+use std::future::Future;
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+/// Cooperative cancellation for long-running operations.
+///
+/// Clones share the same state, so a caller keeps one clone and hands the
+/// other to the operation. Operations check the token between units of work
+/// and drop in-flight futures, such as network requests, once it is
+/// cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails with [`UhpmError::Cancelled`] once the token is cancelled.
+    pub fn check(&self) -> Result<(), UhpmError> {
+        if self.is_cancelled() {
+            Err(UhpmError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Completes once the token is cancelled.
+    pub async fn cancelled(&self) {
+        loop {
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Runs `future` until it completes or the token is cancelled, in which
+    /// case the future is dropped and [`UhpmError::Cancelled`] is returned.
+    pub async fn run<T>(
+        &self,
+        future: impl Future<Output = Result<T, UhpmError>>,
+    ) -> Result<T, UhpmError> {
+        self.check()?;
+        match select(pin!(future), pin!(self.cancelled())).await {
+            Either::Left((outcome, _)) => outcome,
+            Either::Right(((), _)) => Err(UhpmError::Cancelled),
+        }
+    }
+}
+
+/// Tokens are equal when they are clones of each other.
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
+
+impl Eq for CancellationToken {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::block_on;
+
+    #[test]
+    fn test_cancel_drops_pending_future() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+
+        let outcome = block_on(token.run(async {
+            clone.cancel();
+            std::future::pending::<Result<(), UhpmError>>().await
+        }));
+
+        assert!(matches!(outcome, Err(UhpmError::Cancelled)));
+        assert!(token.check().is_err());
+    }
+}
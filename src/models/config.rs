@@ -1,4 +1,5 @@
-use crate::UhpmError;
+use crate::{DownloadOptions, UhpmError};
+use semver::Version;
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -7,6 +8,37 @@ pub struct UhpmConfig {
     pub update_source: String,
     pub default_install_mode: InstallMode,
     pub repositories: Vec<RepositoryConfig>,
+    /// Upper bound for the package cache in bytes, unlimited when unset.
+    #[serde(default)]
+    pub max_cache_size: Option<u64>,
+    /// Directories instlist targets may point into, besides the uhpm base
+    /// directory. A leading `~` stands for the home directory.
+    #[serde(default = "default_install_prefixes")]
+    pub install_prefixes: Vec<String>,
+    /// Directory `${PREFIX}` in instlist targets stands for when an install
+    /// doesn't name one. A leading `~` stands for the home directory.
+    #[serde(default)]
+    pub default_prefix: Option<String>,
+    /// Retry and resume behaviour for package downloads.
+    #[serde(default)]
+    pub downloads: DownloadOptions,
+    /// Packages from repositories below this trust level are only
+    /// installed once confirmed.
+    #[serde(default)]
+    pub trust_threshold: TrustLevel,
+    /// How far `update` may move a package, unless the package sets its
+    /// own policy.
+    #[serde(default)]
+    pub update_policy: UpdatePolicy,
+    /// What instlist glob patterns do with the directories they match.
+    #[serde(default)]
+    pub glob_directories: GlobDirectories,
+}
+
+pub fn default_install_prefixes() -> Vec<String> {
+    ["~/.local/bin", "~/.local/share", "~/.local/lib"]
+        .map(String::from)
+        .to_vec()
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -17,6 +49,18 @@ pub struct RepositoryConfig {
     pub enabled: bool,
     pub priority: u32,
     pub authentication: Option<RepositoryAuth>,
+    /// Fallback URLs serving the same repository, tried in order when `url`
+    /// is unreachable.
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    /// Packages from a trusted repository may place files outside the
+    /// install prefixes.
+    #[serde(default)]
+    pub trusted: bool,
+    /// Whether packages from the repository may be installed without
+    /// confirmation.
+    #[serde(default)]
+    pub trust_level: TrustLevel,
 }
 
 impl RepositoryConfig {
@@ -28,6 +72,9 @@ impl RepositoryConfig {
             enabled: true,
             priority: 100,
             authentication: None,
+            mirrors: Vec::new(),
+            trusted: false,
+            trust_level: TrustLevel::default(),
         }
     }
 
@@ -41,6 +88,21 @@ impl RepositoryConfig {
         self
     }
 
+    pub fn with_mirror<S: Into<String>>(mut self, url: S) -> Self {
+        self.mirrors.push(url.into());
+        self
+    }
+
+    pub fn trusted(mut self) -> Self {
+        self.trusted = true;
+        self
+    }
+
+    pub fn with_trust_level(mut self, trust_level: TrustLevel) -> Self {
+        self.trust_level = trust_level;
+        self
+    }
+
     pub fn disabled(mut self) -> Self {
         self.enabled = false;
         self
@@ -96,6 +158,119 @@ impl Default for RepositoryType {
     }
 }
 
+/// How far packages of a repository are trusted, from least to most.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrustLevel {
+    #[serde(rename = "untrusted")]
+    Untrusted,
+    #[default]
+    #[serde(rename = "normal")]
+    Normal,
+    #[serde(rename = "trusted")]
+    Trusted,
+}
+
+impl fmt::Display for TrustLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Untrusted => write!(f, "untrusted"),
+            Self::Normal => write!(f, "normal"),
+            Self::Trusted => write!(f, "trusted"),
+        }
+    }
+}
+
+/// What an instlist glob pattern does with a directory it matches.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GlobDirectories {
+    /// Places every file below the directory, one entry each.
+    #[default]
+    #[serde(rename = "recurse")]
+    Recurse,
+    /// Links the directory itself as a single entry.
+    #[serde(rename = "link")]
+    Link,
+    /// Leaves the directory out.
+    #[serde(rename = "skip")]
+    Skip,
+}
+
+/// Which version bumps `check_updates` and `update` may propose, relative
+/// to the installed version.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum UpdatePolicy {
+    /// Same major and minor version, e.g. 1.4.2 to 1.4.x.
+    #[serde(rename = "patch")]
+    Patch,
+    /// Same major version, e.g. 1.4.2 to 1.x.y.
+    #[serde(rename = "minor")]
+    Minor,
+    /// Any newer version.
+    #[default]
+    #[serde(rename = "major", alias = "latest")]
+    Major,
+}
+
+impl UpdatePolicy {
+    /// Whether the policy allows moving from `current` to `candidate`.
+    ///
+    /// Only newer versions are allowed. Pre-releases are only allowed when
+    /// `current` is itself a pre-release of the same major, minor and patch.
+    pub fn allows(&self, current: &Version, candidate: &Version) -> bool {
+        if candidate <= current {
+            return false;
+        }
+        let same_triple = (candidate.major, candidate.minor, candidate.patch)
+            == (current.major, current.minor, current.patch);
+        if !candidate.pre.is_empty() && (current.pre.is_empty() || !same_triple) {
+            return false;
+        }
+        match self {
+            Self::Patch => candidate.major == current.major && candidate.minor == current.minor,
+            Self::Minor => candidate.major == current.major,
+            Self::Major => true,
+        }
+    }
+
+    /// The newest of `candidates` the policy allows moving to from `current`.
+    pub fn newest<'a>(
+        &self,
+        current: &Version,
+        candidates: impl IntoIterator<Item = &'a Version>,
+    ) -> Option<&'a Version> {
+        candidates
+            .into_iter()
+            .filter(|candidate| self.allows(current, candidate))
+            .max()
+    }
+}
+
+impl fmt::Display for UpdatePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Patch => write!(f, "patch"),
+            Self::Minor => write!(f, "minor"),
+            Self::Major => write!(f, "major"),
+        }
+    }
+}
+
+impl TryFrom<&str> for UpdatePolicy {
+    type Error = UhpmError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "patch" => Ok(Self::Patch),
+            "minor" => Ok(Self::Minor),
+            "major" | "latest" => Ok(Self::Major),
+            _ => Err(UhpmError::validation(format!(
+                "Invalid update policy: '{}'. Use 'patch', 'minor' or 'major'",
+                value
+            ))),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct RepositoryAuth {
     pub username: Option<String>,
@@ -129,6 +304,8 @@ pub enum InstallMode {
     Direct,
     #[serde(rename = "auto")]
     Auto,
+    #[serde(rename = "hardlink")]
+    Hardlink,
 }
 
 impl InstallMode {
@@ -144,10 +321,14 @@ impl InstallMode {
         matches!(self, Self::Auto)
     }
 
+    pub fn is_hardlink(&self) -> bool {
+        matches!(self, Self::Hardlink)
+    }
+
     pub fn should_use_symlinks(&self, platform_supports_symlinks: bool) -> bool {
         match self {
             Self::Symlink => true,
-            Self::Direct => false,
+            Self::Direct | Self::Hardlink => false,
             Self::Auto => platform_supports_symlinks,
         }
     }
@@ -165,6 +346,7 @@ impl fmt::Display for InstallMode {
             Self::Symlink => write!(f, "symlink"),
             Self::Direct => write!(f, "direct"),
             Self::Auto => write!(f, "auto"),
+            Self::Hardlink => write!(f, "hardlink"),
         }
     }
 }
@@ -175,10 +357,11 @@ impl TryFrom<&str> for InstallMode {
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value.to_lowercase().as_str() {
             "symlink" | "symbolic" | "link" => Ok(Self::Symlink),
-            "direct" | "copy" | "hard" => Ok(Self::Direct),
+            "direct" | "copy" => Ok(Self::Direct),
+            "hardlink" | "hard-link" | "hard" => Ok(Self::Hardlink),
             "auto" | "automatic" => Ok(Self::Auto),
             _ => Err(UhpmError::validation(format!(
-                "Invalid install mode: '{}'. Use 'symlink', 'direct', or 'auto'",
+                "Invalid install mode: '{}'. Use 'symlink', 'direct', 'hardlink', or 'auto'",
                 value
             ))),
         }
@@ -200,6 +383,7 @@ mod tests {
         assert_eq!(repo.enabled, true);
         assert_eq!(repo.priority, 100);
         assert_eq!(repo.authentication, None);
+        assert!(repo.mirrors.is_empty());
     }
 
     #[test]
@@ -208,11 +392,13 @@ mod tests {
         let repo = RepositoryConfig::new("test-repo", "file:///local/path", RepositoryType::Source)
             .with_priority(50)
             .with_auth(auth.clone())
+            .with_mirror("https://mirror.example.com")
             .disabled();
 
         assert_eq!(repo.priority, 50);
         assert_eq!(repo.authentication, Some(auth));
         assert_eq!(repo.enabled, false);
+        assert_eq!(repo.mirrors, ["https://mirror.example.com"]);
     }
 
     #[test]
@@ -270,6 +456,7 @@ mod tests {
         assert_eq!(InstallMode::Symlink.to_string(), "symlink");
         assert_eq!(InstallMode::Direct.to_string(), "direct");
         assert_eq!(InstallMode::Auto.to_string(), "auto");
+        assert_eq!(InstallMode::Hardlink.to_string(), "hardlink");
     }
 
     #[test]
@@ -302,7 +489,20 @@ mod tests {
             InstallMode::Direct
         );
         assert_eq!(InstallMode::try_from("copy").unwrap(), InstallMode::Direct);
-        assert_eq!(InstallMode::try_from("hard").unwrap(), InstallMode::Direct);
+
+        // "hard" used to mean a copy; it now selects hard links.
+        assert_eq!(
+            InstallMode::try_from("hardlink").unwrap(),
+            InstallMode::Hardlink
+        );
+        assert_eq!(
+            InstallMode::try_from("hard-link").unwrap(),
+            InstallMode::Hardlink
+        );
+        assert_eq!(
+            InstallMode::try_from("hard").unwrap(),
+            InstallMode::Hardlink
+        );
 
         assert_eq!(InstallMode::try_from("auto").unwrap(), InstallMode::Auto);
         assert_eq!(InstallMode::try_from("AUTO").unwrap(), InstallMode::Auto);
@@ -387,6 +587,16 @@ mod tests {
                     .with_priority(200)
                     .disabled(),
             ],
+            max_cache_size: Some(512 * 1024 * 1024),
+            install_prefixes: default_install_prefixes(),
+            default_prefix: Some("~/.local".to_string()),
+            downloads: DownloadOptions {
+                max_retries: 5,
+                resume: false,
+            },
+            trust_threshold: TrustLevel::Trusted,
+            update_policy: UpdatePolicy::Patch,
+            glob_directories: GlobDirectories::Link,
         };
 
         // Test that serialization works without panicking
@@ -401,6 +611,59 @@ mod tests {
             config.default_install_mode
         );
         assert_eq!(deserialized.repositories.len(), config.repositories.len());
+        assert_eq!(deserialized.install_prefixes, config.install_prefixes);
+        assert_eq!(deserialized.downloads, config.downloads);
+        assert_eq!(deserialized.trust_threshold, config.trust_threshold);
+        assert_eq!(deserialized.update_policy, config.update_policy);
+        assert_eq!(deserialized.glob_directories, config.glob_directories);
+    }
+
+    #[test]
+    fn test_update_policy_picks_newest_allowed_version() {
+        let available: Vec<Version> = [
+            "1.4.1",
+            "1.4.3",
+            "1.4.10",
+            "1.5.0-beta.1",
+            "1.5.0",
+            "1.9.2",
+            "2.0.0-rc.1",
+            "2.0.0",
+            "2.1.0",
+        ]
+        .iter()
+        .map(|version| Version::parse(version).unwrap())
+        .collect();
+        let cases = [
+            ("1.4.2", UpdatePolicy::Patch, Some("1.4.10")),
+            ("1.4.2", UpdatePolicy::Minor, Some("1.9.2")),
+            ("1.4.2", UpdatePolicy::Major, Some("2.1.0")),
+            ("1.4.10", UpdatePolicy::Patch, None),
+            ("1.9.2", UpdatePolicy::Minor, None),
+            ("2.1.0", UpdatePolicy::Major, None),
+            ("1.5.0-alpha.1", UpdatePolicy::Patch, Some("1.5.0")),
+            ("2.0.0-beta.2", UpdatePolicy::Patch, Some("2.0.0")),
+            ("1.4.3-rc.1", UpdatePolicy::Patch, Some("1.4.10")),
+        ];
+
+        for (installed, policy, expected) in cases {
+            let installed = Version::parse(installed).unwrap();
+            let newest = policy.newest(&installed, &available);
+            assert_eq!(
+                newest.map(ToString::to_string).as_deref(),
+                expected,
+                "{} with {} policy",
+                installed,
+                policy
+            );
+        }
+
+        let beta = Version::parse("1.5.0-beta.1").unwrap();
+        let alpha = Version::parse("1.5.0-alpha.1").unwrap();
+        let rc = Version::parse("2.0.0-rc.1").unwrap();
+        assert!(UpdatePolicy::Patch.allows(&alpha, &beta));
+        assert!(!UpdatePolicy::Major.allows(&Version::new(1, 9, 2), &rc));
+        assert!(!UpdatePolicy::Major.allows(&alpha, &rc));
     }
 
     #[test]
@@ -1,6 +1,7 @@
-use crate::{Package, PackageReference};
-use semver::VersionReq;
+use crate::{Package, PackageReference, UhpmError};
+use semver::{Version, VersionReq};
 use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::hash::{Hash, Hasher};
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -40,7 +41,7 @@ pub struct ResolutionResult {
     pub conflicts: Vec<DependencyConflict>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct DependencyConflict {
     pub package: String,
 
@@ -51,10 +52,148 @@ pub struct DependencyConflict {
     pub message: String,
 }
 
+/// Why a dependency couldn't be resolved: the requirements leading to it
+/// from the requested package, and what was wrong with every version the
+/// repository lists.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolutionFailure {
+    /// Package no version could be picked for.
+    pub package: String,
+    /// The requirement no version satisfied.
+    pub requirement: VersionReq,
+    /// Requirements from the requested package down to the failing one,
+    /// outermost first. Empty until a resolver walking the dependency graph
+    /// fills it in.
+    pub chain: Vec<RequirementEdge>,
+    /// Every version the repository lists, with the reason it was rejected.
+    pub candidates: Vec<RejectedCandidate>,
+}
+
+/// `package` requiring `dependency` at `requirement`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequirementEdge {
+    pub package: PackageReference,
+    pub dependency: String,
+    pub requirement: VersionReq,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectedCandidate {
+    pub version: String,
+    pub reason: String,
+}
+
+impl ResolutionFailure {
+    /// No version of `dependency` could be picked from `available`, of which
+    /// `yanked` were withdrawn by the publisher.
+    pub fn new(dependency: &Dependency, available: &[String], yanked: &[String]) -> Self {
+        let requirement = &dependency.constraint.requirement;
+        let candidates = available
+            .iter()
+            .map(|version| {
+                let reason = match Version::parse(version) {
+                    Err(_) => "not a semantic version".to_string(),
+                    Ok(parsed) if !requirement.matches(&parsed) => {
+                        format!("excluded by {}", requirement)
+                    }
+                    Ok(_) if yanked.contains(version) => "yanked".to_string(),
+                    Ok(_) => "could not be loaded".to_string(),
+                };
+                RejectedCandidate {
+                    version: version.clone(),
+                    reason,
+                }
+            })
+            .collect();
+
+        Self {
+            package: dependency.name.clone(),
+            requirement: requirement.clone(),
+            chain: Vec::new(),
+            candidates,
+        }
+    }
+}
+
+impl fmt::Display for ResolutionFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Cannot resolve dependency: {} {}",
+            self.package, self.requirement
+        )?;
+        if !self.chain.is_empty() {
+            write!(f, "\n  required through:")?;
+            for edge in &self.chain {
+                write!(
+                    f,
+                    "\n    {} requires {} {}",
+                    edge.package, edge.dependency, edge.requirement
+                )?;
+            }
+        }
+        if self.candidates.is_empty() {
+            write!(f, "\n  no versions of {} are available", self.package)
+        } else {
+            write!(f, "\n  available versions of {}:", self.package)?;
+            for candidate in &self.candidates {
+                write!(f, "\n    {}: {}", candidate.version, candidate.reason)?;
+            }
+            Ok(())
+        }
+    }
+}
+
 impl Dependency {
     pub fn matches_version(&self, version: &semver::Version) -> bool {
         self.constraint.requirement.matches(version)
     }
+
+    /// Whether the requirement is `=` on exactly `version`, the only way to
+    /// ask for a yanked version.
+    pub fn pins_exactly(&self, version: &semver::Version) -> bool {
+        match self.constraint.requirement.comparators.as_slice() {
+            [comparator] => {
+                comparator.op == semver::Op::Exact
+                    && comparator.major == version.major
+                    && comparator.minor == Some(version.minor)
+                    && comparator.patch == Some(version.patch)
+                    && comparator.pre == version.pre
+            }
+            _ => false,
+        }
+    }
+}
+
+impl VersionConstraint {
+    /// Returns the `candidates` satisfying every one of `requirements`,
+    /// highest first.
+    ///
+    /// Fails with `DependencyConflict` when the requirements on `package_name`
+    /// have no candidate in common.
+    pub fn intersect(
+        package_name: &str,
+        requirements: &[VersionReq],
+        candidates: &[Version],
+    ) -> Result<Vec<Version>, UhpmError> {
+        let mut satisfying = candidates
+            .iter()
+            .filter(|version| requirements.iter().all(|req| req.matches(version)))
+            .cloned()
+            .collect::<Vec<_>>();
+        if satisfying.is_empty() {
+            let join = |items: Vec<String>| items.join(", ");
+            return Err(UhpmError::DependencyConflict(format!(
+                "no version of {} satisfies {} (available: {})",
+                package_name,
+                join(requirements.iter().map(ToString::to_string).collect()),
+                join(candidates.iter().map(ToString::to_string).collect()),
+            )));
+        }
+        satisfying.sort_by(|a, b| b.cmp(a));
+        satisfying.dedup();
+        Ok(satisfying)
+    }
 }
 
 impl Hash for Dependency {
@@ -66,3 +205,88 @@ impl Hash for Dependency {
         self.features.hash(state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(versions: &[&str]) -> Vec<Version> {
+        versions
+            .iter()
+            .map(|v| Version::parse(v).unwrap())
+            .collect()
+    }
+
+    fn requirements(requirements: &[&str]) -> Vec<VersionReq> {
+        requirements
+            .iter()
+            .map(|r| VersionReq::parse(r).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_intersect_overlapping_ranges() {
+        let candidates = versions(&["1.0.0", "1.2.0", "1.4.0", "2.0.0"]);
+
+        let satisfying =
+            VersionConstraint::intersect("lib", &requirements(&[">=1.1", "<1.3"]), &candidates)
+                .unwrap();
+        assert_eq!(satisfying, versions(&["1.2.0"]));
+
+        let satisfying =
+            VersionConstraint::intersect("lib", &requirements(&["^1", ">=1.2"]), &candidates)
+                .unwrap();
+        assert_eq!(satisfying, versions(&["1.4.0", "1.2.0"]));
+    }
+
+    #[test]
+    fn test_resolution_failure_lists_every_rejected_candidate() {
+        let dependency = Dependency {
+            name: "foo".to_string(),
+            constraint: VersionConstraint {
+                requirement: VersionReq::parse("^2").unwrap(),
+            },
+            kind: DependencyKind::Required,
+            provides: None,
+            features: vec![],
+        };
+        let available = ["1.0.0", "2.1.0", "nightly"].map(String::from);
+        let mut failure = ResolutionFailure::new(&dependency, &available, &["2.1.0".to_string()]);
+        failure.chain.push(RequirementEdge {
+            package: PackageReference::new("app".to_string(), Version::new(1, 0, 0)),
+            dependency: "foo".to_string(),
+            requirement: VersionReq::parse("^2").unwrap(),
+        });
+
+        assert_eq!(
+            failure.to_string(),
+            "Cannot resolve dependency: foo ^2\n  \
+             required through:\n    app@1.0.0 requires foo ^2\n  \
+             available versions of foo:\n    \
+             1.0.0: excluded by ^2\n    \
+             2.1.0: yanked\n    \
+             nightly: not a semantic version"
+        );
+    }
+
+    #[test]
+    fn test_intersect_disjoint_ranges() {
+        let candidates = versions(&["1.4.0", "2.1.0"]);
+
+        let err = VersionConstraint::intersect("lib", &requirements(&["^1", "^2"]), &candidates)
+            .unwrap_err();
+        assert!(matches!(err, UhpmError::DependencyConflict(_)), "{}", err);
+        assert!(
+            err.to_string()
+                .contains("no version of lib satisfies ^1, ^2 (available: 1.4.0, 2.1.0)"),
+            "{}",
+            err
+        );
+
+        // Overlapping ranges without a published version in common conflict too.
+        let err =
+            VersionConstraint::intersect("lib", &requirements(&[">=1.1", "<1.3"]), &candidates)
+                .unwrap_err();
+        assert!(matches!(err, UhpmError::DependencyConflict(_)), "{}", err);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::EventEnvelope;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What a channel subscription does with an event when it is full.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OverflowPolicy {
+    /// Discards the oldest undelivered event to make room.
+    DropOldest,
+    /// Discards the new event.
+    DropNewest,
+    /// Makes the publisher wait until the subscriber has caught up.
+    Block,
+}
+
+/// Receiving end of a channel subscription, see
+/// [`EventPublisher::subscribe_channel`](crate::ports::EventPublisher::subscribe_channel).
+///
+/// Dropping it ends the subscription.
+pub struct EventReceiver {
+    channel: Arc<Channel>,
+}
+
+/// Publisher side of a channel subscription.
+pub(crate) struct EventSender {
+    channel: Arc<Channel>,
+}
+
+struct Channel {
+    state: Mutex<State>,
+    capacity: usize,
+    overflow: OverflowPolicy,
+    /// Signalled when an event is queued or the sender goes away.
+    readable: Notify,
+    /// Signalled when an event is taken or the receiver goes away.
+    writable: Notify,
+}
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<EventEnvelope>,
+    dropped: u64,
+    sender_closed: bool,
+    receiver_closed: bool,
+}
+
+/// Creates a channel holding at most `capacity` undelivered events.
+pub(crate) fn event_channel(
+    capacity: usize,
+    overflow: OverflowPolicy,
+) -> (EventSender, EventReceiver) {
+    let channel = Arc::new(Channel {
+        state: Mutex::default(),
+        capacity: capacity.max(1),
+        overflow,
+        readable: Notify::new(),
+        writable: Notify::new(),
+    });
+    (
+        EventSender {
+            channel: Arc::clone(&channel),
+        },
+        EventReceiver { channel },
+    )
+}
+
+impl EventReceiver {
+    /// Waits for the next event, `None` once the subscription has ended and
+    /// every queued event was received.
+    pub async fn recv(&mut self) -> Option<EventEnvelope> {
+        loop {
+            let readable = self.channel.readable.notified();
+            {
+                let mut state = self.channel.state.lock().unwrap();
+                if let Some(envelope) = state.queue.pop_front() {
+                    self.channel.writable.notify_waiters();
+                    return Some(envelope);
+                }
+                if state.sender_closed {
+                    return None;
+                }
+            }
+            readable.await;
+        }
+    }
+
+    /// Takes the next queued event without waiting.
+    pub fn try_recv(&mut self) -> Option<EventEnvelope> {
+        let envelope = self.channel.state.lock().unwrap().queue.pop_front();
+        if envelope.is_some() {
+            self.channel.writable.notify_waiters();
+        }
+        envelope
+    }
+
+    /// How many events the overflow policy discarded so far.
+    pub fn dropped(&self) -> u64 {
+        self.channel.state.lock().unwrap().dropped
+    }
+}
+
+impl Drop for EventReceiver {
+    fn drop(&mut self) {
+        self.channel.state.lock().unwrap().receiver_closed = true;
+        self.channel.writable.notify_waiters();
+    }
+}
+
+impl EventSender {
+    /// Whether the receiver was dropped.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.channel.state.lock().unwrap().receiver_closed
+    }
+
+    /// Queues `envelope` according to the overflow policy, waiting for room
+    /// with [`OverflowPolicy::Block`]. Events sent after the receiver was
+    /// dropped are discarded.
+    pub(crate) async fn send(&self, envelope: EventEnvelope) {
+        loop {
+            let writable = self.channel.writable.notified();
+            if self.try_send(&envelope) {
+                return;
+            }
+            writable.await;
+        }
+    }
+
+    /// Queues `envelope` unless the policy is to wait for room, returning
+    /// false if it has to.
+    fn try_send(&self, envelope: &EventEnvelope) -> bool {
+        let mut state = self.channel.state.lock().unwrap();
+        if state.receiver_closed {
+            return true;
+        }
+        if state.queue.len() >= self.channel.capacity {
+            match self.channel.overflow {
+                OverflowPolicy::DropNewest => {
+                    state.dropped += 1;
+                    return true;
+                }
+                OverflowPolicy::DropOldest => {
+                    state.queue.pop_front();
+                    state.dropped += 1;
+                }
+                OverflowPolicy::Block => return false,
+            }
+        }
+        state.queue.push_back(envelope.clone());
+        drop(state);
+        self.channel.readable.notify_waiters();
+        true
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        self.channel.state.lock().unwrap().sender_closed = true;
+        self.channel.readable.notify_waiters();
+    }
+}
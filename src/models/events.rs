@@ -1,6 +1,13 @@
-use crate::{Package, PackageReference};
+use crate::{DependencyConflict, Package, PackageReference, UhpmError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Something that happened during a package operation.
+///
+/// Serialized as an object whose `type` field names the variant, e.g.
+/// `{"type":"download_started","package_ref":{...},"size":null}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum PackageEvent {
     InstallationStarted {
         package_ref: PackageReference,
@@ -23,6 +30,11 @@ pub enum PackageEvent {
         package_ref: PackageReference,
     },
 
+    RemovalFailed {
+        package_ref: PackageReference,
+        error: String,
+    },
+
     UpdateStarted {
         package_ref: PackageReference,
     },
@@ -31,6 +43,11 @@ pub enum PackageEvent {
         package: Package,
     },
 
+    UpdateFailed {
+        package_ref: PackageReference,
+        error: String,
+    },
+
     DownloadStarted {
         package_ref: PackageReference,
         size: Option<u64>,
@@ -44,10 +61,227 @@ pub enum PackageEvent {
 
     DownloadCompleted {
         package_ref: PackageReference,
+        /// URL the archive was served from, when known.
+        source_url: Option<String>,
+    },
+
+    DownloadFailed {
+        package_ref: PackageReference,
+        error: String,
+    },
+
+    ExtractionStarted {
+        package_ref: PackageReference,
+    },
+
+    ExtractionProgress {
+        package_ref: PackageReference,
+        files_done: usize,
+        files_total: usize,
+    },
+
+    ExtractionCompleted {
+        package_ref: PackageReference,
     },
 
     DependencyResolved {
         dependency: String,
         package: Package,
     },
+
+    ResolutionFailed {
+        package_ref: PackageReference,
+        error: String,
+        /// Dependencies the selected versions don't satisfy, empty when
+        /// resolution failed for another reason.
+        conflicts: Vec<DependencyConflict>,
+    },
+}
+
+impl PackageEvent {
+    /// Renders the event as a single line of JSON.
+    pub fn to_json(&self) -> Result<String, UhpmError> {
+        serde_json::to_string(self).map_err(|e| UhpmError::SerializationError(e.to_string()))
+    }
+
+    /// Name of the package the event is about.
+    pub fn package_name(&self) -> &str {
+        match self {
+            PackageEvent::InstallationStarted { package_ref }
+            | PackageEvent::InstallationFailed { package_ref, .. }
+            | PackageEvent::RemoveStarted { package_ref }
+            | PackageEvent::RemoveCompleted { package_ref }
+            | PackageEvent::RemovalFailed { package_ref, .. }
+            | PackageEvent::UpdateStarted { package_ref }
+            | PackageEvent::UpdateFailed { package_ref, .. }
+            | PackageEvent::DownloadStarted { package_ref, .. }
+            | PackageEvent::DownloadProgress { package_ref, .. }
+            | PackageEvent::DownloadCompleted { package_ref, .. }
+            | PackageEvent::DownloadFailed { package_ref, .. }
+            | PackageEvent::ExtractionStarted { package_ref }
+            | PackageEvent::ExtractionProgress { package_ref, .. }
+            | PackageEvent::ExtractionCompleted { package_ref }
+            | PackageEvent::ResolutionFailed { package_ref, .. } => &package_ref.name,
+            PackageEvent::InstallationCompleted { package }
+            | PackageEvent::UpdateCompleted { package }
+            | PackageEvent::DependencyResolved { package, .. } => package.name(),
+        }
+    }
+}
+
+/// A published event, stamped by the publisher with its position and time.
+///
+/// Sequence numbers are assigned by a single publisher, start at 1 and grow
+/// by one per event, so subscribers can order events from concurrent
+/// operations and notice ones they missed.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EventEnvelope {
+    pub sequence: u64,
+    pub timestamp: DateTime<Utc>,
+    pub event: PackageEvent,
+}
+
+impl EventEnvelope {
+    pub fn new(sequence: u64, timestamp: DateTime<Utc>, event: PackageEvent) -> Self {
+        Self {
+            sequence,
+            timestamp,
+            event,
+        }
+    }
+
+    /// Checks the envelope against the `get_event_history` filters.
+    pub fn matches(&self, since: Option<DateTime<Utc>>, package_name: Option<&str>) -> bool {
+        since.is_none_or(|since| self.timestamp >= since)
+            && package_name.is_none_or(|name| self.event.package_name() == name)
+    }
+
+    /// Renders the envelope as a single line of JSON.
+    pub fn to_json(&self) -> Result<String, UhpmError> {
+        serde_json::to_string(self).map_err(|e| UhpmError::SerializationError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PackageSource, Target, factories::PackageFactory};
+    use semver::Version;
+
+    fn all_variants() -> Vec<PackageEvent> {
+        let package_ref = PackageReference::new("tool".to_string(), Version::new(1, 2, 3));
+        let package = PackageFactory::create(
+            "tool".to_string(),
+            Version::new(1, 2, 3),
+            "John Doe".to_string(),
+            PackageSource::Http {
+                url: "https://repo.example.com/packages/tool-1.2.3.uhp".to_string(),
+            },
+            Target::current(),
+            None,
+            vec![],
+        )
+        .unwrap();
+        let error = "boom".to_string();
+
+        vec![
+            PackageEvent::InstallationStarted {
+                package_ref: package_ref.clone(),
+            },
+            PackageEvent::InstallationCompleted {
+                package: package.clone(),
+            },
+            PackageEvent::InstallationFailed {
+                package_ref: package_ref.clone(),
+                error: error.clone(),
+            },
+            PackageEvent::RemoveStarted {
+                package_ref: package_ref.clone(),
+            },
+            PackageEvent::RemoveCompleted {
+                package_ref: package_ref.clone(),
+            },
+            PackageEvent::RemovalFailed {
+                package_ref: package_ref.clone(),
+                error: error.clone(),
+            },
+            PackageEvent::UpdateStarted {
+                package_ref: package_ref.clone(),
+            },
+            PackageEvent::UpdateCompleted {
+                package: package.clone(),
+            },
+            PackageEvent::UpdateFailed {
+                package_ref: package_ref.clone(),
+                error: error.clone(),
+            },
+            PackageEvent::DownloadStarted {
+                package_ref: package_ref.clone(),
+                size: Some(42),
+            },
+            PackageEvent::DownloadProgress {
+                package_ref: package_ref.clone(),
+                downloaded: 21,
+                total: 42,
+            },
+            PackageEvent::DownloadCompleted {
+                package_ref: package_ref.clone(),
+                source_url: Some("https://mirror.example.com/tool-1.2.3.uhp".to_string()),
+            },
+            PackageEvent::DownloadFailed {
+                package_ref: package_ref.clone(),
+                error: error.clone(),
+            },
+            PackageEvent::ExtractionStarted {
+                package_ref: package_ref.clone(),
+            },
+            PackageEvent::ExtractionProgress {
+                package_ref: package_ref.clone(),
+                files_done: 1,
+                files_total: 2,
+            },
+            PackageEvent::ExtractionCompleted {
+                package_ref: package_ref.clone(),
+            },
+            PackageEvent::DependencyResolved {
+                dependency: "tool".to_string(),
+                package,
+            },
+            PackageEvent::ResolutionFailed {
+                package_ref,
+                error,
+                conflicts: vec![DependencyConflict {
+                    package: "lib".to_string(),
+                    required: "^1".to_string(),
+                    installed: "2.0.0".to_string(),
+                    message: "tool@1.2.3 requires lib ^1".to_string(),
+                }],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_every_variant_round_trips_through_json() {
+        for event in all_variants() {
+            let json = event.to_json().unwrap();
+            assert!(!json.contains('\n'));
+
+            let parsed: PackageEvent = serde_json::from_str(&json).unwrap();
+            assert_eq!(parsed, event);
+            assert_eq!(parsed.to_json().unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn test_json_is_tagged_with_the_variant() {
+        let event = PackageEvent::DownloadStarted {
+            package_ref: PackageReference::new("tool".to_string(), Version::new(1, 2, 3)),
+            size: None,
+        };
+
+        assert_eq!(
+            event.to_json().unwrap(),
+            r#"{"type":"download_started","package_ref":{"name":"tool","version":"1.2.3"},"size":null}"#
+        );
+    }
 }
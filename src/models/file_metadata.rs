@@ -1,3 +1,4 @@
+use crate::{clock::SystemClock, ports::Clock};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -16,11 +17,21 @@ pub struct FileMetadata {
     pub created_at: DateTime<Utc>,
     pub modified_at: DateTime<Utc>,
     pub file_type: FileType,
+    /// Number of hard links pointing at the file's data.
+    #[serde(default = "default_hard_links")]
+    pub hard_links: u64,
+    /// Full permission bits reported by the file system, when known.
+    #[serde(default)]
+    pub mode: Option<u32>,
+}
+
+fn default_hard_links() -> u64 {
+    1
 }
 
 impl FileMetadata {
     pub fn new(path: PathBuf, size: u64) -> Self {
-        let now = Utc::now();
+        let now = SystemClock.now();
         Self {
             path,
             size,
@@ -29,6 +40,8 @@ impl FileMetadata {
             created_at: now,
             modified_at: now,
             file_type: FileType::Regular,
+            hard_links: 1,
+            mode: None,
         }
     }
 
@@ -45,15 +58,37 @@ impl FileMetadata {
         self
     }
 
+    /// Sets the full permission bits, deriving the owner permissions from them.
+    pub fn with_mode(mut self, mode: u32) -> Self {
+        self.permissions = FilePermissions::from_octal(mode);
+        self.mode = Some(mode);
+        self
+    }
+
+    /// Permission bits, falling back to a mode derived from `permissions`.
+    pub fn mode(&self) -> u32 {
+        self.mode.unwrap_or_else(|| self.permissions.to_mode())
+    }
+
     pub fn with_file_type(mut self, file_type: FileType) -> Self {
         self.file_type = file_type;
         self
     }
 
+    pub fn with_hard_links(mut self, hard_links: u64) -> Self {
+        self.hard_links = hard_links;
+        self
+    }
+
     pub fn is_executable(&self) -> bool {
         self.permissions.is_executable()
     }
 
+    /// Checks if the file's data is shared with another path.
+    pub fn is_hard_linked(&self) -> bool {
+        self.hard_links > 1
+    }
+
     pub fn is_symlink(&self) -> bool {
         matches!(self.file_type, FileType::Symlink)
     }
@@ -64,17 +99,7 @@ impl FileMetadata {
 
     pub fn verify_checksum(&self, data: &[u8]) -> Result<bool, crate::UhpmError> {
         if let Some(checksum) = &self.checksum {
-            let actual_hash = match checksum.algorithm.as_str() {
-                "sha256" => sha256_hash(data),
-                "sha1" => sha1_hash(data),
-                "md5" => md5_hash(data),
-                algo => {
-                    return Err(crate::UhpmError::ValidationError(format!(
-                        "Unsupported checksum algorithm: {}",
-                        algo
-                    )));
-                }
-            };
+            let actual_hash = compute_checksum(&checksum.algorithm, data)?;
             Ok(actual_hash == checksum.hash)
         } else {
             Ok(true)
@@ -130,10 +155,34 @@ impl FilePermissions {
         }
     }
 
+    pub fn from_octal(mode: u32) -> Self {
+        Self {
+            read: mode & 0o400 != 0,
+            write: mode & 0o200 != 0,
+            execute: mode & 0o100 != 0,
+        }
+    }
+
     pub fn is_executable(&self) -> bool {
         self.execute
     }
 
+    /// Expands the owner permissions into a conventional mode such as
+    /// `0o644` or `0o755`.
+    pub fn to_mode(&self) -> u32 {
+        let mut mode = 0;
+        if self.read {
+            mode |= 0o444;
+        }
+        if self.write {
+            mode |= 0o200;
+        }
+        if self.execute {
+            mode |= 0o111;
+        }
+        mode
+    }
+
     pub fn octal(&self) -> u32 {
         let mut result = 0;
         if self.read {
@@ -172,6 +221,36 @@ impl fmt::Display for FileType {
     }
 }
 
+impl TryFrom<&str> for FileType {
+    type Error = crate::UhpmError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "regular" => Ok(Self::Regular),
+            "directory" => Ok(Self::Directory),
+            "symlink" => Ok(Self::Symlink),
+            "executable" => Ok(Self::Executable),
+            _ => Err(crate::UhpmError::validation(format!(
+                "Invalid file type: '{}'",
+                value
+            ))),
+        }
+    }
+}
+
+/// Computes the hex digest of `data` using `sha256`, `sha1` or `md5`.
+pub fn compute_checksum(algorithm: &str, data: &[u8]) -> Result<String, crate::UhpmError> {
+    match algorithm {
+        "sha256" => Ok(sha256_hash(data)),
+        "sha1" => Ok(sha1_hash(data)),
+        "md5" => Ok(md5_hash(data)),
+        algo => Err(crate::UhpmError::ValidationError(format!(
+            "Unsupported checksum algorithm: {}",
+            algo
+        ))),
+    }
+}
+
 fn sha256_hash(data: &[u8]) -> String {
     use sha2::Sha256;
     let mut hasher = Sha256::new();
@@ -20,4 +20,7 @@ pub enum FsError {
 
     #[error("extraction error: {0}")]
     ExtractionError(String),
+
+    #[error("cross-device link: {0}")]
+    CrossDevice(String),
 }
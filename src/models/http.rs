@@ -0,0 +1,67 @@
+use crate::UhpmError;
+use futures_util::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+
+/// What a `HEAD` request reported about a resource.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HttpHeadResult {
+    pub status: u16,
+    pub content_length: Option<u64>,
+    pub validators: CacheValidators,
+    /// Whether the server advertised `Accept-Ranges: bytes`.
+    pub accepts_ranges: bool,
+}
+
+impl HttpHeadResult {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// `ETag`/`Last-Modified` values used to revalidate a cached resource.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheValidators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+/// Outcome of a conditional `GET`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionalFetch {
+    /// The server answered `304 Not Modified`; the cached copy is still valid.
+    NotModified,
+    Modified {
+        data: Vec<u8>,
+        validators: CacheValidators,
+    },
+}
+
+/// Response to a `GET` that may have asked for a byte range.
+pub struct RangeResponse {
+    /// The server answered `206 Partial Content`, so `body` starts at the
+    /// requested offset. Otherwise it holds the whole resource.
+    pub partial: bool,
+    /// Size of the whole resource, when known.
+    pub total: Option<u64>,
+    pub body: BoxStream<'static, Result<Vec<u8>, UhpmError>>,
+}
+
+/// Retry and resume behaviour for package downloads.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct DownloadOptions {
+    /// Further attempts after a download fails with a network error.
+    pub max_retries: u32,
+    /// Continue an interrupted download where it stopped, when the server
+    /// accepts range requests.
+    pub resume: bool,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: 3,
+            resume: true,
+        }
+    }
+}
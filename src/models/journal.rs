@@ -0,0 +1,93 @@
+use crate::{OperationKind, PackageReference, UhpmError};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// Operations in progress, written before they touch the package store or
+/// any target and removed once they are done.
+///
+/// A journal left behind means the process died halfway through; see
+/// [`PackageManager::recover`](crate::application::package_manager::PackageManager::recover).
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Journal {
+    #[serde(default)]
+    pub entries: Vec<JournalEntry>,
+}
+
+/// What an operation did to one package so far.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub operation: OperationKind,
+    pub package: PackageReference,
+    pub started_at: DateTime<Utc>,
+    /// Directory the package archive is extracted into.
+    pub extracted: PathBuf,
+    /// Instlist targets the operation creates, journaled before any of them
+    /// is placed.
+    #[serde(default)]
+    pub placed: Vec<PathBuf>,
+}
+
+impl Journal {
+    pub fn from_toml(content: &str) -> Result<Self, UhpmError> {
+        toml::from_str(content).map_err(|e| UhpmError::DeserializationError(e.to_string()))
+    }
+
+    pub fn to_toml(&self) -> Result<String, UhpmError> {
+        toml::to_string(self).map_err(|e| UhpmError::SerializationError(e.to_string()))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The entry of `package`, if the journal has one.
+    pub fn entry_mut(&mut self, package: &PackageReference) -> Option<&mut JournalEntry> {
+        self.entries
+            .iter_mut()
+            .find(|entry| entry.package == *package)
+    }
+}
+
+impl JournalEntry {
+    pub fn new(
+        operation: OperationKind,
+        package: PackageReference,
+        extracted: PathBuf,
+        started_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            operation,
+            package,
+            started_at,
+            extracted,
+            placed: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use semver::Version;
+
+    #[test]
+    fn test_journal_round_trips_through_toml() {
+        let mut entry = JournalEntry::new(
+            OperationKind::Install,
+            PackageReference::new("tool".to_string(), Version::new(1, 0, 0)),
+            PathBuf::from("/uhpm/packages/tool@1.0.0"),
+            DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        );
+        entry
+            .placed
+            .push(PathBuf::from("/home/user/.local/bin/tool"));
+        let journal = Journal {
+            entries: vec![entry],
+        };
+
+        let written = journal.to_toml().unwrap();
+        assert_eq!(Journal::from_toml(&written).unwrap(), journal);
+        assert!(Journal::from_toml("").unwrap().is_empty());
+    }
+}
@@ -1,19 +1,31 @@
+pub mod cancellation;
 pub mod config;
 pub mod dependency;
+pub mod event_channel;
 pub mod events;
 pub mod file_metadata;
 pub mod file_system;
+pub mod http;
+pub mod journal;
 pub mod operations;
+pub mod package_spec;
 pub mod repository;
 pub mod symlink;
 pub mod target;
+pub mod target_policy;
 
+pub use cancellation::*;
 pub use config::*;
 pub use dependency::*;
+pub use event_channel::*;
 pub use events::*;
 pub use file_metadata::*;
 pub use file_system::*;
+pub use http::*;
+pub use journal::*;
 pub use operations::*;
+pub use package_spec::*;
 pub use repository::*;
 pub use symlink::*;
 pub use target::*;
+pub use target_policy::*;
@@ -1,23 +1,438 @@
+use chrono::{DateTime, Utc};
 use semver::Version;
+use serde::{Deserialize, Serialize};
 
-use crate::{PackageId, Target};
+use crate::{
+    CancellationToken, Checksum, DependencyConflict, Package, PackageId, PackageReference,
+    UhpmError, clock::SystemClock, ports::Clock,
+};
+use std::fmt;
 use std::path::PathBuf;
+use std::time::Duration;
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct InstallResult {
     pub package_id: PackageId,
     pub installed_files: Vec<PathBuf>,
     pub symlinks_created: usize,
+    pub warnings: Vec<String>,
+}
+
+/// Options tweaking how `install` behaves.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstallOptions {
+    /// Install packages built for another platform instead of failing.
+    pub allow_target_mismatch: bool,
+    /// Aborts the install when cancelled, rolling back what was placed.
+    pub cancellation: Option<CancellationToken>,
+    /// Install the newest version of every dependency even when an installed
+    /// version already satisfies it.
+    pub prefer_newest: bool,
+    /// Directory `${PREFIX}` in instlist targets stands for, instead of the
+    /// manager's default prefix.
+    pub prefix: Option<PathBuf>,
+    /// Plan from `plan_install` to carry out. The install fails instead of
+    /// installing anything else when resolving again gives other packages.
+    pub plan: Option<InstallPlan>,
+}
+
+impl InstallOptions {
+    pub fn allow_target_mismatch(mut self) -> Self {
+        self.allow_target_mismatch = true;
+        self
+    }
+
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    pub fn prefer_newest(mut self) -> Self {
+        self.prefer_newest = true;
+        self
+    }
+
+    pub fn with_prefix<P: Into<PathBuf>>(mut self, prefix: P) -> Self {
+        self.prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn with_plan(mut self, plan: InstallPlan) -> Self {
+        self.plan = Some(plan);
+        self
+    }
+}
+
+/// One package an install would place, as seen by `plan_install`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlannedPackage {
+    pub package_ref: PackageReference,
+    /// Configured repository the package comes from, when known.
+    pub repository: Option<String>,
+    pub checksum: Option<Checksum>,
+    /// Archive size in bytes, `None` when the repository doesn't tell.
+    pub download_size: Option<u64>,
+    /// Unpacked size in bytes, `None` when the repository doesn't tell.
+    pub installed_size: Option<u64>,
+    /// The archive is in the cache and won't be downloaded.
+    pub cached: bool,
+    /// This version is installed already.
+    pub installed: bool,
+}
+
+impl PlannedPackage {
+    /// Whether installing this package means downloading its archive.
+    pub fn needs_download(&self) -> bool {
+        !self.cached && !self.installed
+    }
+}
+
+/// What installing a package would do, worked out without changing anything.
+///
+/// `packages` lists what would be installed, dependencies first. Conflicts
+/// and blockers are what would make the real install fail; warnings don't.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstallPlan {
+    pub package_ref: PackageReference,
+    pub packages: Vec<PlannedPackage>,
+    pub conflicts: Vec<DependencyConflict>,
+    pub blockers: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl InstallPlan {
+    pub fn new(package_ref: PackageReference) -> Self {
+        Self {
+            package_ref,
+            packages: Vec::new(),
+            conflicts: Vec::new(),
+            blockers: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// True when the install would fail.
+    pub fn is_blocked(&self) -> bool {
+        !self.conflicts.is_empty() || !self.blockers.is_empty()
+    }
+
+    /// Bytes to download, counting only the archives with a known size.
+    pub fn download_size(&self) -> u64 {
+        self.packages
+            .iter()
+            .filter(|package| package.needs_download())
+            .filter_map(|package| package.download_size)
+            .sum()
+    }
+
+    /// Number of archives to download.
+    pub fn downloads(&self) -> usize {
+        self.packages
+            .iter()
+            .filter(|package| package.needs_download())
+            .count()
+    }
+
+    /// Estimated disk use of the packages not installed yet. The archive
+    /// size stands in for packages without a published unpacked size.
+    pub fn installed_size(&self) -> u64 {
+        self.packages
+            .iter()
+            .filter(|package| !package.installed)
+            .filter_map(|package| package.installed_size.or(package.download_size))
+            .sum()
+    }
+
+    /// Fails unless `packages`, resolved for `package_ref`, are exactly the
+    /// ones planned, same versions and checksums in the same order.
+    pub fn check_matches(
+        &self,
+        package_ref: &PackageReference,
+        packages: &[Package],
+    ) -> Result<(), UhpmError> {
+        if *package_ref != self.package_ref {
+            return Err(UhpmError::PlanOutdated(format!(
+                "planned for {}, not {}",
+                self.package_ref, package_ref
+            )));
+        }
+        if packages.len() != self.packages.len() {
+            return Err(UhpmError::PlanOutdated(format!(
+                "{} packages planned, {} resolved now",
+                self.packages.len(),
+                packages.len()
+            )));
+        }
+        for (planned, package) in self.packages.iter().zip(packages) {
+            let resolved = PackageReference::from_package(package);
+            if resolved != planned.package_ref {
+                return Err(UhpmError::PlanOutdated(format!(
+                    "{} planned, {} resolved now",
+                    planned.package_ref, resolved
+                )));
+            }
+            if package.checksum() != &planned.checksum {
+                return Err(UhpmError::PlanOutdated(format!(
+                    "checksum of {} changed",
+                    resolved
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Options tweaking how `adopt` takes over existing files.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdoptOptions {
+    /// Replace the adopted files with symlinks into the package store
+    /// instead of recording them as direct installs.
+    pub replace: bool,
+    /// Adopt files whose content differs from the package, replacing it.
+    pub overwrite: bool,
+}
+
+impl AdoptOptions {
+    pub fn replace(mut self) -> Self {
+        self.replace = true;
+        self
+    }
+
+    pub fn overwrite(mut self) -> Self {
+        self.overwrite = true;
+        self
+    }
+}
+
+/// Outcome of `adopt`.
+///
+/// When files diverge from the package and overwriting wasn't allowed,
+/// `divergent` lists them and nothing was adopted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdoptResult {
+    pub package_id: PackageId,
+    pub adopted: Vec<PathBuf>,
+    pub divergent: Vec<PathBuf>,
+    pub symlinks_created: usize,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RemovalResult {
     pub package_id: PackageId,
     pub removed_files: usize,
-    pub freed_space: usize,
+    pub freed_space: u64,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RepairResult {
+    pub package_id: PackageId,
+    /// Symlinks that were missing or pointed elsewhere and have been re-created.
+    pub repaired: Vec<PathBuf>,
+    pub warnings: Vec<String>,
+}
+
+/// What `recover` did about the operations of a stale journal.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecoveryResult {
+    /// Packages the interrupted operation had already recorded; they are
+    /// kept.
+    pub completed: Vec<PackageReference>,
+    /// Packages whose placed and extracted files were removed.
+    pub rolled_back: Vec<PackageReference>,
+    pub removed_files: usize,
+}
+
+/// Options tweaking what `doctor` does about its findings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DoctorOptions {
+    /// Fix the issues that are safe to fix: re-create missing symlinks and
+    /// drop cached archives of packages that aren't installed.
+    pub repair: bool,
 }
 
-#[derive(Debug, Clone)]
+impl DoctorOptions {
+    pub fn repair(mut self) -> Self {
+        self.repair = true;
+        self
+    }
+}
+
+/// How serious a `doctor` finding is.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Wastes space but doesn't affect installed packages.
+    Info,
+    /// Something is out of place but installed packages still work.
+    Warning,
+    /// An installed package is broken.
+    Error,
+}
+
+/// An inconsistency between the database, the package store and the files
+/// placed on the system.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DoctorIssue {
+    /// A package is recorded as installed but its store directory is gone.
+    MissingPackageDir {
+        package_id: PackageId,
+        path: PathBuf,
+    },
+    /// A file placed by a direct or hard link install no longer exists.
+    MissingFile {
+        package_id: PackageId,
+        path: PathBuf,
+    },
+    /// A recorded symlink is missing or points somewhere else.
+    BrokenSymlink {
+        package_id: PackageId,
+        target: PathBuf,
+        source: PathBuf,
+    },
+    /// A file recorded as executable at install time no longer is.
+    PermissionDrift {
+        package_id: PackageId,
+        path: PathBuf,
+        expected: u32,
+        actual: u32,
+    },
+    /// The package store holds packages but the database records none, as
+    /// after it was lost or recreated. `DatabaseRepository::rebuild_from_store`
+    /// records them again.
+    UnrecordedStore { packages: usize },
+    /// A directory in the package store no package is recorded for.
+    OrphanPackageDir { path: PathBuf },
+    /// A symlink into the package store no installation recorded.
+    OrphanSymlink { path: PathBuf, points_to: PathBuf },
+    /// A cached archive of a package that isn't installed.
+    OrphanCacheEntry { package: PackageReference },
+}
+
+impl DoctorIssue {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::MissingPackageDir { .. }
+            | Self::MissingFile { .. }
+            | Self::UnrecordedStore { .. } => Severity::Error,
+            Self::BrokenSymlink { .. }
+            | Self::PermissionDrift { .. }
+            | Self::OrphanPackageDir { .. }
+            | Self::OrphanSymlink { .. } => Severity::Warning,
+            Self::OrphanCacheEntry { .. } => Severity::Info,
+        }
+    }
+
+    /// Whether `doctor` can fix the issue without risking data it doesn't own.
+    pub fn is_repairable(&self) -> bool {
+        matches!(
+            self,
+            Self::BrokenSymlink { .. }
+                | Self::PermissionDrift { .. }
+                | Self::OrphanCacheEntry { .. }
+        )
+    }
+}
+
+impl fmt::Display for DoctorIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingPackageDir { package_id, path } => write!(
+                f,
+                "{} is installed but {} is missing",
+                package_id.as_str(),
+                path.display()
+            ),
+            Self::MissingFile { package_id, path } => write!(
+                f,
+                "{} is missing file {}",
+                package_id.as_str(),
+                path.display()
+            ),
+            Self::BrokenSymlink {
+                package_id,
+                target,
+                source,
+            } => write!(
+                f,
+                "{} of {} doesn't point to {}",
+                target.display(),
+                package_id.as_str(),
+                source.display()
+            ),
+            Self::PermissionDrift {
+                package_id,
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} of {} has mode {:o} instead of {:o}",
+                path.display(),
+                package_id.as_str(),
+                actual,
+                expected
+            ),
+            Self::UnrecordedStore { packages } => write!(
+                f,
+                "{} packages are in the store but none are recorded; \
+                 rebuild the database from the store",
+                packages
+            ),
+            Self::OrphanPackageDir { path } => {
+                write!(f, "{} belongs to no recorded package", path.display())
+            }
+            Self::OrphanSymlink { path, points_to } => write!(
+                f,
+                "{} points to {} but belongs to no installation",
+                path.display(),
+                points_to.display()
+            ),
+            Self::OrphanCacheEntry { package } => {
+                write!(
+                    f,
+                    "{}@{} is cached but not installed",
+                    package.name, package.version
+                )
+            }
+        }
+    }
+}
+
+/// Outcome of `doctor`, with the findings grouped by severity.
+///
+/// Issues fixed in repair mode are moved to `repaired`; the others stay
+/// under their severity.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DoctorReport {
+    pub errors: Vec<DoctorIssue>,
+    pub warnings: Vec<DoctorIssue>,
+    pub info: Vec<DoctorIssue>,
+    pub repaired: Vec<DoctorIssue>,
+}
+
+impl DoctorReport {
+    pub fn push(&mut self, issue: DoctorIssue) {
+        match issue.severity() {
+            Severity::Error => self.errors.push(issue),
+            Severity::Warning => self.warnings.push(issue),
+            Severity::Info => self.info.push(issue),
+        }
+    }
+
+    /// Issues left unfixed, most severe first.
+    pub fn issues(&self) -> impl Iterator<Item = &DoctorIssue> {
+        self.errors.iter().chain(&self.warnings).chain(&self.info)
+    }
+
+    /// True when nothing is left to fix.
+    pub fn is_healthy(&self) -> bool {
+        self.issues().next().is_none()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SwitchResult {
     pub package_name: String,
     pub from_version: Option<Version>,
@@ -26,3 +441,158 @@ pub struct SwitchResult {
     pub installed_files: usize,
     pub warnings: Vec<String>,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OperationKind {
+    #[serde(rename = "install")]
+    Install,
+    #[serde(rename = "remove")]
+    Remove,
+    #[serde(rename = "switch")]
+    Switch,
+    #[serde(rename = "update")]
+    Update,
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Install => write!(f, "install"),
+            Self::Remove => write!(f, "remove"),
+            Self::Switch => write!(f, "switch"),
+            Self::Update => write!(f, "update"),
+        }
+    }
+}
+
+impl TryFrom<&str> for OperationKind {
+    type Error = UhpmError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "install" => Ok(Self::Install),
+            "remove" => Ok(Self::Remove),
+            "switch" => Ok(Self::Switch),
+            "update" => Ok(Self::Update),
+            _ => Err(UhpmError::validation(format!(
+                "Invalid operation kind: '{}'",
+                value
+            ))),
+        }
+    }
+}
+
+/// A single entry of the persisted operation history.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OperationRecord {
+    /// Database row id, `None` until the record has been saved.
+    pub id: Option<i64>,
+    pub timestamp: DateTime<Utc>,
+    pub kind: OperationKind,
+    pub package_name: String,
+    pub from_version: Option<Version>,
+    pub to_version: Option<Version>,
+    pub success: bool,
+    pub error_message: Option<String>,
+    pub duration: Duration,
+    /// Id of the record this operation reverted, when it was run by an undo.
+    pub undoes: Option<i64>,
+}
+
+impl OperationRecord {
+    pub fn new<S: Into<String>>(kind: OperationKind, package_name: S) -> Self {
+        Self {
+            id: None,
+            timestamp: SystemClock.now(),
+            kind,
+            package_name: package_name.into(),
+            from_version: None,
+            to_version: None,
+            success: true,
+            error_message: None,
+            duration: Duration::ZERO,
+            undoes: None,
+        }
+    }
+
+    /// Sets when the operation happened, now by default.
+    pub fn at(mut self, timestamp: DateTime<Utc>) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn from_version(mut self, version: Version) -> Self {
+        self.from_version = Some(version);
+        self
+    }
+
+    pub fn to_version(mut self, version: Version) -> Self {
+        self.to_version = Some(version);
+        self
+    }
+
+    /// Marks the operation as reverting the record with id `id`.
+    pub fn undoing(mut self, id: i64) -> Self {
+        self.undoes = Some(id);
+        self
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    pub fn failed<S: Into<String>>(mut self, error: S) -> Self {
+        self.success = false;
+        self.error_message = Some(error.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::de::DeserializeOwned;
+
+    fn assert_snapshot<T: Serialize + DeserializeOwned>(value: &T, snapshot: &str) {
+        let json = serde_json::to_string(value).unwrap();
+        assert_eq!(json, snapshot);
+        let parsed: T = serde_json::from_str(&json).unwrap();
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), snapshot);
+    }
+
+    #[test]
+    fn test_result_json_shapes() {
+        let package_id = PackageId::new("tool", &Version::new(1, 0, 0));
+
+        assert_snapshot(
+            &InstallResult {
+                package_id: package_id.clone(),
+                installed_files: vec![PathBuf::from("/home/user/.local/bin/tool")],
+                symlinks_created: 1,
+                warnings: vec!["careful".to_string()],
+            },
+            r#"{"package_id":"tool@1.0.0","installed_files":["/home/user/.local/bin/tool"],"symlinks_created":1,"warnings":["careful"]}"#,
+        );
+        assert_snapshot(
+            &RemovalResult {
+                package_id,
+                removed_files: 2,
+                freed_space: 1024,
+                warnings: vec![],
+            },
+            r#"{"package_id":"tool@1.0.0","removed_files":2,"freed_space":1024,"warnings":[]}"#,
+        );
+        assert_snapshot(
+            &SwitchResult {
+                package_name: "tool".to_string(),
+                from_version: Some(Version::new(1, 0, 0)),
+                to_version: Version::new(2, 0, 0),
+                removed_files: 1,
+                installed_files: 1,
+                warnings: vec![],
+            },
+            r#"{"package_name":"tool","from_version":"1.0.0","to_version":"2.0.0","removed_files":1,"installed_files":1,"warnings":[]}"#,
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{PackageReference, UhpmError, ports::PackageRepository};
+use semver::{Version, VersionReq};
+use std::fmt;
+use std::str::FromStr;
+
+/// A user supplied package specification.
+///
+/// Accepted forms:
+/// * `name` - the newest available version
+/// * `name@1.2.3` - exactly this version
+/// * `name@^1.2`, `name@>=1, <2` - the newest version matching the requirement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    pub name: String,
+    pub version: VersionSpec,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VersionSpec {
+    Latest,
+    Exact(Version),
+    Range(VersionReq),
+}
+
+impl PackageSpec {
+    pub fn parse(spec: &str) -> Result<Self, UhpmError> {
+        let (name, requirement) = match spec.split_once('@') {
+            Some((name, requirement)) => (name.trim(), Some(requirement.trim())),
+            None => (spec.trim(), None),
+        };
+
+        Self::validate_name(name).map_err(|e| {
+            UhpmError::validation(format!("Invalid package name in '{}': {}", spec, e))
+        })?;
+
+        let version = match requirement {
+            None => VersionSpec::Latest,
+            Some(requirement) => Self::parse_requirement(requirement).map_err(|e| {
+                UhpmError::validation(format!("Invalid version requirement in '{}': {}", spec, e))
+            })?,
+        };
+
+        Ok(Self {
+            name: name.to_string(),
+            version,
+        })
+    }
+
+    fn validate_name(name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("name is empty".to_string());
+        }
+
+        match name
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '+')))
+        {
+            Some(c) => Err(format!("unexpected character '{}'", c)),
+            None => Ok(()),
+        }
+    }
+
+    fn parse_requirement(requirement: &str) -> Result<VersionSpec, String> {
+        if requirement.is_empty() {
+            return Err("requirement is empty".to_string());
+        }
+
+        if let Ok(version) = Version::parse(requirement) {
+            return Ok(VersionSpec::Exact(version));
+        }
+
+        VersionReq::parse(requirement)
+            .map(VersionSpec::Range)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn matches(&self, version: &Version) -> bool {
+        match &self.version {
+            VersionSpec::Latest => true,
+            VersionSpec::Exact(exact) => exact == version,
+            VersionSpec::Range(requirement) => requirement.matches(version),
+        }
+    }
+
+    /// Picks the newest version satisfying the spec.
+    pub fn select<I>(&self, versions: I) -> Option<Version>
+    where
+        I: IntoIterator<Item = Version>,
+    {
+        versions
+            .into_iter()
+            .filter(|version| self.matches(version))
+            .max()
+    }
+
+    /// Resolves the spec to a concrete reference using the versions known to `repository`.
+    pub async fn resolve<R>(&self, repository: &R) -> Result<PackageReference, UhpmError>
+    where
+        R: PackageRepository + ?Sized,
+    {
+        let versions = repository.get_package_versions(&self.name).await?;
+        let versions = versions
+            .iter()
+            .filter_map(|version| Version::parse(version).ok());
+
+        self.select(versions)
+            .map(|version| PackageReference::new(self.name.clone(), version))
+            .ok_or_else(|| UhpmError::PackageNotFound(format!("No version matches {}", self)))
+    }
+}
+
+impl fmt::Display for PackageSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.version {
+            VersionSpec::Latest => write!(f, "{}", self.name),
+            VersionSpec::Exact(version) => write!(f, "{}@{}", self.name, version),
+            VersionSpec::Range(requirement) => write!(f, "{}@{}", self.name, requirement),
+        }
+    }
+}
+
+impl FromStr for PackageSpec {
+    type Err = UhpmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl TryFrom<&str> for PackageSpec {
+    type Error = UhpmError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        Self::parse(s)
+    }
+}
+
+impl From<&PackageReference> for PackageSpec {
+    fn from(package_ref: &PackageReference) -> Self {
+        Self {
+            name: package_ref.name.clone(),
+            version: VersionSpec::Exact(package_ref.version.clone()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn versions(list: &[&str]) -> Vec<Version> {
+        list.iter().map(|v| Version::parse(v).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_parse_forms() {
+        let latest = PackageSpec::parse("ripgrep").unwrap();
+        assert_eq!(latest.version, VersionSpec::Latest);
+
+        let exact = PackageSpec::parse("ripgrep@14.1.0").unwrap();
+        assert_eq!(
+            exact.version,
+            VersionSpec::Exact(Version::parse("14.1.0").unwrap())
+        );
+
+        let range = PackageSpec::parse("ripgrep@>=1, <2").unwrap();
+        assert_eq!(range.name, "ripgrep");
+        assert!(matches!(range.version, VersionSpec::Range(_)));
+    }
+
+    #[test]
+    fn test_parse_errors_name_the_invalid_part() {
+        let name = PackageSpec::parse("rip grep@1.0.0")
+            .unwrap_err()
+            .to_string();
+        assert!(name.contains("package name"), "{}", name);
+
+        let empty = PackageSpec::parse("@1.0.0").unwrap_err().to_string();
+        assert!(empty.contains("package name"), "{}", empty);
+
+        let requirement = PackageSpec::parse("ripgrep@^x").unwrap_err().to_string();
+        assert!(
+            requirement.contains("version requirement"),
+            "{}",
+            requirement
+        );
+    }
+
+    #[test]
+    fn test_select_picks_newest_match() {
+        let available = versions(&["1.0.0", "1.4.2", "2.0.0", "13.0.0", "14.1.0"]);
+
+        let spec = PackageSpec::parse("tool@^1.2").unwrap();
+        assert_eq!(
+            spec.select(available.clone()),
+            Some(Version::parse("1.4.2").unwrap())
+        );
+
+        let spec = PackageSpec::parse("tool").unwrap();
+        assert_eq!(
+            spec.select(available.clone()),
+            Some(Version::parse("14.1.0").unwrap())
+        );
+
+        let spec = PackageSpec::parse("tool@3.0.0").unwrap();
+        assert_eq!(spec.select(available), None);
+    }
+}
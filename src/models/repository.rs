@@ -1,12 +1,21 @@
-use crate::Dependency;
+use crate::{Dependency, UhpmError};
 use semver::Version;
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
 use std::path::PathBuf;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum Repository {
-    Local { path: PathBuf },
-    Http { index_url: String },
+    Local {
+        path: PathBuf,
+    },
+    Http {
+        index_url: String,
+    },
+    Git {
+        url: String,
+        release: Option<String>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
@@ -24,23 +33,350 @@ impl RepositoryIndex {
             .map(|p| p.versions.as_slice())
     }
 
+    pub fn entries(&self) -> impl Iterator<Item = &RepositoryPackageEntry> {
+        self.packages.iter()
+    }
+
+    /// Every package with its highest version, for catalog views. Versions
+    /// that aren't valid semver are skipped, and so are packages left
+    /// without any.
+    pub fn latest_versions(&self) -> Vec<(String, String)> {
+        self.entries()
+            .filter_map(|entry| Some((entry.name.clone(), entry.latest_version()?.to_string())))
+            .collect()
+    }
+
     pub fn latest_satisfying(&self, dep: &Dependency) -> Option<String> {
-        let versions = self.get_versions(&dep.name)?;
-        let mut parsed: Vec<Version> = versions
+        self.packages
+            .iter()
+            .find(|p| p.name == dep.name)?
+            .latest_satisfying(dep)
+    }
+
+    /// Rejects entries without a name, versions that aren't valid semver
+    /// and packages listed more than once.
+    pub fn validate(&self) -> Result<(), UhpmError> {
+        let corrupted = |reason: String| {
+            Err(UhpmError::RepositoryCorrupted(format!(
+                "index of {}: {}",
+                self.name, reason
+            )))
+        };
+
+        let mut names = HashSet::new();
+        for entry in &self.packages {
+            if entry.name.trim().is_empty() {
+                return corrupted("package entry without a name".to_string());
+            }
+            if !names.insert(entry.name.as_str()) {
+                return corrupted(format!("package `{}` is listed twice", entry.name));
+            }
+            if let Some(version) = entry
+                .versions
+                .iter()
+                .find(|version| Version::parse(version).is_err())
+            {
+                return corrupted(format!(
+                    "package `{}` has invalid version `{}`",
+                    entry.name, version
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A package listed in an index.
+///
+/// Items of `versions` are plain version strings or, in newer indexes,
+/// tables carrying the version's flags:
+///
+/// ```toml
+/// versions = ["1.0.0", { version = "1.1.0", yanked = true, deprecated = "use 2.x" }]
+/// ```
+///
+/// Flags read from tables are folded into `yanked` and `deprecated`, which
+/// is also how entries are written back.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "RawPackageEntry")]
+pub struct RepositoryPackageEntry {
+    pub name: String,
+    pub versions: Vec<String>,
+    /// Archive hashes keyed by version, so resolvers can skip fetching the
+    /// per-package meta. Older indexes don't carry it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+    /// Archive sizes in bytes keyed by version. Older indexes don't carry it.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sizes: BTreeMap<String, u64>,
+    /// Versions withdrawn by the publisher. They stay listed, but are only
+    /// resolved for dependencies pinning them exactly.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub yanked: Vec<String>,
+    /// Deprecation messages keyed by version. Deprecated versions still
+    /// resolve; the message is passed on to the package.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub deprecated: BTreeMap<String, String>,
+}
+
+/// `versions` item as written in an index.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VersionItem {
+    Plain(String),
+    Flagged {
+        version: String,
+        #[serde(default)]
+        yanked: bool,
+        #[serde(default)]
+        deprecated: Option<String>,
+    },
+}
+
+#[derive(Deserialize)]
+struct RawPackageEntry {
+    name: String,
+    versions: Vec<VersionItem>,
+    #[serde(default)]
+    checksums: BTreeMap<String, String>,
+    #[serde(default)]
+    sizes: BTreeMap<String, u64>,
+    #[serde(default)]
+    yanked: Vec<String>,
+    #[serde(default)]
+    deprecated: BTreeMap<String, String>,
+}
+
+impl From<RawPackageEntry> for RepositoryPackageEntry {
+    fn from(raw: RawPackageEntry) -> Self {
+        let mut entry = Self {
+            name: raw.name,
+            versions: Vec::with_capacity(raw.versions.len()),
+            checksums: raw.checksums,
+            sizes: raw.sizes,
+            yanked: raw.yanked,
+            deprecated: raw.deprecated,
+        };
+        for item in raw.versions {
+            match item {
+                VersionItem::Plain(version) => entry.versions.push(version),
+                VersionItem::Flagged {
+                    version,
+                    yanked,
+                    deprecated,
+                } => {
+                    if yanked && !entry.yanked.contains(&version) {
+                        entry.yanked.push(version.clone());
+                    }
+                    if let Some(message) = deprecated {
+                        entry.deprecated.insert(version.clone(), message);
+                    }
+                    entry.versions.push(version);
+                }
+            }
+        }
+        entry
+    }
+}
+
+impl RepositoryPackageEntry {
+    pub fn new(name: impl Into<String>, versions: Vec<String>) -> Self {
+        Self {
+            name: name.into(),
+            versions,
+            checksums: BTreeMap::new(),
+            sizes: BTreeMap::new(),
+            yanked: Vec::new(),
+            deprecated: BTreeMap::new(),
+        }
+    }
+
+    /// Highest version listed that isn't yanked, as written in the index.
+    pub fn latest_version(&self) -> Option<&str> {
+        self.versions
+            .iter()
+            .filter_map(|v| Some((Version::parse(v).ok()?, v)))
+            .filter(|(version, _)| !self.is_yanked(version))
+            .max_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn is_yanked(&self, version: &Version) -> bool {
+        self.yanked
+            .iter()
+            .any(|yanked| Version::parse(yanked).is_ok_and(|yanked| yanked == *version))
+    }
+
+    /// Deprecation message for `version`, if the index lists one.
+    pub fn deprecation_for(&self, version: &str) -> Option<&str> {
+        self.deprecated.get(version).map(String::as_str)
+    }
+
+    /// Hash of the archive for `version`, if the index lists one.
+    pub fn checksum_for(&self, version: &str) -> Option<&str> {
+        self.checksums.get(version).map(String::as_str)
+    }
+
+    /// Size of the archive for `version`, if the index lists one.
+    pub fn size_for(&self, version: &str) -> Option<u64> {
+        self.sizes.get(version).copied()
+    }
+
+    pub fn latest_satisfying(&self, dep: &Dependency) -> Option<String> {
+        self.satisfying(dep).first().map(ToString::to_string)
+    }
+
+    /// Versions satisfying `dep`, highest first. Yanked versions are left
+    /// out unless `dep` pins them exactly.
+    pub fn satisfying(&self, dep: &Dependency) -> Vec<Version> {
+        let mut parsed: Vec<Version> = self
+            .versions
             .iter()
             .filter_map(|v| Version::parse(v).ok())
+            .filter(|v| dep.matches_version(v))
+            .filter(|v| !self.is_yanked(v) || dep.pins_exactly(v))
             .collect();
-        parsed.sort();
+        parsed.sort_by(|a, b| b.cmp(a));
         parsed
-            .into_iter()
-            .rev()
-            .find(|v| dep.matches_version(v))
-            .map(|v| v.to_string())
     }
 }
 
+/// Top-level `index.toml` of a repository, in either supported layout.
+///
+/// A document with a `shards` key is a sharded index, one with a `pages`
+/// key a paged index, anything else is the classic single-file index
+/// listing every package.
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-pub struct RepositoryPackageEntry {
+#[serde(untagged)]
+pub enum IndexDocument {
+    Sharded(ShardedIndex),
+    Paged(PagedIndex),
+    Single(RepositoryIndex),
+}
+
+/// Index split into shard files that are fetched on demand.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ShardedIndex {
     pub name: String,
-    pub versions: Vec<String>,
+    pub url: String,
+    pub shards: Vec<IndexShard>,
+}
+
+impl ShardedIndex {
+    /// Returns the shard holding `package_name`, preferring the longest prefix.
+    pub fn shard_for(&self, package_name: &str) -> Option<&IndexShard> {
+        self.shards
+            .iter()
+            .filter(|shard| package_name.starts_with(&shard.prefix))
+            .max_by_key(|shard| shard.prefix.len())
+    }
+
+    /// Returns the shards that may hold packages whose name starts with `query`.
+    pub fn shards_for_query(&self, query: &str) -> Vec<&IndexShard> {
+        self.shards
+            .iter()
+            .filter(|shard| shard.prefix.starts_with(query) || query.starts_with(&shard.prefix))
+            .collect()
+    }
+}
+
+/// Index split into page files, each listing a slice of the packages in the
+/// same layout as a shard file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PagedIndex {
+    pub name: String,
+    pub url: String,
+    /// Locations of the page files, relative to the repository URL.
+    pub pages: Vec<String>,
+    /// Page holding each package, so a lookup reads a single page.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub package_pages: BTreeMap<String, String>,
+}
+
+impl PagedIndex {
+    /// Returns the pages that may hold `package_name`: the mapped one, or
+    /// every page when the index has no map.
+    pub fn pages_for(&self, package_name: &str) -> Vec<&str> {
+        if self.package_pages.is_empty() {
+            self.pages.iter().map(String::as_str).collect()
+        } else {
+            self.package_pages
+                .get(package_name)
+                .map(String::as_str)
+                .into_iter()
+                .collect()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexShard {
+    /// Package name prefix covered by this shard.
+    pub prefix: String,
+    /// Location of the shard file, relative to the repository URL.
+    pub path: String,
+    /// sha256 of the shard file.
+    pub checksum: String,
+    #[serde(default)]
+    pub last_modified: Option<String>,
+}
+
+/// Contents of a single shard or page file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct IndexShardData {
+    pub packages: Vec<RepositoryPackageEntry>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latest_versions_compare_as_semver() {
+        let index = RepositoryIndex {
+            name: "test".to_string(),
+            url: String::new(),
+            packages: vec![
+                RepositoryPackageEntry::new(
+                    "ripgrep",
+                    ["9.0.0", "14.1.0", "latest", "14.1.0-rc.1", "10.0.0"]
+                        .map(String::from)
+                        .to_vec(),
+                ),
+                RepositoryPackageEntry::new("broken", vec!["nightly".to_string()]),
+                RepositoryPackageEntry::new("fd", vec!["8.7.1".to_string()]),
+            ],
+        };
+
+        assert_eq!(index.entries().count(), 3);
+        assert_eq!(
+            index.latest_versions(),
+            [
+                ("ripgrep".to_string(), "14.1.0".to_string()),
+                ("fd".to_string(), "8.7.1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_versions_can_be_plain_strings_or_flagged_tables() {
+        let entry: RepositoryPackageEntry = toml::from_str(
+            "name = \"tool\"\n\
+             versions = [\"1.0.0\", { version = \"1.1.0\", yanked = true }, \
+             { version = \"1.2.0\", deprecated = \"use 2.x\" }]\n",
+        )
+        .unwrap();
+
+        assert_eq!(entry.versions, ["1.0.0", "1.1.0", "1.2.0"]);
+        assert_eq!(entry.yanked, ["1.1.0"]);
+        assert_eq!(entry.deprecation_for("1.2.0"), Some("use 2.x"));
+        assert_eq!(entry.deprecation_for("1.0.0"), None);
+        assert_eq!(entry.latest_version(), Some("1.2.0"));
+
+        let written = toml::to_string(&entry).unwrap();
+        assert_eq!(
+            toml::from_str::<RepositoryPackageEntry>(&written).unwrap(),
+            entry
+        );
+    }
 }
@@ -1,3 +1,4 @@
+use crate::{FilePermissions, clock::SystemClock, ports::Clock};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 use std::hash::{Hash, Hasher};
@@ -104,6 +105,14 @@ impl Hash for Symlink {
     }
 }
 
+/// What had to be done to make a symlink point at its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SymlinkAction {
+    Created,
+    Replaced { previous: PathBuf },
+    Unchanged,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SymlinkType {
     #[serde(rename = "file")]
@@ -143,15 +152,19 @@ pub struct SymlinkMetadata {
     pub owner: Option<String>,
     pub group: Option<String>,
     pub description: Option<String>,
+    /// Permissions a copied file is given, as stated in the instlist.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub permissions: Option<FilePermissions>,
 }
 
 impl Default for SymlinkMetadata {
     fn default() -> Self {
         Self {
-            created_at: chrono::Utc::now(),
+            created_at: SystemClock.now(),
             owner: None,
             group: None,
             description: None,
+            permissions: None,
         }
     }
 }
@@ -175,6 +188,11 @@ impl SymlinkMetadata {
         self.description = Some(description.into());
         self
     }
+
+    pub fn with_permissions(mut self, permissions: FilePermissions) -> Self {
+        self.permissions = Some(permissions);
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
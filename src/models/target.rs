@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Target {
@@ -23,8 +24,8 @@ pub enum Architecture {
 impl Target {
     pub fn current() -> Self {
         Self {
-            os: OperatingSystem::Linux,
-            arch: Architecture::X86_64,
+            os: OperatingSystem::from(std::env::consts::OS),
+            arch: Architecture::from(std::env::consts::ARCH),
         }
     }
 
@@ -32,3 +33,49 @@ impl Target {
         self.os == other.os && self.arch == other.arch
     }
 }
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.os, self.arch)
+    }
+}
+
+impl fmt::Display for OperatingSystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Linux => write!(f, "linux"),
+            Self::MacOS => write!(f, "macos"),
+            Self::Custom(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl From<&str> for OperatingSystem {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "linux" => Self::Linux,
+            "macos" | "darwin" => Self::MacOS,
+            _ => Self::Custom(value.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for Architecture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::X86_64 => write!(f, "x86_64"),
+            Self::Aarch64 => write!(f, "aarch64"),
+            Self::Custom(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+impl From<&str> for Architecture {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "x86_64" | "amd64" => Self::X86_64,
+            "aarch64" | "arm64" => Self::Aarch64,
+            _ => Self::Custom(value.to_string()),
+        }
+    }
+}
@@ -0,0 +1,195 @@
+use crate::{RepositoryConfig, UhpmConfig, UhpmError, factories::InstallationFactory};
+use std::path::{Component, Path, PathBuf};
+
+/// Where instlist entries are allowed to place files.
+///
+/// Targets are expanded and normalized before they are checked: a leading `~`
+/// is replaced by the home directory, relative paths are resolved against the
+/// first allowed prefix and `.`/`..` components are folded. System
+/// directories are always rejected. Anything else must lie under one of the
+/// allowed prefixes unless the policy is unrestricted, e.g. for a trusted
+/// repository.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TargetPolicy {
+    home: PathBuf,
+    prefixes: Vec<PathBuf>,
+    unrestricted: bool,
+}
+
+impl TargetPolicy {
+    pub fn new<I, P>(home: impl Into<PathBuf>, prefixes: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let mut policy = Self {
+            home: home.into(),
+            prefixes: Vec::new(),
+            unrestricted: false,
+        };
+        policy.prefixes = prefixes
+            .into_iter()
+            .map(|prefix| normalize(&policy.expand_home(prefix.as_ref())))
+            .collect();
+        policy
+    }
+
+    /// Allows the configured `install_prefixes` and `default_prefix`, and
+    /// the uhpm base directory.
+    pub fn from_config(config: &UhpmConfig, home: impl Into<PathBuf>, base_dir: &Path) -> Self {
+        let prefixes = config
+            .install_prefixes
+            .iter()
+            .chain(&config.default_prefix)
+            .map(PathBuf::from)
+            .chain(std::iter::once(base_dir.to_path_buf()));
+        Self::new(home, prefixes)
+    }
+
+    /// Also allows targets under `prefix`, e.g. one an install was asked to
+    /// place its files in.
+    pub fn allowing(mut self, prefix: &Path) -> Self {
+        let prefix = normalize(&self.expand_home(prefix));
+        if !self.prefixes.contains(&prefix) {
+            self.prefixes.push(prefix);
+        }
+        self
+    }
+
+    /// Lifts the prefix allowlist for packages of a trusted repository.
+    pub fn for_repository(self, repository: &RepositoryConfig) -> Self {
+        if repository.trusted {
+            self.unrestricted()
+        } else {
+            self
+        }
+    }
+
+    /// Accepts targets outside the allowed prefixes. System directories are
+    /// still rejected.
+    pub fn unrestricted(mut self) -> Self {
+        self.unrestricted = true;
+        self
+    }
+
+    pub fn prefixes(&self) -> &[PathBuf] {
+        &self.prefixes
+    }
+
+    /// Returns the absolute, normalized form of an instlist target.
+    pub fn normalize(&self, target: &Path) -> PathBuf {
+        let expanded = self.expand_home(target);
+        if expanded.is_absolute() {
+            return normalize(&expanded);
+        }
+        let base = self.prefixes.first().unwrap_or(&self.home);
+        normalize(&base.join(expanded))
+    }
+
+    /// Normalizes `target` and checks it is allowed.
+    pub fn check(&self, target: &Path) -> Result<PathBuf, UhpmError> {
+        let normalized = self.normalize(target);
+
+        if InstallationFactory::is_system_directory(&normalized) {
+            return Err(UhpmError::ValidationError(format!(
+                "Install target {} is a system directory",
+                normalized.display()
+            )));
+        }
+
+        if !self.unrestricted
+            && !self
+                .prefixes
+                .iter()
+                .any(|prefix| normalized.starts_with(prefix))
+        {
+            let allowed = self
+                .prefixes
+                .iter()
+                .map(|prefix| prefix.display().to_string())
+                .collect::<Vec<_>>();
+            return Err(UhpmError::ValidationError(format!(
+                "Install target {} is outside the allowed prefixes: {}",
+                normalized.display(),
+                allowed.join(", ")
+            )));
+        }
+
+        Ok(normalized)
+    }
+
+    /// Replaces a leading `~` with the home directory.
+    pub fn expand_home(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix("~") {
+            Ok(rest) => self.home.join(rest),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+}
+
+/// Folds `.` and `..` components without touching the file system.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy() -> TargetPolicy {
+        TargetPolicy::new(
+            "/home/user",
+            [
+                "~/.local/bin",
+                "~/.local/share",
+                "/home/user/.local/share/uhpm",
+            ],
+        )
+    }
+
+    #[test]
+    fn test_targets_are_normalized() {
+        let policy = policy();
+
+        assert_eq!(
+            policy.normalize(Path::new("~/.local/bin/tool")),
+            PathBuf::from("/home/user/.local/bin/tool")
+        );
+        assert_eq!(
+            policy.normalize(Path::new("tool")),
+            PathBuf::from("/home/user/.local/bin/tool")
+        );
+        assert_eq!(
+            policy.normalize(Path::new("/home/user/.local/bin/../share/./doc")),
+            PathBuf::from("/home/user/.local/share/doc")
+        );
+    }
+
+    #[test]
+    fn test_targets_outside_prefixes_are_rejected() {
+        let policy = policy();
+
+        assert!(policy.check(Path::new("~/.local/bin/tool")).is_ok());
+        for target in ["~/.ssh/authorized_keys", "~/.local/bin/../../.bashrc"] {
+            let err = policy.check(Path::new(target)).unwrap_err().to_string();
+            assert!(err.contains("/home/user/.local/bin"), "{}", err);
+        }
+
+        let trusted = policy.unrestricted();
+        assert_eq!(
+            trusted.check(Path::new("~/.ssh/config")).unwrap(),
+            PathBuf::from("/home/user/.ssh/config")
+        );
+        assert!(trusted.check(Path::new("/usr/bin/tool")).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{
+    CacheValidators, HttpHeadResult, UhpmError, compute_checksum,
+    ports::{FileSystemOperations, NetworkOperations},
+};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use url::Url;
+
+/// [`NetworkOperations`] serving `file://` URLs from a file system.
+///
+/// Lets [`RemotePackagesRepository`](crate::repositories::RemotePackagesRepository)
+/// read a published repository from a directory, such as a mounted drive,
+/// with the same index, meta and archive handling as over HTTP. Files that
+/// can't be read are reported as network errors, like a failed request.
+#[derive(Debug, Clone, Default)]
+pub struct FileNetwork<FS> {
+    file_system: FS,
+}
+
+impl<FS> FileNetwork<FS>
+where
+    FS: FileSystemOperations,
+{
+    pub fn new(file_system: FS) -> Self {
+        Self { file_system }
+    }
+
+    fn path(&self, url: &str) -> Result<PathBuf, UhpmError> {
+        self.parse_url(url)?
+            .to_file_path()
+            .map_err(|()| UhpmError::network(format!("{} is not a local file", url)))
+    }
+}
+
+#[async_trait]
+impl<FS> NetworkOperations for FileNetwork<FS>
+where
+    FS: FileSystemOperations,
+{
+    async fn get(&self, url: &str) -> Result<Vec<u8>, UhpmError> {
+        self.file_system
+            .read_file(&self.path(url)?)
+            .await
+            .map_err(|e| UhpmError::network(format!("{}: {}", url, e)))
+    }
+
+    async fn get_with_progress(
+        &self,
+        url: &str,
+        on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<Vec<u8>, UhpmError> {
+        let data = self.get(url).await?;
+        if let Some(on_progress) = on_progress {
+            on_progress(data.len() as u64, data.len() as u64);
+        }
+        Ok(data)
+    }
+
+    async fn head(&self, url: &str) -> Result<HttpHeadResult, UhpmError> {
+        let path = self.path(url)?;
+        let (status, content_length) = match self.file_system.metadata(&path).await {
+            Ok(metadata) => (200, Some(metadata.size)),
+            Err(_) => (404, None),
+        };
+        Ok(HttpHeadResult {
+            status,
+            content_length,
+            validators: CacheValidators::default(),
+            accepts_ranges: false,
+        })
+    }
+
+    async fn is_url_available(&self, url: &str) -> bool {
+        self.head(url).await.is_ok_and(|head| head.is_success())
+    }
+
+    async fn download_with_checksum(
+        &self,
+        url: &str,
+        expected_checksum: Option<(&str, &str)>,
+        on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<Vec<u8>, UhpmError> {
+        let data = self.get_with_progress(url, on_progress).await?;
+        if let Some((algorithm, expected)) = expected_checksum
+            && !compute_checksum(algorithm, &data)?.eq_ignore_ascii_case(expected)
+        {
+            return Err(UhpmError::ChecksumMismatch(url.to_string()));
+        }
+        Ok(data)
+    }
+
+    fn parse_url(&self, url: &str) -> Result<Url, UhpmError> {
+        let parsed = Url::parse(url).map_err(|e| UhpmError::network(e.to_string()))?;
+        if parsed.scheme() != "file" {
+            return Err(UhpmError::network(format!("{} is not a file:// URL", url)));
+        }
+        Ok(parsed)
+    }
+}
@@ -0,0 +1,7 @@
+mod file_network;
+#[cfg(feature = "facade")]
+mod reqwest_network;
+
+pub use file_network::FileNetwork;
+#[cfg(feature = "facade")]
+pub use reqwest_network::ReqwestNetwork;
@@ -0,0 +1,183 @@
+use crate::{
+    CacheValidators, ConditionalFetch, HttpHeadResult, RangeResponse, UhpmError, compute_checksum,
+    ports::NetworkOperations,
+};
+use async_trait::async_trait;
+use futures_util::{StreamExt, stream};
+use reqwest::{Client, Response, StatusCode, header};
+use url::Url;
+
+/// [`NetworkOperations`] over HTTP(S) using `reqwest`.
+///
+/// Responses outside the 2xx range are reported as network errors, so
+/// repositories fall back on their mirrors for them.
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestNetwork {
+    client: Client,
+}
+
+impl ReqwestNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses a preconfigured client, e.g. with a proxy or custom timeouts.
+    pub fn with_client(client: Client) -> Self {
+        Self { client }
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Response, UhpmError> {
+        let response = request
+            .send()
+            .await
+            .map_err(|e| UhpmError::network(e.to_string()))?;
+        if response.status() == StatusCode::NOT_MODIFIED || response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(UhpmError::network(format!(
+                "{} returned {}",
+                response.url(),
+                response.status()
+            )))
+        }
+    }
+
+    async fn read_body(
+        mut response: Response,
+        on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<Vec<u8>, UhpmError> {
+        let total = response.content_length().unwrap_or(0);
+        let mut data = Vec::with_capacity(total as usize);
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| UhpmError::network(e.to_string()))?
+        {
+            data.extend_from_slice(&chunk);
+            if let Some(on_progress) = &on_progress {
+                on_progress(data.len() as u64, total);
+            }
+        }
+        Ok(data)
+    }
+}
+
+fn header_value(response: &Response, name: header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
+
+fn validators(response: &Response) -> CacheValidators {
+    CacheValidators {
+        etag: header_value(response, header::ETAG),
+        last_modified: header_value(response, header::LAST_MODIFIED),
+    }
+}
+
+#[async_trait]
+impl NetworkOperations for ReqwestNetwork {
+    async fn get(&self, url: &str) -> Result<Vec<u8>, UhpmError> {
+        self.get_with_progress(url, None).await
+    }
+
+    async fn get_with_progress(
+        &self,
+        url: &str,
+        on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<Vec<u8>, UhpmError> {
+        let response = self.send(self.client.get(self.parse_url(url)?)).await?;
+        Self::read_body(response, on_progress).await
+    }
+
+    async fn head(&self, url: &str) -> Result<HttpHeadResult, UhpmError> {
+        let response = self
+            .client
+            .head(self.parse_url(url)?)
+            .send()
+            .await
+            .map_err(|e| UhpmError::network(e.to_string()))?;
+        Ok(HttpHeadResult {
+            status: response.status().as_u16(),
+            content_length: response.content_length(),
+            validators: validators(&response),
+            accepts_ranges: header_value(&response, header::ACCEPT_RANGES)
+                .is_some_and(|ranges| ranges.eq_ignore_ascii_case("bytes")),
+        })
+    }
+
+    async fn get_conditional(
+        &self,
+        url: &str,
+        validators: &CacheValidators,
+    ) -> Result<ConditionalFetch, UhpmError> {
+        let mut request = self.client.get(self.parse_url(url)?);
+        if let Some(etag) = &validators.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &validators.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+
+        let response = self.send(request).await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(ConditionalFetch::NotModified);
+        }
+        let validators = self::validators(&response);
+        Ok(ConditionalFetch::Modified {
+            data: Self::read_body(response, None).await?,
+            validators,
+        })
+    }
+
+    async fn get_range(&self, url: &str, offset: u64) -> Result<RangeResponse, UhpmError> {
+        let mut request = self.client.get(self.parse_url(url)?);
+        if offset > 0 {
+            request = request.header(header::RANGE, format!("bytes={}-", offset));
+        }
+
+        let response = self.send(request).await?;
+        let partial = response.status() == StatusCode::PARTIAL_CONTENT;
+        let total = response
+            .content_length()
+            .map(|length| if partial { length + offset } else { length });
+        let body = stream::unfold(Some(response), |response| async move {
+            let mut response = response?;
+            match response.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk.to_vec()), Some(response))),
+                Ok(None) => None,
+                Err(e) => Some((Err(UhpmError::network(e.to_string())), None)),
+            }
+        });
+        Ok(RangeResponse {
+            partial,
+            total,
+            body: body.boxed(),
+        })
+    }
+
+    async fn is_url_available(&self, url: &str) -> bool {
+        self.head(url).await.is_ok_and(|head| head.is_success())
+    }
+
+    async fn download_with_checksum(
+        &self,
+        url: &str,
+        expected_checksum: Option<(&str, &str)>,
+        on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<Vec<u8>, UhpmError> {
+        let data = self.get_with_progress(url, on_progress).await?;
+        if let Some((algorithm, expected)) = expected_checksum
+            && !compute_checksum(algorithm, &data)?.eq_ignore_ascii_case(expected)
+        {
+            return Err(UhpmError::ChecksumMismatch(url.to_string()));
+        }
+        Ok(data)
+    }
+
+    fn parse_url(&self, url: &str) -> Result<Url, UhpmError> {
+        Url::parse(url).map_err(|e| UhpmError::network(e.to_string()))
+    }
+}
@@ -0,0 +1,58 @@
+mod windows;
+mod xdg;
+
+pub use windows::WindowsPaths;
+pub use xdg::XdgPaths;
+
+use crate::{UhpmError, ports::FileSystemOperations};
+use std::path::PathBuf;
+
+pub trait UhpmPaths: Send + Sync {
+    fn base_dir(&self) -> PathBuf;
+
+    fn packages_dir(&self) -> PathBuf {
+        self.base_dir().join("packages")
+    }
+
+    fn db_path(&self) -> PathBuf {
+        self.base_dir().join("packages.db")
+    }
+
+    fn config_path(&self) -> PathBuf;
+
+    fn cache_dir(&self) -> PathBuf;
+
+    fn temp_dir(&self) -> PathBuf;
+
+    fn log_dir(&self) -> PathBuf {
+        self.base_dir().join("logs")
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        self.base_dir().join("uhpm.lock")
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.base_dir().join("journal")
+    }
+}
+
+/// Creates the directories `paths` names, including the parent of the
+/// config file.
+pub async fn create_directories<P, FS>(paths: &P, fs: &FS) -> Result<(), UhpmError>
+where
+    P: UhpmPaths + ?Sized,
+    FS: FileSystemOperations + ?Sized,
+{
+    fs.create_dir_all(&paths.base_dir()).await?;
+    fs.create_dir_all(&paths.packages_dir()).await?;
+    fs.create_dir_all(&paths.cache_dir()).await?;
+    fs.create_dir_all(&paths.temp_dir()).await?;
+    fs.create_dir_all(&paths.log_dir()).await?;
+
+    if let Some(config_parent) = paths.config_path().parent() {
+        fs.create_dir_all(config_parent).await?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,118 @@
+use super::UhpmPaths;
+use crate::UhpmError;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+const APP_DIR: &str = "uhpm";
+
+/// Paths following the Windows known-folder conventions.
+///
+/// * `base_dir` - `%LOCALAPPDATA%\uhpm`
+/// * `config_path` - `%APPDATA%\uhpm\config.toml`
+/// * `cache_dir` - `%LOCALAPPDATA%\uhpm\cache`
+/// * `temp_dir` - `uhpm` inside the system temp directory
+///
+/// When `%APPDATA%` or `%LOCALAPPDATA%` is missing the default locations under
+/// `%USERPROFILE%\AppData` are used. Resolution only looks at environment
+/// variables, so the type is available (and testable) on every platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowsPaths {
+    roaming_app_data: PathBuf,
+    local_app_data: PathBuf,
+    temp_dir: PathBuf,
+}
+
+impl WindowsPaths {
+    /// Resolves the directories from the current environment.
+    pub fn from_env() -> Result<Self, UhpmError> {
+        Self::resolve(|key| std::env::var_os(key))
+    }
+
+    fn resolve<F>(var: F) -> Result<Self, UhpmError>
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let profile = var("USERPROFILE")
+            .filter(|profile| !profile.is_empty())
+            .map(PathBuf::from);
+        let dir = |key: &str, fallback: &str| -> Result<PathBuf, UhpmError> {
+            match var(key).filter(|value| !value.is_empty()) {
+                Some(value) => Ok(PathBuf::from(value)),
+                None => profile
+                    .as_ref()
+                    .map(|profile| profile.join("AppData").join(fallback))
+                    .ok_or_else(|| {
+                        UhpmError::ConfigError(format!("Neither {} nor USERPROFILE is set", key))
+                    }),
+            }
+        };
+
+        Ok(Self {
+            roaming_app_data: dir("APPDATA", "Roaming")?,
+            local_app_data: dir("LOCALAPPDATA", "Local")?,
+            temp_dir: std::env::temp_dir(),
+        })
+    }
+}
+
+impl UhpmPaths for WindowsPaths {
+    fn base_dir(&self) -> PathBuf {
+        self.local_app_data.join(APP_DIR)
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.roaming_app_data.join(APP_DIR).join("config.toml")
+    }
+
+    fn cache_dir(&self) -> PathBuf {
+        self.local_app_data.join(APP_DIR).join("cache")
+    }
+
+    fn temp_dir(&self) -> PathBuf {
+        self.temp_dir.join(APP_DIR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_windows_paths_from_app_data_variables() {
+        let vars: HashMap<&str, &str> = [
+            ("USERPROFILE", "C:/Users/user"),
+            ("APPDATA", "D:/Roaming"),
+            ("LOCALAPPDATA", "D:/Local"),
+        ]
+        .into_iter()
+        .collect();
+
+        let paths = WindowsPaths::resolve(|key| vars.get(key).map(OsString::from)).unwrap();
+
+        let local = PathBuf::from("D:/Local");
+        assert_eq!(paths.base_dir(), local.join("uhpm"));
+        assert_eq!(paths.packages_dir(), local.join("uhpm").join("packages"));
+        assert_eq!(
+            paths.config_path(),
+            PathBuf::from("D:/Roaming").join("uhpm").join("config.toml")
+        );
+        assert_eq!(paths.cache_dir(), local.join("uhpm").join("cache"));
+        assert_eq!(paths.temp_dir(), std::env::temp_dir().join("uhpm"));
+    }
+
+    #[test]
+    fn test_windows_paths_fall_back_to_user_profile() {
+        let vars: HashMap<&str, &str> = [("USERPROFILE", "C:/Users/user")].into_iter().collect();
+
+        let paths = WindowsPaths::resolve(|key| vars.get(key).map(OsString::from)).unwrap();
+
+        let app_data = PathBuf::from("C:/Users/user").join("AppData");
+        assert_eq!(paths.base_dir(), app_data.join("Local").join("uhpm"));
+        assert_eq!(
+            paths.config_path(),
+            app_data.join("Roaming").join("uhpm").join("config.toml")
+        );
+        assert!(WindowsPaths::resolve(|_| None).is_err());
+    }
+}
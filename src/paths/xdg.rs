@@ -0,0 +1,138 @@
+use super::UhpmPaths;
+use crate::UhpmError;
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+const APP_DIR: &str = "uhpm";
+
+/// Paths following the XDG Base Directory specification.
+///
+/// * `base_dir` - `$XDG_DATA_HOME/uhpm` or `~/.local/share/uhpm`
+/// * `config_path` - `$XDG_CONFIG_HOME/uhpm/config.toml` or `~/.config/uhpm/config.toml`
+/// * `cache_dir` - `$XDG_CACHE_HOME/uhpm` or `~/.cache/uhpm`
+/// * `temp_dir` - `uhpm` inside the system temp directory
+///
+/// Relative values of the `XDG_*` variables are ignored, as the specification requires.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct XdgPaths {
+    data_home: PathBuf,
+    config_home: PathBuf,
+    cache_home: PathBuf,
+    temp_dir: PathBuf,
+}
+
+impl XdgPaths {
+    /// Resolves the directories from the current environment.
+    pub fn from_env() -> Result<Self, UhpmError> {
+        Self::resolve(|key| std::env::var_os(key))
+    }
+
+    fn resolve<F>(var: F) -> Result<Self, UhpmError>
+    where
+        F: Fn(&str) -> Option<OsString>,
+    {
+        let home = var("HOME")
+            .filter(|home| !home.is_empty())
+            .map(PathBuf::from);
+        let dir = |key: &str, fallback: &str| -> Result<PathBuf, UhpmError> {
+            match var(key)
+                .map(PathBuf::from)
+                .filter(|path| path.is_absolute())
+            {
+                Some(path) => Ok(path),
+                None => home
+                    .as_ref()
+                    .map(|home| home.join(fallback))
+                    .ok_or_else(|| {
+                        UhpmError::ConfigError(format!("Neither {} nor HOME is set", key))
+                    }),
+            }
+        };
+
+        Ok(Self {
+            data_home: dir("XDG_DATA_HOME", ".local/share")?,
+            config_home: dir("XDG_CONFIG_HOME", ".config")?,
+            cache_home: dir("XDG_CACHE_HOME", ".cache")?,
+            temp_dir: std::env::temp_dir(),
+        })
+    }
+}
+
+impl UhpmPaths for XdgPaths {
+    fn base_dir(&self) -> PathBuf {
+        self.data_home.join(APP_DIR)
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.config_home.join(APP_DIR).join("config.toml")
+    }
+
+    fn cache_dir(&self) -> PathBuf {
+        self.cache_home.join(APP_DIR)
+    }
+
+    fn temp_dir(&self) -> PathBuf {
+        self.temp_dir.join(APP_DIR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_xdg_paths_from_xdg_variables() {
+        let vars: HashMap<&str, &str> = [
+            ("HOME", "/home/user"),
+            ("XDG_DATA_HOME", "/xdg/data"),
+            ("XDG_CONFIG_HOME", "/xdg/config"),
+            ("XDG_CACHE_HOME", "/xdg/cache"),
+        ]
+        .into_iter()
+        .collect();
+
+        let paths = XdgPaths::resolve(|key| vars.get(key).map(OsString::from)).unwrap();
+
+        assert_eq!(paths.base_dir(), PathBuf::from("/xdg/data/uhpm"));
+        assert_eq!(
+            paths.packages_dir(),
+            PathBuf::from("/xdg/data/uhpm/packages")
+        );
+        assert_eq!(paths.db_path(), PathBuf::from("/xdg/data/uhpm/packages.db"));
+        assert_eq!(
+            paths.config_path(),
+            PathBuf::from("/xdg/config/uhpm/config.toml")
+        );
+        assert_eq!(paths.cache_dir(), PathBuf::from("/xdg/cache/uhpm"));
+        assert_eq!(paths.temp_dir(), std::env::temp_dir().join("uhpm"));
+    }
+
+    #[test]
+    fn test_xdg_paths_fall_back_to_home() {
+        let vars: HashMap<&str, &str> = [
+            ("HOME", "/home/user"),
+            ("XDG_DATA_HOME", "relative/data"),
+            ("XDG_CACHE_HOME", "/var/cache/user"),
+        ]
+        .into_iter()
+        .collect();
+
+        let paths = XdgPaths::resolve(|key| vars.get(key).map(OsString::from)).unwrap();
+
+        assert_eq!(
+            paths.base_dir(),
+            PathBuf::from("/home/user/.local/share/uhpm")
+        );
+        assert_eq!(
+            paths.config_path(),
+            PathBuf::from("/home/user/.config/uhpm/config.toml")
+        );
+        assert_eq!(paths.cache_dir(), PathBuf::from("/var/cache/user/uhpm"));
+    }
+
+    #[test]
+    fn test_xdg_paths_without_home() {
+        assert!(XdgPaths::resolve(|_| None).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::{PackageReference, UhpmError};
 use async_trait::async_trait;
 use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
 #[async_trait]
@@ -20,10 +21,16 @@ pub trait CacheManager: Send + Sync {
 
     async fn clear_packages(&self) -> Result<(), UhpmError>;
 
+    /// References of every cached package archive.
+    async fn list_packages(&self) -> Result<Vec<PackageReference>, UhpmError>;
+
     async fn get_index(&self, repository_url: &str) -> Result<Option<Vec<u8>>, UhpmError>;
 
     async fn put_index(&self, repository_url: &str, data: &[u8]) -> Result<(), UhpmError>;
 
+    /// Drops the cached index of a repository, so it is downloaded again.
+    async fn invalidate_index(&self, repository_url: &str) -> Result<(), UhpmError>;
+
     async fn get_cache_size(&self) -> Result<u64, UhpmError>;
 
     async fn cleanup_old_entries(&self, max_age: Duration) -> Result<(), UhpmError>;
@@ -31,4 +38,104 @@ pub trait CacheManager: Send + Sync {
     fn get_cache_path(&self) -> &PathBuf;
 
     async fn has_package(&self, package_ref: &PackageReference) -> bool;
+
+    /// Size of a cached package archive in bytes, `None` if it isn't cached.
+    async fn get_package_size(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<u64>, UhpmError> {
+        Ok(self
+            .get_package(package_ref)
+            .await?
+            .map(|data| data.len() as u64))
+    }
+
+    /// Protects a package archive from eviction until it is unpinned.
+    fn pin_package(&self, package_ref: &PackageReference);
+
+    fn unpin_package(&self, package_ref: &PackageReference);
+
+    /// Evicts entries until the cache is within its size limit, returning the bytes freed.
+    async fn evict_to_fit(&self) -> Result<u64, UhpmError>;
+}
+
+/// Shares one cache between the package manager and its repositories.
+#[async_trait]
+impl<C> CacheManager for Arc<C>
+where
+    C: CacheManager + ?Sized,
+{
+    async fn get_package(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<Vec<u8>>, UhpmError> {
+        (**self).get_package(package_ref).await
+    }
+
+    async fn put_package(
+        &self,
+        package_ref: &PackageReference,
+        data: &[u8],
+    ) -> Result<(), UhpmError> {
+        (**self).put_package(package_ref, data).await
+    }
+
+    async fn remove_package(&self, package_ref: &PackageReference) -> Result<(), UhpmError> {
+        (**self).remove_package(package_ref).await
+    }
+
+    async fn clear_packages(&self) -> Result<(), UhpmError> {
+        (**self).clear_packages().await
+    }
+
+    async fn list_packages(&self) -> Result<Vec<PackageReference>, UhpmError> {
+        (**self).list_packages().await
+    }
+
+    async fn get_index(&self, repository_url: &str) -> Result<Option<Vec<u8>>, UhpmError> {
+        (**self).get_index(repository_url).await
+    }
+
+    async fn put_index(&self, repository_url: &str, data: &[u8]) -> Result<(), UhpmError> {
+        (**self).put_index(repository_url, data).await
+    }
+
+    async fn invalidate_index(&self, repository_url: &str) -> Result<(), UhpmError> {
+        (**self).invalidate_index(repository_url).await
+    }
+
+    async fn get_cache_size(&self) -> Result<u64, UhpmError> {
+        (**self).get_cache_size().await
+    }
+
+    async fn cleanup_old_entries(&self, max_age: Duration) -> Result<(), UhpmError> {
+        (**self).cleanup_old_entries(max_age).await
+    }
+
+    fn get_cache_path(&self) -> &PathBuf {
+        (**self).get_cache_path()
+    }
+
+    async fn has_package(&self, package_ref: &PackageReference) -> bool {
+        (**self).has_package(package_ref).await
+    }
+
+    async fn get_package_size(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<u64>, UhpmError> {
+        (**self).get_package_size(package_ref).await
+    }
+
+    fn pin_package(&self, package_ref: &PackageReference) {
+        (**self).pin_package(package_ref)
+    }
+
+    fn unpin_package(&self, package_ref: &PackageReference) {
+        (**self).unpin_package(package_ref)
+    }
+
+    async fn evict_to_fit(&self) -> Result<u64, UhpmError> {
+        (**self).evict_to_fit().await
+    }
 }
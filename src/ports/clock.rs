@@ -0,0 +1,13 @@
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+/// Source of the current time for timestamps and age checks.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> DateTime<Utc> {
+        (**self).now()
+    }
+}
@@ -1,20 +1,114 @@
-use crate::PackageEvent;
 use crate::UhpmError;
+use crate::{EventEnvelope, EventReceiver, OverflowPolicy, PackageEvent};
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
 
+/// Decides whether a subscriber receives an event.
+pub type EventFilter = Box<dyn Fn(&PackageEvent) -> bool + Send + Sync>;
+
+/// Receives published events along with their sequence number and time.
+pub type EventCallback = Box<dyn Fn(EventEnvelope) + Send + Sync>;
+
+/// Publishes package events to subscribers and keeps a history of them.
+///
+/// Events are published bare; the publisher wraps each one in an
+/// [`EventEnvelope`] with the next sequence number and the current time, and
+/// hands that envelope to subscribers and the history.
 #[async_trait]
 pub trait EventPublisher: Send + Sync {
     async fn publish(&self, event: PackageEvent) -> Result<(), UhpmError>;
 
-    async fn subscribe(
+    async fn subscribe(&self, callback: EventCallback) -> Result<String, UhpmError>;
+
+    /// Subscribes to the events for which `predicate` returns true.
+    async fn subscribe_filtered(
+        &self,
+        predicate: EventFilter,
+        callback: EventCallback,
+    ) -> Result<String, UhpmError> {
+        self.subscribe(Box::new(move |envelope| {
+            if predicate(&envelope.event) {
+                callback(envelope)
+            }
+        }))
+        .await
+    }
+
+    /// Subscribes through a channel holding at most `capacity` undelivered
+    /// events, for subscribers that process events asynchronously.
+    ///
+    /// When the channel is full, `overflow` decides whether an event is
+    /// dropped or `publish` waits. Each channel is handled on its own, so a
+    /// full one never keeps events from other subscribers. Dropping the
+    /// receiver unsubscribes it.
+    async fn subscribe_channel(
         &self,
-        callback: Box<dyn Fn(PackageEvent) + Send + Sync>,
-    ) -> Result<String, UhpmError>;
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> Result<EventReceiver, UhpmError>;
 
     async fn unsubscribe(&self, subscription_id: &str) -> Result<(), UhpmError>;
 
-    async fn get_event_history(&self, limit: Option<usize>)
-    -> Result<Vec<PackageEvent>, UhpmError>;
+    /// Returns recorded events in sequence order.
+    ///
+    /// Only events published at or after `since` and about `package_name`
+    /// are returned when given; `limit` then keeps the most recent ones.
+    async fn get_event_history(
+        &self,
+        limit: Option<usize>,
+        since: Option<DateTime<Utc>>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<EventEnvelope>, UhpmError>;
 
     async fn clear_event_history(&self) -> Result<(), UhpmError>;
 }
+
+/// Lets a shared or type-erased publisher, such as
+/// `Arc<dyn EventPublisher>`, stand in for a concrete one.
+#[async_trait]
+impl<P> EventPublisher for Arc<P>
+where
+    P: EventPublisher + ?Sized,
+{
+    async fn publish(&self, event: PackageEvent) -> Result<(), UhpmError> {
+        (**self).publish(event).await
+    }
+
+    async fn subscribe(&self, callback: EventCallback) -> Result<String, UhpmError> {
+        (**self).subscribe(callback).await
+    }
+
+    async fn subscribe_filtered(
+        &self,
+        predicate: EventFilter,
+        callback: EventCallback,
+    ) -> Result<String, UhpmError> {
+        (**self).subscribe_filtered(predicate, callback).await
+    }
+
+    async fn subscribe_channel(
+        &self,
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> Result<EventReceiver, UhpmError> {
+        (**self).subscribe_channel(capacity, overflow).await
+    }
+
+    async fn unsubscribe(&self, subscription_id: &str) -> Result<(), UhpmError> {
+        (**self).unsubscribe(subscription_id).await
+    }
+
+    async fn get_event_history(
+        &self,
+        limit: Option<usize>,
+        since: Option<DateTime<Utc>>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<EventEnvelope>, UhpmError> {
+        (**self).get_event_history(limit, since, package_name).await
+    }
+
+    async fn clear_event_history(&self) -> Result<(), UhpmError> {
+        (**self).clear_event_history().await
+    }
+}
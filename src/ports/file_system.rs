@@ -1,13 +1,35 @@
-use crate::{FileMetadata, Symlink, UhpmError};
+use crate::{FileMetadata, FsError, Symlink, UhpmError};
 use async_trait::async_trait;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncRead;
+use uuid::Uuid;
 
 #[async_trait]
-pub trait FileSystemOperations: Send + Sync + Clone {
+pub trait FileSystemOperations: Send + Sync {
     async fn read_file(&self, path: &Path) -> Result<Vec<u8>, UhpmError>;
 
     async fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), UhpmError>;
 
+    /// Opens the file at `path` to be read in pieces.
+    ///
+    /// By default the whole file is read up front; implementations backed by
+    /// a disk should stream it instead.
+    async fn open_read(&self, path: &Path) -> Result<Box<dyn AsyncRead + Send + Unpin>, UhpmError> {
+        Ok(Box::new(std::io::Cursor::new(self.read_file(path).await?)))
+    }
+
+    /// Appends `data` to the file at `path`, creating it if missing.
+    async fn append_file(&self, path: &Path, data: &[u8]) -> Result<(), UhpmError> {
+        let mut contents = if self.exists(path).await {
+            self.read_file(path).await?
+        } else {
+            Vec::new()
+        };
+        contents.extend_from_slice(data);
+        self.write_file(path, &contents).await
+    }
+
     async fn create_dir(&self, path: &Path) -> Result<(), UhpmError>;
 
     async fn create_dir_all(&self, path: &Path) -> Result<(), UhpmError>;
@@ -18,8 +40,46 @@ pub trait FileSystemOperations: Send + Sync + Clone {
 
     async fn copy_file(&self, from: &Path, to: &Path) -> Result<(), UhpmError>;
 
+    /// Moves `from` to `to`, replacing `to` if it exists.
+    ///
+    /// Within one filesystem this is a rename, so readers of `to` see either
+    /// the old or the new file, never a mix. Across filesystems it falls
+    /// back to copying, which gives no such guarantee.
     async fn move_file(&self, from: &Path, to: &Path) -> Result<(), UhpmError>;
 
+    /// Writes `data` to `path` so that readers never see a partial file.
+    ///
+    /// The data goes to a uniquely named temporary file next to `path`,
+    /// which is then moved over it and its size checked.
+    async fn atomic_write(&self, path: &Path, data: &[u8]) -> Result<(), UhpmError> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| FsError::InvalidPath(path.display().to_string()))?;
+        let temp = path.with_file_name(format!(
+            ".{}.{}.tmp",
+            file_name.to_string_lossy(),
+            Uuid::new_v4()
+        ));
+
+        self.write_file(&temp, data).await?;
+        if let Err(e) = self.move_file(&temp, path).await {
+            let _ = self.remove(&temp).await;
+            return Err(e);
+        }
+
+        let written = self.metadata(path).await?.size;
+        if written != data.len() as u64 {
+            return Err(FsError::Io(format!(
+                "{}: wrote {} bytes, found {}",
+                path.display(),
+                data.len(),
+                written
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
     async fn exists(&self, path: &Path) -> bool;
 
     async fn metadata(&self, path: &Path) -> Result<FileMetadata, UhpmError>;
@@ -35,4 +95,97 @@ pub trait FileSystemOperations: Send + Sync + Clone {
     async fn is_symlink(&self, path: &Path) -> bool;
 
     async fn set_permissions(&self, path: &Path, permissions: u32) -> Result<(), UhpmError>;
+
+    /// Creates a hard link at `to` for the file at `from`.
+    ///
+    /// Fails with [`crate::FsError::CrossDevice`] when both paths are on different filesystems.
+    async fn create_hard_link(&self, from: &Path, to: &Path) -> Result<(), UhpmError>;
+}
+
+/// Lets a shared or type-erased file system, such as
+/// `Arc<dyn FileSystemOperations>`, stand in for a concrete one.
+#[async_trait]
+impl<F> FileSystemOperations for Arc<F>
+where
+    F: FileSystemOperations + ?Sized,
+{
+    async fn read_file(&self, path: &Path) -> Result<Vec<u8>, UhpmError> {
+        (**self).read_file(path).await
+    }
+
+    async fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), UhpmError> {
+        (**self).write_file(path, data).await
+    }
+
+    async fn open_read(&self, path: &Path) -> Result<Box<dyn AsyncRead + Send + Unpin>, UhpmError> {
+        (**self).open_read(path).await
+    }
+
+    async fn append_file(&self, path: &Path, data: &[u8]) -> Result<(), UhpmError> {
+        (**self).append_file(path, data).await
+    }
+
+    async fn create_dir(&self, path: &Path) -> Result<(), UhpmError> {
+        (**self).create_dir(path).await
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> Result<(), UhpmError> {
+        (**self).create_dir_all(path).await
+    }
+
+    async fn remove(&self, path: &Path) -> Result<(), UhpmError> {
+        (**self).remove(path).await
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> Result<(), UhpmError> {
+        (**self).remove_dir_all(path).await
+    }
+
+    async fn copy_file(&self, from: &Path, to: &Path) -> Result<(), UhpmError> {
+        (**self).copy_file(from, to).await
+    }
+
+    async fn move_file(&self, from: &Path, to: &Path) -> Result<(), UhpmError> {
+        (**self).move_file(from, to).await
+    }
+
+    async fn atomic_write(&self, path: &Path, data: &[u8]) -> Result<(), UhpmError> {
+        (**self).atomic_write(path, data).await
+    }
+
+    async fn exists(&self, path: &Path) -> bool {
+        (**self).exists(path).await
+    }
+
+    async fn metadata(&self, path: &Path) -> Result<FileMetadata, UhpmError> {
+        (**self).metadata(path).await
+    }
+
+    async fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, UhpmError> {
+        (**self).read_dir(path).await
+    }
+
+    async fn create_symlink(&self, symlink: &Symlink) -> Result<(), UhpmError> {
+        (**self).create_symlink(symlink).await
+    }
+
+    async fn remove_symlink(&self, path: &Path) -> Result<(), UhpmError> {
+        (**self).remove_symlink(path).await
+    }
+
+    async fn read_symlink(&self, path: &Path) -> Result<PathBuf, UhpmError> {
+        (**self).read_symlink(path).await
+    }
+
+    async fn is_symlink(&self, path: &Path) -> bool {
+        (**self).is_symlink(path).await
+    }
+
+    async fn set_permissions(&self, path: &Path, permissions: u32) -> Result<(), UhpmError> {
+        (**self).set_permissions(path, permissions).await
+    }
+
+    async fn create_hard_link(&self, from: &Path, to: &Path) -> Result<(), UhpmError> {
+        (**self).create_hard_link(from, to).await
+    }
 }
@@ -0,0 +1,23 @@
+use crate::UhpmError;
+use async_trait::async_trait;
+use std::path::Path;
+
+#[async_trait]
+pub trait GitOperations: Send + Sync {
+    /// Clones `url` into `destination`, or fetches new commits and tags if it is already there.
+    async fn clone_or_fetch(&self, url: &str, destination: &Path) -> Result<(), UhpmError>;
+
+    async fn checkout(&self, repository: &Path, reference: &str) -> Result<(), UhpmError>;
+
+    async fn list_tags(&self, repository: &Path) -> Result<Vec<String>, UhpmError>;
+
+    async fn read_file(
+        &self,
+        repository: &Path,
+        reference: &str,
+        path: &str,
+    ) -> Result<Vec<u8>, UhpmError>;
+
+    /// Returns a `tar.gz` archive of the tree at `reference`.
+    async fn archive(&self, repository: &Path, reference: &str) -> Result<Vec<u8>, UhpmError>;
+}
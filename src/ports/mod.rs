@@ -1,17 +1,23 @@
 // src/ports/mod.rs
 
 pub use cache_manager::CacheManager;
+pub use clock::Clock;
 pub use dependency_resolver::DependencyResolver;
-pub use event_publisher::EventPublisher;
+pub use event_publisher::{EventCallback, EventFilter, EventPublisher};
 pub use file_system::FileSystemOperations;
+pub use git::GitOperations;
 pub use network::NetworkOperations;
 pub use package_manager::PackageManager;
 pub use package_repository::PackageRepository;
+pub use state_store::StateStore;
 
 pub mod cache_manager;
+pub mod clock;
 pub mod dependency_resolver;
 pub mod event_publisher;
 pub mod file_system;
+pub mod git;
 pub mod network;
 pub mod package_manager;
 pub mod package_repository;
+pub mod state_store;
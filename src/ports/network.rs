@@ -1,6 +1,13 @@
-use crate::UhpmError;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{
+    CacheValidators, ConditionalFetch, DownloadOptions, HttpHeadResult, RangeResponse, UhpmError,
+    compute_checksum, ports::FileSystemOperations,
+};
 use async_trait::async_trait;
-use reqwest::Response;
+use futures_util::{StreamExt, stream};
+use tracing::warn;
 use url::Url;
 
 #[async_trait]
@@ -13,7 +20,37 @@ pub trait NetworkOperations: Send + Sync {
         on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
     ) -> Result<Vec<u8>, UhpmError>;
 
-    async fn head(&self, url: &str) -> Result<Response, UhpmError>;
+    async fn head(&self, url: &str) -> Result<HttpHeadResult, UhpmError>;
+
+    /// Fetches `url` unless it still matches `validators`, sending them as
+    /// `If-None-Match`/`If-Modified-Since`.
+    ///
+    /// Backends without conditional requests always download the resource.
+    async fn get_conditional(
+        &self,
+        url: &str,
+        validators: &CacheValidators,
+    ) -> Result<ConditionalFetch, UhpmError> {
+        let _ = validators;
+        Ok(ConditionalFetch::Modified {
+            data: self.get(url).await?,
+            validators: CacheValidators::default(),
+        })
+    }
+
+    /// Fetches `url` from byte `offset` on, sending `Range: bytes=<offset>-`
+    /// when the offset isn't zero.
+    ///
+    /// Backends without range requests always return the whole resource.
+    async fn get_range(&self, url: &str, offset: u64) -> Result<RangeResponse, UhpmError> {
+        let _ = offset;
+        let data = self.get(url).await?;
+        Ok(RangeResponse {
+            partial: false,
+            total: Some(data.len() as u64),
+            body: stream::iter([Ok(data)]).boxed(),
+        })
+    }
 
     async fn is_url_available(&self, url: &str) -> bool;
 
@@ -24,5 +61,164 @@ pub trait NetworkOperations: Send + Sync {
         on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
     ) -> Result<Vec<u8>, UhpmError>;
 
+    /// Downloads `url` to `destination`, going through `<destination>.part`.
+    ///
+    /// Network failures are retried up to `options.max_retries` times. When
+    /// `options.resume` is set and the server accepts ranges, a retry
+    /// continues from the bytes already in the part file; a server that
+    /// answers the range with the whole resource restarts it. Progress
+    /// counts the bytes already present. The assembled file must match
+    /// `expected_checksum` before it is moved into place.
+    async fn download_to_file<F>(
+        &self,
+        file_system: &F,
+        url: &str,
+        destination: &Path,
+        expected_checksum: Option<(&str, &str)>,
+        options: &DownloadOptions,
+        on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<(), UhpmError>
+    where
+        Self: Sized,
+        F: FileSystemOperations,
+    {
+        let part = part_path(destination);
+        let resumable =
+            options.resume && self.head(url).await.is_ok_and(|head| head.accepts_ranges);
+
+        let mut attempt = 0;
+        loop {
+            let offset = if resumable && file_system.exists(&part).await {
+                file_system.metadata(&part).await?.size
+            } else {
+                0
+            };
+            match download_part(
+                self,
+                file_system,
+                url,
+                &part,
+                offset,
+                on_progress.as_deref(),
+            )
+            .await
+            {
+                Ok(()) => break,
+                Err(error @ (UhpmError::NetworkError(_) | UhpmError::DownloadError(_)))
+                    if attempt < options.max_retries =>
+                {
+                    attempt += 1;
+                    warn!(%url, %error, attempt, "download interrupted, retrying");
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        if let Some((algorithm, expected)) = expected_checksum {
+            let data = file_system.read_file(&part).await?;
+            if !compute_checksum(algorithm, &data)?.eq_ignore_ascii_case(expected) {
+                file_system.remove(&part).await?;
+                return Err(UhpmError::ChecksumMismatch(url.to_string()));
+            }
+        }
+        file_system.move_file(&part, destination).await
+    }
+
     fn parse_url(&self, url: &str) -> Result<Url, UhpmError>;
 }
+
+fn part_path(destination: &Path) -> PathBuf {
+    let mut part = destination.as_os_str().to_owned();
+    part.push(".part");
+    PathBuf::from(part)
+}
+
+/// Streams `url` into `part` from `offset` on, starting the file over when
+/// the server sends the whole resource.
+async fn download_part<N, F>(
+    network: &N,
+    file_system: &F,
+    url: &str,
+    part: &Path,
+    offset: u64,
+    on_progress: Option<&(dyn Fn(u64, u64) + Send + Sync)>,
+) -> Result<(), UhpmError>
+where
+    N: NetworkOperations,
+    F: FileSystemOperations,
+{
+    let response = network.get_range(url, offset).await?;
+    let mut downloaded = if response.partial {
+        offset
+    } else {
+        file_system.write_file(part, &[]).await?;
+        0
+    };
+    let total = response.total.unwrap_or(0);
+
+    let mut body = response.body;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        file_system.append_file(part, &chunk).await?;
+        downloaded += chunk.len() as u64;
+        if let Some(on_progress) = on_progress {
+            on_progress(downloaded, total);
+        }
+    }
+    Ok(())
+}
+
+/// Lets a shared or type-erased network, such as
+/// `Arc<dyn NetworkOperations>`, stand in for a concrete one.
+#[async_trait]
+impl<N> NetworkOperations for Arc<N>
+where
+    N: NetworkOperations + ?Sized,
+{
+    async fn get(&self, url: &str) -> Result<Vec<u8>, UhpmError> {
+        (**self).get(url).await
+    }
+
+    async fn get_with_progress(
+        &self,
+        url: &str,
+        on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<Vec<u8>, UhpmError> {
+        (**self).get_with_progress(url, on_progress).await
+    }
+
+    async fn head(&self, url: &str) -> Result<HttpHeadResult, UhpmError> {
+        (**self).head(url).await
+    }
+
+    async fn get_conditional(
+        &self,
+        url: &str,
+        validators: &CacheValidators,
+    ) -> Result<ConditionalFetch, UhpmError> {
+        (**self).get_conditional(url, validators).await
+    }
+
+    async fn get_range(&self, url: &str, offset: u64) -> Result<RangeResponse, UhpmError> {
+        (**self).get_range(url, offset).await
+    }
+
+    async fn is_url_available(&self, url: &str) -> bool {
+        (**self).is_url_available(url).await
+    }
+
+    async fn download_with_checksum(
+        &self,
+        url: &str,
+        expected_checksum: Option<(&str, &str)>,
+        on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<Vec<u8>, UhpmError> {
+        (**self)
+            .download_with_checksum(url, expected_checksum, on_progress)
+            .await
+    }
+
+    fn parse_url(&self, url: &str) -> Result<Url, UhpmError> {
+        (**self).parse_url(url)
+    }
+}
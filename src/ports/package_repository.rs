@@ -1,12 +1,23 @@
 use std::collections::HashSet;
+use std::sync::Arc;
 
-use crate::{Dependency, Package, PackageReference, Repository, RepositoryIndex, UhpmError};
+use crate::{
+    Dependency, Package, PackageId, PackageReference, Repository, RepositoryIndex, UhpmError,
+};
 use async_trait::async_trait;
 
 #[async_trait]
 pub trait PackageRepository: Send + Sync {
     async fn get_package(&self, package_ref: &PackageReference) -> Result<Package, UhpmError>;
 
+    /// Looks a package up by its `name@version` id.
+    async fn get_package_by_id(&self, package_id: &PackageId) -> Result<Package, UhpmError> {
+        let package_ref = package_id.reference().ok_or_else(|| {
+            UhpmError::ValidationError(format!("Invalid package id: {}", package_id.as_str()))
+        })?;
+        self.get_package(&package_ref).await
+    }
+
     async fn search_packages(&self, query: &str) -> Result<Vec<Package>, UhpmError>;
 
     async fn get_package_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError>;
@@ -20,6 +31,45 @@ pub trait PackageRepository: Send + Sync {
 
     async fn download_package(&self, package_ref: &PackageReference) -> Result<Vec<u8>, UhpmError>;
 
+    /// Versions of `package_name` withdrawn by the publisher. Repositories
+    /// that can't yank versions return none.
+    async fn get_yanked_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        let _ = package_name;
+        Ok(Vec::new())
+    }
+
+    /// Size of the package archive in bytes, when the repository can tell
+    /// without downloading it.
+    async fn download_size(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<u64>, UhpmError> {
+        let _ = package_ref;
+        Ok(None)
+    }
+
+    /// Downloads a package archive along with the URL that served it, when
+    /// the repository knows it.
+    async fn download_package_with_source(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<(Vec<u8>, Option<String>), UhpmError> {
+        Ok((self.download_package(package_ref).await?, None))
+    }
+
+    /// Like [`download_package_with_source`](Self::download_package_with_source),
+    /// reporting `(downloaded, total)` bytes while the archive arrives.
+    ///
+    /// Repositories that can't report progress just download the archive.
+    async fn download_package_with_progress(
+        &self,
+        package_ref: &PackageReference,
+        on_progress: Box<dyn Fn(u64, u64) + Send + Sync>,
+    ) -> Result<(Vec<u8>, Option<String>), UhpmError> {
+        let _ = on_progress;
+        self.download_package_with_source(package_ref).await
+    }
+
     async fn get_index(&self) -> Result<RepositoryIndex, UhpmError>;
 
     async fn update_index(&self) -> Result<RepositoryIndex, UhpmError>;
@@ -28,3 +78,86 @@ pub trait PackageRepository: Send + Sync {
 
     fn get_repository(&self) -> &Repository;
 }
+
+/// Lets a shared or type-erased repository, such as
+/// `Arc<dyn PackageRepository>`, stand in for a concrete one.
+#[async_trait]
+impl<R> PackageRepository for Arc<R>
+where
+    R: PackageRepository + ?Sized,
+{
+    async fn get_package(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
+        (**self).get_package(package_ref).await
+    }
+
+    async fn get_package_by_id(&self, package_id: &PackageId) -> Result<Package, UhpmError> {
+        (**self).get_package_by_id(package_id).await
+    }
+
+    async fn search_packages(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
+        (**self).search_packages(query).await
+    }
+
+    async fn get_package_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        (**self).get_package_versions(package_name).await
+    }
+
+    async fn get_latest_version(&self, package_name: &str) -> Result<String, UhpmError> {
+        (**self).get_latest_version(package_name).await
+    }
+
+    async fn resolve_dependencies(
+        &self,
+        dependencies: &HashSet<Dependency>,
+    ) -> Result<Vec<Package>, UhpmError> {
+        (**self).resolve_dependencies(dependencies).await
+    }
+
+    async fn download_package(&self, package_ref: &PackageReference) -> Result<Vec<u8>, UhpmError> {
+        (**self).download_package(package_ref).await
+    }
+
+    async fn get_yanked_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        (**self).get_yanked_versions(package_name).await
+    }
+
+    async fn download_size(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<u64>, UhpmError> {
+        (**self).download_size(package_ref).await
+    }
+
+    async fn download_package_with_source(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<(Vec<u8>, Option<String>), UhpmError> {
+        (**self).download_package_with_source(package_ref).await
+    }
+
+    async fn download_package_with_progress(
+        &self,
+        package_ref: &PackageReference,
+        on_progress: Box<dyn Fn(u64, u64) + Send + Sync>,
+    ) -> Result<(Vec<u8>, Option<String>), UhpmError> {
+        (**self)
+            .download_package_with_progress(package_ref, on_progress)
+            .await
+    }
+
+    async fn get_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        (**self).get_index().await
+    }
+
+    async fn update_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        (**self).update_index().await
+    }
+
+    async fn is_available(&self) -> bool {
+        (**self).is_available().await
+    }
+
+    fn get_repository(&self) -> &Repository {
+        (**self).get_repository()
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{Installation, InstallationId, OperationRecord, Package, PackageId, UhpmError};
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Persistent record of installed packages, their installations and the
+/// operation history.
+#[async_trait]
+pub trait StateStore: Send + Sync {
+    async fn save_package(&self, package: &Package) -> Result<(), UhpmError>;
+
+    async fn get_package(&self, package_id: &PackageId) -> Result<Option<Package>, UhpmError>;
+
+    async fn list_installed_packages(&self) -> Result<Vec<Package>, UhpmError>;
+
+    /// Deletes the record of a package and its dependencies.
+    async fn delete_package(&self, package_id: &PackageId) -> Result<(), UhpmError>;
+
+    /// Returns the installed packages that declare a dependency on `package_name`.
+    async fn get_dependents(&self, package_name: &str) -> Result<Vec<PackageId>, UhpmError>;
+
+    async fn save_installation(&self, installation: &Installation) -> Result<(), UhpmError>;
+
+    /// Records packages together with their installations atomically: either
+    /// all of them are saved or none is.
+    async fn save_installs(
+        &self,
+        packages: &[Package],
+        installations: &[Installation],
+    ) -> Result<(), UhpmError>;
+
+    async fn get_active_installation(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Option<Installation>, UhpmError>;
+
+    /// Returns every installation recorded for a package, oldest first.
+    async fn list_installations(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Vec<Installation>, UhpmError>;
+
+    async fn delete_installation(&self, installation_id: &InstallationId) -> Result<(), UhpmError>;
+
+    /// Returns the package whose installation placed `path`.
+    async fn find_owner(&self, path: &Path) -> Result<Option<PackageId>, UhpmError>;
+
+    /// Appends an entry to the operation history and returns its id.
+    async fn record_operation(&self, record: &OperationRecord) -> Result<i64, UhpmError>;
+
+    /// Returns the operation history, newest first.
+    async fn get_history(
+        &self,
+        limit: Option<usize>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<OperationRecord>, UhpmError>;
+
+    /// Reclaims space left behind by deleted records.
+    async fn compact(&self) -> Result<(), UhpmError>;
+}
+
+/// Lets a shared or type-erased store, such as `Arc<dyn StateStore>`,
+/// stand in for a concrete one.
+#[async_trait]
+impl<S> StateStore for Arc<S>
+where
+    S: StateStore + ?Sized,
+{
+    async fn save_package(&self, package: &Package) -> Result<(), UhpmError> {
+        (**self).save_package(package).await
+    }
+
+    async fn get_package(&self, package_id: &PackageId) -> Result<Option<Package>, UhpmError> {
+        (**self).get_package(package_id).await
+    }
+
+    async fn list_installed_packages(&self) -> Result<Vec<Package>, UhpmError> {
+        (**self).list_installed_packages().await
+    }
+
+    async fn delete_package(&self, package_id: &PackageId) -> Result<(), UhpmError> {
+        (**self).delete_package(package_id).await
+    }
+
+    async fn get_dependents(&self, package_name: &str) -> Result<Vec<PackageId>, UhpmError> {
+        (**self).get_dependents(package_name).await
+    }
+
+    async fn save_installation(&self, installation: &Installation) -> Result<(), UhpmError> {
+        (**self).save_installation(installation).await
+    }
+
+    async fn save_installs(
+        &self,
+        packages: &[Package],
+        installations: &[Installation],
+    ) -> Result<(), UhpmError> {
+        (**self).save_installs(packages, installations).await
+    }
+
+    async fn get_active_installation(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Option<Installation>, UhpmError> {
+        (**self).get_active_installation(package_id).await
+    }
+
+    async fn list_installations(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Vec<Installation>, UhpmError> {
+        (**self).list_installations(package_id).await
+    }
+
+    async fn delete_installation(&self, installation_id: &InstallationId) -> Result<(), UhpmError> {
+        (**self).delete_installation(installation_id).await
+    }
+
+    async fn find_owner(&self, path: &Path) -> Result<Option<PackageId>, UhpmError> {
+        (**self).find_owner(path).await
+    }
+
+    async fn record_operation(&self, record: &OperationRecord) -> Result<i64, UhpmError> {
+        (**self).record_operation(record).await
+    }
+
+    async fn get_history(
+        &self,
+        limit: Option<usize>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<OperationRecord>, UhpmError> {
+        (**self).get_history(limit, package_name).await
+    }
+
+    async fn compact(&self) -> Result<(), UhpmError> {
+        (**self).compact().await
+    }
+}
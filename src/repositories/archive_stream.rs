@@ -0,0 +1,364 @@
+//! Gzipped tar archives read from and written to async streams.
+//!
+//! `tar` and `flate2` only offer blocking readers, so archives are decoded
+//! by pushing compressed bytes into a `flate2::write::GzDecoder` and parsing
+//! the tar blocks it produces. Only a bounded window of the archive is held
+//! in memory at any time.
+
+use crate::{FsError, UhpmError};
+use flate2::{
+    Compression, GzBuilder,
+    write::{GzDecoder, GzEncoder},
+};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tar::{Builder, EntryType, Header};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const BLOCK_SIZE: usize = 512;
+
+/// Compressed bytes decoded at a time. Deflate expands data at most about
+/// a thousandfold, which bounds the decoded window.
+const READ_CHUNK: usize = 8 * 1024;
+
+/// Size of the pieces file contents are read and written in.
+pub(crate) const DATA_CHUNK: usize = 64 * 1024;
+
+pub(crate) enum StreamEntryKind {
+    Directory,
+    File,
+    Symlink(PathBuf),
+}
+
+pub(crate) struct StreamEntry {
+    pub path: PathBuf,
+    pub mode: u32,
+    pub kind: StreamEntryKind,
+}
+
+/// Reads the entries of a gzipped tar archive from `reader`.
+///
+/// Entry types packages don't use, hard links among them, are skipped.
+pub(crate) struct ArchiveReader<R> {
+    reader: R,
+    decoder: GzDecoder<Vec<u8>>,
+    decoded: Vec<u8>,
+    position: usize,
+    eof: bool,
+    /// Unread data of the current entry.
+    remaining: u64,
+    /// Zeros between the current entry's data and the next header.
+    padding: usize,
+}
+
+impl<R> ArchiveReader<R>
+where
+    R: AsyncRead + Unpin,
+{
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: GzDecoder::new(Vec::new()),
+            decoded: Vec::new(),
+            position: 0,
+            eof: false,
+            remaining: 0,
+            padding: 0,
+        }
+    }
+
+    /// The next entry, `None` at the end of the archive.
+    ///
+    /// Data of the previous entry that wasn't read is skipped.
+    pub async fn next_entry(&mut self) -> Result<Option<StreamEntry>, UhpmError> {
+        let mut long_name = None;
+        let mut long_link = None;
+        loop {
+            self.skip_data().await?;
+            if !self.fill(BLOCK_SIZE).await? {
+                if self.available() == 0 {
+                    return Ok(None);
+                }
+                return Err(extraction_error("archive ends inside an entry header"));
+            }
+            let block = self.take(BLOCK_SIZE);
+            if block.iter().all(|byte| *byte == 0) {
+                return Ok(None);
+            }
+
+            let header = Header::from_byte_slice(&block);
+            check_checksum(header)?;
+            let size = header.entry_size().map_err(extraction_error)?;
+            self.remaining = size;
+            self.padding = (BLOCK_SIZE - (size as usize % BLOCK_SIZE)) % BLOCK_SIZE;
+
+            let entry_type = header.entry_type();
+            match entry_type {
+                EntryType::GNULongName => long_name = Some(self.read_extension(size).await?),
+                EntryType::GNULongLink => long_link = Some(self.read_extension(size).await?),
+                EntryType::XHeader => {
+                    let records = self.read_extension(size).await?;
+                    for (key, value) in pax_records(&records) {
+                        match key {
+                            b"path" => long_name = Some(value.to_vec()),
+                            b"linkpath" => long_link = Some(value.to_vec()),
+                            _ => {}
+                        }
+                    }
+                }
+                EntryType::Directory
+                | EntryType::Symlink
+                | EntryType::Regular
+                | EntryType::Continuous => {
+                    let path = match long_name.take() {
+                        Some(name) => bytes_to_path(name)?,
+                        None => header.path().map_err(extraction_error)?.into_owned(),
+                    };
+                    let mode = header.mode().map_err(extraction_error)?;
+                    let kind = match entry_type {
+                        EntryType::Directory => StreamEntryKind::Directory,
+                        EntryType::Symlink => {
+                            let link = match long_link.take() {
+                                Some(link) => bytes_to_path(link)?,
+                                None => header
+                                    .link_name()
+                                    .map_err(extraction_error)?
+                                    .ok_or_else(|| {
+                                        extraction_error(format!(
+                                            "Symlink without target: {}",
+                                            path.display()
+                                        ))
+                                    })?
+                                    .into_owned(),
+                            };
+                            StreamEntryKind::Symlink(link)
+                        }
+                        _ => StreamEntryKind::File,
+                    };
+                    return Ok(Some(StreamEntry { path, mode, kind }));
+                }
+                _ => {
+                    long_name = None;
+                    long_link = None;
+                }
+            }
+        }
+    }
+
+    /// The next piece of the current file's contents, `None` once all of
+    /// it was read.
+    pub async fn read_data(&mut self) -> Result<Option<Vec<u8>>, UhpmError> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let wanted = self.remaining.min(DATA_CHUNK as u64) as usize;
+        if !self.fill(wanted).await? {
+            return Err(extraction_error("archive ends inside an entry"));
+        }
+        self.remaining -= wanted as u64;
+        Ok(Some(self.take(wanted)))
+    }
+
+    async fn skip_data(&mut self) -> Result<(), UhpmError> {
+        while self.read_data().await?.is_some() {}
+        if self.padding > 0 {
+            if !self.fill(self.padding).await? {
+                return Err(extraction_error("archive ends inside an entry"));
+            }
+            self.position += self.padding;
+            self.padding = 0;
+        }
+        Ok(())
+    }
+
+    /// Reads the contents of a long name or pax header entry.
+    async fn read_extension(&mut self, size: u64) -> Result<Vec<u8>, UhpmError> {
+        if size > DATA_CHUNK as u64 {
+            return Err(extraction_error(format!(
+                "extended header of {} bytes is too large",
+                size
+            )));
+        }
+        let mut data = Vec::with_capacity(size as usize);
+        while let Some(chunk) = self.read_data().await? {
+            data.extend_from_slice(&chunk);
+        }
+        while data.last() == Some(&0) {
+            data.pop();
+        }
+        Ok(data)
+    }
+
+    fn available(&self) -> usize {
+        self.decoded.len() - self.position
+    }
+
+    fn take(&mut self, len: usize) -> Vec<u8> {
+        let data = self.decoded[self.position..self.position + len].to_vec();
+        self.position += len;
+        data
+    }
+
+    /// Decodes until `wanted` bytes are available, returning false if the
+    /// stream ends first.
+    async fn fill(&mut self, wanted: usize) -> Result<bool, UhpmError> {
+        while self.available() < wanted && !self.eof {
+            self.decoded.drain(..self.position);
+            self.position = 0;
+
+            let mut chunk = vec![0; READ_CHUNK];
+            let read = self
+                .reader
+                .read(&mut chunk)
+                .await
+                .map_err(extraction_error)?;
+            if read == 0 {
+                self.eof = true;
+                self.decoder.try_finish().map_err(extraction_error)?;
+            } else {
+                self.decoder
+                    .write_all(&chunk[..read])
+                    .map_err(extraction_error)?;
+            }
+            self.decoded.append(self.decoder.get_mut());
+        }
+        Ok(self.available() >= wanted)
+    }
+}
+
+/// Writes a reproducible gzipped tar archive to `writer`.
+///
+/// The gzip header is fixed, and compressed output is handed to `writer` as
+/// soon as it is produced.
+pub(crate) struct ArchiveWriter<W> {
+    writer: W,
+    builder: Builder<GzEncoder<Vec<u8>>>,
+}
+
+impl<W> ArchiveWriter<W>
+where
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(writer: W) -> Self {
+        let encoder = GzBuilder::new()
+            .mtime(0)
+            .write(Vec::new(), Compression::default());
+        Self {
+            writer,
+            builder: Builder::new(encoder),
+        }
+    }
+
+    /// Appends the header of an entry at `path`.
+    ///
+    /// An entry with contents must be followed by exactly `header`'s size in
+    /// [`write_data`](Self::write_data) calls and then
+    /// [`finish_data`](Self::finish_data).
+    pub async fn append(&mut self, header: &mut Header, path: &Path) -> Result<(), UhpmError> {
+        self.builder
+            .append_data(header, path, std::io::empty())
+            .map_err(serialization_error)?;
+        self.flush().await
+    }
+
+    pub async fn append_link(
+        &mut self,
+        header: &mut Header,
+        path: &Path,
+        link: &Path,
+    ) -> Result<(), UhpmError> {
+        self.builder
+            .append_link(header, path, link)
+            .map_err(serialization_error)?;
+        self.flush().await
+    }
+
+    pub async fn write_data(&mut self, data: &[u8]) -> Result<(), UhpmError> {
+        self.builder
+            .get_mut()
+            .write_all(data)
+            .map_err(serialization_error)?;
+        self.flush().await
+    }
+
+    /// Pads the contents of an entry of `len` bytes to a whole block.
+    pub async fn finish_data(&mut self, len: u64) -> Result<(), UhpmError> {
+        let padding = (BLOCK_SIZE - (len as usize % BLOCK_SIZE)) % BLOCK_SIZE;
+        self.write_data(&[0; BLOCK_SIZE][..padding]).await
+    }
+
+    pub async fn finish(mut self) -> Result<(), UhpmError> {
+        self.builder.finish().map_err(serialization_error)?;
+        let rest = self
+            .builder
+            .into_inner()
+            .and_then(GzEncoder::finish)
+            .map_err(serialization_error)?;
+        self.writer
+            .write_all(&rest)
+            .await
+            .map_err(|e| FsError::Io(e.to_string()))?;
+        self.writer
+            .flush()
+            .await
+            .map_err(|e| FsError::Io(e.to_string()).into())
+    }
+
+    async fn flush(&mut self) -> Result<(), UhpmError> {
+        let compressed = std::mem::take(self.builder.get_mut().get_mut());
+        if compressed.is_empty() {
+            return Ok(());
+        }
+        self.writer
+            .write_all(&compressed)
+            .await
+            .map_err(|e| FsError::Io(e.to_string()).into())
+    }
+}
+
+fn check_checksum(header: &Header) -> Result<(), UhpmError> {
+    let bytes = header.as_bytes();
+    let sum = bytes[..148]
+        .iter()
+        .chain(&[b' '; 8])
+        .chain(&bytes[156..])
+        .map(|byte| u32::from(*byte))
+        .sum::<u32>();
+    match header.cksum() {
+        Ok(cksum) if cksum == sum => Ok(()),
+        _ => Err(extraction_error("archive entry header checksum mismatch")),
+    }
+}
+
+/// Splits pax extended header records, `<length> <key>=<value>\n` each.
+fn pax_records(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        let space = data.iter().position(|byte| *byte == b' ')?;
+        let len = std::str::from_utf8(&data[..space])
+            .ok()?
+            .parse::<usize>()
+            .ok()?;
+        if len <= space + 1 || len > data.len() {
+            return None;
+        }
+        let record = &data[space + 1..len];
+        data = &data[len..];
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        let equals = record.iter().position(|byte| *byte == b'=')?;
+        Some((&record[..equals], &record[equals + 1..]))
+    })
+}
+
+fn bytes_to_path(bytes: Vec<u8>) -> Result<PathBuf, UhpmError> {
+    String::from_utf8(bytes)
+        .map(PathBuf::from)
+        .map_err(|e| extraction_error(format!("archive path is not UTF-8: {}", e)))
+}
+
+fn extraction_error(error: impl ToString) -> UhpmError {
+    FsError::ExtractionError(error.to_string()).into()
+}
+
+fn serialization_error(error: std::io::Error) -> UhpmError {
+    UhpmError::SerializationError(error.to_string())
+}
@@ -0,0 +1,314 @@
+use crate::{
+    Dependency, Package, PackageReference, Repository, RepositoryIndex, RepositoryPackageEntry,
+    UhpmError, ports::PackageRepository,
+};
+use async_trait::async_trait;
+use futures_util::future::BoxFuture;
+use semver::Version;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Repository combining several repositories, consulted in order.
+///
+/// Lookups and downloads are answered by the first repository that succeeds,
+/// searches and indexes merge the results of all of them. When several
+/// repositories offer the same package version, the earlier one wins.
+///
+/// Packages served by a named repository record its name as their
+/// [`source_repository`](Package::source_repository).
+pub struct CompositeRepository {
+    repositories: Vec<Member>,
+    repository: Repository,
+}
+
+struct Member {
+    name: Option<String>,
+    repository: Box<dyn PackageRepository>,
+}
+
+impl Member {
+    fn tag(&self, mut package: Package) -> Package {
+        if package.source_repository().is_none() {
+            package.set_source_repository(self.name.clone());
+        }
+        package
+    }
+}
+
+impl CompositeRepository {
+    pub fn new() -> Self {
+        Self {
+            repositories: Vec::new(),
+            repository: Repository::Local {
+                path: PathBuf::new(),
+            },
+        }
+    }
+
+    /// Appends a repository, consulted after the ones already added.
+    pub fn with_repository<R>(mut self, repository: R) -> Self
+    where
+        R: PackageRepository + 'static,
+    {
+        self.repositories.push(Member {
+            name: None,
+            repository: Box::new(repository),
+        });
+        self
+    }
+
+    /// Appends a repository whose packages are tagged with `name`.
+    pub fn with_named_repository<R>(mut self, name: impl Into<String>, repository: R) -> Self
+    where
+        R: PackageRepository + 'static,
+    {
+        self.repositories.push(Member {
+            name: Some(name.into()),
+            repository: Box::new(repository),
+        });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.repositories.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.repositories.is_empty()
+    }
+
+    /// Returns the first successful answer, or the first error when every
+    /// repository fails.
+    async fn first_success<'a, T, F>(&'a self, mut request: F) -> Result<T, UhpmError>
+    where
+        F: FnMut(&'a Member) -> BoxFuture<'a, Result<T, UhpmError>>,
+    {
+        let mut first_error = None;
+        for member in &self.repositories {
+            match request(member).await {
+                Ok(value) => return Ok(value),
+                Err(error) => {
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+        Err(first_error.unwrap_or_else(|| {
+            UhpmError::RepositoryUnavailable("no repositories configured".to_string())
+        }))
+    }
+
+    fn merge_indexes(indexes: Vec<RepositoryIndex>) -> RepositoryIndex {
+        let mut packages: BTreeMap<String, RepositoryPackageEntry> = BTreeMap::new();
+        for entry in indexes.into_iter().flat_map(|index| index.packages) {
+            let merged = packages
+                .entry(entry.name.clone())
+                .or_insert_with(|| RepositoryPackageEntry::new(entry.name, Vec::new()));
+            for version in entry.versions {
+                if !merged.versions.contains(&version) {
+                    merged.versions.push(version);
+                }
+            }
+            for (version, checksum) in entry.checksums {
+                merged.checksums.entry(version).or_insert(checksum);
+            }
+            for (version, size) in entry.sizes {
+                merged.sizes.entry(version).or_insert(size);
+            }
+            for version in entry.yanked {
+                if !merged.yanked.contains(&version) {
+                    merged.yanked.push(version);
+                }
+            }
+            for (version, message) in entry.deprecated {
+                merged.deprecated.entry(version).or_insert(message);
+            }
+        }
+
+        RepositoryIndex {
+            name: "composite".to_string(),
+            url: String::new(),
+            packages: packages.into_values().collect(),
+        }
+    }
+}
+
+impl Default for CompositeRepository {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl PackageRepository for CompositeRepository {
+    async fn get_package(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
+        self.first_success(|member| {
+            Box::pin(async move {
+                let package = member.repository.get_package(package_ref).await?;
+                Ok(member.tag(package))
+            })
+        })
+        .await
+    }
+
+    async fn search_packages(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
+        let mut seen = HashSet::new();
+        let mut packages = Vec::new();
+        for member in &self.repositories {
+            match member.repository.search_packages(query).await {
+                Ok(found) => packages.extend(
+                    found
+                        .into_iter()
+                        .filter(|package| seen.insert(PackageReference::from_package(package)))
+                        .map(|package| member.tag(package)),
+                ),
+                Err(error) => warn!(%error, "repository search failed"),
+            }
+        }
+        Ok(packages)
+    }
+
+    async fn get_package_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        let mut versions: Vec<String> = Vec::new();
+        for member in &self.repositories {
+            if let Ok(found) = member.repository.get_package_versions(package_name).await {
+                for version in found {
+                    if !versions.contains(&version) {
+                        versions.push(version);
+                    }
+                }
+            }
+        }
+        Ok(versions)
+    }
+
+    async fn get_latest_version(&self, package_name: &str) -> Result<String, UhpmError> {
+        self.get_package_versions(package_name)
+            .await?
+            .into_iter()
+            .filter_map(|version| Version::parse(&version).ok())
+            .max()
+            .map(|version| version.to_string())
+            .ok_or_else(|| UhpmError::PackageNotFound(package_name.to_string()))
+    }
+
+    async fn resolve_dependencies(
+        &self,
+        dependencies: &HashSet<Dependency>,
+    ) -> Result<Vec<Package>, UhpmError> {
+        self.first_success(|member| {
+            Box::pin(async move {
+                let packages = member.repository.resolve_dependencies(dependencies).await?;
+                Ok(packages
+                    .into_iter()
+                    .map(|package| member.tag(package))
+                    .collect())
+            })
+        })
+        .await
+    }
+
+    async fn download_package(&self, package_ref: &PackageReference) -> Result<Vec<u8>, UhpmError> {
+        self.first_success(|member| member.repository.download_package(package_ref))
+            .await
+    }
+
+    /// Versions yanked in any of the repositories.
+    async fn get_yanked_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        let mut yanked = Vec::new();
+        for member in &self.repositories {
+            if let Ok(versions) = member.repository.get_yanked_versions(package_name).await {
+                for version in versions {
+                    if !yanked.contains(&version) {
+                        yanked.push(version);
+                    }
+                }
+            }
+        }
+        Ok(yanked)
+    }
+
+    /// Size reported by the first repository that knows it.
+    async fn download_size(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<u64>, UhpmError> {
+        for member in &self.repositories {
+            if let Ok(Some(size)) = member.repository.download_size(package_ref).await {
+                return Ok(Some(size));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn download_package_with_source(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<(Vec<u8>, Option<String>), UhpmError> {
+        self.first_success(|member| member.repository.download_package_with_source(package_ref))
+            .await
+    }
+
+    async fn download_package_with_progress(
+        &self,
+        package_ref: &PackageReference,
+        on_progress: Box<dyn Fn(u64, u64) + Send + Sync>,
+    ) -> Result<(Vec<u8>, Option<String>), UhpmError> {
+        let on_progress: Arc<dyn Fn(u64, u64) + Send + Sync> = Arc::from(on_progress);
+        self.first_success(|member| {
+            let on_progress = on_progress.clone();
+            member.repository.download_package_with_progress(
+                package_ref,
+                Box::new(move |downloaded, total| on_progress(downloaded, total)),
+            )
+        })
+        .await
+    }
+
+    async fn get_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        let mut indexes = Vec::new();
+        for member in &self.repositories {
+            match member.repository.get_index().await {
+                Ok(index) => indexes.push(index),
+                Err(error) => warn!(%error, "repository index unavailable"),
+            }
+        }
+        Ok(Self::merge_indexes(indexes))
+    }
+
+    async fn update_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        let mut indexes = Vec::new();
+        let mut first_error = None;
+        for member in &self.repositories {
+            match member.repository.update_index().await {
+                Ok(index) => indexes.push(index),
+                Err(error) => {
+                    warn!(%error, "repository index update failed");
+                    first_error.get_or_insert(error);
+                }
+            }
+        }
+        match first_error {
+            Some(error) if indexes.is_empty() => Err(error),
+            _ => Ok(Self::merge_indexes(indexes)),
+        }
+    }
+
+    async fn is_available(&self) -> bool {
+        for member in &self.repositories {
+            if member.repository.is_available().await {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Returns the first repository's location.
+    fn get_repository(&self) -> &Repository {
+        self.repositories
+            .first()
+            .map(|member| member.repository.get_repository())
+            .unwrap_or(&self.repository)
+    }
+}
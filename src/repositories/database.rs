@@ -0,0 +1,1686 @@
+use crate::{
+    Architecture, BuildRequirement, Checksum, Dependency, DependencyKind, FileChecksum,
+    FileMetadata, FilePermissions, FileType, InstallMode, Installation, InstallationId,
+    OperatingSystem, OperationKind, OperationRecord, Package, PackageId, PackageReference,
+    PackageSource, Symlink, SymlinkType, Target, UhpmError, UpdatePolicy, VersionConstraint,
+    clock::SystemClock,
+    factories::InstallationFactory,
+    ports::{Clock, FileSystemOperations, PackageRepository},
+    repositories::PackageFilesRepository,
+};
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, params};
+use semver::{Version, VersionReq};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const PACKAGE_COLUMNS: &str = "id, name, version, author, source_kind, source_location, \
+     source_release, target_os, target_arch, checksum_algorithm, checksum_hash, installed, active, \
+     explicitly_installed, build_kind, build_consumer, pinned, update_policy, description";
+
+const OPERATION_COLUMNS: &str = "id, timestamp, kind, package_name, from_version, to_version, \
+     success, error_message, duration_ms, undoes";
+
+/// SQLite-backed storage for packages, installations and the operation history.
+pub struct DatabaseRepository {
+    connection: Connection,
+    clock: Arc<dyn Clock>,
+}
+
+struct PackageRow {
+    id: String,
+    name: String,
+    version: String,
+    author: String,
+    source_kind: String,
+    source_location: String,
+    source_release: Option<String>,
+    target_os: String,
+    target_arch: String,
+    checksum_algorithm: Option<String>,
+    checksum_hash: Option<String>,
+    installed: bool,
+    active: bool,
+    explicitly_installed: bool,
+    build_kind: Option<String>,
+    build_consumer: Option<String>,
+    pinned: bool,
+    update_policy: Option<String>,
+    description: Option<String>,
+}
+
+struct OperationRow {
+    id: i64,
+    timestamp: String,
+    kind: String,
+    package_name: String,
+    from_version: Option<String>,
+    to_version: Option<String>,
+    success: bool,
+    error_message: Option<String>,
+    duration_ms: i64,
+    undoes: Option<i64>,
+}
+
+impl DatabaseRepository {
+    /// Opens (or creates) the database at `db_path` and ensures the schema exists.
+    ///
+    /// A file that isn't a database or fails SQLite's integrity check is
+    /// reported as [`UhpmError::DatabaseCorrupted`].
+    pub fn new(db_path: &Path) -> Result<Self, UhpmError> {
+        let connection = Connection::open(db_path)?;
+        let repository = Self {
+            connection,
+            clock: Arc::new(SystemClock),
+        };
+        repository
+            .configure()
+            .and_then(|()| repository.check_integrity())
+            .map_err(corruption)?;
+        repository.init_tables()?;
+        Ok(repository)
+    }
+
+    /// Opens the database at `db_path` like [`new`](Self::new), replacing a
+    /// corrupted one instead of failing.
+    ///
+    /// The broken file is moved aside to `<name>.corrupt-<timestamp>` and an
+    /// empty database created in its place. The returned path is where it was
+    /// moved to, `None` when the database was fine. The packages it recorded
+    /// can be recovered with [`rebuild_from_store`](Self::rebuild_from_store).
+    pub fn open_or_recover(db_path: &Path) -> Result<(Self, Option<PathBuf>), UhpmError> {
+        match Self::new(db_path) {
+            Err(UhpmError::DatabaseCorrupted(reason)) => {
+                let moved = set_aside(db_path, SystemClock.now())?;
+                warn!(
+                    database = %db_path.display(),
+                    moved_to = %moved.display(),
+                    %reason,
+                    "database is corrupted, starting a new one"
+                );
+                Ok((Self::new(db_path)?, Some(moved)))
+            }
+            opened => opened.map(|repository| (repository, None)),
+        }
+    }
+
+    /// Records the packages found in the package store `files` manages, for
+    /// a database that lost them.
+    ///
+    /// Every `name@version` directory becomes an installed package, looked
+    /// up in `packages` or, when no repository knows it, built from its
+    /// `meta.toml`. Instlist entries whose target is a symlink into that
+    /// directory are recorded as its installation, and a package with any
+    /// such link is active. Packages placed by copying or hard linking can't
+    /// be told apart from unrelated files and are recorded inactive.
+    /// Everything is marked as explicitly installed so `autoremove` leaves it
+    /// alone.
+    ///
+    /// Returns the ids of the recorded packages.
+    pub async fn rebuild_from_store<R, FS>(
+        &mut self,
+        packages: &R,
+        files: &PackageFilesRepository<FS>,
+    ) -> Result<Vec<PackageId>, UhpmError>
+    where
+        R: PackageRepository + ?Sized,
+        FS: FileSystemOperations,
+    {
+        let factory = InstallationFactory::with_clock(self.clock.clone());
+        let mut rebuilt = Vec::new();
+        for package_ref in files.stored_packages().await? {
+            let package_id = PackageId::new(&package_ref.name, &package_ref.version);
+            let mut package = match packages.get_package(&package_ref).await {
+                Ok(package) => package,
+                Err(error) => {
+                    debug!(package = %package_ref, %error, "not in a repository, reading its meta");
+                    let Some(meta) = files.load_package_meta(&package_id).await? else {
+                        warn!(package = %package_ref, "no meta.toml, skipped");
+                        continue;
+                    };
+                    meta.into_package(
+                        package_ref.version.clone(),
+                        PackageSource::Local {
+                            path: files.get_package_path(&package_id),
+                        },
+                    )?
+                }
+            };
+
+            let mut installation = factory.create_installation(package_id.clone());
+            match files.load_package_instlist(&package_id).await {
+                Ok(symlinks) => {
+                    for symlink in symlinks {
+                        if files.is_linked(&symlink).await {
+                            installation.add_symlink(symlink);
+                        }
+                    }
+                }
+                Err(error) => warn!(package = %package_ref, %error, "instlist unreadable"),
+            }
+            let active = !installation.symlinks().is_empty();
+            if active {
+                installation.activate();
+            }
+            installation.set_size(files.package_size(&package_id).await.unwrap_or(0));
+
+            package.set_installed(true);
+            package.set_active(active);
+            package.set_explicit(true);
+            self.save_package(&package)?;
+            self.save_installation(&installation)?;
+            rebuilt.push(package_id);
+        }
+        Ok(rebuilt)
+    }
+
+    /// Creates a database that lives only in memory, mostly useful for tests.
+    pub fn in_memory() -> Result<Self, UhpmError> {
+        let connection = Connection::open_in_memory()?;
+        let repository = Self {
+            connection,
+            clock: Arc::new(SystemClock),
+        };
+        repository.configure()?;
+        repository.init_tables()?;
+        Ok(repository)
+    }
+
+    /// Sets the clock update times are recorded with, the system clock by
+    /// default.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Runs SQLite's integrity check, failing with what it found.
+    fn check_integrity(&self) -> Result<(), UhpmError> {
+        let result: String = self
+            .connection
+            .query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+        if result != "ok" {
+            return Err(UhpmError::DatabaseCorrupted(result));
+        }
+        Ok(())
+    }
+
+    fn configure(&self) -> Result<(), UhpmError> {
+        self.connection
+            .pragma_update_and_check(None, "journal_mode", "WAL", |_row| Ok(()))?;
+        self.connection.pragma_update(None, "foreign_keys", true)?;
+        Ok(())
+    }
+
+    fn init_tables(&self) -> Result<(), UhpmError> {
+        self.connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS packages (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                version TEXT NOT NULL,
+                author TEXT NOT NULL,
+                source_kind TEXT NOT NULL,
+                source_location TEXT NOT NULL,
+                source_release TEXT,
+                target_os TEXT NOT NULL,
+                target_arch TEXT NOT NULL,
+                checksum_algorithm TEXT,
+                checksum_hash TEXT,
+                installed INTEGER NOT NULL DEFAULT 0,
+                active INTEGER NOT NULL DEFAULT 0,
+                explicitly_installed INTEGER NOT NULL DEFAULT 0,
+                build_kind TEXT,
+                build_consumer TEXT,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                update_policy TEXT,
+                description TEXT,
+                updated_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS dependencies (
+                package_id TEXT NOT NULL REFERENCES packages(id) ON DELETE CASCADE,
+                name TEXT NOT NULL,
+                requirement TEXT NOT NULL,
+                kind TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS installations (
+                id TEXT PRIMARY KEY,
+                package_id TEXT NOT NULL,
+                installed_at TEXT NOT NULL,
+                active INTEGER NOT NULL DEFAULT 0,
+                install_mode TEXT NOT NULL DEFAULT 'symlink',
+                size INTEGER NOT NULL DEFAULT 0,
+                prefix TEXT
+            );
+
+            CREATE TABLE IF NOT EXISTS installed_files (
+                installation_id TEXT NOT NULL REFERENCES installations(id) ON DELETE CASCADE,
+                file_path TEXT NOT NULL,
+                size INTEGER NOT NULL,
+                checksum_algorithm TEXT,
+                checksum_hash TEXT,
+                permissions INTEGER NOT NULL,
+                file_type TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                modified_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS symlinks (
+                installation_id TEXT NOT NULL REFERENCES installations(id) ON DELETE CASCADE,
+                source_path TEXT NOT NULL,
+                target_path TEXT NOT NULL,
+                link_type TEXT NOT NULL,
+                created_at TEXT NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS operations (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp TEXT NOT NULL,
+                kind TEXT NOT NULL,
+                package_name TEXT NOT NULL,
+                from_version TEXT,
+                to_version TEXT,
+                success INTEGER NOT NULL,
+                error_message TEXT,
+                duration_ms INTEGER NOT NULL,
+                undoes INTEGER
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_dependencies_package ON dependencies(package_id);
+            CREATE INDEX IF NOT EXISTS idx_installations_package ON installations(package_id);
+            CREATE INDEX IF NOT EXISTS idx_installed_files_installation
+                ON installed_files(installation_id);
+            CREATE INDEX IF NOT EXISTS idx_installed_files_path ON installed_files(file_path);
+            CREATE INDEX IF NOT EXISTS idx_symlinks_installation ON symlinks(installation_id);
+            CREATE INDEX IF NOT EXISTS idx_symlinks_target ON symlinks(target_path);
+            CREATE INDEX IF NOT EXISTS idx_operations_package ON operations(package_name);",
+        )?;
+
+        // Packages recorded before the flag existed count as user-requested,
+        // so they are never picked up by autoremove.
+        self.add_column_if_missing(
+            "packages",
+            "explicitly_installed",
+            "INTEGER NOT NULL DEFAULT 1",
+        )?;
+        self.add_column_if_missing("packages", "build_kind", "TEXT")?;
+        self.add_column_if_missing("packages", "build_consumer", "TEXT")?;
+        self.add_column_if_missing("packages", "pinned", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("packages", "update_policy", "TEXT")?;
+        self.add_column_if_missing("packages", "description", "TEXT")?;
+        self.add_column_if_missing("installations", "size", "INTEGER NOT NULL DEFAULT 0")?;
+        self.add_column_if_missing("installations", "prefix", "TEXT")?;
+        self.add_column_if_missing("operations", "undoes", "INTEGER")?;
+        Ok(())
+    }
+
+    /// Adds a column to a table created by an older version of the schema.
+    fn add_column_if_missing(
+        &self,
+        table: &str,
+        column: &str,
+        definition: &str,
+    ) -> Result<(), UhpmError> {
+        let mut statement = self
+            .connection
+            .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+        let exists = statement
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?
+            .iter()
+            .any(|name| name == column);
+
+        if !exists {
+            self.connection.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition),
+                [],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Rebuilds the database file to reclaim space left behind by deleted rows.
+    ///
+    /// The WAL is checkpointed and truncated afterwards so the freed pages are
+    /// actually returned to the file system.
+    pub fn compact(&self) -> Result<(), UhpmError> {
+        self.connection.execute_batch("VACUUM; PRAGMA optimize;")?;
+        self.connection
+            .query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_row| Ok(()))?;
+        Ok(())
+    }
+
+    /// Inserts or replaces a package together with its dependencies.
+    pub fn save_package(&mut self, package: &Package) -> Result<(), UhpmError> {
+        let tx = self.connection.transaction()?;
+        Self::insert_package(&tx, package, self.clock.now())?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Inserts or replaces several packages and their dependencies in a
+    /// single transaction: either all of them are saved or none is.
+    pub fn save_packages(&mut self, packages: &[Package]) -> Result<(), UhpmError> {
+        self.save_installs(packages, &[])
+    }
+
+    fn insert_package(
+        connection: &Connection,
+        package: &Package,
+        updated_at: DateTime<Utc>,
+    ) -> Result<(), UhpmError> {
+        let (source_kind, source_location, source_release) = source_columns(package.source());
+        let (checksum_algorithm, checksum_hash) = match package.checksum() {
+            Some(checksum) => (Some(&checksum.algorithm), Some(&checksum.hash)),
+            None => (None, None),
+        };
+        let (build_kind, build_consumer) = match package.build_requirement() {
+            Some(requirement) => (
+                Some(dependency_kind_to_str(&requirement.kind)),
+                Some(requirement.consumer.as_str()),
+            ),
+            None => (None, None),
+        };
+
+        connection.execute(
+            "INSERT OR REPLACE INTO packages (
+                id, name, version, author, source_kind, source_location, source_release,
+                target_os, target_arch, checksum_algorithm, checksum_hash, installed, active,
+                explicitly_installed, build_kind, build_consumer, pinned, update_policy,
+                description, updated_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17,
+                      ?18, ?19, ?20)",
+            params![
+                package.id().as_str(),
+                package.name(),
+                package.version().to_string(),
+                package.author(),
+                source_kind,
+                source_location,
+                source_release,
+                package.target().os.to_string(),
+                package.target().arch.to_string(),
+                checksum_algorithm,
+                checksum_hash,
+                package.is_installed(),
+                package.is_active(),
+                package.is_explicit(),
+                build_kind,
+                build_consumer,
+                package.is_pinned(),
+                package.update_policy().map(|policy| policy.to_string()),
+                package.description(),
+                updated_at.to_rfc3339(),
+            ],
+        )?;
+
+        connection.execute(
+            "DELETE FROM dependencies WHERE package_id = ?1",
+            params![package.id().as_str()],
+        )?;
+
+        for dependency in package.dependencies() {
+            connection.execute(
+                "INSERT INTO dependencies (package_id, name, requirement, kind)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    package.id().as_str(),
+                    dependency.name,
+                    dependency.constraint.requirement.to_string(),
+                    dependency_kind_to_str(&dependency.kind),
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_package(&self, package_id: &PackageId) -> Result<Option<Package>, UhpmError> {
+        let row = self
+            .connection
+            .query_row(
+                &format!("SELECT {} FROM packages WHERE id = ?1", PACKAGE_COLUMNS),
+                params![package_id.as_str()],
+                Self::package_row,
+            )
+            .optional()?;
+
+        row.map(|row| self.package_from_row(row)).transpose()
+    }
+
+    pub fn list_installed_packages(&self) -> Result<Vec<Package>, UhpmError> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {} FROM packages WHERE installed = 1",
+            PACKAGE_COLUMNS
+        ))?;
+        let rows = statement
+            .query_map([], Self::package_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut packages = rows
+            .into_iter()
+            .map(|row| self.package_from_row(row))
+            .collect::<Result<Vec<_>, _>>()?;
+        packages.sort_by_cached_key(PackageReference::from_package);
+
+        Ok(packages)
+    }
+
+    /// Returns installed packages the user asked for, leaving out those that
+    /// were only pulled in as dependencies.
+    pub fn list_explicit(&self) -> Result<Vec<Package>, UhpmError> {
+        Ok(self
+            .list_installed_packages()?
+            .into_iter()
+            .filter(Package::is_explicit)
+            .collect())
+    }
+
+    /// Returns the installed packages that declare a dependency on `package_name`.
+    pub fn get_dependents(&self, package_name: &str) -> Result<Vec<PackageId>, UhpmError> {
+        let mut statement = self.connection.prepare(
+            "SELECT DISTINCT p.id FROM dependencies d
+             JOIN packages p ON p.id = d.package_id
+             WHERE d.name = ?1 AND p.installed = 1 AND p.name != ?1
+             ORDER BY p.id",
+        )?;
+        let ids = statement
+            .query_map(params![package_name], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        ids.iter().map(|id| parse_package_id(id)).collect()
+    }
+
+    pub fn delete_package(&mut self, package_id: &PackageId) -> Result<(), UhpmError> {
+        self.connection.execute(
+            "DELETE FROM packages WHERE id = ?1",
+            params![package_id.as_str()],
+        )?;
+        Ok(())
+    }
+
+    fn package_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<PackageRow> {
+        Ok(PackageRow {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            version: row.get("version")?,
+            author: row.get("author")?,
+            source_kind: row.get("source_kind")?,
+            source_location: row.get("source_location")?,
+            source_release: row.get("source_release")?,
+            target_os: row.get("target_os")?,
+            target_arch: row.get("target_arch")?,
+            checksum_algorithm: row.get("checksum_algorithm")?,
+            checksum_hash: row.get("checksum_hash")?,
+            installed: row.get("installed")?,
+            active: row.get("active")?,
+            explicitly_installed: row.get("explicitly_installed")?,
+            build_kind: row.get("build_kind")?,
+            build_consumer: row.get("build_consumer")?,
+            pinned: row.get("pinned")?,
+            update_policy: row.get("update_policy")?,
+            description: row.get("description")?,
+        })
+    }
+
+    fn package_from_row(&self, row: PackageRow) -> Result<Package, UhpmError> {
+        let version = parse_version(&row.version)?;
+        let checksum = match (row.checksum_algorithm, row.checksum_hash) {
+            (Some(algorithm), Some(hash)) => Some(Checksum { algorithm, hash }),
+            _ => None,
+        };
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT name, requirement, kind FROM dependencies WHERE package_id = ?1")?;
+        let dependency_rows = statement
+            .query_map(params![row.id], |dep| {
+                Ok((
+                    dep.get::<_, String>(0)?,
+                    dep.get::<_, String>(1)?,
+                    dep.get::<_, String>(2)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let dependencies = dependency_rows
+            .into_iter()
+            .map(|(name, requirement, kind)| {
+                Ok(Dependency {
+                    name,
+                    constraint: VersionConstraint {
+                        requirement: VersionReq::parse(&requirement)
+                            .map_err(|e| UhpmError::DatabaseError(e.to_string()))?,
+                    },
+                    kind: dependency_kind_from_str(&kind)?,
+                    provides: None,
+                    features: Vec::new(),
+                })
+            })
+            .collect::<Result<_, UhpmError>>()?;
+
+        let mut package = Package::new(
+            PackageId::new(&row.name, &version),
+            row.name,
+            version,
+            row.author,
+            source_from_columns(&row.source_kind, row.source_location, row.source_release)?,
+            Target {
+                os: OperatingSystem::from(row.target_os.as_str()),
+                arch: Architecture::from(row.target_arch.as_str()),
+            },
+            checksum,
+            dependencies,
+            row.installed,
+            row.active,
+        );
+        package.set_explicit(row.explicitly_installed);
+        package.set_pinned(row.pinned);
+        package.set_description(row.description);
+        if let Some(policy) = row.update_policy {
+            package.set_update_policy(Some(UpdatePolicy::try_from(policy.as_str())?));
+        }
+        if let (Some(kind), Some(consumer)) = (row.build_kind, row.build_consumer) {
+            package.set_build_requirement(Some(BuildRequirement {
+                kind: dependency_kind_from_str(&kind)?,
+                consumer: parse_package_id(&consumer)?,
+            }));
+        }
+        Ok(package)
+    }
+
+    /// Inserts or replaces an installation together with its files and symlinks.
+    pub fn save_installation(&mut self, installation: &Installation) -> Result<(), UhpmError> {
+        let tx = self.connection.transaction()?;
+        Self::insert_installation(&tx, installation)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Records freshly installed packages together with their installations
+    /// in a single transaction: either all of them are saved or none is.
+    pub fn save_installs(
+        &mut self,
+        packages: &[Package],
+        installations: &[Installation],
+    ) -> Result<(), UhpmError> {
+        let updated_at = self.clock.now();
+        let tx = self.connection.transaction()?;
+        for package in packages {
+            Self::insert_package(&tx, package, updated_at)?;
+        }
+        for installation in installations {
+            Self::insert_installation(&tx, installation)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn insert_installation(tx: &Connection, installation: &Installation) -> Result<(), UhpmError> {
+        let installation_id = installation.id().to_string();
+
+        tx.execute(
+            "INSERT OR REPLACE INTO installations (
+                id, package_id, installed_at, active, install_mode, size, prefix
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                installation_id,
+                installation.package_id().as_str(),
+                installation.installed_at().to_rfc3339(),
+                installation.is_active(),
+                installation.install_mode().to_string(),
+                installation.size() as i64,
+                installation
+                    .prefix()
+                    .map(|prefix| prefix.to_string_lossy().into_owned()),
+            ],
+        )?;
+
+        // Only one version of a package may be active at a time.
+        if installation.is_active() {
+            let package_id = installation.package_id();
+            tx.execute(
+                "UPDATE installations SET active = 0
+                 WHERE id != ?1 AND package_id != ?2
+                   AND substr(package_id, 1, length(?3) + 1) = ?3 || '@'",
+                params![installation_id, package_id.as_str(), package_id.name()],
+            )?;
+            tx.execute(
+                "UPDATE packages SET active = 0 WHERE name = ?1 AND id != ?2",
+                params![package_id.name(), package_id.as_str()],
+            )?;
+        }
+
+        tx.execute(
+            "DELETE FROM installed_files WHERE installation_id = ?1",
+            params![installation_id],
+        )?;
+        tx.execute(
+            "DELETE FROM symlinks WHERE installation_id = ?1",
+            params![installation_id],
+        )?;
+
+        for (path, metadata) in installation.installed_files() {
+            let (checksum_algorithm, checksum_hash) = match &metadata.checksum {
+                Some(checksum) => (Some(&checksum.algorithm), Some(&checksum.hash)),
+                None => (None, None),
+            };
+
+            tx.execute(
+                "INSERT INTO installed_files (
+                    installation_id, file_path, size, checksum_algorithm, checksum_hash,
+                    permissions, file_type, created_at, modified_at
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    installation_id,
+                    path.to_string_lossy(),
+                    metadata.size as i64,
+                    checksum_algorithm,
+                    checksum_hash,
+                    metadata.permissions.octal(),
+                    metadata.file_type.to_string(),
+                    metadata.created_at.to_rfc3339(),
+                    metadata.modified_at.to_rfc3339(),
+                ],
+            )?;
+        }
+
+        for symlink in installation.symlinks() {
+            tx.execute(
+                "INSERT INTO symlinks (
+                    installation_id, source_path, target_path, link_type, created_at
+                ) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    installation_id,
+                    symlink.source.to_string_lossy(),
+                    symlink.target.to_string_lossy(),
+                    symlink.link_type.to_string(),
+                    symlink.metadata.created_at.to_rfc3339(),
+                ],
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn get_installation(
+        &self,
+        installation_id: &InstallationId,
+    ) -> Result<Option<Installation>, UhpmError> {
+        let row = self
+            .connection
+            .query_row(
+                "SELECT package_id, installed_at, active, install_mode, size, prefix
+                 FROM installations WHERE id = ?1",
+                params![installation_id.to_string()],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, bool>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, i64>(4)?,
+                        row.get::<_, Option<String>>(5)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        let Some((package_id, installed_at, active, install_mode, size, prefix)) = row else {
+            return Ok(None);
+        };
+
+        let mut installation = InstallationFactory::from_existing(
+            installation_id.clone(),
+            parse_package_id(&package_id)?,
+            self.load_installed_files(installation_id)?
+                .into_iter()
+                .collect(),
+            self.load_symlinks(installation_id)?,
+            parse_timestamp(&installed_at)?,
+            active,
+        )?;
+        installation.set_install_mode(InstallMode::try_from(install_mode.as_str())?);
+        installation.set_size(size.max(0) as u64);
+        installation.set_prefix(prefix.map(PathBuf::from));
+
+        Ok(Some(installation))
+    }
+
+    /// Returns the active installation of a package, if any.
+    pub fn get_active_installation(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Option<Installation>, UhpmError> {
+        let installation_id = self
+            .connection
+            .query_row(
+                "SELECT id FROM installations WHERE package_id = ?1 AND active = 1 LIMIT 1",
+                params![package_id.as_str()],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+
+        match installation_id {
+            Some(id) => self.get_installation(&parse_installation_id(&id)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns every installation recorded for a package, oldest first.
+    pub fn list_installations(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Vec<Installation>, UhpmError> {
+        let mut statement = self
+            .connection
+            .prepare("SELECT id FROM installations WHERE package_id = ?1 ORDER BY installed_at")?;
+        let ids = statement
+            .query_map(params![package_id.as_str()], |row| row.get::<_, String>(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut installations = Vec::new();
+        for id in ids {
+            if let Some(installation) = self.get_installation(&parse_installation_id(&id)?)? {
+                installations.push(installation);
+            }
+        }
+
+        Ok(installations)
+    }
+
+    pub fn delete_installation(
+        &mut self,
+        installation_id: &InstallationId,
+    ) -> Result<(), UhpmError> {
+        self.connection.execute(
+            "DELETE FROM installations WHERE id = ?1",
+            params![installation_id.to_string()],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the package whose installation placed `path`.
+    ///
+    /// Installed files and symlink targets are matched exactly; a path below a
+    /// directory symlink belongs to the package that created the link. Active
+    /// installations win over inactive ones.
+    pub fn find_owner(&self, path: &Path) -> Result<Option<PackageId>, UhpmError> {
+        let exact = self
+            .connection
+            .query_row(
+                "SELECT i.package_id FROM installations i
+                 WHERE i.id IN (
+                     SELECT installation_id FROM installed_files WHERE file_path = ?1
+                     UNION
+                     SELECT installation_id FROM symlinks WHERE target_path = ?1
+                 )
+                 ORDER BY i.active DESC LIMIT 1",
+                params![path.to_string_lossy()],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        if let Some(package_id) = exact {
+            return parse_package_id(&package_id).map(Some);
+        }
+
+        let mut statement = self.connection.prepare(
+            "SELECT i.package_id FROM symlinks s
+             JOIN installations i ON i.id = s.installation_id
+             WHERE s.target_path = ?1 AND s.link_type = 'directory'
+             ORDER BY i.active DESC LIMIT 1",
+        )?;
+        for ancestor in path.ancestors().skip(1) {
+            let owner = statement
+                .query_row(params![ancestor.to_string_lossy()], |row| {
+                    row.get::<_, String>(0)
+                })
+                .optional()?;
+            if let Some(package_id) = owner {
+                return parse_package_id(&package_id).map(Some);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn load_installed_files(
+        &self,
+        installation_id: &InstallationId,
+    ) -> Result<Vec<(PathBuf, FileMetadata)>, UhpmError> {
+        let mut statement = self.connection.prepare(
+            "SELECT file_path, size, checksum_algorithm, checksum_hash, permissions, file_type,
+                    created_at, modified_at
+             FROM installed_files WHERE installation_id = ?1",
+        )?;
+        let rows = statement
+            .query_map(params![installation_id.to_string()], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, u32>(4)?,
+                    row.get::<_, String>(5)?,
+                    row.get::<_, String>(6)?,
+                    row.get::<_, String>(7)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(
+                |(path, size, algorithm, hash, permissions, file_type, created, modified)| {
+                    let path = PathBuf::from(path);
+                    let mut metadata = FileMetadata::new(path.clone(), size as u64)
+                        .with_permissions(FilePermissions::from_octal(permissions))
+                        .with_file_type(FileType::try_from(file_type.as_str())?);
+                    if let (Some(algorithm), Some(hash)) = (algorithm, hash) {
+                        metadata.checksum = Some(FileChecksum { algorithm, hash });
+                    }
+                    metadata.created_at = parse_timestamp(&created)?;
+                    metadata.modified_at = parse_timestamp(&modified)?;
+                    Ok((path, metadata))
+                },
+            )
+            .collect()
+    }
+
+    fn load_symlinks(&self, installation_id: &InstallationId) -> Result<Vec<Symlink>, UhpmError> {
+        let mut statement = self.connection.prepare(
+            "SELECT source_path, target_path, link_type, created_at
+             FROM symlinks WHERE installation_id = ?1",
+        )?;
+        let rows = statement
+            .query_map(params![installation_id.to_string()], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        rows.into_iter()
+            .map(|(source, target, link_type, created_at)| {
+                let link_type = match link_type.as_str() {
+                    "file" => SymlinkType::File,
+                    "directory" => SymlinkType::Directory,
+                    other => {
+                        return Err(UhpmError::DatabaseError(format!(
+                            "Invalid symlink type: {}",
+                            other
+                        )));
+                    }
+                };
+                let mut symlink = Symlink::new(source, target, link_type);
+                symlink.metadata.created_at = parse_timestamp(&created_at)?;
+                Ok(symlink)
+            })
+            .collect()
+    }
+
+    /// Appends an entry to the operation history and returns its id.
+    pub fn record_operation(&self, record: &OperationRecord) -> Result<i64, UhpmError> {
+        self.connection.execute(
+            "INSERT INTO operations (
+                timestamp, kind, package_name, from_version, to_version, success,
+                error_message, duration_ms, undoes
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                record.timestamp.to_rfc3339(),
+                record.kind.to_string(),
+                record.package_name,
+                record.from_version.as_ref().map(|v| v.to_string()),
+                record.to_version.as_ref().map(|v| v.to_string()),
+                record.success,
+                record.error_message,
+                record.duration.as_millis() as i64,
+                record.undoes,
+            ],
+        )?;
+        Ok(self.connection.last_insert_rowid())
+    }
+
+    /// Returns the operation history, newest first.
+    ///
+    /// # Arguments
+    /// * `limit` - Maximum number of records to return
+    /// * `package_name` - Only return records for this package
+    pub fn get_history(
+        &self,
+        limit: Option<usize>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<OperationRecord>, UhpmError> {
+        let mut sql = format!("SELECT {} FROM operations", OPERATION_COLUMNS);
+        if package_name.is_some() {
+            sql.push_str(" WHERE package_name = ?1");
+        }
+        sql.push_str(" ORDER BY id DESC");
+        if let Some(limit) = limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        let mut statement = self.connection.prepare(&sql)?;
+        let rows = match package_name {
+            Some(name) => statement
+                .query_map(params![name], Self::operation_row)?
+                .collect::<Result<Vec<_>, _>>()?,
+            None => statement
+                .query_map([], Self::operation_row)?
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+
+        rows.into_iter().map(operation_from_row).collect()
+    }
+
+    fn operation_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<OperationRow> {
+        Ok(OperationRow {
+            id: row.get("id")?,
+            timestamp: row.get("timestamp")?,
+            kind: row.get("kind")?,
+            package_name: row.get("package_name")?,
+            from_version: row.get("from_version")?,
+            to_version: row.get("to_version")?,
+            success: row.get("success")?,
+            error_message: row.get("error_message")?,
+            duration_ms: row.get("duration_ms")?,
+            undoes: row.get("undoes")?,
+        })
+    }
+}
+
+/// Reports SQLite errors meaning the file is damaged or no database at all
+/// as [`UhpmError::DatabaseCorrupted`].
+fn corruption(error: UhpmError) -> UhpmError {
+    match error {
+        UhpmError::RusqliteError(rusqlite::Error::SqliteFailure(failure, message))
+            if matches!(
+                failure.code,
+                rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase
+            ) =>
+        {
+            UhpmError::DatabaseCorrupted(message.unwrap_or_else(|| failure.to_string()))
+        }
+        other => other,
+    }
+}
+
+/// Renames the database at `db_path`, along with its WAL and shared memory
+/// files, to `<name>.corrupt-<timestamp>` and returns the new path.
+fn set_aside(db_path: &Path, now: DateTime<Utc>) -> Result<PathBuf, UhpmError> {
+    let suffix = format!(".corrupt-{}", now.format("%Y%m%dT%H%M%SZ"));
+    let moved = |path: &Path| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(&suffix);
+        PathBuf::from(name)
+    };
+
+    for extra in ["-wal", "-shm"] {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(extra);
+        let path = PathBuf::from(path);
+        if path.exists() {
+            std::fs::rename(&path, moved(&path))?;
+        }
+    }
+    let target = moved(db_path);
+    std::fs::rename(db_path, &target)?;
+    Ok(target)
+}
+
+fn operation_from_row(row: OperationRow) -> Result<OperationRecord, UhpmError> {
+    Ok(OperationRecord {
+        id: Some(row.id),
+        timestamp: parse_timestamp(&row.timestamp)?,
+        kind: OperationKind::try_from(row.kind.as_str())?,
+        package_name: row.package_name,
+        from_version: row.from_version.as_deref().map(parse_version).transpose()?,
+        to_version: row.to_version.as_deref().map(parse_version).transpose()?,
+        success: row.success,
+        error_message: row.error_message,
+        duration: Duration::from_millis(row.duration_ms.max(0) as u64),
+        undoes: row.undoes,
+    })
+}
+
+fn source_columns(source: &PackageSource) -> (&'static str, String, Option<String>) {
+    match source {
+        PackageSource::Git { url, release } => ("git", url.clone(), release.clone()),
+        PackageSource::Http { url } => ("http", url.clone(), None),
+        PackageSource::Local { path } => ("local", path.to_string_lossy().to_string(), None),
+    }
+}
+
+fn source_from_columns(
+    kind: &str,
+    location: String,
+    release: Option<String>,
+) -> Result<PackageSource, UhpmError> {
+    match kind {
+        "git" => Ok(PackageSource::Git {
+            url: location,
+            release,
+        }),
+        "http" => Ok(PackageSource::Http { url: location }),
+        "local" => Ok(PackageSource::Local {
+            path: PathBuf::from(location),
+        }),
+        other => Err(UhpmError::DatabaseError(format!(
+            "Invalid package source kind: {}",
+            other
+        ))),
+    }
+}
+
+fn dependency_kind_to_str(kind: &DependencyKind) -> &'static str {
+    match kind {
+        DependencyKind::Required => "required",
+        DependencyKind::Optional => "optional",
+        DependencyKind::Build => "build",
+        DependencyKind::Dev => "dev",
+    }
+}
+
+fn dependency_kind_from_str(kind: &str) -> Result<DependencyKind, UhpmError> {
+    match kind {
+        "required" => Ok(DependencyKind::Required),
+        "optional" => Ok(DependencyKind::Optional),
+        "build" => Ok(DependencyKind::Build),
+        "dev" => Ok(DependencyKind::Dev),
+        other => Err(UhpmError::DatabaseError(format!(
+            "Invalid dependency kind: {}",
+            other
+        ))),
+    }
+}
+
+fn parse_version(value: &str) -> Result<Version, UhpmError> {
+    Version::parse(value)
+        .map_err(|e| UhpmError::DatabaseError(format!("Invalid version '{}': {}", value, e)))
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, UhpmError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|e| UhpmError::DatabaseError(format!("Invalid timestamp '{}': {}", value, e)))
+}
+
+fn parse_package_id(value: &str) -> Result<PackageId, UhpmError> {
+    let (name, version) = value
+        .split_once('@')
+        .ok_or_else(|| UhpmError::DatabaseError(format!("Invalid package id: {}", value)))?;
+    Ok(PackageId::new(name, &parse_version(version)?))
+}
+
+fn parse_installation_id(value: &str) -> Result<InstallationId, UhpmError> {
+    InstallationId::try_from(value).map_err(|e| UhpmError::DatabaseError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::factories::PackageFactory;
+
+    fn test_package(name: &str, version: &str) -> Package {
+        PackageFactory::create(
+            name.to_string(),
+            Version::parse(version).unwrap(),
+            "John Doe".to_string(),
+            PackageSource::Local {
+                path: "/tmp".into(),
+            },
+            Target::current(),
+            None,
+            vec![],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_package_round_trip() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let mut package = test_package("my-package", "1.2.3");
+        package.set_installed(true);
+
+        db.save_package(&package).unwrap();
+
+        let loaded = db.get_package(package.id()).unwrap().unwrap();
+        assert_eq!(loaded, package);
+        assert!(loaded.is_installed());
+        assert_eq!(db.list_installed_packages().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_build_requirement_round_trip() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let consumer = test_package("app", "1.0.0");
+        let mut package = test_package("compiler", "2.0.0");
+        package.set_installed(true);
+        package.set_build_requirement(Some(BuildRequirement {
+            kind: DependencyKind::Dev,
+            consumer: consumer.id().clone(),
+        }));
+
+        db.save_package(&package).unwrap();
+        db.save_package(&consumer).unwrap();
+
+        let loaded = db.get_package(package.id()).unwrap().unwrap();
+        assert_eq!(loaded.build_requirement(), package.build_requirement());
+        assert!(
+            !db.get_package(consumer.id())
+                .unwrap()
+                .unwrap()
+                .is_build_only()
+        );
+    }
+
+    #[test]
+    fn test_corrupted_database_is_set_aside_and_rebuilt_from_the_store() {
+        use crate::fs::TokioFileSystem;
+        use crate::test_utils::{MemoryRepository, block_on};
+
+        let dir = std::env::temp_dir().join(format!("uhpm-{}", uuid::Uuid::new_v4()));
+        let packages_dir = dir.join("packages");
+        let db_path = dir.join("packages.db");
+        for (name, meta) in [
+            (
+                "tool",
+                "name = \"tool\"\nversion = \"1.0.0\"\ndependencies = [\"lib@^1\"]\n",
+            ),
+            ("lib", "name = \"lib\"\nversion = \"1.0.0\"\n"),
+        ] {
+            let package_dir = packages_dir.join(format!("{}@1.0.0", name));
+            std::fs::create_dir_all(package_dir.join("bin")).unwrap();
+            std::fs::write(package_dir.join("meta.toml"), meta).unwrap();
+            std::fs::write(package_dir.join("bin").join(name), b"#!/bin/sh\n").unwrap();
+            let target = dir.join("bin").join(name);
+            std::fs::write(
+                package_dir.join("instlist"),
+                format!("bin/{} {}\n", name, target.display()),
+            )
+            .unwrap();
+        }
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(
+            packages_dir.join("tool@1.0.0/bin/tool"),
+            dir.join("bin/tool"),
+        )
+        .unwrap();
+        std::fs::write(&db_path, b"this is not a database").unwrap();
+
+        assert!(matches!(
+            DatabaseRepository::new(&db_path),
+            Err(UhpmError::DatabaseCorrupted(_))
+        ));
+        let (mut db, moved) = DatabaseRepository::open_or_recover(&db_path).unwrap();
+        let moved = moved.unwrap();
+        assert!(
+            moved
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("packages.db.corrupt-")
+        );
+        assert_eq!(std::fs::read(&moved).unwrap(), b"this is not a database");
+        assert!(db.list_installed_packages().unwrap().is_empty());
+
+        let files = PackageFilesRepository::new(TokioFileSystem::new(), packages_dir.clone());
+        let rebuilt = block_on(db.rebuild_from_store(&MemoryRepository::new(), &files)).unwrap();
+        assert_eq!(rebuilt.len(), 2);
+
+        let mut installed = db.list_installed_packages().unwrap();
+        installed.sort_by(|a, b| a.name().cmp(b.name()));
+        let names = installed.iter().map(|p| p.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["lib", "tool"]);
+        assert!(installed.iter().all(|package| package.is_explicit()));
+        assert_eq!(installed[1].dependencies().len(), 1);
+        #[cfg(unix)]
+        {
+            assert!(!installed[0].is_active());
+            assert!(installed[1].is_active());
+            let installation = db
+                .get_active_installation(installed[1].id())
+                .unwrap()
+                .unwrap();
+            assert_eq!(installation.symlinks().len(), 1);
+            assert_eq!(installation.symlinks()[0].target, dir.join("bin/tool"));
+        }
+        drop(db);
+
+        // The fresh database keeps what was rebuilt.
+        let (db, moved) = DatabaseRepository::open_or_recover(&db_path).unwrap();
+        assert!(moved.is_none());
+        assert_eq!(db.list_installed_packages().unwrap().len(), 2);
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_pin_and_update_policy_round_trip() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let mut package = test_package("tool", "1.0.0");
+        package.set_pinned(true);
+        package.set_update_policy(Some(UpdatePolicy::Patch));
+
+        db.save_package(&package).unwrap();
+
+        let loaded = db.get_package(package.id()).unwrap().unwrap();
+        assert!(loaded.is_pinned());
+        assert_eq!(loaded.update_policy(), Some(UpdatePolicy::Patch));
+    }
+
+    #[test]
+    fn test_save_packages_in_one_call() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let mut packages = (0..100)
+            .map(|i| test_package(&format!("package-{}", i), "1.0.0"))
+            .collect::<Vec<_>>();
+        packages[7].set_description(Some("first".to_string()));
+        db.save_packages(&packages).unwrap();
+
+        for package in &packages {
+            let loaded = db.get_package(package.id()).unwrap().unwrap();
+            assert_eq!(loaded.name(), package.name());
+        }
+
+        // Saving again replaces the rows instead of failing on them.
+        packages[7].set_description(Some("second".to_string()));
+        db.save_packages(&packages).unwrap();
+        let loaded = db.get_package(packages[7].id()).unwrap().unwrap();
+        assert_eq!(loaded.description(), Some("second"));
+        let count: i64 = db
+            .connection
+            .query_row("SELECT COUNT(*) FROM packages", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 100);
+    }
+
+    #[test]
+    fn test_description_round_trip() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let mut described = test_package("tool", "1.0.0");
+        described.set_description(Some("Does tool things".to_string()));
+        let plain = test_package("lib", "1.0.0");
+
+        db.save_package(&described).unwrap();
+        db.save_package(&plain).unwrap();
+
+        let loaded = db.get_package(described.id()).unwrap().unwrap();
+        assert_eq!(loaded.description(), Some("Does tool things"));
+        assert_eq!(
+            db.get_package(plain.id()).unwrap().unwrap().description(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_update_time_comes_from_the_clock() {
+        let clock = MockClock::new(parse_timestamp("2024-05-01T12:00:00+00:00").unwrap());
+        let mut db = DatabaseRepository::in_memory()
+            .unwrap()
+            .with_clock(clock.clone());
+        let package = test_package("tool", "1.0.0");
+        let updated_at = |db: &DatabaseRepository| {
+            db.connection
+                .query_row(
+                    "SELECT updated_at FROM packages WHERE id = 'tool@1.0.0'",
+                    [],
+                    |row| row.get::<_, String>(0),
+                )
+                .unwrap()
+        };
+
+        db.save_package(&package).unwrap();
+        assert_eq!(updated_at(&db), "2024-05-01T12:00:00+00:00");
+
+        clock.advance(Duration::from_secs(3600));
+        db.save_package(&package).unwrap();
+        assert_eq!(updated_at(&db), "2024-05-01T13:00:00+00:00");
+    }
+
+    #[test]
+    fn test_explicit_flag_and_migration() {
+        let db_path = std::env::temp_dir().join(format!("uhpm-{}.db", uuid::Uuid::new_v4()));
+        {
+            let db = DatabaseRepository::new(&db_path).unwrap();
+            db.connection
+                .execute_batch(
+                    "ALTER TABLE packages DROP COLUMN explicitly_installed;
+                     ALTER TABLE packages DROP COLUMN description;
+                     INSERT INTO packages (
+                         id, name, version, author, source_kind, source_location,
+                         target_os, target_arch, installed, active, updated_at
+                     ) VALUES (
+                         'legacy@1.0.0', 'legacy', '1.0.0', 'John Doe', 'local', '/tmp',
+                         'linux', 'x86_64', 1, 1, '2024-01-01T00:00:00+00:00'
+                     );",
+                )
+                .unwrap();
+        }
+
+        let mut db = DatabaseRepository::new(&db_path).unwrap();
+        let mut dependency = test_package("dependency", "1.0.0");
+        dependency.set_installed(true);
+        db.save_package(&dependency).unwrap();
+
+        let explicit = db
+            .list_explicit()
+            .unwrap()
+            .into_iter()
+            .map(|package| package.name().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(explicit, ["legacy"]);
+        let legacy = PackageId::new("legacy", &Version::new(1, 0, 0));
+        assert_eq!(
+            db.get_package(&legacy).unwrap().unwrap().description(),
+            None
+        );
+
+        drop(db);
+        for suffix in ["db", "db-wal", "db-shm"] {
+            let _ = std::fs::remove_file(db_path.with_extension(suffix));
+        }
+    }
+
+    #[test]
+    fn test_get_dependents() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let dependency = |name: &str| Dependency {
+            name: name.to_string(),
+            constraint: VersionConstraint {
+                requirement: VersionReq::STAR,
+            },
+            kind: DependencyKind::Required,
+            provides: None,
+            features: vec![],
+        };
+
+        for (name, installed) in [("app", true), ("tool", true), ("stale", false)] {
+            let mut package = PackageFactory::create(
+                name.to_string(),
+                Version::new(1, 0, 0),
+                "John Doe".to_string(),
+                PackageSource::Local {
+                    path: "/tmp".into(),
+                },
+                Target::current(),
+                None,
+                vec![dependency("lib")],
+            )
+            .unwrap();
+            package.set_installed(installed);
+            db.save_package(&package).unwrap();
+        }
+
+        let dependents = db.get_dependents("lib").unwrap();
+        assert_eq!(
+            dependents,
+            [
+                PackageId::new("app", &Version::new(1, 0, 0)),
+                PackageId::new("tool", &Version::new(1, 0, 0))
+            ]
+        );
+        assert!(db.get_dependents("app").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_installation_round_trip() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let package = test_package("my-package", "1.0.0");
+
+        let mut installation = InstallationFactory::create(package.id().clone());
+        installation.add_installed_file(
+            PathBuf::from("/store/bin/tool"),
+            FileMetadata::new(PathBuf::from("/store/bin/tool"), 42),
+        );
+        installation.add_symlink(Symlink::file("/store/bin/tool", "/home/user/bin/tool"));
+        installation.set_install_mode(InstallMode::Hardlink);
+        installation.set_size(1024);
+        installation.set_prefix(Some(PathBuf::from("/home/user/my prefix")));
+        installation.activate();
+        db.save_installation(&installation).unwrap();
+
+        let loaded = db.get_active_installation(package.id()).unwrap().unwrap();
+        assert_eq!(loaded.id(), installation.id());
+        assert_eq!(loaded.installed_files().len(), 1);
+        assert_eq!(loaded.symlinks().len(), 1);
+        assert_eq!(loaded.install_mode(), InstallMode::Hardlink);
+        assert_eq!(loaded.size(), 1024);
+        assert_eq!(loaded.prefix(), Some(Path::new("/home/user/my prefix")));
+        assert!(loaded.is_active());
+    }
+
+    #[test]
+    fn test_save_installs_records_everything_or_nothing() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let mut packages = Vec::new();
+        let mut installations = Vec::new();
+        for name in ["app", "lib"] {
+            let mut package = test_package(name, "1.0.0");
+            package.set_installed(true);
+            let mut installation = InstallationFactory::create(package.id().clone());
+            installation.add_symlink(Symlink::file(
+                format!("/store/{}", name),
+                format!("/home/user/bin/{}", name),
+            ));
+            installation.activate();
+            packages.push(package);
+            installations.push(installation);
+        }
+
+        db.save_installs(&packages, &installations).unwrap();
+        assert_eq!(db.list_installed_packages().unwrap().len(), 2);
+        for package in &packages {
+            assert!(db.get_active_installation(package.id()).unwrap().is_some());
+        }
+
+        // A failure partway through leaves none of the batch behind.
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        db.connection.execute_batch("DROP TABLE symlinks").unwrap();
+        assert!(db.save_installs(&packages, &installations).is_err());
+        assert!(db.list_installed_packages().unwrap().is_empty());
+        for package in &packages {
+            assert!(db.get_active_installation(package.id()).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_single_active_version_per_name() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let mut installations = Vec::new();
+
+        for version in ["1.0.0", "2.0.0"] {
+            let mut package = test_package("foo", version);
+            package.set_installed(true);
+            package.set_active(true);
+            db.save_package(&package).unwrap();
+
+            let mut installation = InstallationFactory::create(package.id().clone());
+            installation.activate();
+            db.save_installation(&installation).unwrap();
+            installations.push(installation);
+        }
+        // Same name prefix, different package.
+        let mut other = InstallationFactory::create(test_package("foo-bar", "1.0.0").id().clone());
+        other.activate();
+        db.save_installation(&other).unwrap();
+
+        let old = PackageId::new("foo", &Version::new(1, 0, 0));
+        let new = PackageId::new("foo", &Version::new(2, 0, 0));
+        assert!(db.get_active_installation(&old).unwrap().is_none());
+        assert!(!db.get_package(&old).unwrap().unwrap().is_active());
+        assert_eq!(
+            db.get_active_installation(&new).unwrap().unwrap().id(),
+            installations[1].id()
+        );
+        assert!(db.get_package(&new).unwrap().unwrap().is_active());
+        assert!(
+            db.get_active_installation(other.package_id())
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    fn test_invalid_installation_rows_are_rejected() {
+        let db_path = std::env::temp_dir().join(format!("uhpm-{}.db", uuid::Uuid::new_v4()));
+        let package = test_package("my-package", "1.0.0");
+
+        let mut installation = InstallationFactory::create(package.id().clone());
+        installation.add_installed_file(
+            PathBuf::from("/store/bin/tool"),
+            FileMetadata::new(PathBuf::from("/store/bin/tool"), 42),
+        );
+        installation.add_symlink(Symlink::file("/store/bin/tool", "/home/user/bin/tool"));
+        DatabaseRepository::new(&db_path)
+            .unwrap()
+            .save_installation(&installation)
+            .unwrap();
+
+        let corruptions = [
+            "UPDATE installed_files SET file_path = ''",
+            "UPDATE symlinks SET target_path = source_path",
+        ];
+        for corruption in corruptions {
+            let db = DatabaseRepository::new(&db_path).unwrap();
+            db.connection.execute(corruption, []).unwrap();
+
+            let result = db.get_installation(installation.id());
+            assert!(
+                matches!(result, Err(UhpmError::ValidationError(_))),
+                "{}",
+                corruption
+            );
+
+            db.connection
+                .execute(
+                    "UPDATE installed_files SET file_path = '/store/bin/tool'",
+                    [],
+                )
+                .unwrap();
+            db.connection
+                .execute(
+                    "UPDATE symlinks SET target_path = '/home/user/bin/tool'",
+                    [],
+                )
+                .unwrap();
+            assert!(db.get_installation(installation.id()).unwrap().is_some());
+        }
+
+        for suffix in ["db", "db-wal", "db-shm"] {
+            let _ = std::fs::remove_file(db_path.with_extension(suffix));
+        }
+    }
+
+    #[test]
+    fn test_find_owner() {
+        let mut db = DatabaseRepository::in_memory().unwrap();
+        let package = test_package("my-package", "1.0.0");
+
+        let mut installation = InstallationFactory::create(package.id().clone());
+        installation.add_installed_file(
+            PathBuf::from("/home/user/bin/tool"),
+            FileMetadata::new(PathBuf::from("/home/user/bin/tool"), 42),
+        );
+        installation.add_symlink(Symlink::file("/store/lib.so", "/home/user/lib/lib.so"));
+        installation.add_symlink(Symlink::directory("/store/share", "/home/user/share/tool"));
+        db.save_installation(&installation).unwrap();
+
+        for path in [
+            "/home/user/bin/tool",
+            "/home/user/lib/lib.so",
+            "/home/user/share/tool",
+            "/home/user/share/tool/icons/tool.png",
+        ] {
+            assert_eq!(
+                db.find_owner(Path::new(path)).unwrap().as_ref(),
+                Some(package.id()),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(db.find_owner(Path::new("/home/user/bin")).unwrap(), None);
+        assert_eq!(
+            db.find_owner(Path::new("/home/user/lib/lib.so/nested"))
+                .unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_operation_history() {
+        let db = DatabaseRepository::in_memory().unwrap();
+        let version = Version::parse("1.0.0").unwrap();
+
+        let install = db
+            .record_operation(
+                &OperationRecord::new(OperationKind::Install, "foo").to_version(version.clone()),
+            )
+            .unwrap();
+        db.record_operation(
+            &OperationRecord::new(OperationKind::Install, "bar").failed("network down"),
+        )
+        .unwrap();
+        db.record_operation(
+            &OperationRecord::new(OperationKind::Remove, "foo")
+                .from_version(version.clone())
+                .undoing(install),
+        )
+        .unwrap();
+
+        let history = db.get_history(None, None).unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].kind, OperationKind::Remove);
+        assert_eq!(history[0].undoes, Some(install));
+        assert_eq!(history[1].error_message.as_deref(), Some("network down"));
+        assert!(!history[1].success);
+        assert_eq!(history[2].undoes, None);
+
+        let foo_history = db.get_history(Some(1), Some("foo")).unwrap();
+        assert_eq!(foo_history.len(), 1);
+        assert_eq!(foo_history[0].kind, OperationKind::Remove);
+        assert_eq!(foo_history[0].from_version, Some(version));
+    }
+
+    #[test]
+    fn test_compact_after_many_deletes() {
+        let db_path = std::env::temp_dir().join(format!("uhpm-{}.db", uuid::Uuid::new_v4()));
+        let wal_path = db_path.with_extension("db-wal");
+        let on_disk_size = || {
+            [&db_path, &wal_path]
+                .iter()
+                .filter_map(|path| std::fs::metadata(path).ok())
+                .map(|metadata| metadata.len())
+                .sum::<u64>()
+        };
+
+        let mut db = DatabaseRepository::new(&db_path).unwrap();
+        let packages: Vec<Package> = (0..300)
+            .map(|i| test_package(&format!("package-{}", i), "1.0.0"))
+            .collect();
+        for package in &packages {
+            db.save_package(package).unwrap();
+        }
+        for package in &packages {
+            db.delete_package(package.id()).unwrap();
+        }
+
+        let size_before = on_disk_size();
+        db.compact().unwrap();
+        let size_after = on_disk_size();
+
+        assert!(size_after <= size_before);
+        assert!(db.list_installed_packages().unwrap().is_empty());
+
+        drop(db);
+        let _ = std::fs::remove_file(&db_path);
+        let _ = std::fs::remove_file(&wal_path);
+        let _ = std::fs::remove_file(db_path.with_extension("db-shm"));
+    }
+}
@@ -0,0 +1,480 @@
+use crate::{
+    Dependency, Package, PackageReference, PackageSource, Repository, RepositoryIndex,
+    RepositoryPackageEntry, ResolutionFailure, UhpmError, compute_checksum,
+    paths::UhpmPaths,
+    ports::{GitOperations, PackageRepository},
+    repositories::package_files::PackageMeta,
+};
+use async_trait::async_trait;
+use semver::Version;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Repository serving a single package from the tags of a git repository.
+///
+/// Every tag that parses as a semantic version (optionally prefixed with `v`)
+/// is a release. When a `release` is configured only that tag is offered. The
+/// repository is cloned into the cache directory and `meta.toml` is read from
+/// the tagged tree.
+pub struct GitPackagesRepository<GIT, P>
+where
+    GIT: GitOperations,
+    P: UhpmPaths,
+{
+    git: GIT,
+    paths: P,
+    repository: Repository,
+    url: String,
+    release: Option<String>,
+}
+
+impl<GIT, P> GitPackagesRepository<GIT, P>
+where
+    GIT: GitOperations,
+    P: UhpmPaths,
+{
+    pub fn new(git: GIT, paths: P, repository: Repository) -> Result<Self, UhpmError> {
+        let (url, release) = match &repository {
+            Repository::Git { url, release } => (url.clone(), release.clone()),
+            _ => {
+                return Err(UhpmError::ValidationError(
+                    "GitPackagesRepository requires git repository".into(),
+                ));
+            }
+        };
+
+        Ok(Self {
+            git,
+            paths,
+            repository,
+            url,
+            release,
+        })
+    }
+
+    fn checkout_dir(&self) -> Result<PathBuf, UhpmError> {
+        let name = self
+            .url
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches(".git");
+        let hash = compute_checksum("sha256", self.url.as_bytes())?;
+
+        Ok(self
+            .paths
+            .cache_dir()
+            .join("git")
+            .join(format!("{}-{}", name, &hash[..12])))
+    }
+
+    async fn sync(&self) -> Result<PathBuf, UhpmError> {
+        let checkout_dir = self.checkout_dir()?;
+        self.git.clone_or_fetch(&self.url, &checkout_dir).await?;
+        Ok(checkout_dir)
+    }
+
+    /// Release tags with their versions, oldest first.
+    async fn releases(&self, checkout_dir: &Path) -> Result<Vec<(String, Version)>, UhpmError> {
+        let mut releases = self
+            .git
+            .list_tags(checkout_dir)
+            .await?
+            .into_iter()
+            .filter(|tag| self.release.as_ref().is_none_or(|release| release == tag))
+            .filter_map(|tag| {
+                let version = Version::parse(tag.strip_prefix('v').unwrap_or(&tag)).ok()?;
+                Some((tag, version))
+            })
+            .collect::<Vec<_>>();
+
+        releases.sort_by(|a, b| a.1.cmp(&b.1));
+        Ok(releases)
+    }
+
+    async fn read_meta(&self, checkout_dir: &Path, tag: &str) -> Result<PackageMeta, UhpmError> {
+        let data = self.git.read_file(checkout_dir, tag, "meta.toml").await?;
+        let meta_str = std::str::from_utf8(&data)
+            .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+
+        toml::from_str(meta_str).map_err(|e| UhpmError::DeserializationError(e.to_string()))
+    }
+
+    async fn load_package(
+        &self,
+        checkout_dir: &Path,
+        tag: &str,
+        version: &Version,
+    ) -> Result<Package, UhpmError> {
+        self.git.checkout(checkout_dir, tag).await?;
+        let meta = self.read_meta(checkout_dir, tag).await?;
+
+        meta.into_package(
+            version.clone(),
+            PackageSource::Git {
+                url: self.url.clone(),
+                release: Some(tag.to_string()),
+            },
+        )
+    }
+
+    /// Name of the package, taken from `meta.toml` of the newest release.
+    async fn package_name(&self, checkout_dir: &Path) -> Result<Option<String>, UhpmError> {
+        match self.releases(checkout_dir).await?.last() {
+            Some((tag, _)) => Ok(Some(self.read_meta(checkout_dir, tag).await?.name)),
+            None => Ok(None),
+        }
+    }
+
+    async fn find_release(
+        &self,
+        checkout_dir: &Path,
+        package_ref: &PackageReference,
+    ) -> Result<String, UhpmError> {
+        if self.package_name(checkout_dir).await?.as_deref() != Some(package_ref.name.as_str()) {
+            return Err(UhpmError::PackageNotFound(package_ref.to_string()));
+        }
+
+        self.releases(checkout_dir)
+            .await?
+            .into_iter()
+            .find(|(_, version)| *version == package_ref.version)
+            .map(|(tag, _)| tag)
+            .ok_or_else(|| UhpmError::PackageNotFound(package_ref.to_string()))
+    }
+}
+
+#[async_trait]
+impl<GIT, P> PackageRepository for GitPackagesRepository<GIT, P>
+where
+    GIT: GitOperations,
+    P: UhpmPaths,
+{
+    async fn get_package(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
+        let checkout_dir = self.sync().await?;
+        let tag = self.find_release(&checkout_dir, package_ref).await?;
+
+        self.load_package(&checkout_dir, &tag, &package_ref.version)
+            .await
+    }
+
+    async fn search_packages(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
+        let checkout_dir = self.sync().await?;
+        let Some(name) = self.package_name(&checkout_dir).await? else {
+            return Ok(Vec::new());
+        };
+        if !name.contains(query) {
+            return Ok(Vec::new());
+        }
+
+        let mut results = Vec::new();
+        for (tag, version) in self.releases(&checkout_dir).await? {
+            results.push(self.load_package(&checkout_dir, &tag, &version).await?);
+        }
+        Ok(results)
+    }
+
+    async fn get_package_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        let checkout_dir = self.sync().await?;
+        if self.package_name(&checkout_dir).await?.as_deref() != Some(package_name) {
+            return Ok(Vec::new());
+        }
+
+        Ok(self
+            .releases(&checkout_dir)
+            .await?
+            .into_iter()
+            .map(|(_, version)| version.to_string())
+            .collect())
+    }
+
+    async fn get_latest_version(&self, package_name: &str) -> Result<String, UhpmError> {
+        let versions = self.get_package_versions(package_name).await?;
+        versions
+            .last()
+            .cloned()
+            .ok_or_else(|| UhpmError::PackageNotFound(package_name.to_string()))
+    }
+
+    async fn resolve_dependencies(
+        &self,
+        dependencies: &HashSet<Dependency>,
+    ) -> Result<Vec<Package>, UhpmError> {
+        let mut resolved_packages = Vec::new();
+
+        for dependency in dependencies {
+            let versions = self.get_package_versions(&dependency.name).await?;
+
+            let version = versions
+                .iter()
+                .rev()
+                .filter_map(|v| Version::parse(v).ok())
+                .find(|v| dependency.matches_version(v))
+                .ok_or_else(|| {
+                    UhpmError::ResolutionError(Box::new(ResolutionFailure::new(
+                        dependency,
+                        &versions,
+                        &[],
+                    )))
+                })?;
+
+            let package_ref = PackageReference::new(dependency.name.clone(), version);
+            resolved_packages.push(self.get_package(&package_ref).await?);
+        }
+
+        Ok(resolved_packages)
+    }
+
+    async fn download_package(&self, package_ref: &PackageReference) -> Result<Vec<u8>, UhpmError> {
+        let checkout_dir = self.sync().await?;
+        let tag = self.find_release(&checkout_dir, package_ref).await?;
+
+        self.git.archive(&checkout_dir, &tag).await
+    }
+
+    async fn get_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        let checkout_dir = self.sync().await?;
+        let mut packages = Vec::new();
+
+        if let Some(name) = self.package_name(&checkout_dir).await? {
+            let versions = self
+                .releases(&checkout_dir)
+                .await?
+                .into_iter()
+                .map(|(_, version)| version.to_string())
+                .collect();
+            packages.push(RepositoryPackageEntry::new(name, versions));
+        }
+
+        Ok(RepositoryIndex {
+            name: "git".to_string(),
+            url: self.url.clone(),
+            packages,
+        })
+    }
+
+    async fn update_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        self.get_index().await
+    }
+
+    async fn is_available(&self) -> bool {
+        self.sync().await.is_ok()
+    }
+
+    fn get_repository(&self) -> &Repository {
+        &self.repository
+    }
+}
+
+/// [`GitOperations`] backed by the `git` command line tool.
+#[derive(Debug, Clone, Default)]
+pub struct GitCli {
+    program: Option<PathBuf>,
+}
+
+impl GitCli {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses the given `git` executable instead of the one on `PATH`.
+    pub fn with_program<P: Into<PathBuf>>(program: P) -> Self {
+        Self {
+            program: Some(program.into()),
+        }
+    }
+
+    fn run(&self, repository: Option<&Path>, args: &[&str]) -> Result<Vec<u8>, UhpmError> {
+        let mut command = Command::new(self.program.as_deref().unwrap_or_else(|| Path::new("git")));
+        if let Some(repository) = repository {
+            command.arg("-C").arg(repository);
+        }
+
+        let output = command
+            .args(args)
+            .output()
+            .map_err(|e| UhpmError::ExternalToolError(format!("Failed to run git: {}", e)))?;
+
+        if !output.status.success() {
+            return Err(UhpmError::ExternalToolError(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+
+        Ok(output.stdout)
+    }
+}
+
+#[async_trait]
+impl GitOperations for GitCli {
+    async fn clone_or_fetch(&self, url: &str, destination: &Path) -> Result<(), UhpmError> {
+        if destination.join(".git").exists() {
+            self.run(
+                Some(destination),
+                &["fetch", "--quiet", "--tags", "--force", "origin"],
+            )?;
+        } else {
+            let destination = destination.to_string_lossy();
+            self.run(None, &["clone", "--quiet", url, &destination])?;
+        }
+        Ok(())
+    }
+
+    async fn checkout(&self, repository: &Path, reference: &str) -> Result<(), UhpmError> {
+        self.run(
+            Some(repository),
+            &["checkout", "--quiet", "--force", reference],
+        )?;
+        Ok(())
+    }
+
+    async fn list_tags(&self, repository: &Path) -> Result<Vec<String>, UhpmError> {
+        let output = self.run(Some(repository), &["tag", "--list"])?;
+
+        Ok(String::from_utf8_lossy(&output)
+            .lines()
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+            .map(String::from)
+            .collect())
+    }
+
+    async fn read_file(
+        &self,
+        repository: &Path,
+        reference: &str,
+        path: &str,
+    ) -> Result<Vec<u8>, UhpmError> {
+        self.run(
+            Some(repository),
+            &["show", &format!("{}:{}", reference, path)],
+        )
+    }
+
+    async fn archive(&self, repository: &Path, reference: &str) -> Result<Vec<u8>, UhpmError> {
+        self.run(Some(repository), &["archive", "--format=tar.gz", reference])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TestPaths, block_on};
+
+    fn git(dir: &Path, args: &[&str]) {
+        let status = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .output()
+            .unwrap()
+            .status;
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    /// Creates a bare repository with `v1.0.0` and `v1.1.0` tags.
+    fn fixture(root: &Path) -> PathBuf {
+        let work = root.join("work");
+        let bare = root.join("tool.git");
+        std::fs::create_dir_all(&work).unwrap();
+        git(&work, &["init", "--quiet"]);
+
+        for version in ["1.0.0", "1.1.0"] {
+            std::fs::write(
+                work.join("meta.toml"),
+                format!(
+                    "name = \"tool\"\nversion = \"{}\"\nauthor = \"test\"\ndependencies = []\n",
+                    version
+                ),
+            )
+            .unwrap();
+            git(&work, &["add", "meta.toml"]);
+            git(&work, &["commit", "--quiet", "-m", version]);
+            git(&work, &["tag", &format!("v{}", version)]);
+        }
+
+        git(root, &["clone", "--quiet", "--bare", "work", "tool.git"]);
+        bare
+    }
+
+    fn source(url: &Path, release: Option<&str>) -> Repository {
+        Repository::Git {
+            url: format!("file://{}", url.display()),
+            release: release.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_fetches_tagged_versions() {
+        let root = std::env::temp_dir().join(format!("uhpm-git-{}", uuid::Uuid::new_v4()));
+        let bare = fixture(&root);
+
+        let repo = GitPackagesRepository::new(
+            GitCli::new(),
+            TestPaths::new(root.join("uhpm")),
+            source(&bare, None),
+        )
+        .unwrap();
+
+        block_on(async {
+            let versions = repo.get_package_versions("tool").await.unwrap();
+            assert_eq!(versions, vec!["1.0.0", "1.1.0"]);
+
+            let package = repo
+                .get_package(&PackageReference::new(
+                    "tool".to_string(),
+                    Version::new(1, 0, 0),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(package.name(), "tool");
+            assert_eq!(
+                package.source(),
+                &PackageSource::Git {
+                    url: format!("file://{}", bare.display()),
+                    release: Some("v1.0.0".to_string()),
+                }
+            );
+
+            let archive = repo
+                .download_package(&PackageReference::from_package(&package))
+                .await
+                .unwrap();
+            assert!(!archive.is_empty());
+        });
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_configured_release_limits_versions() {
+        let root = std::env::temp_dir().join(format!("uhpm-git-{}", uuid::Uuid::new_v4()));
+        let bare = fixture(&root);
+
+        let repo = GitPackagesRepository::new(
+            GitCli::new(),
+            TestPaths::new(root.join("uhpm")),
+            source(&bare, Some("v1.0.0")),
+        )
+        .unwrap();
+
+        block_on(async {
+            assert_eq!(repo.get_latest_version("tool").await.unwrap(), "1.0.0");
+            assert!(
+                repo.get_package(&PackageReference::new(
+                    "tool".to_string(),
+                    Version::new(1, 1, 0),
+                ))
+                .await
+                .is_err()
+            );
+        });
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,13 +1,19 @@
 use crate::{
-    Dependency, DependencyKind, FsError, Package, PackageReference, Repository, RepositoryIndex,
-    UhpmError, VersionConstraint,
+    Dependency, DependencyKind, FsError, Package, PackageId, PackageReference, Repository,
+    RepositoryIndex, ResolutionFailure, UhpmError, VersionConstraint, compute_checksum,
     factories::PackageFactory,
     paths::UhpmPaths,
     ports::{FileSystemOperations, PackageRepository},
+    repositories::{
+        package_files::{BUILD_DIR, PackageFilesRepository, PackageMeta},
+        package_index::PackageIndex,
+    },
 };
 use async_trait::async_trait;
 use semver::{Version, VersionReq};
-use std::{collections::HashSet, path::PathBuf};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
 
 #[derive(Clone)]
 pub struct LocalPackagesRepository<FS, P>
@@ -33,12 +39,135 @@ where
         })
     }
 
-    fn get_package_meta_path(&self, package_ref: &PackageReference) -> PathBuf {
+    /// Returns the package index, building it from the packages directory
+    /// when it is missing or can't be read.
+    async fn index(&self) -> Result<PackageIndex, UhpmError> {
+        match PackageIndex::load(&self.file_system, &self.paths.packages_dir()).await {
+            Ok(Some(index)) => Ok(index),
+            Ok(None) => self.rebuild_index().await,
+            Err(error) => {
+                warn!(%error, "package index is unreadable, rebuilding it");
+                self.rebuild_index().await
+            }
+        }
+    }
+
+    /// Rebuilds the package index by walking the packages directory.
+    ///
+    /// Both `<name>/<version>` directories and the `<name>@<version>`
+    /// directories of installed packages are indexed. Packages whose
+    /// `meta.toml` is missing, unreadable or doesn't match their directory
+    /// are left out.
+    pub async fn rebuild_index(&self) -> Result<PackageIndex, UhpmError> {
+        let packages_dir = self.paths.packages_dir();
+        let mut index = PackageIndex::new();
+        if !self.file_system.exists(&packages_dir).await {
+            return Ok(index);
+        }
+
+        for entry in self.file_system.read_dir(&packages_dir).await? {
+            let Some(file_name) = entry.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if file_name.starts_with('.') || file_name == BUILD_DIR {
+                continue;
+            }
+
+            if let Some((name, version)) = file_name.rsplit_once('@') {
+                self.index_package_dir(&mut index, name, version, PathBuf::from(file_name))
+                    .await;
+            } else if let Ok(versions) = self.file_system.read_dir(&entry).await {
+                for version_dir in versions {
+                    if let Some(version) = version_dir.file_name().and_then(|n| n.to_str()) {
+                        let path = Path::new(file_name).join(version);
+                        self.index_package_dir(&mut index, file_name, version, path)
+                            .await;
+                    }
+                }
+            }
+        }
+
+        index.save(&self.file_system, &packages_dir).await?;
+        Ok(index)
+    }
+
+    async fn index_package_dir(
+        &self,
+        index: &mut PackageIndex,
+        name: &str,
+        version: &str,
+        path: PathBuf,
+    ) {
+        let Ok(version) = Version::parse(version) else {
+            return;
+        };
+        let meta_path = self.paths.packages_dir().join(&path).join("meta.toml");
+        match self.read_meta(&meta_path, name, &version).await {
+            Ok(meta) => index.insert(path, meta),
+            Err(error) => debug!(path = %meta_path.display(), %error, "not indexing package"),
+        }
+    }
+
+    async fn read_meta(
+        &self,
+        meta_path: &Path,
+        name: &str,
+        version: &Version,
+    ) -> Result<PackageMeta, UhpmError> {
+        let data = self.file_system.read_file(meta_path).await?;
+        let meta_str = std::str::from_utf8(&data)
+            .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+
+        let meta = PackageMeta::from_toml(meta_path, meta_str)?;
+        meta.check_identity(meta_path, name, version)?;
+        Ok(meta)
+    }
+
+    /// Where the archive built for `package_ref` is kept between downloads.
+    fn archive_cache_path(&self, package_ref: &PackageReference) -> PathBuf {
+        let package_id = PackageId::new(&package_ref.name, &package_ref.version);
         self.paths
-            .packages_dir()
-            .join(&package_ref.name)
-            .join(&package_ref.version.to_string())
-            .join("meta.toml")
+            .cache_dir()
+            .join("archives")
+            .join(format!("{}.uhp", package_id.as_str()))
+    }
+
+    /// Builds a package from its meta. Without a checksum in the meta, the
+    /// one recorded for its last built archive is used.
+    fn package_from_meta(
+        &self,
+        meta: PackageMeta,
+        path: &Path,
+        archive_checksum: Option<&String>,
+    ) -> Result<Package, UhpmError> {
+        let version =
+            Version::parse(&meta.version).map_err(|e| UhpmError::ValidationError(e.to_string()))?;
+        let target = meta.target();
+        let checksum = meta.checksum().or_else(|| {
+            archive_checksum.map(|hash| crate::Checksum {
+                algorithm: "sha256".to_string(),
+                hash: hash.clone(),
+            })
+        });
+        let dependencies: Vec<Dependency> = meta
+            .dependencies
+            .iter()
+            .map(|dep_str| self.parse_dependency(dep_str))
+            .collect::<Result<Vec<_>, UhpmError>>()?;
+
+        let mut package = PackageFactory::create(
+            meta.name,
+            version,
+            meta.author,
+            crate::PackageSource::Local {
+                path: self.paths.packages_dir().join(path),
+            },
+            target,
+            checksum,
+            dependencies,
+        )?;
+        package.set_description(meta.description);
+        Ok(package)
     }
 
     fn parse_dependency(&self, dep_str: &str) -> Result<Dependency, UhpmError> {
@@ -79,98 +208,45 @@ where
 #[async_trait]
 impl<FS, P> PackageRepository for LocalPackagesRepository<FS, P>
 where
-    FS: FileSystemOperations + Send + Sync,
+    FS: FileSystemOperations + Clone + Send + Sync,
     P: UhpmPaths + Send + Sync,
 {
     async fn get_package(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
-        let meta_path = self.get_package_meta_path(package_ref);
+        let version = package_ref.version.to_string();
+        let (path, archive_checksum) = match self.index().await?.get(&package_ref.name, &version) {
+            Some(entry) => (entry.path.clone(), entry.archive_checksum.clone()),
+            None => (Path::new(&package_ref.name).join(&version), None),
+        };
+        let meta_path = self.paths.packages_dir().join(&path).join("meta.toml");
 
         if !self.file_system.exists(&meta_path).await {
             return Err(UhpmError::PackageNotFound(package_ref.to_string()));
         }
 
-        let data = self.file_system.read_file(&meta_path).await?;
-        let meta_str = std::str::from_utf8(&data)
-            .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
-
-        let meta: crate::repositories::package_files::PackageMeta =
-            toml::from_str(meta_str).map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
-
-        let dependencies: Vec<Dependency> = meta
-            .dependencies
-            .into_iter()
-            .map(|dep_str| self.parse_dependency(&dep_str))
-            .collect::<Result<Vec<_>, UhpmError>>()?;
-
-        let package = PackageFactory::create(
-            meta.name,
-            package_ref.version.clone(),
-            meta.author,
-            crate::PackageSource::Local {
-                path: self
-                    .paths
-                    .packages_dir()
-                    .join(&package_ref.name)
-                    .join(&package_ref.version.to_string()),
-            },
-            crate::Target::current(),
-            None,
-            dependencies,
-        )?;
-
-        Ok(package)
+        let meta = self
+            .read_meta(&meta_path, &package_ref.name, &package_ref.version)
+            .await?;
+        self.package_from_meta(meta, &path, archive_checksum.as_ref())
     }
 
     async fn search_packages(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
-        let packages_dir = self.paths.packages_dir();
-        let mut results = Vec::new();
-
-        if self.file_system.exists(&packages_dir).await {
-            if let Ok(entries) = self.file_system.read_dir(&packages_dir).await {
-                for package_dir in entries {
-                    if let Some(package_name) = package_dir.file_name().and_then(|n| n.to_str()) {
-                        if package_name.contains(query) {
-                            let versions = self.get_package_versions(package_name).await?;
-
-                            for version_str in versions {
-                                if let Ok(version) = Version::parse(&version_str) {
-                                    let package_ref =
-                                        PackageReference::new(package_name.to_string(), version);
-                                    match self.get_package(&package_ref).await {
-                                        Ok(package) => results.push(package),
-                                        Err(_) => continue,
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        Ok(results)
+        let index = self.index().await?;
+        Ok(index
+            .search(query)
+            .into_iter()
+            .filter_map(|entry| {
+                self.package_from_meta(
+                    entry.meta.clone(),
+                    &entry.path,
+                    entry.archive_checksum.as_ref(),
+                )
+                .ok()
+            })
+            .collect())
     }
 
     async fn get_package_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
-        let packages_dir = self.paths.packages_dir();
-        let package_dir = packages_dir.join(package_name);
-        let mut versions = Vec::new();
-
-        if self.file_system.exists(&package_dir).await {
-            if let Ok(entries) = self.file_system.read_dir(&package_dir).await {
-                for entry in entries {
-                    if let Some(version_str) = entry.file_name().and_then(|n| n.to_str()) {
-                        if Version::parse(version_str).is_ok() {
-                            versions.push(version_str.to_string());
-                        }
-                    }
-                }
-            }
-        }
-
-        versions.sort_by(|a, b| Version::parse(a).unwrap().cmp(&Version::parse(b).unwrap()));
-
-        Ok(versions)
+        Ok(self.index().await?.versions(package_name))
     }
 
     async fn get_latest_version(&self, package_name: &str) -> Result<String, UhpmError> {
@@ -190,21 +266,20 @@ where
         for dependency in dependencies {
             let versions = self.get_package_versions(&dependency.name).await?;
 
-            if let Some(version_str) = versions.into_iter().rev().find(|v| {
+            if let Some(version_str) = versions.iter().rev().find(|v| {
                 Version::parse(v)
                     .map(|ver| dependency.matches_version(&ver))
                     .unwrap_or(false)
             }) {
-                let version = Version::parse(&version_str)
+                let version = Version::parse(version_str)
                     .map_err(|e| UhpmError::ValidationError(e.to_string()))?;
 
                 let package_ref = PackageReference::new(dependency.name.clone(), version);
                 let package = self.get_package(&package_ref).await?;
                 resolved_packages.push(package);
             } else {
-                return Err(UhpmError::ResolutionError(format!(
-                    "Cannot resolve dependency: {} {}",
-                    dependency.name, dependency.constraint.requirement
+                return Err(UhpmError::ResolutionError(Box::new(
+                    ResolutionFailure::new(dependency, &versions, &[]),
                 )));
             }
         }
@@ -212,53 +287,64 @@ where
         Ok(resolved_packages)
     }
 
+    /// Packs the package directory into an archive.
+    ///
+    /// Archives are reproducible and kept in the cache directory, with their
+    /// sha256 recorded in the package index, so repeated downloads return
+    /// the same bytes and the package reports a checksum that matches them.
     async fn download_package(&self, package_ref: &PackageReference) -> Result<Vec<u8>, UhpmError> {
-        let meta_path = self.get_package_meta_path(package_ref);
-        if !self.file_system.exists(&meta_path).await {
+        let packages_dir = self.paths.packages_dir();
+        let version = package_ref.version.to_string();
+        let mut index = self.index().await?;
+        let Some(entry) = index.get(&package_ref.name, &version).cloned() else {
             return Err(UhpmError::PackageNotFound(package_ref.to_string()));
+        };
+
+        let archive_path = self.archive_cache_path(package_ref);
+        if let Some(checksum) = &entry.archive_checksum
+            && self.file_system.exists(&archive_path).await
+        {
+            let cached = self.file_system.read_file(&archive_path).await?;
+            if compute_checksum("sha256", &cached)? == *checksum {
+                return Ok(cached);
+            }
+            debug!(path = %archive_path.display(), "cached archive is stale, rebuilding it");
         }
 
-        let package_files_repo = crate::repositories::package_files::PackageFilesRepository::new(
-            self.file_system.clone(),
-            self.paths.packages_dir(),
-        );
-
-        package_files_repo
-            .create_package_archive(&crate::PackageId::new(
-                &package_ref.name,
-                &package_ref.version,
-            ))
-            .await
+        let archive = PackageFilesRepository::new(self.file_system.clone(), packages_dir.clone())
+            .create_archive_from_dir(&packages_dir.join(&entry.path))
+            .await?;
+        let checksum = compute_checksum("sha256", &archive)?;
+        if let Some(archive_dir) = archive_path.parent() {
+            self.file_system.create_dir_all(archive_dir).await?;
+        }
+        self.file_system.write_file(&archive_path, &archive).await?;
+        if entry.archive_checksum.as_ref() != Some(&checksum) {
+            index.set_archive_checksum(&package_ref.name, &version, checksum);
+            index.save(&self.file_system, &packages_dir).await?;
+        }
+        Ok(archive)
     }
 
     async fn get_index(&self) -> Result<RepositoryIndex, UhpmError> {
-        let packages_dir = self.paths.packages_dir();
-        let mut packages = Vec::new();
-
-        if self.file_system.exists(&packages_dir).await {
-            if let Ok(entries) = self.file_system.read_dir(&packages_dir).await {
-                for package_dir in entries {
-                    if let Some(package_name) = package_dir.file_name().and_then(|n| n.to_str()) {
-                        let versions = self.get_package_versions(package_name).await?;
-                        if !versions.is_empty() {
-                            packages.push(crate::RepositoryPackageEntry {
-                                name: package_name.to_string(),
-                                versions,
-                            });
-                        }
-                    }
-                }
-            }
-        }
+        let index = self.index().await?;
+        let packages = index
+            .packages
+            .keys()
+            .map(|name| crate::RepositoryPackageEntry::new(name.clone(), index.versions(name)))
+            .filter(|entry| !entry.versions.is_empty())
+            .collect();
 
         Ok(RepositoryIndex {
             name: "local".to_string(),
-            url: packages_dir.to_string_lossy().to_string(),
+            url: self.paths.packages_dir().to_string_lossy().to_string(),
             packages,
         })
     }
 
+    /// Rebuilds the package index from the packages directory.
     async fn update_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        self.rebuild_index().await?;
         self.get_index().await
     }
 
@@ -270,3 +356,299 @@ where
         &self.repository
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MemoryFileSystem, TestPaths, block_on};
+    use crate::{Architecture, Checksum, OperatingSystem, PackageId, Target};
+
+    #[test]
+    fn test_get_package_honors_meta_target_and_checksum() {
+        let file_system = MemoryFileSystem::new();
+        file_system.add_file(
+            "/uhpm/packages/tool/1.0.0/meta.toml",
+            br#"
+name = "tool"
+version = "1.0.0"
+author = "test"
+dependencies = []
+checksum_algorithm = "sha256"
+checksum_hash = "abc123"
+target_os = "macos"
+target_arch = "aarch64"
+"#,
+        );
+
+        let repo = LocalPackagesRepository::new(
+            file_system,
+            TestPaths::new("/uhpm"),
+            Repository::Local {
+                path: PathBuf::from("/uhpm/packages"),
+            },
+        )
+        .unwrap();
+
+        let package = block_on(repo.get_package(&PackageReference::new(
+            "tool".to_string(),
+            Version::new(1, 0, 0),
+        )))
+        .unwrap();
+
+        assert_eq!(
+            package.target(),
+            &Target {
+                os: OperatingSystem::MacOS,
+                arch: Architecture::Aarch64,
+            }
+        );
+        assert_eq!(
+            package.checksum(),
+            &Some(Checksum {
+                algorithm: "sha256".to_string(),
+                hash: "abc123".to_string(),
+            })
+        );
+    }
+
+    const MINIMAL_META: &[u8] = br#"
+name = "tool"
+version = "1.0.0"
+"#;
+
+    const MAXIMAL_META: &[u8] = br#"
+name = "tool"
+version = "1.0.0"
+author = "test"
+description = "A tool"
+dependencies = ["lib@^1"]
+provides = ["tool-bin"]
+conflicts = ["old-tool"]
+target_os = "linux"
+target_arch = "x86_64"
+homepage = "https://example.com"
+
+[build]
+system = "cargo"
+"#;
+
+    const CONFLICTING_META: &[u8] = br#"
+name = "tool"
+version = "1.2.0"
+"#;
+
+    fn repository_with_meta(version: &str, meta: &[u8]) -> impl PackageRepository {
+        let file_system = MemoryFileSystem::new();
+        file_system.add_file(format!("/uhpm/packages/tool/{}/meta.toml", version), meta);
+        LocalPackagesRepository::new(
+            file_system,
+            TestPaths::new("/uhpm"),
+            Repository::Local {
+                path: PathBuf::from("/uhpm/packages"),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_meta_fixtures() {
+        let tool = |version: &str| {
+            PackageReference::new("tool".to_string(), Version::parse(version).unwrap())
+        };
+
+        let minimal =
+            block_on(repository_with_meta("1.0.0", MINIMAL_META).get_package(&tool("1.0.0")))
+                .unwrap();
+        assert_eq!(minimal.author(), "unknown");
+        assert_eq!(minimal.description(), None);
+        assert!(minimal.dependencies().is_empty());
+
+        let maximal =
+            block_on(repository_with_meta("1.0.0", MAXIMAL_META).get_package(&tool("1.0.0")))
+                .unwrap();
+        assert_eq!(maximal.author(), "test");
+        assert_eq!(maximal.description(), Some("A tool"));
+        assert_eq!(maximal.dependencies().len(), 1);
+
+        let meta: crate::repositories::package_files::PackageMeta =
+            toml::from_str(std::str::from_utf8(MAXIMAL_META).unwrap()).unwrap();
+        assert_eq!(
+            meta.extras.keys().collect::<Vec<_>>(),
+            ["build", "homepage"]
+        );
+
+        let conflicting =
+            block_on(repository_with_meta("1.2.1", CONFLICTING_META).get_package(&tool("1.2.1")));
+        match conflicting {
+            Err(UhpmError::InvalidPackage { reason, .. }) => {
+                assert!(
+                    reason.contains("1.2.0") && reason.contains("1.2.1"),
+                    "{}",
+                    reason
+                )
+            }
+            other => panic!("unexpected result: {:?}", other.map(|p| p.id().clone())),
+        }
+    }
+
+    #[test]
+    fn test_meta_version_must_match_its_directory() {
+        let repo = repository_with_meta("2.0.0", MINIMAL_META);
+
+        let error = block_on(repo.get_package(&PackageReference::new(
+            "tool".to_string(),
+            Version::new(2, 0, 0),
+        )))
+        .unwrap_err();
+
+        match error {
+            UhpmError::InvalidPackage { path, reason } => {
+                assert_eq!(path, PathBuf::from("/uhpm/packages/tool/2.0.0/meta.toml"));
+                assert_eq!(
+                    reason,
+                    "meta.toml declares version 1.0.0 but it is stored as 2.0.0"
+                );
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn test_get_package_by_id() {
+        let repo = repository_with_meta("1.0.0", MINIMAL_META);
+
+        let id = PackageId::new("tool", &Version::new(1, 0, 0));
+        assert_eq!(block_on(repo.get_package_by_id(&id)).unwrap().id(), &id);
+
+        let invalid: PackageId = serde_json::from_str("\"tool@latest\"").unwrap();
+        let err = block_on(repo.get_package_by_id(&invalid)).unwrap_err();
+        assert!(matches!(err, UhpmError::ValidationError(_)), "{}", err);
+    }
+
+    fn indexed_repository(
+        file_system: &MemoryFileSystem,
+    ) -> LocalPackagesRepository<MemoryFileSystem, TestPaths> {
+        LocalPackagesRepository::new(
+            file_system.clone(),
+            TestPaths::new("/uhpm"),
+            Repository::Local {
+                path: PathBuf::from("/uhpm/packages"),
+            },
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_versions_skip_stray_directories() {
+        let file_system = MemoryFileSystem::new();
+        for version in ["1.10.0", "1.2.0", "not-a-version", "latest"] {
+            file_system.add_file(
+                format!("/uhpm/packages/tool/{}/meta.toml", version),
+                format!("name = \"tool\"\nversion = \"{}\"\n", version).as_bytes(),
+            );
+        }
+        file_system.add_file("/uhpm/packages/tool/.DS_Store/junk", b"");
+        let repo = indexed_repository(&file_system);
+
+        let versions = block_on(repo.get_package_versions("tool")).unwrap();
+
+        assert_eq!(versions, ["1.2.0", "1.10.0"]);
+        assert_eq!(block_on(repo.get_latest_version("tool")).unwrap(), "1.10.0");
+    }
+
+    #[test]
+    fn test_downloads_return_the_same_archive() {
+        let file_system = MemoryFileSystem::new();
+        file_system.add_file("/uhpm/packages/tool/1.0.0/meta.toml", MINIMAL_META);
+        file_system.add_file("/uhpm/packages/tool/1.0.0/bin/tool", b"#!/bin/sh\n");
+        file_system.add_file(
+            "/uhpm/packages/tool/1.0.0/instlist",
+            b"bin/tool ~/.local/bin/tool\n",
+        );
+        let repo = indexed_repository(&file_system);
+        let tool = PackageReference::new("tool".to_string(), Version::new(1, 0, 0));
+
+        let first = block_on(repo.download_package(&tool)).unwrap();
+        let second = block_on(repo.download_package(&tool)).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(
+            file_system.file(Path::new("/uhpm/cache/archives/tool@1.0.0.uhp")),
+            Some(first.clone())
+        );
+
+        // The checksum the package reports matches what consumers download.
+        let package = block_on(repo.get_package(&tool)).unwrap();
+        assert_eq!(
+            package.checksum(),
+            &Some(Checksum {
+                algorithm: "sha256".to_string(),
+                hash: compute_checksum("sha256", &first).unwrap(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_search_reads_only_the_index() {
+        let file_system = MemoryFileSystem::new();
+        for i in 0..500 {
+            file_system.add_file(
+                format!("/uhpm/packages/pkg{}/1.0.0/meta.toml", i),
+                format!("name = \"pkg{}\"\nversion = \"1.0.0\"\n", i).as_bytes(),
+            );
+        }
+        let repo = indexed_repository(&file_system);
+
+        let index = block_on(repo.rebuild_index()).unwrap();
+        assert_eq!(index.packages.len(), 500);
+        file_system.take_reads();
+
+        let found = block_on(repo.search_packages("pkg499")).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].name(), "pkg499");
+        assert_eq!(
+            file_system.take_reads(),
+            [PathBuf::from("/uhpm/packages/.index.json")]
+        );
+
+        assert_eq!(
+            block_on(repo.get_package_versions("pkg7")).unwrap(),
+            ["1.0.0"]
+        );
+        assert_eq!(file_system.take_reads().len(), 1);
+    }
+
+    #[test]
+    fn test_missing_or_unreadable_index_is_rebuilt() {
+        let file_system = MemoryFileSystem::new();
+        file_system.add_file("/uhpm/packages/tool/1.0.0/meta.toml", MINIMAL_META);
+        file_system.add_file(
+            "/uhpm/packages/tool@2.0.0/meta.toml",
+            b"name = \"tool\"\nversion = \"2.0.0\"\n",
+        );
+        let repo = indexed_repository(&file_system);
+        let index_path = Path::new("/uhpm/packages/.index.json");
+
+        assert_eq!(
+            block_on(repo.get_package_versions("tool")).unwrap(),
+            ["1.0.0", "2.0.0"]
+        );
+        assert!(file_system.file(index_path).is_some());
+
+        file_system.add_file(index_path, b"{ not json");
+        let found = block_on(repo.search_packages("tool")).unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(PackageIndex::parse(&file_system.file(index_path).unwrap()).is_ok());
+
+        let installed = block_on(repo.get_package(&PackageReference::new(
+            "tool".to_string(),
+            Version::new(2, 0, 0),
+        )))
+        .unwrap();
+        assert_eq!(
+            installed.source(),
+            &crate::PackageSource::Local {
+                path: PathBuf::from("/uhpm/packages/tool@2.0.0")
+            }
+        );
+    }
+}
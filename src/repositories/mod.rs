@@ -1,7 +1,18 @@
+mod archive_stream;
+pub mod composite;
+pub mod database;
+pub mod git_packages;
 pub mod local_packages;
 pub mod package_files;
+pub mod package_index;
 pub mod remote_packages;
+pub mod sqlite_state_store;
 
+pub use composite::CompositeRepository;
+pub use database::DatabaseRepository;
+pub use git_packages::{GitCli, GitPackagesRepository};
 pub use local_packages::LocalPackagesRepository;
 pub use package_files::PackageFilesRepository;
+pub use package_index::PackageIndex;
 pub use remote_packages::RemotePackagesRepository;
+pub use sqlite_state_store::SqliteStateStore;
@@ -1,27 +1,392 @@
-use flate2::{Compression, read::GzDecoder, write::GzEncoder};
-use std::path::PathBuf;
-use tar::{Archive, Builder};
-
-use crate::{FsError, PackageId, Symlink, SymlinkType, UhpmError, ports::FileSystemOperations};
+use flate2::read::GzDecoder;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, EntryType};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
+use tracing::{debug, warn};
+
+use crate::{
+    Architecture, CancellationToken, Checksum, Dependency, DependencyKind, ErrorContext,
+    FileMetadata, FilePermissions, FileType, FsError, GlobDirectories, OperatingSystem, Package,
+    PackageEvent, PackageId, PackageReference, PackageSource, ResultExt, Symlink, SymlinkAction,
+    SymlinkType, Target, TargetPolicy, UhpmError, VersionConstraint,
+    factories::PackageFactory,
+    ports::{EventPublisher, FileSystemOperations},
+    repositories::{
+        archive_stream::{ArchiveReader, ArchiveWriter, DATA_CHUNK, StreamEntryKind},
+        package_index::PackageIndex,
+    },
+};
 use serde::{Deserialize, Serialize};
 
+/// Newest `meta.toml` format this version of the crate understands.
+pub const META_SCHEMA_VERSION: u32 = 1;
+
+/// Contents of a package's `meta.toml`.
+///
+/// Only `name` and `version` are required. Fields this version doesn't know
+/// about are kept in `extras` instead of being rejected.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PackageMeta {
+    /// Format of the file, 1 when missing.
+    #[serde(default = "initial_schema_version")]
+    pub schema_version: u32,
     pub name: String,
     pub version: String,
+    #[serde(default = "unknown_author")]
     pub author: String,
+    #[serde(default)]
     pub description: Option<String>,
+    #[serde(default)]
     pub dependencies: Vec<String>,
+    #[serde(default)]
     pub provides: Option<Vec<String>>,
+    #[serde(default)]
     pub conflicts: Option<Vec<String>>,
+    #[serde(default)]
+    pub checksum_algorithm: Option<String>,
+    #[serde(default)]
+    pub checksum_hash: Option<String>,
+    #[serde(default)]
+    pub target_os: Option<String>,
+    #[serde(default)]
+    pub target_arch: Option<String>,
+    #[serde(flatten)]
+    pub extras: BTreeMap<String, toml::Value>,
+}
+
+fn unknown_author() -> String {
+    "unknown".to_string()
+}
+
+pub(crate) fn initial_schema_version() -> u32 {
+    1
+}
+
+/// Rejects a meta at `path` written in a format newer than
+/// [`META_SCHEMA_VERSION`], whose fields might mean something else.
+pub(crate) fn check_schema_version(path: &Path, schema_version: u32) -> Result<(), UhpmError> {
+    if schema_version > META_SCHEMA_VERSION {
+        return Err(UhpmError::InvalidPackage {
+            path: path.to_path_buf(),
+            reason: format!(
+                "meta schema version {} is newer than the supported {}",
+                schema_version, META_SCHEMA_VERSION
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Parses a meta dependency, `name` or `name@requirement`.
+pub(crate) fn parse_dependency(dep_str: &str) -> Result<Dependency, UhpmError> {
+    let (name, requirement) = dep_str.split_once('@').unwrap_or((dep_str, "*"));
+    let requirement = semver::VersionReq::parse(requirement).map_err(|e| {
+        UhpmError::ValidationError(format!(
+            "Invalid version constraint '{}': {}",
+            requirement, e
+        ))
+    })?;
+
+    Ok(Dependency {
+        name: name.trim().to_string(),
+        constraint: VersionConstraint { requirement },
+        kind: DependencyKind::Required,
+        provides: None,
+        features: Vec::new(),
+    })
+}
+
+impl PackageMeta {
+    /// Parses the meta read from `path`, rejecting unsupported schema
+    /// versions.
+    pub fn from_toml(path: &Path, content: &str) -> Result<Self, UhpmError> {
+        let meta: Self =
+            toml::from_str(content).map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+        check_schema_version(path, meta.schema_version)?;
+        Ok(meta)
+    }
+
+    /// The package this meta describes, as `version` published at `source`.
+    pub fn into_package(
+        self,
+        version: semver::Version,
+        source: PackageSource,
+    ) -> Result<Package, UhpmError> {
+        let target = self.target();
+        let checksum = self.checksum();
+        let dependencies = self
+            .dependencies
+            .iter()
+            .map(|dep_str| parse_dependency(dep_str))
+            .collect::<Result<Vec<_>, UhpmError>>()?;
+
+        let mut package = PackageFactory::create(
+            self.name,
+            version,
+            self.author,
+            source,
+            target,
+            checksum,
+            dependencies,
+        )?;
+        package.set_description(self.description);
+        Ok(package)
+    }
+
+    /// Checksum declared in the meta file, if both algorithm and hash are set.
+    pub fn checksum(&self) -> Option<Checksum> {
+        match (&self.checksum_algorithm, &self.checksum_hash) {
+            (Some(algorithm), Some(hash)) => Some(Checksum {
+                algorithm: algorithm.clone(),
+                hash: hash.clone(),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Checks that the meta describes the package stored at `path` as
+    /// `name` at `version`.
+    pub fn check_identity(
+        &self,
+        path: &Path,
+        name: &str,
+        version: &semver::Version,
+    ) -> Result<(), UhpmError> {
+        let invalid = |reason: String| UhpmError::InvalidPackage {
+            path: path.to_path_buf(),
+            reason,
+        };
+
+        if self.name != name {
+            return Err(invalid(format!(
+                "meta.toml names the package '{}' but it is stored as '{}'",
+                self.name, name
+            )));
+        }
+
+        let declared = semver::Version::parse(&self.version).map_err(|e| {
+            invalid(format!(
+                "invalid version '{}' in meta.toml: {}",
+                self.version, e
+            ))
+        })?;
+        if &declared != version {
+            return Err(invalid(format!(
+                "meta.toml declares version {} but it is stored as {}",
+                declared, version
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Declared target, falling back to the current platform for missing parts.
+    pub fn target(&self) -> Target {
+        declared_target(self.target_os.as_deref(), self.target_arch.as_deref())
+    }
+}
+
+/// Target declared by a meta's `target_os` and `target_arch`, falling back to
+/// the current platform for missing parts.
+pub(crate) fn declared_target(os: Option<&str>, arch: Option<&str>) -> Target {
+    let current = Target::current();
+    Target {
+        os: os.map(OperatingSystem::from).unwrap_or(current.os),
+        arch: arch.map(Architecture::from).unwrap_or(current.arch),
+    }
+}
+
+/// One line of an instlist.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstlistEntry {
+    /// Path relative to the package root.
+    pub source: PathBuf,
+    pub target: PathBuf,
+    /// Permissions given to the target when the file is copied.
+    pub permissions: Option<FilePermissions>,
+    /// Whether a glob source matching nothing fails the install.
+    pub required: bool,
+}
+
+impl InstlistEntry {
+    /// Whether the source is a glob pattern to expand against the package.
+    pub fn is_glob(&self) -> bool {
+        self.source
+            .to_str()
+            .is_some_and(|source| source.contains(['*', '?']))
+    }
+}
+
+/// Parses instlist content, one `source target [mode] [required]` entry per
+/// line.
+///
+/// The optional mode is octal, such as `0755`. `required` makes a glob
+/// source that matches nothing an error. Blank lines and `#` comments are
+/// skipped; any other line that doesn't fit the format is reported as an
+/// [`UhpmError::InvalidPackage`] at `path`, naming the line.
+pub fn parse_instlist(path: &Path, content: &str) -> Result<Vec<InstlistEntry>, UhpmError> {
+    let mut entries = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: String| UhpmError::InvalidPackage {
+            path: path.to_path_buf(),
+            reason: format!("instlist line {}: {}", index + 1, reason),
+        };
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (source, target, options) = match parts[..] {
+            [source] => return Err(invalid(format!("'{}' has no target", source))),
+            [source, target, ref options @ ..] if options.len() <= 2 => (source, target, options),
+            _ => {
+                return Err(invalid(format!(
+                    "expected 'source target [mode] [required]', got {} fields",
+                    parts.len()
+                )));
+            }
+        };
+
+        if !stays_inside(Path::new(""), Path::new(source)) {
+            return Err(invalid(format!("'{}' leaves the package", source)));
+        }
+
+        let mut entry = InstlistEntry {
+            source: PathBuf::from(source),
+            target: PathBuf::from(target),
+            permissions: None,
+            required: false,
+        };
+        for option in options {
+            if *option == "required" && !entry.required {
+                entry.required = true;
+            } else if entry.permissions.is_none() {
+                let mode = parse_mode(option)
+                    .ok_or_else(|| invalid(format!("'{}' is not an octal mode", option)))?;
+                entry.permissions = Some(FilePermissions::from_octal(mode));
+            } else {
+                return Err(invalid(format!("unexpected '{}'", option)));
+            }
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Splits a glob source into the leading directory without wildcards and
+/// the pattern components after it.
+fn split_glob(source: &Path) -> (PathBuf, Vec<String>) {
+    let mut root = PathBuf::new();
+    let mut pattern = Vec::new();
+    for component in source.components() {
+        let component = component.as_os_str().to_string_lossy();
+        if pattern.is_empty() && !component.contains(['*', '?']) {
+            root.push(component.as_ref());
+        } else {
+            pattern.push(component.into_owned());
+        }
+    }
+    (root, pattern)
 }
 
+/// Matches one path component against a pattern where `*` stands for any
+/// run of characters and `?` for a single one.
+fn matches_component(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Parses an octal file mode such as `755` or `0644`.
+fn parse_mode(mode: &str) -> Option<u32> {
+    u32::from_str_radix(mode, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+}
+
+/// Placeholder an instlist target may start with, standing for the install
+/// prefix.
+pub const PREFIX_PLACEHOLDER: &str = "${PREFIX}";
+
+/// Replaces a leading [`PREFIX_PLACEHOLDER`] of an instlist target with
+/// `prefix`. Other targets are returned as they are.
+fn relocate(target: PathBuf, prefix: Option<&Path>) -> Result<PathBuf, UhpmError> {
+    let Ok(relative) = target.strip_prefix(PREFIX_PLACEHOLDER) else {
+        return Ok(target);
+    };
+    match prefix {
+        Some(prefix) => Ok(prefix.join(relative)),
+        None => Err(UhpmError::ValidationError(format!(
+            "Install target {} needs a prefix, but none is configured",
+            target.display()
+        ))),
+    }
+}
+
+/// A file or link placed at an instlist target, kept to undo an interrupted
+/// placement.
+#[derive(Debug)]
+enum Placement {
+    Placed(PathBuf),
+    /// A link that used to point at `previous`.
+    Replaced {
+        target: PathBuf,
+        previous: PathBuf,
+    },
+}
+
+/// Context for a failure of `operation` on `path`, one of the files of
+/// `package_id`.
+pub(crate) fn file_context(
+    operation: &'static str,
+    package_id: &PackageId,
+    path: &Path,
+) -> ErrorContext {
+    let context = ErrorContext::new(operation).with_path(path);
+    match package_id.reference() {
+        Some(package_ref) => context.with_package(package_ref),
+        None => context,
+    }
+}
+
+/// Directory inside the packages directory holding build-time-only
+/// dependencies, one subdirectory per package they are needed to build.
+pub const BUILD_DIR: &str = "_build";
+
+#[derive(Clone)]
 pub struct PackageFilesRepository<FS>
 where
     FS: FileSystemOperations,
 {
     file_system: FS,
     packages_dir: PathBuf,
+    target_policy: Option<TargetPolicy>,
+    prefix: Option<PathBuf>,
+    glob_directories: GlobDirectories,
 }
 
 impl<FS> PackageFilesRepository<FS>
@@ -32,9 +397,53 @@ where
         Self {
             file_system,
             packages_dir,
+            target_policy: None,
+            prefix: None,
+            glob_directories: GlobDirectories::default(),
         }
     }
 
+    /// Normalizes instlist targets and restricts where they may point.
+    ///
+    /// Without a policy targets are used as written.
+    pub fn with_target_policy(mut self, policy: TargetPolicy) -> Self {
+        self.target_policy = Some(policy);
+        self
+    }
+
+    /// Places `${PREFIX}` instlist targets under `prefix`, which the target
+    /// policy then allows as well.
+    pub fn with_prefix(mut self, prefix: PathBuf) -> Self {
+        self.target_policy = self.target_policy.map(|policy| policy.allowing(&prefix));
+        self.prefix = Some(prefix);
+        self
+    }
+
+    /// Sets what instlist glob patterns do with the directories they match.
+    pub fn with_glob_directories(mut self, glob_directories: GlobDirectories) -> Self {
+        self.glob_directories = glob_directories;
+        self
+    }
+
+    /// Directory the build-time-only dependencies of `consumer` are
+    /// extracted to.
+    pub fn get_build_dir(&self, consumer: &PackageId) -> PathBuf {
+        self.packages_dir.join(BUILD_DIR).join(consumer.as_str())
+    }
+
+    /// Package files stored in the build directory of `consumer`.
+    pub fn for_build_of(&self, consumer: &PackageId) -> Self
+    where
+        FS: Clone,
+    {
+        Self::new(self.file_system.clone(), self.get_build_dir(consumer))
+    }
+
+    /// Directory packages are extracted to.
+    pub fn packages_dir(&self) -> &Path {
+        &self.packages_dir
+    }
+
     pub fn get_package_path(&self, package_id: &PackageId) -> PathBuf {
         self.packages_dir.join(package_id.as_str())
     }
@@ -52,32 +461,251 @@ impl<FS> PackageFilesRepository<FS>
 where
     FS: FileSystemOperations + Send + Sync,
 {
+    /// Unpacks a package archive into the package store.
+    ///
+    /// Every entry is checked to stay inside the package, symlink targets
+    /// included, before anything is written, and nothing is written through
+    /// a symlink. Returns the unpacked size of the package, the sum of its
+    /// file sizes.
     pub async fn extract_package(
         &self,
         package_id: &PackageId,
         package_data: &[u8],
-    ) -> Result<(), UhpmError> {
+    ) -> Result<u64, UhpmError> {
+        self.extract(package_id, package_data, None, &CancellationToken::new())
+            .await
+    }
+
+    /// Like `extract_package`, reading the archive from `reader` as it is
+    /// unpacked instead of holding all of it in memory.
+    ///
+    /// Entries are checked as they are reached, so an archive rejected part
+    /// way leaves the entries before the offending one behind.
+    pub async fn extract_package_from<R>(
+        &self,
+        package_id: &PackageId,
+        reader: R,
+    ) -> Result<u64, UhpmError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        self.extract_stream(package_id, reader, None, &CancellationToken::new())
+            .await
+    }
+
+    /// Like `extract_package`, publishing extraction events for `package_ref`
+    /// to `events` as files are written.
+    ///
+    /// `cancellation` is checked before every archive entry. A cancelled
+    /// extraction leaves the entries written so far behind.
+    pub async fn extract_package_with_events(
+        &self,
+        package_ref: &PackageReference,
+        package_data: &[u8],
+        events: &dyn EventPublisher,
+        cancellation: &CancellationToken,
+    ) -> Result<u64, UhpmError> {
+        let package_id = PackageId::new(&package_ref.name, &package_ref.version);
+        self.extract(
+            &package_id,
+            package_data,
+            Some((package_ref, events)),
+            cancellation,
+        )
+        .await
+    }
+
+    async fn extract(
+        &self,
+        package_id: &PackageId,
+        package_data: &[u8],
+        events: Option<(&PackageReference, &dyn EventPublisher)>,
+        cancellation: &CancellationToken,
+    ) -> Result<u64, UhpmError> {
         let package_path = self.get_package_path(package_id);
+        let files_total = scan_archive(package_data)
+            .with_context(|| file_context("extract", package_id, &package_path))?;
+        let events = events.map(|(package_ref, events)| (package_ref, events, files_total));
+        self.extract_stream(package_id, package_data, events, cancellation)
+            .await
+    }
 
-        self.file_system.create_dir_all(&package_path).await?;
+    /// Unpacks the archive read from `reader`, publishing extraction events
+    /// out of `files_total` files if `events` is given.
+    async fn extract_stream<R>(
+        &self,
+        package_id: &PackageId,
+        reader: R,
+        events: Option<(&PackageReference, &dyn EventPublisher, usize)>,
+        cancellation: &CancellationToken,
+    ) -> Result<u64, UhpmError>
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        let package_path = self.get_package_path(package_id);
+        let context = |path: &Path| file_context("extract", package_id, path);
+        let mut archive = ArchiveReader::new(reader);
+
+        let mut files_done = 0;
+        if let Some((package_ref, events, _)) = events {
+            events
+                .publish(PackageEvent::ExtractionStarted {
+                    package_ref: package_ref.clone(),
+                })
+                .await?;
+        }
 
-        let temp_path = package_path.join("package.uhp");
         self.file_system
-            .write_file(&temp_path, package_data)
-            .await?;
+            .create_dir_all(&package_path)
+            .await
+            .with_context(|| context(&package_path))?;
+
+        // Directory modes are applied last so read-only directories can
+        // still be filled.
+        let mut directories = Vec::new();
+        let mut size = 0;
+        while let Some(entry) = archive
+            .next_entry()
+            .await
+            .with_context(|| context(&package_path))?
+        {
+            cancellation.check()?;
+            if !stays_inside(Path::new(""), &entry.path) {
+                return Err(FsError::ExtractionError(format!(
+                    "Archive entry escapes the package: {}",
+                    entry.path.display()
+                )))
+                .with_context(|| context(&package_path));
+            }
+            if let StreamEntryKind::Symlink(link) = &entry.kind
+                && !link_stays_inside(&entry.path, link)
+            {
+                return Err(FsError::ExtractionError(format!(
+                    "Archive symlink points outside the package: {} -> {}",
+                    entry.path.display(),
+                    link.display()
+                )))
+                .with_context(|| context(&package_path));
+            }
+            // Links written by earlier entries could lead anywhere, so
+            // nothing is written through them.
+            let linked = matches!(entry.kind, StreamEntryKind::Symlink(_));
+            if let Some(symlink) = self
+                .symlink_on_path(&package_path, &entry.path, !linked)
+                .await
+            {
+                return Err(FsError::ExtractionError(format!(
+                    "Archive entry {} would be written through the symlink {}",
+                    entry.path.display(),
+                    symlink.display()
+                )))
+                .with_context(|| context(&package_path));
+            }
+            let path = package_path.join(&entry.path);
+            if let Some(parent) = path.parent() {
+                self.file_system
+                    .create_dir_all(parent)
+                    .await
+                    .with_context(|| context(parent))?;
+            }
 
-        let tar_gz =
-            std::fs::File::open(&temp_path).map_err(|e| FsError::ExtractionError(e.to_string()))?;
-        let tar = GzDecoder::new(tar_gz);
-        let mut archive = Archive::new(tar);
+            match entry.kind {
+                StreamEntryKind::Directory => {
+                    self.file_system
+                        .create_dir_all(&path)
+                        .await
+                        .with_context(|| context(&path))?;
+                    directories.push((path, entry.mode));
+                }
+                StreamEntryKind::File => {
+                    let mut written = false;
+                    while let Some(data) =
+                        archive.read_data().await.with_context(|| context(&path))?
+                    {
+                        size += data.len() as u64;
+                        if written {
+                            self.file_system.append_file(&path, &data).await
+                        } else {
+                            self.file_system.write_file(&path, &data).await
+                        }
+                        .with_context(|| context(&path))?;
+                        written = true;
+                    }
+                    if !written {
+                        self.file_system
+                            .write_file(&path, &[])
+                            .await
+                            .with_context(|| context(&path))?;
+                    }
+                    self.file_system
+                        .set_permissions(&path, entry.mode)
+                        .await
+                        .with_context(|| context(&path))?;
+
+                    files_done += 1;
+                    if let Some((package_ref, events, files_total)) = events {
+                        events
+                            .publish(PackageEvent::ExtractionProgress {
+                                package_ref: package_ref.clone(),
+                                files_done,
+                                files_total,
+                            })
+                            .await?;
+                    }
+                }
+                StreamEntryKind::Symlink(link) => {
+                    if self.file_system.is_symlink(&path).await {
+                        self.file_system
+                            .remove_symlink(&path)
+                            .await
+                            .with_context(|| context(&path))?;
+                    }
+                    self.file_system
+                        .create_symlink(&Symlink::file(link, &path))
+                        .await
+                        .with_context(|| context(&path))?;
+                }
+            }
+        }
 
-        archive
-            .unpack(&package_path)
-            .map_err(|e| FsError::ExtractionError(e.to_string()))?;
+        for (path, mode) in directories.into_iter().rev() {
+            self.file_system
+                .set_permissions(&path, mode)
+                .await
+                .with_context(|| context(&path))?;
+        }
 
-        self.file_system.remove(&temp_path).await?;
+        self.update_index(package_id, true).await?;
+        if let Some((package_ref, events, _)) = events {
+            events
+                .publish(PackageEvent::ExtractionCompleted {
+                    package_ref: package_ref.clone(),
+                })
+                .await?;
+        }
+        Ok(size)
+    }
 
-        Ok(())
+    /// First symlink among the ancestors of `relative` below `base`, and
+    /// `relative` itself when `including_self` is set.
+    async fn symlink_on_path(
+        &self,
+        base: &Path,
+        relative: &Path,
+        including_self: bool,
+    ) -> Option<PathBuf> {
+        let mut path = base.to_path_buf();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            path.push(component);
+            if components.peek().is_none() && !including_self {
+                break;
+            }
+            if self.file_system.is_symlink(&path).await {
+                return Some(path);
+            }
+        }
+        None
     }
 
     pub async fn remove_package_files(&self, package_id: &PackageId) -> Result<(), UhpmError> {
@@ -87,7 +715,55 @@ where
             self.file_system.remove_dir_all(&package_path).await?;
         }
 
-        Ok(())
+        self.update_index(package_id, false).await
+    }
+
+    /// Adds the package to the package index, or drops it when it is no
+    /// longer `present`.
+    ///
+    /// Nothing is done without an index; it is built from the directory
+    /// when first needed. An unreadable index is deleted so it gets rebuilt.
+    async fn update_index(&self, package_id: &PackageId, present: bool) -> Result<(), UhpmError> {
+        let mut index = match PackageIndex::load(&self.file_system, &self.packages_dir).await {
+            Ok(Some(index)) => index,
+            Ok(None) => return Ok(()),
+            Err(error) => {
+                warn!(%error, "discarding unreadable package index");
+                return self
+                    .file_system
+                    .remove(&PackageIndex::path(&self.packages_dir))
+                    .await;
+            }
+        };
+
+        let path = PathBuf::from(package_id.as_str());
+        index.remove_path(&path);
+        if present && let Some(meta) = self.load_package_meta(package_id).await? {
+            index.insert(path, meta);
+        }
+        index.save(&self.file_system, &self.packages_dir).await
+    }
+
+    /// Packages extracted to the packages directory, from the names of their
+    /// `name@version` directories. Other entries are skipped.
+    pub async fn stored_packages(&self) -> Result<Vec<PackageReference>, UhpmError> {
+        if !self.file_system.exists(&self.packages_dir).await {
+            return Ok(Vec::new());
+        }
+        let mut stored = Vec::new();
+        for path in self.file_system.read_dir(&self.packages_dir).await? {
+            let package_ref = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.rsplit_once('@'))
+                .and_then(|(name, version)| {
+                    let version = semver::Version::parse(version).ok()?;
+                    Some(PackageReference::new(name.to_string(), version))
+                });
+            stored.extend(package_ref);
+        }
+        stored.sort();
+        Ok(stored)
     }
 
     pub async fn load_package_meta(
@@ -103,10 +779,7 @@ where
         let data = self.file_system.read_file(&meta_path).await?;
         let meta_str = std::str::from_utf8(&data)
             .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
-        let meta: PackageMeta =
-            toml::from_str(meta_str).map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
-
-        Ok(Some(meta))
+        Ok(Some(PackageMeta::from_toml(&meta_path, meta_str)?))
     }
 
     pub async fn save_package_meta(
@@ -146,72 +819,438 @@ where
 
         let mut symlinks = Vec::new();
 
-        for line in content.lines() {
-            let line = line.trim();
+        for entry in parse_instlist(&instlist_path, content)? {
+            let target_absolute = relocate(entry.target.clone(), self.prefix.as_deref())?;
+            let placements = if entry.is_glob() {
+                self.expand_glob(&package_path, &entry, &target_absolute)
+                    .await?
+            } else {
+                let source_absolute = package_path.join(&entry.source);
+                let link_type = match self.file_system.metadata(&source_absolute).await {
+                    Ok(metadata) if metadata.is_directory() => SymlinkType::Directory,
+                    _ => SymlinkType::File,
+                };
+                vec![(source_absolute, target_absolute, link_type)]
+            };
+
+            for (source, target, link_type) in placements {
+                let target = match &self.target_policy {
+                    Some(policy) => policy.normalize(&target),
+                    None => target,
+                };
+                let mut symlink = Symlink::new(source, target, link_type);
+                if let Some(permissions) = &entry.permissions {
+                    symlink.metadata = symlink.metadata.with_permissions(permissions.clone());
+                }
+                symlinks.push(symlink);
+            }
+        }
+
+        Ok(symlinks)
+    }
 
-            if line.is_empty() || line.starts_with('#') {
+    /// Expands the glob source of `entry` against the package contents.
+    ///
+    /// Each match is placed at `target` joined with its path below the glob
+    /// root, the part of the source before the first wildcard. Directories
+    /// matched are handled according to the configured [`GlobDirectories`].
+    async fn expand_glob(
+        &self,
+        package_path: &Path,
+        entry: &InstlistEntry,
+        target: &Path,
+    ) -> Result<Vec<(PathBuf, PathBuf, SymlinkType)>, UhpmError> {
+        let (root, pattern) = split_glob(&entry.source);
+        let root = package_path.join(root);
+
+        let mut matches = BTreeSet::new();
+        let root_relative = root.strip_prefix(package_path).unwrap_or(&root);
+        if self.is_directory(&root).await
+            && self
+                .symlink_on_path(package_path, root_relative, true)
+                .await
+                .is_none()
+        {
+            self.match_glob(&root, &pattern, &mut matches).await?;
+        }
+
+        let mut placements = BTreeMap::new();
+        for path in matches {
+            if !stays_inside(package_path, &path) {
+                return Err(FsError::InvalidPath(format!(
+                    "Instlist pattern {} matched {} outside the package",
+                    entry.source.display(),
+                    path.display()
+                ))
+                .into());
+            }
+            if !self.is_directory(&path).await {
+                placements.insert(path, SymlinkType::File);
                 continue;
             }
+            match self.glob_directories {
+                GlobDirectories::Recurse => {
+                    for file in self.files_below(&path).await? {
+                        placements.insert(file, SymlinkType::File);
+                    }
+                }
+                GlobDirectories::Link => {
+                    placements.insert(path, SymlinkType::Directory);
+                }
+                GlobDirectories::Skip => {}
+            }
+        }
 
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if parts.len() == 2 {
-                let source_relative = PathBuf::from(parts[0]);
-                let target_absolute = PathBuf::from(parts[1]);
+        if placements.is_empty() {
+            if entry.required {
+                return Err(UhpmError::ValidationError(format!(
+                    "Instlist pattern {} matched no files",
+                    entry.source.display()
+                )));
+            }
+            warn!(pattern = %entry.source.display(), "instlist pattern matched no files");
+        }
 
-                let source_absolute = package_path.join(&source_relative);
+        placements
+            .into_iter()
+            .map(|(source, link_type)| {
+                let relative = source
+                    .strip_prefix(&root)
+                    .map_err(|e| FsError::InvalidPath(e.to_string()))?;
+                let target = target.join(relative);
+                Ok((source, target, link_type))
+            })
+            .collect()
+    }
 
-                let link_type =
-                    if let Ok(metadata) = self.file_system.metadata(&source_absolute).await {
-                        if metadata.is_directory() {
-                            SymlinkType::Directory
-                        } else {
-                            SymlinkType::File
-                        }
-                    } else {
-                        SymlinkType::File
-                    };
+    /// Collects the paths below `dir` matching `pattern`, one glob component
+    /// per directory level with `**` standing for any number of levels.
+    async fn match_glob(
+        &self,
+        dir: &Path,
+        pattern: &[String],
+        matches: &mut BTreeSet<PathBuf>,
+    ) -> Result<(), UhpmError> {
+        let Some((component, rest)) = pattern.split_first() else {
+            return Ok(());
+        };
+        if component == "**" && !rest.is_empty() {
+            Box::pin(self.match_glob(dir, rest, matches)).await?;
+        }
 
-                let symlink = Symlink::new(source_absolute, target_absolute, link_type);
-                symlinks.push(symlink);
+        for child in self.file_system.read_dir(dir).await? {
+            let name = child
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+            let is_directory = self.is_directory(&child).await;
+
+            if component == "**" {
+                if rest.is_empty() {
+                    matches.insert(child.clone());
+                }
+                if is_directory {
+                    Box::pin(self.match_glob(&child, pattern, matches)).await?;
+                }
+            } else if matches_component(component, &name) {
+                if rest.is_empty() {
+                    matches.insert(child);
+                } else if is_directory {
+                    Box::pin(self.match_glob(&child, rest, matches)).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `path` is a directory and not a symlink to one, so globs
+    /// never follow links out of the package or around in a loop.
+    async fn is_directory(&self, path: &Path) -> bool {
+        !self.file_system.is_symlink(path).await
+            && self
+                .file_system
+                .metadata(path)
+                .await
+                .is_ok_and(|metadata| metadata.is_directory())
+    }
+
+    /// Every file and link below `dir`, at any depth. Symlinked directories
+    /// are returned as links rather than descended into.
+    async fn files_below(&self, dir: &Path) -> Result<Vec<PathBuf>, UhpmError> {
+        let mut files = Vec::new();
+        let mut pending = vec![dir.to_path_buf()];
+        while let Some(dir) = pending.pop() {
+            for child in self.file_system.read_dir(&dir).await? {
+                if self.is_directory(&child).await {
+                    pending.push(child);
+                } else {
+                    files.push(child);
+                }
             }
         }
+        Ok(files)
+    }
 
+    /// Loads the instlist, rejecting targets the policy doesn't allow.
+    async fn load_checked_instlist(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Vec<Symlink>, UhpmError> {
+        let symlinks = self.load_package_instlist(package_id).await?;
+        if let Some(policy) = &self.target_policy {
+            for symlink in &symlinks {
+                policy.check(&symlink.target)?;
+            }
+        }
         Ok(symlinks)
     }
 
+    /// Links every instlist entry to its target.
+    ///
+    /// `cancellation` is checked before every entry; once it is cancelled the
+    /// links placed so far are undone and [`UhpmError::Cancelled`] is returned.
     pub async fn create_symlinks_from_instlist(
         &self,
         package_id: &PackageId,
+        cancellation: &CancellationToken,
     ) -> Result<Vec<Symlink>, UhpmError> {
-        let symlinks = self.load_package_instlist(package_id).await?;
+        let symlinks = self.load_checked_instlist(package_id).await?;
 
+        let mut placed = Vec::new();
         for symlink in &symlinks {
-            if let Some(parent) = symlink.target.parent() {
-                self.file_system.create_dir_all(parent).await?;
+            if cancellation.is_cancelled() {
+                return Err(self.undo_placements(placed).await);
             }
 
-            self.file_system.create_symlink(symlink).await?;
+            self.ensure_source_mode(symlink).await?;
+            let action = self.ensure_symlink(symlink).await?;
+            placed.push(match &action {
+                SymlinkAction::Replaced { previous } => Placement::Replaced {
+                    target: symlink.target.clone(),
+                    previous: previous.clone(),
+                },
+                _ => Placement::Placed(symlink.target.clone()),
+            });
+            debug!(
+                source = %symlink.source.display(),
+                target = %symlink.target.display(),
+                ?action,
+                "linked file"
+            );
         }
 
         Ok(symlinks)
     }
 
-    pub async fn copy_files_direct(&self, package_id: &PackageId) -> Result<(), UhpmError> {
-        let symlinks = self.load_package_instlist(package_id).await?;
+    /// Whether `symlink.target` is a symlink pointing at `symlink.source`.
+    pub async fn is_linked(&self, symlink: &Symlink) -> bool {
+        self.file_system.is_symlink(&symlink.target).await
+            && self
+                .file_system
+                .read_symlink(&symlink.target)
+                .await
+                .is_ok_and(|current| current == symlink.source)
+    }
+
+    /// Makes `symlink.target` point at `symlink.source`.
+    ///
+    /// A link that already points at the source is left alone. A link pointing
+    /// into the packages directory belonged to another installation and is
+    /// replaced. Anything else at the target is a conflict and is not touched.
+    pub async fn ensure_symlink(&self, symlink: &Symlink) -> Result<SymlinkAction, UhpmError> {
+        if self.file_system.is_symlink(&symlink.target).await {
+            let current = self.file_system.read_symlink(&symlink.target).await?;
+            if current == symlink.source {
+                return Ok(SymlinkAction::Unchanged);
+            }
+
+            if !current.starts_with(&self.packages_dir) {
+                return Err(UhpmError::FileConflict {
+                    path: symlink.target.clone(),
+                    reason: format!("existing symlink points to {}", current.display()),
+                });
+            }
+
+            self.file_system.remove_symlink(&symlink.target).await?;
+            self.file_system.create_symlink(symlink).await?;
+            return Ok(SymlinkAction::Replaced { previous: current });
+        }
+
+        if self.file_system.exists(&symlink.target).await {
+            return Err(UhpmError::FileConflict {
+                path: symlink.target.clone(),
+                reason: "a file or directory already exists".to_string(),
+            });
+        }
+
+        if let Some(parent) = symlink.target.parent() {
+            self.file_system.create_dir_all(parent).await?;
+        }
+        self.file_system.create_symlink(symlink).await?;
+        Ok(SymlinkAction::Created)
+    }
 
+    /// Copies every instlist entry to its target, undoing the copies made so
+    /// far once `cancellation` is cancelled.
+    pub async fn copy_files_direct(
+        &self,
+        package_id: &PackageId,
+        cancellation: &CancellationToken,
+    ) -> Result<(), UhpmError> {
+        let symlinks = self.load_checked_instlist(package_id).await?;
+
+        let mut placed = Vec::new();
         for symlink in symlinks {
+            if cancellation.is_cancelled() {
+                return Err(self.undo_placements(placed).await);
+            }
+
             if let Some(parent) = symlink.target.parent() {
                 self.file_system.create_dir_all(parent).await?;
             }
 
+            let mode = self.placed_mode(&symlink).await?;
             self.file_system
                 .copy_file(&symlink.source, &symlink.target)
                 .await?;
+            self.file_system
+                .set_permissions(&symlink.target, mode)
+                .await?;
+            debug!(
+                source = %symlink.source.display(),
+                target = %symlink.target.display(),
+                "copied file"
+            );
+            placed.push(Placement::Placed(symlink.target));
         }
 
         Ok(())
     }
 
+    /// Hard links the instlist entries to their targets.
+    ///
+    /// Targets on a different filesystem than the package store can't be hard
+    /// linked; those are copied instead and reported in the returned warnings.
+    /// Links made so far are undone once `cancellation` is cancelled.
+    pub async fn hard_link_files(
+        &self,
+        package_id: &PackageId,
+        cancellation: &CancellationToken,
+    ) -> Result<Vec<String>, UhpmError> {
+        let symlinks = self.load_checked_instlist(package_id).await?;
+        let mut warnings = Vec::new();
+
+        let mut placed = Vec::new();
+        for symlink in symlinks {
+            if cancellation.is_cancelled() {
+                return Err(self.undo_placements(placed).await);
+            }
+
+            if let Some(parent) = symlink.target.parent() {
+                self.file_system.create_dir_all(parent).await?;
+            }
+
+            let mode = self.placed_mode(&symlink).await?;
+            match self
+                .file_system
+                .create_hard_link(&symlink.source, &symlink.target)
+                .await
+            {
+                Ok(()) => debug!(
+                    source = %symlink.source.display(),
+                    target = %symlink.target.display(),
+                    "hard linked file"
+                ),
+                Err(UhpmError::FileSystemError(FsError::CrossDevice(_))) => {
+                    warn!(
+                        target = %symlink.target.display(),
+                        "cross-device hard link, copying instead"
+                    );
+                    self.file_system
+                        .copy_file(&symlink.source, &symlink.target)
+                        .await?;
+                    warnings.push(format!(
+                        "{} is on another filesystem, copied instead of hard linked",
+                        symlink.target.display()
+                    ));
+                }
+                Err(e) => return Err(e),
+            }
+            self.file_system
+                .set_permissions(&symlink.target, mode)
+                .await?;
+            placed.push(Placement::Placed(symlink.target));
+        }
+
+        Ok(warnings)
+    }
+
+    /// Mode a copy or hard link of `symlink.source` is given: the one the
+    /// instlist states, otherwise the one the file has in the package store.
+    /// Executables always keep their owner execute bit.
+    async fn placed_mode(&self, symlink: &Symlink) -> Result<u32, UhpmError> {
+        if let Some(permissions) = &symlink.metadata.permissions {
+            return Ok(permissions.to_mode());
+        }
+        let metadata = self.file_system.metadata(&symlink.source).await?;
+        let mut mode = metadata.mode();
+        if metadata.file_type == FileType::Executable {
+            mode |= 0o100;
+        }
+        Ok(mode)
+    }
+
+    /// Gives the store file a symlink points at the mode its instlist entry
+    /// states, the link itself having no permissions of its own.
+    async fn ensure_source_mode(&self, symlink: &Symlink) -> Result<(), UhpmError> {
+        let Some(permissions) = &symlink.metadata.permissions else {
+            return Ok(());
+        };
+        let mode = permissions.to_mode();
+        let current = self.file_system.metadata(&symlink.source).await?;
+        if current.mode() != mode {
+            debug!(
+                source = %symlink.source.display(),
+                from = format_args!("{:o}", current.mode()),
+                to = format_args!("{:o}", mode),
+                "fixed store file mode"
+            );
+            self.file_system
+                .set_permissions(&symlink.source, mode)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Reverts `placed`, newest first, and returns [`UhpmError::Cancelled`].
+    ///
+    /// Undoing is best effort: a placement that can't be reverted is logged
+    /// and skipped so the rest are still undone.
+    async fn undo_placements(&self, placed: Vec<Placement>) -> UhpmError {
+        for placement in placed.into_iter().rev() {
+            let outcome = match &placement {
+                Placement::Placed(target) if self.file_system.is_symlink(target).await => {
+                    self.file_system.remove_symlink(target).await
+                }
+                Placement::Placed(target) => self.file_system.remove(target).await,
+                Placement::Replaced { target, previous } => {
+                    match self.file_system.remove_symlink(target).await {
+                        Ok(()) => {
+                            self.file_system
+                                .create_symlink(&Symlink::file(previous.clone(), target.clone()))
+                                .await
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+            };
+            if let Err(error) = outcome {
+                warn!(?placement, %error, "failed to undo placement");
+            }
+        }
+        UhpmError::Cancelled
+    }
+
     pub async fn remove_installation_files(&self, package_id: &PackageId) -> Result<(), UhpmError> {
         let symlinks = self.load_package_instlist(package_id).await?;
 
@@ -228,6 +1267,25 @@ where
         Ok(())
     }
 
+    /// Sums the sizes of the regular files in a package's store directory.
+    pub async fn package_size(&self, package_id: &PackageId) -> Result<u64, UhpmError> {
+        let mut pending = vec![self.get_package_path(package_id)];
+        let mut size = 0;
+
+        while let Some(dir) = pending.pop() {
+            for entry in self.file_system.read_dir(&dir).await? {
+                let metadata = self.file_system.metadata(&entry).await?;
+                if metadata.is_directory() {
+                    pending.push(entry);
+                } else if !metadata.is_symlink() {
+                    size += metadata.size;
+                }
+            }
+        }
+
+        Ok(size)
+    }
+
     pub async fn package_exists(&self, package_id: &PackageId) -> bool {
         let package_path = self.get_package_path(package_id);
         self.file_system.exists(&package_path).await
@@ -263,60 +1321,1083 @@ where
         &self,
         package_id: &PackageId,
     ) -> Result<Vec<u8>, UhpmError> {
+        let mut archive_data = Vec::new();
+        self.create_package_archive_to(package_id, &mut archive_data)
+            .await?;
+        Ok(archive_data)
+    }
+
+    /// Like `create_package_archive`, writing the archive to `writer` as it
+    /// is produced instead of collecting it in memory.
+    pub async fn create_package_archive_to<W>(
+        &self,
+        package_id: &PackageId,
+        writer: W,
+    ) -> Result<(), UhpmError>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
         let package_path = self.get_package_path(package_id);
 
         if !self.file_system.exists(&package_path).await {
             return Err(UhpmError::PackageNotFound(package_id.as_str().to_string()));
         }
 
+        self.write_archive(&package_path, writer).await
+    }
+
+    /// Packs the contents of `package_path` into a gzipped tar archive.
+    ///
+    /// The archive is reproducible: entries are sorted by path and carry no
+    /// timestamps, and the gzip header is fixed, so the same directory
+    /// always packs to the same bytes.
+    pub async fn create_archive_from_dir(&self, package_path: &Path) -> Result<Vec<u8>, UhpmError> {
         let mut archive_data = Vec::new();
-        {
-            let enc = GzEncoder::new(&mut archive_data, Compression::default());
-            let mut tar = Builder::new(enc);
+        self.write_archive(package_path, &mut archive_data).await?;
+        Ok(archive_data)
+    }
 
-            self.add_directory_to_tar(&mut tar, &package_path, &package_path)
-                .await?;
+    async fn write_archive<W>(&self, package_path: &Path, writer: W) -> Result<(), UhpmError>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let mut archive = ArchiveWriter::new(writer);
+        self.add_directory_to_tar(&mut archive, package_path, package_path)
+            .await?;
+        archive.finish().await
+    }
 
-            tar.finish()
-                .map_err(|e| UhpmError::SerializationError(e.to_string()))?;
+    /// Appends the contents of `current_path` to `tar`, depth first.
+    ///
+    /// Entries keep their permission bits. Directories get their own entries
+    /// so empty ones survive, and symlinks pointing inside the package are
+    /// stored as links while links leading out of it are stored as the file
+    /// they point to.
+    async fn add_directory_to_tar<W>(
+        &self,
+        tar: &mut ArchiveWriter<W>,
+        base_path: &Path,
+        current_path: &Path,
+    ) -> Result<(), UhpmError>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let mut entries = self.file_system.read_dir(current_path).await?;
+        entries.sort();
+        for entry in entries {
+            let metadata = self.file_system.metadata(&entry).await?;
+            let relative_path = entry
+                .strip_prefix(base_path)
+                .map_err(|e| FsError::InvalidPath(e.to_string()))?;
+
+            if metadata.is_symlink() {
+                let link = self.file_system.read_symlink(&entry).await?;
+                let resolved = if link.is_absolute() {
+                    link.clone()
+                } else {
+                    entry.parent().unwrap_or(base_path).join(&link)
+                };
+                if stays_inside(base_path, &resolved) {
+                    let mut header = tar_header(EntryType::Symlink, 0o777, 0);
+                    tar.append_link(&mut header, relative_path, &link).await?;
+                    continue;
+                }
+
+                let target = self.file_system.metadata(&resolved).await?;
+                self.add_file_to_tar(tar, &entry, relative_path, &target)
+                    .await?;
+            } else if metadata.is_directory() {
+                let mut header = tar_header(EntryType::Directory, metadata.mode(), 0);
+                tar.append(&mut header, relative_path).await?;
+
+                Box::pin(self.add_directory_to_tar(tar, base_path, &entry)).await?;
+            } else {
+                self.add_file_to_tar(tar, &entry, relative_path, &metadata)
+                    .await?;
+            }
         }
 
-        Ok(archive_data)
+        Ok(())
     }
 
-    async fn add_directory_to_tar(
+    /// Appends the file at `path`, described by `metadata`, reading it in
+    /// pieces.
+    async fn add_file_to_tar<W>(
         &self,
-        tar: &mut Builder<GzEncoder<&mut Vec<u8>>>,
-        base_path: &PathBuf,
-        current_path: &PathBuf,
-    ) -> Result<(), UhpmError> {
-        if let Ok(entries) = self.file_system.read_dir(current_path).await {
-            for entry in entries {
-                let metadata = self.file_system.metadata(&entry).await?;
+        tar: &mut ArchiveWriter<W>,
+        path: &Path,
+        relative_path: &Path,
+        metadata: &FileMetadata,
+    ) -> Result<(), UhpmError>
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        let mut header = tar_header(EntryType::Regular, metadata.mode(), 0);
+        header.set_size(metadata.size);
+        tar.append(&mut header, relative_path).await?;
+
+        let mut reader = self.file_system.open_read(path).await?;
+        let mut chunk = vec![0; DATA_CHUNK];
+        let mut written = 0;
+        loop {
+            let read = reader
+                .read(&mut chunk)
+                .await
+                .map_err(|e| FsError::Io(format!("{}: {}", path.display(), e)))?;
+            if read == 0 {
+                break;
+            }
+            written += read as u64;
+            if written > metadata.size {
+                break;
+            }
+            tar.write_data(&chunk[..read]).await?;
+        }
+        if written != metadata.size {
+            return Err(FsError::Io(format!(
+                "{} changed while it was archived: expected {} bytes, read {}",
+                path.display(),
+                metadata.size,
+                written
+            ))
+            .into());
+        }
+        tar.finish_data(written).await
+    }
+}
 
-                if metadata.is_directory() {
-                    let future = Box::pin(self.add_directory_to_tar(tar, base_path, &entry));
-                    future.await?;
-                } else {
-                    let relative_path = entry
-                        .strip_prefix(base_path)
-                        .map_err(|e| FsError::InvalidPath(e.to_string()))?;
+/// Checks a package archive without unpacking it, rejecting entries that
+/// would be written outside the package directory.
+///
+/// Returns the number of files in the archive.
+fn scan_archive(package_data: &[u8]) -> Result<usize, UhpmError> {
+    let mut archive = Archive::new(GzDecoder::new(package_data));
+    let mut files = 0;
+
+    for entry in archive
+        .entries()
+        .map_err(|e| FsError::ExtractionError(e.to_string()))?
+    {
+        let entry = entry.map_err(|e| FsError::ExtractionError(e.to_string()))?;
+        let path = entry
+            .path()
+            .map_err(|e| FsError::ExtractionError(e.to_string()))?
+            .into_owned();
+        if !stays_inside(Path::new(""), &path) {
+            return Err(FsError::ExtractionError(format!(
+                "Archive entry escapes the package: {}",
+                path.display()
+            ))
+            .into());
+        }
+        match entry.header().entry_type() {
+            EntryType::Symlink => {
+                let link = entry
+                    .link_name()
+                    .map_err(|e| FsError::ExtractionError(e.to_string()))?
+                    .ok_or_else(|| {
+                        FsError::ExtractionError(format!(
+                            "Symlink without target: {}",
+                            path.display()
+                        ))
+                    })?;
+                if !link_stays_inside(&path, &link) {
+                    return Err(FsError::ExtractionError(format!(
+                        "Archive symlink points outside the package: {} -> {}",
+                        path.display(),
+                        link.display()
+                    ))
+                    .into());
+                }
+            }
+            EntryType::Regular | EntryType::Continuous => files += 1,
+            _ => {}
+        }
+    }
 
-                    let content = self.file_system.read_file(&entry).await?;
+    Ok(files)
+}
 
-                    let mut header = tar::Header::new_gnu();
-                    header
-                        .set_path(relative_path)
-                        .map_err(|e| UhpmError::SerializationError(e.to_string()))?;
-                    header.set_size(content.len() as u64);
-                    header.set_cksum();
+/// Checks lexically that `path`, relative or below `base`, doesn't leave `base`.
+fn stays_inside(base: &Path, path: &Path) -> bool {
+    let relative = match path.strip_prefix(base) {
+        Ok(relative) => relative,
+        Err(_) if path.is_relative() => path,
+        Err(_) => return false,
+    };
+
+    let mut depth = 0usize;
+    for component in relative.components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return false,
+        }
+    }
+    true
+}
+
+/// Checks lexically that the symlink at `path` pointing to `link` resolves
+/// inside the package, `path` being relative to the package root.
+fn link_stays_inside(path: &Path, link: &Path) -> bool {
+    let directory = path.parent().unwrap_or(Path::new(""));
+    !link.is_absolute() && stays_inside(Path::new(""), &directory.join(link))
+}
+
+fn tar_header(entry_type: EntryType, mode: u32, size: usize) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_mode(mode);
+    header.set_size(size as u64);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MemoryFileSystem, block_on};
+    use flate2::{Compression, write::GzEncoder};
+    use std::path::Path;
+    use tar::Builder;
+
+    fn repository(file_system: &MemoryFileSystem) -> PackageFilesRepository<MemoryFileSystem> {
+        PackageFilesRepository::new(file_system.clone(), PathBuf::from("/uhpm/packages"))
+    }
+
+    #[test]
+    fn test_ensure_symlink_is_idempotent() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let symlink = Symlink::file("/uhpm/packages/tool@1.0.0/bin/tool", "/usr/local/bin/tool");
+
+        block_on(async {
+            assert_eq!(
+                repo.ensure_symlink(&symlink).await.unwrap(),
+                SymlinkAction::Created
+            );
+            assert_eq!(
+                repo.ensure_symlink(&symlink).await.unwrap(),
+                SymlinkAction::Unchanged
+            );
+        });
+    }
+
+    #[test]
+    fn test_empty_directories_survive_an_archive_round_trip() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = PackageId::new("server", &semver::Version::new(1, 0, 0));
+        let package_dir = repo.get_package_path(&package_id);
+        file_system.add_file(package_dir.join("bin/server"), b"server");
+
+        block_on(async {
+            file_system
+                .create_dir_all(&package_dir.join("logs"))
+                .await
+                .unwrap();
+            let archive = repo.create_package_archive(&package_id).await.unwrap();
+            file_system.remove_dir_all(&package_dir).await.unwrap();
+
+            repo.extract_package(&package_id, &archive).await.unwrap();
+
+            let logs = file_system.metadata(&package_dir.join("logs")).await;
+            assert!(logs.unwrap().is_directory());
+            assert!(
+                file_system
+                    .read_dir(&package_dir.join("logs"))
+                    .await
+                    .unwrap()
+                    .is_empty()
+            );
+        });
+    }
 
-                    tar.append(&header, &content[..])
-                        .map_err(|e| UhpmError::SerializationError(e.to_string()))?;
+    #[test]
+    fn test_ensure_symlink_replaces_links_into_packages_dir() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let old = Symlink::file("/uhpm/packages/tool@0.9.0/bin/tool", "/usr/local/bin/tool");
+        let new = Symlink::file("/uhpm/packages/tool@1.0.0/bin/tool", "/usr/local/bin/tool");
+
+        block_on(async {
+            file_system.create_symlink(&old).await.unwrap();
+
+            assert_eq!(
+                repo.ensure_symlink(&new).await.unwrap(),
+                SymlinkAction::Replaced {
+                    previous: old.source.clone()
                 }
+            );
+            assert_eq!(
+                file_system.symlink_target(&new.target),
+                Some(new.source.clone())
+            );
+        });
+    }
+
+    #[test]
+    fn test_ensure_symlink_refuses_foreign_files_and_links() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let foreign = Symlink::file("/opt/other/bin/tool", "/usr/local/bin/tool");
+        let ours = Symlink::file("/uhpm/packages/tool@1.0.0/bin/tool", "/usr/local/bin/tool");
+        let file = Symlink::file(
+            "/uhpm/packages/tool@1.0.0/bin/helper",
+            "/usr/local/bin/helper",
+        );
+
+        block_on(async {
+            file_system.create_symlink(&foreign).await.unwrap();
+            file_system.add_file("/usr/local/bin/helper", b"user file");
+
+            let err = repo.ensure_symlink(&ours).await.unwrap_err();
+            assert!(matches!(err, UhpmError::FileConflict { .. }));
+            assert!(err.to_string().contains("/opt/other/bin/tool"));
+            assert_eq!(
+                file_system.symlink_target(&ours.target),
+                Some(foreign.source.clone())
+            );
+
+            assert!(matches!(
+                repo.ensure_symlink(&file).await,
+                Err(UhpmError::FileConflict { .. })
+            ));
+            assert_eq!(
+                file_system.file(Path::new("/usr/local/bin/helper")),
+                Some(b"user file".to_vec())
+            );
+        });
+    }
+
+    #[test]
+    fn test_hard_link_files_places_instlist_targets() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+        file_system.add_file(
+            "/uhpm/packages/tool@1.0.0/instlist",
+            b"bin/tool /usr/local/bin/tool\n",
+        );
+        file_system.add_file("/uhpm/packages/tool@1.0.0/bin/tool", b"binary");
+
+        let warnings =
+            block_on(repo.hard_link_files(&package_id, &CancellationToken::new())).unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(
+            file_system.file(Path::new("/usr/local/bin/tool")),
+            Some(b"binary".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_meta_schema_version_defaults_to_the_first() {
+        let path = Path::new("meta.toml");
+
+        let meta = PackageMeta::from_toml(path, "name = \"tool\"\nversion = \"1.0.0\"\n");
+        assert_eq!(meta.unwrap().schema_version, 1);
+        let meta = PackageMeta::from_toml(
+            path,
+            "schema_version = 1\nname = \"tool\"\nversion = \"1.0.0\"\n",
+        );
+        assert_eq!(meta.unwrap().schema_version, META_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_meta_with_newer_schema_is_rejected() {
+        let path = Path::new("/uhpm/packages/tool@1.0.0/meta.toml");
+
+        let error = PackageMeta::from_toml(
+            path,
+            "schema_version = 2\nname = \"tool\"\nversion = \"1.0.0\"\n",
+        )
+        .unwrap_err();
+
+        assert!(matches!(error, UhpmError::InvalidPackage { path: at, .. } if at == path));
+    }
+
+    #[test]
+    fn test_parse_instlist_reads_an_optional_mode() {
+        let entries = parse_instlist(
+            Path::new("instlist"),
+            "# installed files\n\
+             bin/tool /usr/local/bin/tool\n\
+             \n\
+             bin/daemon /usr/local/sbin/daemon 0750\n\
+             share/doc /usr/share/doc/tool 644\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            entries,
+            vec![
+                InstlistEntry {
+                    source: PathBuf::from("bin/tool"),
+                    target: PathBuf::from("/usr/local/bin/tool"),
+                    permissions: None,
+                    required: false,
+                },
+                InstlistEntry {
+                    source: PathBuf::from("bin/daemon"),
+                    target: PathBuf::from("/usr/local/sbin/daemon"),
+                    permissions: Some(FilePermissions {
+                        read: true,
+                        write: true,
+                        execute: true,
+                    }),
+                    required: false,
+                },
+                InstlistEntry {
+                    source: PathBuf::from("share/doc"),
+                    target: PathBuf::from("/usr/share/doc/tool"),
+                    permissions: Some(FilePermissions::read_write()),
+                    required: false,
+                },
+            ]
+        );
+    }
+
+    fn glob_package(file_system: &MemoryFileSystem, instlist: &str) -> PackageId {
+        let package_dir = Path::new("/uhpm/packages/art@1.0.0");
+        file_system.add_file(package_dir.join("instlist"), instlist.as_bytes());
+        for file in [
+            "bin/art",
+            "bin/art-convert",
+            "share/man/man1/art.1",
+            "share/man/man1/art-convert.1",
+            "share/man/man1/README",
+            "share/icons/app.png",
+            "share/icons/hicolor/16x16/app.png",
+            "share/icons/hicolor/16x16/notes.txt",
+        ] {
+            file_system.add_file(package_dir.join(file), file.as_bytes());
+        }
+        PackageId::new("art", &semver::Version::new(1, 0, 0))
+    }
+
+    fn placed(symlinks: &[Symlink]) -> Vec<(String, String)> {
+        let package_dir = Path::new("/uhpm/packages/art@1.0.0");
+        symlinks
+            .iter()
+            .map(|symlink| {
+                let source = symlink.source.strip_prefix(package_dir).unwrap();
+                (
+                    source.display().to_string(),
+                    symlink.target.display().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_instlist_reports_malformed_lines() {
+        let path = Path::new("/uhpm/packages/tool@1.0.0/instlist");
+        for (content, expected) in [
+            ("bin/tool /usr/local/bin/tool\nbin/other\n", "line 2"),
+            (
+                "bin/tool /usr/local/bin/tool 0755 required extra\n",
+                "line 1",
+            ),
+            ("# tools\n\nbin/tool /usr/local/bin/tool rwx\n", "line 3"),
+        ] {
+            match parse_instlist(path, content) {
+                Err(UhpmError::InvalidPackage { path: at, reason }) => {
+                    assert_eq!(at, path);
+                    assert!(reason.contains(expected), "{}", reason);
+                }
+                other => panic!("expected an invalid package, got {:?}", other),
             }
         }
+    }
 
-        Ok(())
+    #[test]
+    fn test_parse_instlist_of_only_comments_is_empty() {
+        let entries = parse_instlist(Path::new("instlist"), "# nothing yet\n\n   \n").unwrap();
+
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn test_glob_sources_expand_to_one_entry_per_file() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = glob_package(
+            &file_system,
+            "bin/* /usr/local/bin\n\
+             share/man/man1/*.1 /usr/share/man/man1 0644\n\
+             share/icons/**/*.png /usr/share/icons\n\
+             share/fonts/* /usr/share/fonts\n",
+        );
+
+        let symlinks = block_on(repo.load_package_instlist(&package_id)).unwrap();
+
+        assert_eq!(
+            placed(&symlinks),
+            [
+                ("bin/art", "/usr/local/bin/art"),
+                ("bin/art-convert", "/usr/local/bin/art-convert"),
+                (
+                    "share/man/man1/art-convert.1",
+                    "/usr/share/man/man1/art-convert.1"
+                ),
+                ("share/man/man1/art.1", "/usr/share/man/man1/art.1"),
+                ("share/icons/app.png", "/usr/share/icons/app.png"),
+                (
+                    "share/icons/hicolor/16x16/app.png",
+                    "/usr/share/icons/hicolor/16x16/app.png"
+                ),
+            ]
+            .map(|(source, target)| (source.to_string(), target.to_string()))
+        );
+        assert!(symlinks.iter().all(Symlink::is_file_link));
+        assert_eq!(
+            symlinks[2].metadata.permissions,
+            Some(FilePermissions::read_write())
+        );
+    }
+
+    #[test]
+    fn test_glob_directory_matches_follow_the_configured_rule() {
+        let file_system = MemoryFileSystem::new();
+        let package_id = glob_package(&file_system, "share/icons/* /usr/share/icons\n");
+        let load = |rule| {
+            let repo = repository(&file_system).with_glob_directories(rule);
+            block_on(repo.load_package_instlist(&package_id)).unwrap()
+        };
+
+        assert_eq!(
+            placed(&load(GlobDirectories::Recurse)).len(),
+            3,
+            "app.png and both files below hicolor"
+        );
+
+        let linked = load(GlobDirectories::Link);
+        assert_eq!(linked.len(), 2);
+        assert!(linked[1].is_directory_link());
+        assert_eq!(linked[1].target, PathBuf::from("/usr/share/icons/hicolor"));
+
+        assert_eq!(
+            placed(&load(GlobDirectories::Skip)),
+            [(
+                "share/icons/app.png".to_string(),
+                "/usr/share/icons/app.png".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_instlist_sources_leaving_the_package_are_rejected() {
+        for source in ["../../etc/passwd", "bin/../../x", "/etc/passwd", "../*"] {
+            let content = format!("bin/tool /usr/bin/tool\n{} /usr/bin/x\n", source);
+            let err = parse_instlist(Path::new("instlist"), &content).unwrap_err();
+            assert!(err.to_string().contains("leaves the package"), "{}", err);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_globs_do_not_follow_symlinked_directories() {
+        use crate::fs::TokioFileSystem;
+
+        let root = std::env::temp_dir().join(format!("uhpm-glob-{}", uuid::Uuid::new_v4()));
+        let file_system = TokioFileSystem::new();
+        let repo = PackageFilesRepository::new(file_system.clone(), root.join("packages"));
+        let package_id = PackageId::new("art", &semver::Version::new(1, 0, 0));
+        let package_dir = repo.get_package_path(&package_id);
+
+        let symlinks = block_on(async {
+            file_system
+                .create_dir_all(&package_dir.join("share/doc"))
+                .await
+                .unwrap();
+            file_system
+                .write_file(&package_dir.join("share/doc/README"), b"art")
+                .await
+                .unwrap();
+            file_system
+                .create_symlink(&Symlink::directory(".", package_dir.join("share/loop")))
+                .await
+                .unwrap();
+            file_system
+                .create_symlink(&Symlink::directory(&root, package_dir.join("linked")))
+                .await
+                .unwrap();
+            file_system
+                .write_file(
+                    &package_dir.join("instlist"),
+                    b"share/** /usr/share/art\nlinked/* /usr/share/linked\n",
+                )
+                .await
+                .unwrap();
+
+            repo.load_package_instlist(&package_id).await.unwrap()
+        });
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let placed = symlinks
+            .iter()
+            .map(|symlink| {
+                (
+                    symlink.source.strip_prefix(&package_dir).unwrap(),
+                    symlink.target.as_path(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            placed,
+            [
+                (
+                    Path::new("share/doc/README"),
+                    Path::new("/usr/share/art/doc/README")
+                ),
+                (Path::new("share/loop"), Path::new("/usr/share/art/loop")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_required_glob_without_matches_fails() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = glob_package(&file_system, "lib/*.so /usr/local/lib required\n");
+
+        let error = block_on(repo.load_package_instlist(&package_id)).unwrap_err();
+
+        assert!(matches!(error, UhpmError::ValidationError(_)));
+    }
+
+    #[test]
+    fn test_symlinked_sources_get_their_instlist_mode() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+        file_system.add_file(
+            "/uhpm/packages/tool@1.0.0/instlist",
+            b"bin/tool /usr/local/bin/tool 0755\n",
+        );
+        file_system.add_file("/uhpm/packages/tool@1.0.0/bin/tool", b"binary");
+
+        block_on(repo.create_symlinks_from_instlist(&package_id, &CancellationToken::new()))
+            .unwrap();
+
+        assert_eq!(
+            file_system.permissions(Path::new("/uhpm/packages/tool@1.0.0/bin/tool")),
+            Some(0o755)
+        );
+    }
+
+    #[test]
+    fn test_copy_files_direct_applies_instlist_and_store_modes() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+        file_system.add_file(
+            "/uhpm/packages/tool@1.0.0/instlist",
+            b"bin/tool /usr/local/bin/tool 0755\nshare/tool.conf /etc/tool.conf\n",
+        );
+        file_system.add_file("/uhpm/packages/tool@1.0.0/bin/tool", b"binary");
+        file_system.add_file("/uhpm/packages/tool@1.0.0/share/tool.conf", b"conf");
+        block_on(file_system.set_permissions(
+            Path::new("/uhpm/packages/tool@1.0.0/share/tool.conf"),
+            0o640,
+        ))
+        .unwrap();
+
+        block_on(repo.copy_files_direct(&package_id, &CancellationToken::new())).unwrap();
+
+        assert_eq!(
+            file_system.permissions(Path::new("/usr/local/bin/tool")),
+            Some(0o755)
+        );
+        assert_eq!(
+            file_system.permissions(Path::new("/etc/tool.conf")),
+            Some(0o640)
+        );
+        assert_eq!(
+            file_system.file(Path::new("/etc/tool.conf")),
+            Some(b"conf".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_extract_and_remove_update_the_package_index() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let packages_dir = Path::new("/uhpm/packages");
+        let id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+        let archive = crate::test_utils::package_archive(&[(
+            "meta.toml",
+            b"name = \"tool\"\nversion = \"1.0.0\"\ndescription = \"A tool\"\n",
+        )]);
+
+        block_on(async {
+            // Without an index nothing is written; it is built when needed.
+            repo.extract_package(&id, &archive).await.unwrap();
+            assert!(!file_system.exists(&PackageIndex::path(packages_dir)).await);
+
+            PackageIndex::new()
+                .save(&file_system, packages_dir)
+                .await
+                .unwrap();
+            repo.extract_package(&id, &archive).await.unwrap();
+            let index = PackageIndex::load(&file_system, packages_dir)
+                .await
+                .unwrap()
+                .unwrap();
+            let entry = index.get("tool", "1.0.0").unwrap();
+            assert_eq!(entry.path, PathBuf::from("tool@1.0.0"));
+            assert_eq!(entry.meta.description.as_deref(), Some("A tool"));
+
+            repo.remove_package_files(&id).await.unwrap();
+            let index = PackageIndex::load(&file_system, packages_dir)
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(index.packages.is_empty());
+        });
+    }
+
+    #[test]
+    fn test_extraction_reports_progress() {
+        use crate::test_utils::{RecordingEventPublisher, package_archive};
+
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let events = RecordingEventPublisher::new();
+        let package_ref = PackageReference::new("tool".to_string(), semver::Version::new(1, 0, 0));
+        let archive = package_archive(&[
+            ("bin/tool", b"binary"),
+            ("share/doc/README", b"readme"),
+            ("instlist", b"bin/tool /usr/local/bin/tool\n"),
+        ]);
+
+        block_on(repo.extract_package_with_events(
+            &package_ref,
+            &archive,
+            &events,
+            &CancellationToken::new(),
+        ))
+        .unwrap();
+
+        let events = events.events();
+        assert!(matches!(
+            events.first(),
+            Some(PackageEvent::ExtractionStarted { .. })
+        ));
+        assert!(matches!(
+            events.last(),
+            Some(PackageEvent::ExtractionCompleted { .. })
+        ));
+        let progress: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                PackageEvent::ExtractionProgress {
+                    files_done,
+                    files_total,
+                    ..
+                } => Some((*files_done, *files_total)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(progress, [(1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_archive_round_trip_keeps_modes_links_and_empty_dirs() {
+        use crate::fs::TokioFileSystem;
+
+        let root = std::env::temp_dir().join(format!("uhpm-archive-{}", uuid::Uuid::new_v4()));
+        let file_system = TokioFileSystem::new();
+        let source = PackageFilesRepository::new(file_system.clone(), root.join("source"));
+        let target = PackageFilesRepository::new(file_system.clone(), root.join("target"));
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+        let package_dir = source.get_package_path(&package_id);
+
+        block_on(async {
+            file_system
+                .create_dir_all(&package_dir.join("bin"))
+                .await
+                .unwrap();
+            file_system
+                .create_dir_all(&package_dir.join("share/empty"))
+                .await
+                .unwrap();
+            let tool = package_dir.join("bin/tool");
+            file_system.write_file(&tool, b"#!/bin/sh\n").await.unwrap();
+            file_system.set_permissions(&tool, 0o755).await.unwrap();
+            file_system
+                .create_symlink(&Symlink::file("tool", package_dir.join("bin/alias")))
+                .await
+                .unwrap();
+
+            let archive = source.create_package_archive(&package_id).await.unwrap();
+            target.extract_package(&package_id, &archive).await.unwrap();
+
+            let extracted = target.get_package_path(&package_id);
+            let tool = file_system
+                .metadata(&extracted.join("bin/tool"))
+                .await
+                .unwrap();
+            assert_eq!(tool.mode(), 0o755);
+            assert_eq!(
+                file_system
+                    .read_symlink(&extracted.join("bin/alias"))
+                    .await
+                    .unwrap(),
+                PathBuf::from("tool")
+            );
+            assert!(
+                file_system
+                    .metadata(&extracted.join("share/empty"))
+                    .await
+                    .unwrap()
+                    .is_directory()
+            );
+        });
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_archives_are_reproducible() {
+        use crate::fs::TokioFileSystem;
+
+        let root = std::env::temp_dir().join(format!("uhpm-archive-{}", uuid::Uuid::new_v4()));
+        let file_system = TokioFileSystem::new();
+        let repo = PackageFilesRepository::new(file_system.clone(), root.clone());
+
+        let archives = block_on(async {
+            let mut archives = Vec::new();
+            // The same files, created in a different order.
+            for (dir, names) in [("first", ["a", "b", "c"]), ("second", ["c", "a", "b"])] {
+                let dir = root.join(dir);
+                file_system.create_dir_all(&dir.join("bin")).await.unwrap();
+                for name in names {
+                    file_system
+                        .write_file(&dir.join("bin").join(name), name.as_bytes())
+                        .await
+                        .unwrap();
+                }
+                archives.push(repo.create_archive_from_dir(&dir).await.unwrap());
+            }
+            archives.push(
+                repo.create_archive_from_dir(&root.join("first"))
+                    .await
+                    .unwrap(),
+            );
+            archives
+        });
+
+        assert_eq!(archives[0], archives[1]);
+        assert_eq!(archives[0], archives[2]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_streamed_archive_extracts_like_the_buffered_one() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+        let package_dir = repo.get_package_path(&package_id);
+        // Spans several chunks, and a path too long for a plain tar header.
+        let large = (0..200_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let long_path = format!("share/{}/notes", "nested".repeat(20));
+        file_system.add_file(package_dir.join("bin/tool"), &large);
+        file_system.add_file(package_dir.join(&long_path), b"notes");
+        file_system.add_file(package_dir.join("empty"), b"");
+
+        block_on(async {
+            let buffered = repo.create_package_archive(&package_id).await.unwrap();
+            let mut streamed = Vec::new();
+            repo.create_package_archive_to(&package_id, &mut streamed)
+                .await
+                .unwrap();
+            assert_eq!(streamed, buffered);
+
+            let from_buffer = MemoryFileSystem::new();
+            let from_stream = MemoryFileSystem::new();
+            repository(&from_buffer)
+                .extract_package(&package_id, &buffered)
+                .await
+                .unwrap();
+            let size = repository(&from_stream)
+                .extract_package_from(&package_id, &streamed[..])
+                .await
+                .unwrap();
+
+            assert_eq!(size, 200_005);
+            for (path, contents) in [
+                ("bin/tool", &large[..]),
+                (long_path.as_str(), b"notes"),
+                ("empty", b""),
+            ] {
+                let path = package_dir.join(path);
+                assert_eq!(from_stream.file(&path).as_deref(), Some(contents));
+                assert_eq!(from_stream.file(&path), from_buffer.file(&path));
+            }
+        });
+    }
+
+    #[test]
+    fn test_truncated_stream_fails_extraction() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+        let contents = (0..100_000u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+            .collect::<Vec<_>>();
+        let archive = crate::test_utils::package_archive(&[("bin/tool", &contents)]);
+
+        let result =
+            block_on(repo.extract_package_from(&package_id, &archive[..archive.len() / 2]));
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_extract_rejects_escaping_entries() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+
+        let mut data = Vec::new();
+        {
+            let encoder = GzEncoder::new(&mut data, Compression::default());
+            let mut tar = Builder::new(encoder);
+            let mut header = tar_header(EntryType::Regular, 0o644, 4);
+            // `append_data` refuses `..`, so the path is written raw.
+            header.as_old_mut().name[..13].copy_from_slice(b"../etc/passwd");
+            header.set_cksum();
+            tar.append(&header, &b"evil"[..]).unwrap();
+            tar.into_inner().unwrap().finish().unwrap();
+        }
+
+        let result = block_on(repo.extract_package(&package_id, &data));
+
+        assert!(result.is_err());
+        assert!(
+            file_system
+                .file(Path::new("/uhpm/packages/etc/passwd"))
+                .is_none()
+        );
+    }
+
+    /// Archive of regular files (`None` targets) and symlinks, in order.
+    fn archive_with_links(entries: &[(&str, Option<&str>)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        {
+            let encoder = GzEncoder::new(&mut data, Compression::default());
+            let mut tar = Builder::new(encoder);
+            for (path, link) in entries {
+                match link {
+                    Some(link) => {
+                        let mut header = tar_header(EntryType::Symlink, 0o777, 0);
+                        tar.append_link(&mut header, path, link).unwrap();
+                    }
+                    None => {
+                        let mut header = tar_header(EntryType::Regular, 0o644, 4);
+                        tar.append_data(&mut header, path, &b"evil"[..]).unwrap();
+                    }
+                }
+            }
+            tar.into_inner().unwrap().finish().unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn test_extract_rejects_symlinks_leaving_the_package() {
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+
+        for entries in [
+            vec![("evil", Some("/home/u")), ("evil/.bashrc", None)],
+            vec![("share/up", Some("../../etc")), ("share/up/passwd", None)],
+        ] {
+            let archive = archive_with_links(&entries);
+            for streamed in [false, true] {
+                let file_system = MemoryFileSystem::new();
+                let repo = repository(&file_system);
+                let result = block_on(async {
+                    if streamed {
+                        repo.extract_package_from(&package_id, &archive[..]).await
+                    } else {
+                        repo.extract_package(&package_id, &archive).await
+                    }
+                });
+                let err = result.unwrap_err();
+                assert!(err.to_string().contains("outside the package"), "{}", err);
+                let package_dir = repo.get_package_path(&package_id);
+                assert_eq!(
+                    file_system.symlink_target(&package_dir.join(entries[0].0)),
+                    None
+                );
+                assert_eq!(file_system.file(&package_dir.join(entries[1].0)), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_refuses_to_write_through_symlinks() {
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+
+        for entries in [
+            // The link stays inside, but a regular entry would overwrite
+            // whatever it points to.
+            vec![
+                ("share/data", None),
+                ("link", Some("share/data")),
+                ("link", None),
+            ],
+            // `d/a` is the package root, so `d/a/b -> ..` leaves it.
+            vec![("d/a", Some("..")), ("d/a/b", Some(".."))],
+            vec![("d/a", Some("..")), ("d/a/file", None)],
+        ] {
+            let archive = archive_with_links(&entries);
+            for streamed in [false, true] {
+                let file_system = MemoryFileSystem::new();
+                let repo = repository(&file_system);
+                let result = block_on(async {
+                    if streamed {
+                        repo.extract_package_from(&package_id, &archive[..]).await
+                    } else {
+                        repo.extract_package(&package_id, &archive).await
+                    }
+                });
+                let err = result.unwrap_err();
+                assert!(err.to_string().contains("through the symlink"), "{}", err);
+            }
+        }
+    }
+
+    #[test]
+    fn test_extract_errors_name_the_package_and_path() {
+        let file_system = MemoryFileSystem::new();
+        let repo = repository(&file_system);
+        let package_id = PackageId::new("tool", &semver::Version::new(1, 0, 0));
+
+        let err = block_on(repo.extract_package(&package_id, b"not an archive")).unwrap_err();
+
+        let context = err.error_context().expect("context attached");
+        assert_eq!(context.operation, "extract");
+        assert_eq!(
+            context.package,
+            Some(PackageReference::new(
+                "tool".to_string(),
+                semver::Version::new(1, 0, 0)
+            ))
+        );
+        assert!(
+            err.to_string()
+                .starts_with("failed to extract tool@1.0.0 at /uhpm/packages/"),
+            "{}",
+            err
+        );
+        assert!(std::error::Error::source(&err).is_some());
+        assert!(!matches!(err.root(), UhpmError::WithContext { .. }));
     }
 }
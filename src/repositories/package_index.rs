@@ -0,0 +1,154 @@
+use crate::{UhpmError, ports::FileSystemOperations, repositories::package_files::PackageMeta};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Name of the index file inside the packages directory.
+pub const INDEX_FILE: &str = ".index.json";
+
+/// Generation of the index layout; indexes of another generation are rebuilt.
+const INDEX_GENERATION: u32 = 1;
+
+/// Index of the packages in a packages directory, kept in a single file so
+/// lookups don't have to walk the directory tree.
+///
+/// Entries hold the package's `meta.toml` and its directory relative to the
+/// packages directory.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PackageIndex {
+    pub generation: u32,
+    /// Package name to version string to entry.
+    pub packages: BTreeMap<String, BTreeMap<String, IndexedPackage>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexedPackage {
+    pub path: PathBuf,
+    pub meta: PackageMeta,
+    /// sha256 of the archive last built from the package directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub archive_checksum: Option<String>,
+}
+
+impl Default for PackageIndex {
+    fn default() -> Self {
+        Self {
+            generation: INDEX_GENERATION,
+            packages: BTreeMap::new(),
+        }
+    }
+}
+
+impl PackageIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn path(packages_dir: &Path) -> PathBuf {
+        packages_dir.join(INDEX_FILE)
+    }
+
+    /// Parses an index file, rejecting indexes of another generation.
+    pub fn parse(data: &[u8]) -> Result<Self, UhpmError> {
+        let index: Self = serde_json::from_slice(data).map_err(|e| {
+            UhpmError::DeserializationError(format!("Invalid package index: {}", e))
+        })?;
+        if index.generation != INDEX_GENERATION {
+            return Err(UhpmError::DeserializationError(format!(
+                "Package index generation {} is not {}",
+                index.generation, INDEX_GENERATION
+            )));
+        }
+        Ok(index)
+    }
+
+    /// Reads the index of `packages_dir`, `None` if there is none.
+    pub async fn load<FS: FileSystemOperations>(
+        file_system: &FS,
+        packages_dir: &Path,
+    ) -> Result<Option<Self>, UhpmError> {
+        let path = Self::path(packages_dir);
+        if !file_system.exists(&path).await {
+            return Ok(None);
+        }
+        Self::parse(&file_system.read_file(&path).await?).map(Some)
+    }
+
+    pub async fn save<FS: FileSystemOperations>(
+        &self,
+        file_system: &FS,
+        packages_dir: &Path,
+    ) -> Result<(), UhpmError> {
+        let data = serde_json::to_vec(self)
+            .map_err(|e| UhpmError::SerializationError(format!("Invalid package index: {}", e)))?;
+        file_system.create_dir_all(packages_dir).await?;
+        file_system
+            .write_file(&Self::path(packages_dir), &data)
+            .await
+    }
+
+    /// Adds or replaces the entry for the package `meta` describes, stored
+    /// at `path` relative to the packages directory.
+    pub fn insert(&mut self, path: PathBuf, meta: PackageMeta) {
+        self.packages.entry(meta.name.clone()).or_default().insert(
+            meta.version.clone(),
+            IndexedPackage {
+                path,
+                meta,
+                archive_checksum: None,
+            },
+        );
+    }
+
+    /// Records the sha256 of the archive built for `name` at `version`.
+    pub fn set_archive_checksum(&mut self, name: &str, version: &str, checksum: String) {
+        if let Some(entry) = self
+            .packages
+            .get_mut(name)
+            .and_then(|versions| versions.get_mut(version))
+        {
+            entry.archive_checksum = Some(checksum);
+        }
+    }
+
+    /// Drops the entry of the package stored at `path`.
+    pub fn remove_path(&mut self, path: &Path) {
+        for versions in self.packages.values_mut() {
+            versions.retain(|_, entry| entry.path != path);
+        }
+        self.packages.retain(|_, versions| !versions.is_empty());
+    }
+
+    pub fn get(&self, name: &str, version: &str) -> Option<&IndexedPackage> {
+        self.packages.get(name)?.get(version)
+    }
+
+    /// Versions of `name`, lowest first.
+    pub fn versions(&self, name: &str) -> Vec<String> {
+        let mut versions = self
+            .packages
+            .get(name)
+            .into_iter()
+            .flat_map(|versions| versions.keys())
+            .filter_map(|key| Version::parse(key).ok().map(|version| (version, key)))
+            .collect::<Vec<_>>();
+        versions.sort();
+        versions.into_iter().map(|(_, key)| key.clone()).collect()
+    }
+
+    /// Entries of the packages whose name contains `query`, by name and then
+    /// lowest version first.
+    pub fn search(&self, query: &str) -> Vec<&IndexedPackage> {
+        self.packages
+            .keys()
+            .filter(|name| name.contains(query))
+            .flat_map(|name| {
+                self.versions(name)
+                    .into_iter()
+                    .filter_map(|version| self.get(name, &version))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
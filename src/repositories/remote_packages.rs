@@ -1,15 +1,28 @@
 use std::collections::HashSet;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
 
 use crate::{
-    Dependency, DependencyKind, Package, PackageReference, Repository, RepositoryIndex, UhpmError,
+    CacheValidators, ConditionalFetch, Dependency, DependencyKind, DownloadOptions, IndexDocument,
+    IndexShard, IndexShardData, Package, PackageReference, PackageSource, PagedIndex, Repository,
+    RepositoryIndex, RepositoryPackageEntry, ResolutionFailure, ShardedIndex, UhpmError,
     VersionConstraint,
+    clock::SystemClock,
+    compute_checksum,
     factories::PackageFactory,
     paths::UhpmPaths,
-    ports::{CacheManager, FileSystemOperations, NetworkOperations, PackageRepository},
+    ports::{CacheManager, Clock, FileSystemOperations, NetworkOperations, PackageRepository},
+    repositories::package_files::{check_schema_version, declared_target, initial_schema_version},
 };
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use semver::{Version, VersionReq};
 use serde::Deserialize;
+use tracing::{Instrument, debug, debug_span, field, warn};
+use url::Url;
 
 pub struct RemotePackagesRepository<NET, CACHE, FS, P>
 where
@@ -24,10 +37,53 @@ where
     paths: P,
     repository: Repository,
     base_url: String,
+    /// The primary URL followed by the configured mirrors.
+    mirrors: Vec<Mirror>,
+    download_options: DownloadOptions,
+    index_ttl: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+/// How long a cached index is served before it is fetched again.
+const DEFAULT_INDEX_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Consecutive failures after which a mirror is only tried once every
+/// healthier one has failed too.
+const MAX_CONSECUTIVE_FAILURES: u32 = 3;
+
+struct Mirror {
+    base_url: String,
+    failures: AtomicU32,
+}
+
+impl Mirror {
+    fn new(base_url: String) -> Self {
+        Self {
+            base_url,
+            failures: AtomicU32::new(0),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url.trim_end_matches('/'), path)
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.failures.load(Ordering::Relaxed) < MAX_CONSECUTIVE_FAILURES
+    }
+}
+
+/// A response and the URL of the mirror that served it.
+struct Fetched<T> {
+    value: T,
+    url: String,
+    primary: bool,
 }
 
 #[derive(Deserialize)]
 struct RemotePackageMeta {
+    #[serde(default = "initial_schema_version")]
+    pub schema_version: u32,
     pub name: String,
     pub version: String,
     pub author: String,
@@ -37,8 +93,17 @@ struct RemotePackageMeta {
     pub conflicts: Option<Vec<String>>,
     pub checksum_algorithm: Option<String>,
     pub checksum_hash: Option<String>,
+    /// Size of the archive in bytes.
+    pub size: Option<u64>,
+    /// Size of the unpacked package in bytes.
+    pub installed_size: Option<u64>,
     pub target_os: Option<String>,
     pub target_arch: Option<String>,
+    /// Withdrawn by the publisher, see [`RepositoryPackageEntry::yanked`].
+    #[serde(default)]
+    pub yanked: bool,
+    /// Deprecation message, see [`RepositoryPackageEntry::deprecated`].
+    pub deprecated: Option<String>,
 }
 
 impl<NET, CACHE, FS, P> RemotePackagesRepository<NET, CACHE, FS, P>
@@ -48,6 +113,11 @@ where
     FS: FileSystemOperations,
     P: UhpmPaths,
 {
+    /// Creates a repository reading the published layout at `repository`.
+    ///
+    /// A [`Repository::Local`] directory is addressed through `file://`
+    /// URLs, to be served by [`FileNetwork`](crate::network::FileNetwork).
+    /// Its index is read again on every lookup instead of being cached.
     pub fn new(
         network: NET,
         cache: CACHE,
@@ -55,11 +125,23 @@ where
         paths: P,
         repository: Repository,
     ) -> Result<Self, UhpmError> {
-        let base_url = match &repository {
-            Repository::Http { index_url } => index_url.clone(),
+        let (base_url, index_ttl) = match &repository {
+            Repository::Http { index_url } => (index_url.clone(), DEFAULT_INDEX_TTL),
+            Repository::Local { path } => {
+                let url = std::path::absolute(path)
+                    .ok()
+                    .and_then(|path| Url::from_file_path(path).ok())
+                    .ok_or_else(|| {
+                        UhpmError::ValidationError(format!(
+                            "Invalid repository path: {}",
+                            path.display()
+                        ))
+                    })?;
+                (url.to_string(), Duration::ZERO)
+            }
             _ => {
                 return Err(UhpmError::ValidationError(
-                    "RemotePackagesRepository requires HTTP repository".into(),
+                    "RemotePackagesRepository requires an HTTP or local repository".into(),
                 ));
             }
         };
@@ -70,30 +152,131 @@ where
             file_system,
             paths,
             repository,
+            mirrors: vec![Mirror::new(base_url.clone())],
             base_url,
+            download_options: DownloadOptions::default(),
+            index_ttl,
+            clock: Arc::new(SystemClock),
         })
     }
 
-    fn get_package_meta_url(&self, package_ref: &PackageReference) -> String {
+    /// Sets how long the cached index is served before `get_index` fetches
+    /// it again.
+    pub fn with_index_ttl(mut self, index_ttl: Duration) -> Self {
+        self.index_ttl = index_ttl;
+        self
+    }
+
+    /// Replaces the system clock used to age the cached index.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets how package downloads are retried and resumed.
+    pub fn with_download_options(mut self, options: DownloadOptions) -> Self {
+        self.download_options = options;
+        self
+    }
+
+    /// Adds mirrors to fall back on, in order, when the primary URL fails.
+    ///
+    /// Package archives served by a mirror must match the checksum published
+    /// in their metadata.
+    pub fn with_mirrors<I, S>(mut self, mirrors: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.mirrors
+            .extend(mirrors.into_iter().map(|url| Mirror::new(url.into())));
+        self
+    }
+
+    /// Requests `path` from each mirror until one answers.
+    ///
+    /// Network failures (including 5xx responses) move on to the next
+    /// mirror, any other error is returned as is. Mirrors that keep failing
+    /// are tried last.
+    async fn fetch<T, F, Fut>(&self, path: &str, request: F) -> Result<Fetched<T>, UhpmError>
+    where
+        F: Fn(String) -> Fut,
+        Fut: Future<Output = Result<T, UhpmError>>,
+    {
+        let mut mirrors = self.mirrors.iter().enumerate().collect::<Vec<_>>();
+        mirrors.sort_by_key(|(_, mirror)| !mirror.is_healthy());
+
+        let mut last_error = None;
+        for (position, mirror) in mirrors {
+            let url = mirror.url(path);
+            match request(url.clone()).await {
+                Ok(value) => {
+                    mirror.failures.store(0, Ordering::Relaxed);
+                    return Ok(Fetched {
+                        value,
+                        url,
+                        primary: position == 0,
+                    });
+                }
+                Err(error @ (UhpmError::NetworkError(_) | UhpmError::DownloadError(_))) => {
+                    warn!(%url, %error, "mirror failed, trying the next one");
+                    mirror.failures.fetch_add(1, Ordering::Relaxed);
+                    last_error = Some(error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| UhpmError::RepositoryUnavailable(self.base_url.clone())))
+    }
+
+    fn get_package_meta_path(&self, package_ref: &PackageReference) -> String {
         format!(
-            "{}/packages/{}-{}-meta.toml",
-            self.base_url.trim_end_matches('/'),
-            package_ref.name,
-            package_ref.version
+            "packages/{}-{}-meta.toml",
+            package_ref.name, package_ref.version
         )
     }
 
+    fn get_package_download_path(&self, package_ref: &PackageReference) -> String {
+        format!("packages/{}-{}.uhp", package_ref.name, package_ref.version)
+    }
+
+    fn get_primary_url(&self, path: &str) -> String {
+        self.mirrors[0].url(path)
+    }
+
     fn get_package_download_url(&self, package_ref: &PackageReference) -> String {
-        format!(
-            "{}/packages/{}-{}.uhp",
-            self.base_url.trim_end_matches('/'),
-            package_ref.name,
-            package_ref.version
-        )
+        self.get_primary_url(&self.get_package_download_path(package_ref))
+    }
+
+    /// Where `package_ref`'s archive is published.
+    fn get_package_source(&self, package_ref: &PackageReference) -> PackageSource {
+        match &self.repository {
+            Repository::Local { path } => PackageSource::Local {
+                path: path.join(self.get_package_download_path(package_ref)),
+            },
+            _ => PackageSource::Http {
+                url: self.get_package_download_url(package_ref),
+            },
+        }
     }
 
     fn get_index_url(&self) -> String {
-        format!("{}/index.toml", self.base_url.trim_end_matches('/'))
+        self.get_primary_url("index.toml")
+    }
+
+    /// Cache key under which the index's `ETag`/`Last-Modified` are kept.
+    fn get_validators_key(&self) -> String {
+        format!("{}#validators", self.get_index_url())
+    }
+
+    /// Cache key under which the time the index was last fetched is kept.
+    fn get_fetched_at_key(&self) -> String {
+        format!("{}#fetched_at", self.get_index_url())
+    }
+
+    fn get_shard_path<'a>(&self, shard: &'a IndexShard) -> &'a str {
+        shard.path.trim_start_matches('/')
     }
 
     fn parse_dependency(&self, dep_str: &str) -> Result<Dependency, UhpmError> {
@@ -129,11 +312,18 @@ where
         &self,
         package_ref: &PackageReference,
     ) -> Result<RemotePackageMeta, UhpmError> {
-        let meta_url = self.get_package_meta_url(package_ref);
+        let meta_path = self.get_package_meta_path(package_ref);
+        let meta_url = self.get_primary_url(&meta_path);
         let meta_data = if let Some(cached) = self.cache.get_index(&meta_url).await? {
             cached
         } else {
-            let data = self.network.get(&meta_url).await?;
+            let data = self
+                .fetch(
+                    &meta_path,
+                    |url| async move { self.network.get(&url).await },
+                )
+                .await?
+                .value;
             self.cache.put_index(&meta_url, &data).await?;
             data
         };
@@ -143,9 +333,402 @@ where
 
         let remote_meta: RemotePackageMeta =
             toml::from_str(meta_str).map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+        check_schema_version(Path::new(&meta_path), remote_meta.schema_version)?;
 
         Ok(remote_meta)
     }
+
+    /// Whether the cached index was fetched less than the TTL ago.
+    async fn is_index_fresh(&self) -> Result<bool, UhpmError> {
+        let Some(data) = self.cache.get_index(&self.get_fetched_at_key()).await? else {
+            return Ok(false);
+        };
+        let fetched_at = std::str::from_utf8(&data)
+            .ok()
+            .and_then(|text| DateTime::parse_from_rfc3339(text).ok());
+        Ok(fetched_at.is_some_and(|fetched_at| {
+            (self.clock.now() - fetched_at.with_timezone(&Utc))
+                .to_std()
+                .is_ok_and(|age| age < self.index_ttl)
+        }))
+    }
+
+    async fn record_index_fetched(&self) -> Result<(), UhpmError> {
+        self.cache
+            .put_index(
+                &self.get_fetched_at_key(),
+                self.clock.now().to_rfc3339().as_bytes(),
+            )
+            .await
+    }
+
+    async fn load_validators(&self) -> Result<CacheValidators, UhpmError> {
+        let Some(data) = self.cache.get_index(&self.get_validators_key()).await? else {
+            return Ok(CacheValidators::default());
+        };
+        // Validators only save bandwidth; unreadable ones just force a full fetch.
+        Ok(std::str::from_utf8(&data)
+            .ok()
+            .and_then(|text| toml::from_str(text).ok())
+            .unwrap_or_default())
+    }
+
+    /// Loads `index.toml`, from the cache unless `refresh` is set.
+    ///
+    /// A refresh revalidates the cached copy with the server and only
+    /// downloads the index again when it has changed. A cached copy that
+    /// doesn't parse is invalidated and downloaded again.
+    async fn load_index_document(&self, refresh: bool) -> Result<IndexDocument, UhpmError> {
+        let (data, mut downloaded) = self.load_index_data(refresh).await?;
+        let document = match parse_index_document(&data) {
+            Err(error) if !downloaded => {
+                warn!(%error, "cached index is unreadable, downloading it again");
+                downloaded = true;
+                self.cache.invalidate_index(&self.get_index_url()).await?;
+                self.cache
+                    .invalidate_index(&self.get_validators_key())
+                    .await?;
+                let (data, _) = self.load_index_data(true).await?;
+                parse_index_document(&data)?
+            }
+            result => result?,
+        };
+        // Pages carry no checksum, so cached ones only hold as long as the
+        // index they were read with.
+        if downloaded && let IndexDocument::Paged(index) = &document {
+            for page in &index.pages {
+                self.cache
+                    .invalidate_index(&self.get_primary_url(page))
+                    .await?;
+            }
+        }
+        Ok(document)
+    }
+
+    /// Raw index document, and whether it was just downloaded rather than
+    /// taken from the cache.
+    ///
+    /// A cached copy older than the TTL is revalidated, but still served
+    /// when no mirror can be reached.
+    async fn load_index_data(&self, refresh: bool) -> Result<(Vec<u8>, bool), UhpmError> {
+        let cached = self.cache.get_index(&self.get_index_url()).await?;
+        let fresh = !refresh && self.is_index_fresh().await?;
+
+        Ok(match cached {
+            Some(data) if fresh => (data, false),
+            cached => {
+                let validators = match cached {
+                    Some(_) => self.load_validators().await?,
+                    None => CacheValidators::default(),
+                };
+                let fetched = match self
+                    .fetch("index.toml", |url| {
+                        let validators = &validators;
+                        async move { self.network.get_conditional(&url, validators).await }
+                    })
+                    .await
+                {
+                    Ok(fetched) => fetched.value,
+                    Err(
+                        error @ (UhpmError::NetworkError(_)
+                        | UhpmError::DownloadError(_)
+                        | UhpmError::RepositoryUnavailable(_)),
+                    ) if !refresh => match cached {
+                        Some(data) => {
+                            warn!(%error, "index unreachable, serving the stale cached copy");
+                            return Ok((data, false));
+                        }
+                        None => return Err(error),
+                    },
+                    Err(error) => return Err(error),
+                };
+                match (fetched, cached) {
+                    (ConditionalFetch::NotModified, Some(data)) => {
+                        debug!("index not modified, keeping the cached copy");
+                        self.record_index_fetched().await?;
+                        (data, false)
+                    }
+                    (ConditionalFetch::NotModified, None) => {
+                        return Err(UhpmError::network(format!(
+                            "{} reported not modified but no index is cached",
+                            self.get_index_url()
+                        )));
+                    }
+                    (ConditionalFetch::Modified { data, validators }, _) => {
+                        debug!(bytes = data.len(), "downloaded index");
+                        self.cache.put_index(&self.get_index_url(), &data).await?;
+                        let validators = toml::to_string(&validators)
+                            .map_err(|e| UhpmError::SerializationError(e.to_string()))?;
+                        self.cache
+                            .put_index(&self.get_validators_key(), validators.as_bytes())
+                            .await?;
+                        self.record_index_fetched().await?;
+                        (data, true)
+                    }
+                }
+            }
+        })
+    }
+
+    /// Loads a shard, reusing the cached copy while it still matches the
+    /// checksum advertised by the index.
+    async fn load_shard(
+        &self,
+        shard: &IndexShard,
+    ) -> Result<Vec<RepositoryPackageEntry>, UhpmError> {
+        let shard_path = self.get_shard_path(shard);
+        let shard_url = self.get_primary_url(shard_path);
+
+        let data = match self.cache.get_index(&shard_url).await? {
+            Some(cached) if compute_checksum("sha256", &cached)? == shard.checksum => cached,
+            _ => {
+                let data = self
+                    .fetch(
+                        shard_path,
+                        |url| async move { self.network.get(&url).await },
+                    )
+                    .await?
+                    .value;
+                if compute_checksum("sha256", &data)? != shard.checksum {
+                    return Err(UhpmError::RepositoryCorrupted(format!(
+                        "Checksum mismatch for index shard {}",
+                        shard_url
+                    )));
+                }
+                self.cache.put_index(&shard_url, &data).await?;
+                data
+            }
+        };
+
+        let shard_str = std::str::from_utf8(&data)
+            .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+        let shard_data: IndexShardData = toml::from_str(shard_str)
+            .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+
+        Ok(shard_data.packages)
+    }
+
+    /// Loads a page of a paged index, from the cache when it was read since
+    /// the index was last downloaded.
+    async fn load_page(&self, path: &str) -> Result<Vec<RepositoryPackageEntry>, UhpmError> {
+        let page_url = self.get_primary_url(path);
+        let data = match self.cache.get_index(&page_url).await? {
+            Some(cached) => cached,
+            None => {
+                let data = self
+                    .fetch(path, |url| async move { self.network.get(&url).await })
+                    .await?
+                    .value;
+                self.cache.put_index(&page_url, &data).await?;
+                data
+            }
+        };
+        let page_str = std::str::from_utf8(&data)
+            .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+        let page: IndexShardData =
+            toml::from_str(page_str).map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+        Ok(page.packages)
+    }
+
+    async fn assemble_index(&self, document: IndexDocument) -> Result<RepositoryIndex, UhpmError> {
+        let index = match document {
+            IndexDocument::Single(index) => index,
+            IndexDocument::Sharded(ShardedIndex { name, url, shards }) => {
+                let mut packages = Vec::new();
+                for shard in &shards {
+                    packages.extend(self.load_shard(shard).await?);
+                }
+                RepositoryIndex {
+                    name,
+                    url,
+                    packages,
+                }
+            }
+            IndexDocument::Paged(PagedIndex {
+                name, url, pages, ..
+            }) => {
+                let mut packages = Vec::new();
+                for page in &pages {
+                    packages.extend(self.load_page(page).await?);
+                }
+                RepositoryIndex {
+                    name,
+                    url,
+                    packages,
+                }
+            }
+        };
+        index.validate()?;
+        Ok(index)
+    }
+
+    /// Downloads a package archive, recording the serving URL and size on
+    /// the current span.
+    async fn download_from_mirrors(
+        &self,
+        package_ref: &PackageReference,
+        on_progress: Option<Arc<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<(Vec<u8>, Option<String>), UhpmError> {
+        if let Some(cached_data) = self.cache.get_package(package_ref).await? {
+            debug!("served from the cache");
+            return Ok((cached_data, None));
+        }
+
+        // Interrupted downloads leave their part file here to be resumed.
+        let temp_dir = self.paths.temp_dir();
+        self.file_system.create_dir_all(&temp_dir).await?;
+        let destination =
+            temp_dir.join(format!("{}-{}.uhp", package_ref.name, package_ref.version));
+
+        let download_path = self.get_package_download_path(package_ref);
+        let fetched = self
+            .fetch(&download_path, |url| {
+                let destination = &destination;
+                let on_progress = on_progress.clone().map(|on_progress| {
+                    Box::new(move |downloaded, total| on_progress(downloaded, total))
+                        as Box<dyn Fn(u64, u64) + Send + Sync>
+                });
+                async move {
+                    self.network
+                        .download_to_file(
+                            &self.file_system,
+                            &url,
+                            destination,
+                            None,
+                            &self.download_options,
+                            on_progress,
+                        )
+                        .await?;
+                    let data = self.file_system.read_file(destination).await?;
+                    self.file_system.remove(destination).await?;
+                    Ok(data)
+                }
+            })
+            .await?;
+
+        if !fetched.primary {
+            let meta = self.load_remote_meta(package_ref).await?;
+            let Some(expected) = self
+                .published_checksum(package_ref, meta.checksum_hash)
+                .await?
+            else {
+                return Err(UhpmError::ChecksumMismatch(format!(
+                    "{} was served by mirror {} but has no published checksum",
+                    package_ref, fetched.url
+                )));
+            };
+            let algorithm = meta.checksum_algorithm.as_deref().unwrap_or("sha256");
+            let actual = compute_checksum(algorithm, &fetched.value)?;
+            if actual != expected {
+                return Err(UhpmError::ChecksumMismatch(format!(
+                    "{} from mirror {}: expected {}, got {}",
+                    package_ref, fetched.url, expected, actual
+                )));
+            }
+        }
+
+        let span = tracing::Span::current();
+        span.record("url", fetched.url.as_str());
+        span.record("bytes", fetched.value.len());
+        self.cache.put_package(package_ref, &fetched.value).await?;
+
+        Ok((fetched.value, Some(fetched.url)))
+    }
+
+    /// Checksum from the package meta, or from the index entry when the meta
+    /// doesn't publish one.
+    async fn published_checksum(
+        &self,
+        package_ref: &PackageReference,
+        meta_checksum: Option<String>,
+    ) -> Result<Option<String>, UhpmError> {
+        if let Some(hash) = meta_checksum.filter(|hash| !hash.is_empty()) {
+            return Ok(Some(hash));
+        }
+        Ok(self.find_entry(&package_ref.name).await?.and_then(|entry| {
+            entry
+                .checksum_for(&package_ref.version.to_string())
+                .map(str::to_string)
+        }))
+    }
+
+    /// Archive size from the package meta, or from the index entry when the
+    /// meta doesn't publish one.
+    async fn published_size(
+        &self,
+        package_ref: &PackageReference,
+        meta_size: Option<u64>,
+    ) -> Result<Option<u64>, UhpmError> {
+        if meta_size.is_some() {
+            return Ok(meta_size);
+        }
+        Ok(self
+            .find_entry(&package_ref.name)
+            .await?
+            .and_then(|entry| entry.size_for(&package_ref.version.to_string())))
+    }
+
+    /// Looks up a single package, fetching at most one shard.
+    ///
+    /// Pages are read in order until one holds the package, or just the
+    /// mapped page when the index maps packages to pages.
+    async fn find_entry(
+        &self,
+        package_name: &str,
+    ) -> Result<Option<RepositoryPackageEntry>, UhpmError> {
+        let entries = match self.load_index_document(false).await? {
+            IndexDocument::Single(index) => index.packages,
+            IndexDocument::Sharded(index) => match index.shard_for(package_name) {
+                Some(shard) => self.load_shard(shard).await?,
+                None => Vec::new(),
+            },
+            IndexDocument::Paged(index) => {
+                for page in index.pages_for(package_name) {
+                    let entry = self
+                        .load_page(page)
+                        .await?
+                        .into_iter()
+                        .find(|entry| entry.name == package_name);
+                    if entry.is_some() {
+                        return Ok(entry);
+                    }
+                }
+                Vec::new()
+            }
+        };
+
+        Ok(entries.into_iter().find(|entry| entry.name == package_name))
+    }
+
+    /// Returns the entries whose name starts with `query`.
+    ///
+    /// Every index layout is searched by name prefix, so that a sharded index
+    /// only fetches the shards whose prefix is compatible with the query and
+    /// still finds what a single-file index would.
+    async fn find_entries(&self, query: &str) -> Result<Vec<RepositoryPackageEntry>, UhpmError> {
+        let entries = match self.load_index_document(false).await? {
+            IndexDocument::Single(index) => index.packages,
+            IndexDocument::Sharded(index) => {
+                let mut entries = Vec::new();
+                for shard in index.shards_for_query(query) {
+                    entries.extend(self.load_shard(shard).await?);
+                }
+                entries
+            }
+            IndexDocument::Paged(index) => {
+                let mut entries = Vec::new();
+                for page in &index.pages {
+                    entries.extend(self.load_page(page).await?);
+                }
+                entries
+            }
+        };
+
+        Ok(entries
+            .into_iter()
+            .filter(|entry| entry.name.starts_with(query))
+            .collect())
+    }
 }
 
 #[async_trait]
@@ -159,48 +742,74 @@ where
     async fn get_package(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
         let remote_meta = self.load_remote_meta(package_ref).await?;
 
+        let checksum_hash = self
+            .published_checksum(package_ref, remote_meta.checksum_hash)
+            .await?
+            .unwrap_or_default();
+
         let dependencies: Vec<Dependency> = remote_meta
             .dependencies
             .into_iter()
             .map(|dep_str| self.parse_dependency(&dep_str))
             .collect::<Result<Vec<_>, UhpmError>>()?;
 
-        let package = PackageFactory::create(
+        let download_size = self.published_size(package_ref, remote_meta.size).await?;
+        let target = declared_target(
+            remote_meta.target_os.as_deref(),
+            remote_meta.target_arch.as_deref(),
+        );
+
+        let mut package = PackageFactory::create(
             remote_meta.name,
             package_ref.version.clone(),
             remote_meta.author,
-            crate::PackageSource::Http {
-                url: self.get_package_download_url(package_ref),
-            },
-            crate::Target::current(),
+            self.get_package_source(package_ref),
+            target,
             Some(crate::Checksum {
                 algorithm: remote_meta
                     .checksum_algorithm
                     .unwrap_or_else(|| "sha256".to_string()),
-                hash: remote_meta.checksum_hash.unwrap_or_default(),
+                hash: checksum_hash,
             }),
             dependencies,
         )?;
+        package.set_description(remote_meta.description);
+        package.set_download_size(download_size);
+        package.set_installed_size(remote_meta.installed_size);
+
+        // Flags can be set in the meta, the index or both.
+        let entry = self.find_entry(&package_ref.name).await?;
+        let version = package_ref.version.to_string();
+        package.set_yanked(
+            remote_meta.yanked
+                || entry
+                    .as_ref()
+                    .is_some_and(|entry| entry.is_yanked(&package_ref.version)),
+        );
+        package.set_deprecation(remote_meta.deprecated.or_else(|| {
+            entry
+                .as_ref()
+                .and_then(|entry| entry.deprecation_for(&version))
+                .map(str::to_string)
+        }));
 
         Ok(package)
     }
 
+    /// Packages whose name starts with `query`, at their latest version.
     async fn search_packages(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
-        let index = self.get_index().await?;
         let mut results = Vec::new();
 
-        for entry in index.packages {
-            if entry.name.contains(query) {
-                if let Some(latest_version) = entry.versions.last() {
-                    let package_ref = PackageReference::new(
-                        entry.name.clone(),
-                        Version::parse(latest_version)
-                            .map_err(|e| UhpmError::ValidationError(e.to_string()))?,
-                    );
-                    match self.get_package(&package_ref).await {
-                        Ok(package) => results.push(package),
-                        Err(_) => continue,
-                    }
+        for entry in self.find_entries(query).await? {
+            if let Some(latest_version) = entry.versions.last() {
+                let package_ref = PackageReference::new(
+                    entry.name.clone(),
+                    Version::parse(latest_version)
+                        .map_err(|e| UhpmError::ValidationError(e.to_string()))?,
+                );
+                match self.get_package(&package_ref).await {
+                    Ok(package) => results.push(package),
+                    Err(_) => continue,
                 }
             }
         }
@@ -209,18 +818,25 @@ where
     }
 
     async fn get_package_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
-        let index = self.get_index().await?;
-        match index.get_versions(package_name) {
-            Some(versions) => Ok(versions.to_vec()),
+        match self.find_entry(package_name).await? {
+            Some(entry) => Ok(entry.versions),
             None => Err(UhpmError::PackageNotFound(package_name.to_string())),
         }
     }
 
+    async fn get_yanked_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        Ok(self
+            .find_entry(package_name)
+            .await?
+            .map(|entry| entry.yanked)
+            .unwrap_or_default())
+    }
+
+    /// Highest version the index lists that it doesn't mark as yanked.
     async fn get_latest_version(&self, package_name: &str) -> Result<String, UhpmError> {
-        let versions = self.get_package_versions(package_name).await?;
-        versions
-            .last()
-            .cloned()
+        self.find_entry(package_name)
+            .await?
+            .and_then(|entry| entry.latest_version().map(str::to_string))
             .ok_or_else(|| UhpmError::PackageNotFound(package_name.to_string()))
     }
 
@@ -229,21 +845,40 @@ where
         dependencies: &HashSet<Dependency>,
     ) -> Result<Vec<Package>, UhpmError> {
         let mut resolved_packages = Vec::new();
-        let index = self.get_index().await?;
 
         for dependency in dependencies {
-            if let Some(version_str) = index.latest_satisfying(dependency) {
-                let version = Version::parse(&version_str)
-                    .map_err(|e| UhpmError::ValidationError(e.to_string()))?;
+            let entry = self.find_entry(&dependency.name).await?;
+            let candidates = entry
+                .as_ref()
+                .map(|entry| entry.satisfying(dependency))
+                .unwrap_or_default();
 
+            let mut resolved = None;
+            let mut meta_yanked = Vec::new();
+            for version in candidates {
                 let package_ref = PackageReference::new(dependency.name.clone(), version);
-                let package = self.get_package(&package_ref).await?;
-                resolved_packages.push(package);
-            } else {
-                return Err(UhpmError::ResolutionError(format!(
-                    "Cannot resolve dependency: {} {}",
-                    dependency.name, dependency.constraint.requirement
-                )));
+                // Versions can also be yanked in their meta only.
+                if !dependency.pins_exactly(&package_ref.version)
+                    && self.load_remote_meta(&package_ref).await?.yanked
+                {
+                    meta_yanked.push(package_ref.version.to_string());
+                    continue;
+                }
+                resolved = Some(self.get_package(&package_ref).await?);
+                break;
+            }
+
+            match resolved {
+                Some(package) => resolved_packages.push(package),
+                None => {
+                    let (versions, mut yanked) = entry
+                        .map(|entry| (entry.versions, entry.yanked))
+                        .unwrap_or_default();
+                    yanked.extend(meta_yanked);
+                    return Err(UhpmError::ResolutionError(Box::new(
+                        ResolutionFailure::new(dependency, &versions, &yanked),
+                    )));
+                }
             }
         }
 
@@ -251,53 +886,952 @@ where
     }
 
     async fn download_package(&self, package_ref: &PackageReference) -> Result<Vec<u8>, UhpmError> {
-        if let Some(cached_data) = self.cache.get_package(package_ref).await? {
-            return Ok(cached_data);
-        }
+        Ok(self.download_package_with_source(package_ref).await?.0)
+    }
 
-        let download_url = self.get_package_download_url(package_ref);
-        let data = self.network.get(&download_url).await?;
+    /// The published size, or the `Content-Length` the primary URL answers a
+    /// HEAD request with.
+    async fn download_size(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<u64>, UhpmError> {
+        let meta = self.load_remote_meta(package_ref).await?;
+        if let Some(size) = self.published_size(package_ref, meta.size).await? {
+            return Ok(Some(size));
+        }
+        let head = self
+            .network
+            .head(&self.get_package_download_url(package_ref))
+            .await?;
+        Ok(head.content_length.filter(|_| head.is_success()))
+    }
 
-        self.cache.put_package(package_ref, &data).await?;
+    async fn download_package_with_source(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<(Vec<u8>, Option<String>), UhpmError> {
+        let span = download_span(package_ref);
+        self.download_from_mirrors(package_ref, None)
+            .instrument(span)
+            .await
+    }
 
-        Ok(data)
+    async fn download_package_with_progress(
+        &self,
+        package_ref: &PackageReference,
+        on_progress: Box<dyn Fn(u64, u64) + Send + Sync>,
+    ) -> Result<(Vec<u8>, Option<String>), UhpmError> {
+        let span = download_span(package_ref);
+        self.download_from_mirrors(package_ref, Some(Arc::from(on_progress)))
+            .instrument(span)
+            .await
     }
 
     async fn get_index(&self) -> Result<RepositoryIndex, UhpmError> {
-        if let Some(cached_data) = self.cache.get_index(&self.base_url).await? {
-            let index_str = std::str::from_utf8(&cached_data)
-                .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
-            let index: RepositoryIndex = toml::from_str(index_str)
-                .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
-            return Ok(index);
+        async {
+            let document = self.load_index_document(false).await?;
+            self.assemble_index(document).await
         }
-
-        let index_url = self.get_index_url();
-        let data = self.network.get(&index_url).await?;
-        let index_str = std::str::from_utf8(&data)
-            .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
-
-        let index: RepositoryIndex = toml::from_str(index_str)
-            .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
-
-        self.cache.put_index(&self.base_url, &data).await?;
-
-        Ok(index)
+        .instrument(debug_span!("get_index", url = %self.get_index_url()))
+        .await
     }
 
+    /// Re-downloads `index.toml`. For sharded indexes only the shards whose
+    /// checksum changed are downloaded again.
     async fn update_index(&self) -> Result<RepositoryIndex, UhpmError> {
-        self.cache.put_index(&self.base_url, &[]).await?;
-        self.get_index().await
+        async {
+            let document = self.load_index_document(true).await?;
+            self.assemble_index(document).await
+        }
+        .instrument(debug_span!("update_index", url = %self.get_index_url()))
+        .await
     }
 
     async fn is_available(&self) -> bool {
-        match self.network.head(&self.get_index_url()).await {
-            Ok(response) => response.status().is_success(),
-            Err(_) => false,
+        for mirror in &self.mirrors {
+            if let Ok(head) = self.network.head(&mirror.url("index.toml")).await
+                && head.is_success()
+            {
+                return true;
+            }
         }
+        false
     }
 
     fn get_repository(&self) -> &Repository {
         &self.repository
     }
 }
+
+fn parse_index_document(data: &[u8]) -> Result<IndexDocument, UhpmError> {
+    let index_str =
+        std::str::from_utf8(data).map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+    toml::from_str(index_str).map_err(|e| UhpmError::DeserializationError(e.to_string()))
+}
+
+fn download_span(package_ref: &PackageReference) -> tracing::Span {
+    debug_span!(
+        "download_package",
+        package = %package_ref,
+        url = field::Empty,
+        bytes = field::Empty
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::test_utils::{MemoryCache, MemoryFileSystem, MockNetwork, TestPaths, block_on};
+    use std::path::Path;
+    use std::sync::Mutex;
+
+    const BASE_URL: &str = "https://repo.example.com";
+
+    type TestRepository =
+        RemotePackagesRepository<MockNetwork, MemoryCache, MemoryFileSystem, TestPaths>;
+
+    fn repository(network: MockNetwork) -> TestRepository {
+        RemotePackagesRepository::new(
+            network,
+            MemoryCache::new(),
+            MemoryFileSystem::new(),
+            TestPaths::new("/uhpm"),
+            Repository::Http {
+                index_url: BASE_URL.to_string(),
+            },
+        )
+        .unwrap()
+    }
+
+    fn sharded_network() -> MockNetwork {
+        let network = MockNetwork::new();
+        let mut shards = Vec::new();
+
+        for prefix in ["aa", "ab", "ba", "bb", "ca", "cb", "da", "db", "ea", "eb"] {
+            let shard = IndexShardData {
+                packages: vec![RepositoryPackageEntry::new(
+                    format!("{}-tool", prefix),
+                    vec!["1.0.0".to_string()],
+                )],
+            };
+            let data = toml::to_string(&shard).unwrap();
+            let path = format!("shards/{}.toml", prefix);
+
+            network.respond(format!("{}/{}", BASE_URL, path), data.as_bytes());
+            shards.push(IndexShard {
+                prefix: prefix.to_string(),
+                path,
+                checksum: compute_checksum("sha256", data.as_bytes()).unwrap(),
+                last_modified: None,
+            });
+        }
+
+        let index = ShardedIndex {
+            name: "test".to_string(),
+            url: BASE_URL.to_string(),
+            shards,
+        };
+        network.respond(
+            format!("{}/index.toml", BASE_URL),
+            toml::to_string(&index).unwrap().as_bytes(),
+        );
+        network
+    }
+
+    fn shard_requests(repo: &TestRepository) -> Vec<String> {
+        repo.network
+            .requests()
+            .into_iter()
+            .filter(|url| url.contains("/shards/"))
+            .collect()
+    }
+
+    #[test]
+    fn test_sharded_search_fetches_only_matching_shards() {
+        let repo = repository(sharded_network());
+
+        block_on(repo.search_packages("b")).unwrap();
+
+        let mut fetched = shard_requests(&repo);
+        fetched.sort();
+        assert_eq!(
+            fetched,
+            vec![
+                format!("{}/shards/ba.toml", BASE_URL),
+                format!("{}/shards/bb.toml", BASE_URL),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_search_matches_name_prefixes_in_every_layout() {
+        let names = |entries: Vec<RepositoryPackageEntry>| {
+            entries
+                .into_iter()
+                .map(|entry| entry.name)
+                .collect::<Vec<_>>()
+        };
+
+        let sharded = repository(sharded_network());
+        assert_eq!(
+            names(block_on(sharded.find_entries("b")).unwrap()),
+            ["ba-tool", "bb-tool"]
+        );
+        assert!(block_on(sharded.find_entries("tool")).unwrap().is_empty());
+
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"zlib\"\nversions = [\"1.0.0\"]\n\
+             [[packages]]\nname = \"libz\"\nversions = [\"1.0.0\"]\n",
+        );
+        let single = repository(network);
+        assert_eq!(
+            names(block_on(single.find_entries("lib")).unwrap()),
+            ["libz"]
+        );
+    }
+
+    #[test]
+    fn test_sharded_versions_lookup_and_incremental_update() {
+        let repo = repository(sharded_network());
+
+        let versions = block_on(repo.get_package_versions("cb-tool")).unwrap();
+        assert_eq!(versions, vec!["1.0.0".to_string()]);
+        assert_eq!(shard_requests(&repo).len(), 1);
+
+        let index = block_on(repo.update_index()).unwrap();
+        assert_eq!(index.packages.len(), 10);
+        // The cached cb shard still matches its checksum and is not fetched again.
+        assert_eq!(shard_requests(&repo).len(), 10);
+    }
+
+    #[test]
+    fn test_update_index_revalidates_with_etag() {
+        let network = MockNetwork::new();
+        let index_url = format!("{}/index.toml", BASE_URL);
+        let index = |version: &str| RepositoryIndex {
+            name: "test".to_string(),
+            url: BASE_URL.to_string(),
+            packages: vec![RepositoryPackageEntry::new(
+                "ripgrep".to_string(),
+                vec![version.to_string()],
+            )],
+        };
+        let old_index = index("14.0.0");
+        network.respond_with_etag(
+            &index_url,
+            toml::to_string(&old_index).unwrap().as_bytes(),
+            "\"v1\"",
+        );
+        let repo = repository(network);
+
+        assert_eq!(block_on(repo.get_index()).unwrap(), old_index);
+        let head = block_on(repo.network.head(&index_url)).unwrap();
+        assert!(head.is_success());
+        assert_eq!(head.validators.etag.as_deref(), Some("\"v1\""));
+        assert!(block_on(repo.is_available()));
+
+        // Same ETag: the server answers 304 and the cached index is kept.
+        let new_index = index("14.1.0");
+        repo.network.respond_with_etag(
+            &index_url,
+            toml::to_string(&new_index).unwrap().as_bytes(),
+            "\"v1\"",
+        );
+        assert_eq!(block_on(repo.update_index()).unwrap(), old_index);
+        assert_eq!(repo.network.request_count(&index_url), 2);
+
+        repo.network.respond_with_etag(
+            &index_url,
+            toml::to_string(&new_index).unwrap().as_bytes(),
+            "\"v2\"",
+        );
+        assert_eq!(block_on(repo.update_index()).unwrap(), new_index);
+        assert_eq!(block_on(repo.get_index()).unwrap(), new_index);
+    }
+
+    #[test]
+    fn test_update_index_replaces_an_unreadable_cached_index() {
+        let network = MockNetwork::new();
+        let index_url = format!("{}/index.toml", BASE_URL);
+        let index = RepositoryIndex {
+            name: "test".to_string(),
+            url: BASE_URL.to_string(),
+            packages: vec![RepositoryPackageEntry::new(
+                "ripgrep".to_string(),
+                vec!["14.0.0".to_string()],
+            )],
+        };
+        network.respond_with_etag(
+            &index_url,
+            toml::to_string(&index).unwrap().as_bytes(),
+            "\"v1\"",
+        );
+        let repo = repository(network);
+        block_on(repo.get_index()).unwrap();
+        block_on(repo.cache.put_index(&index_url, b"")).unwrap();
+
+        // The server still answers 304, so the broken copy is dropped and
+        // the index downloaded without validators.
+        assert_eq!(block_on(repo.update_index()).unwrap(), index);
+        assert_eq!(repo.network.request_count(&index_url), 3);
+        assert_eq!(block_on(repo.get_index()).unwrap(), index);
+        assert_eq!(repo.network.request_count(&index_url), 3);
+    }
+
+    fn served_index(network: &MockNetwork, packages: &[&str]) -> RepositoryIndex {
+        let index = RepositoryIndex {
+            name: "test".to_string(),
+            url: BASE_URL.to_string(),
+            packages: packages
+                .iter()
+                .map(|name| {
+                    RepositoryPackageEntry::new(name.to_string(), vec!["1.0.0".to_string()])
+                })
+                .collect(),
+        };
+        network.respond(
+            format!("{}/index.toml", BASE_URL),
+            toml::to_string(&index).unwrap().as_bytes(),
+        );
+        index
+    }
+
+    #[test]
+    fn test_cached_index_is_served_within_the_ttl() {
+        let network = MockNetwork::new();
+        let index = served_index(&network, &["ripgrep"]);
+        let clock = MockClock::default();
+        let repo = repository(network)
+            .with_index_ttl(Duration::from_secs(600))
+            .with_clock(clock.clone());
+
+        assert_eq!(block_on(repo.get_index()).unwrap(), index);
+        served_index(&repo.network, &["ripgrep", "fd"]);
+        clock.advance(Duration::from_secs(599));
+
+        assert_eq!(block_on(repo.get_index()).unwrap(), index);
+        let index_url = format!("{}/index.toml", BASE_URL);
+        assert_eq!(repo.network.request_count(&index_url), 1);
+    }
+
+    #[test]
+    fn test_cached_index_is_fetched_again_after_the_ttl() {
+        let network = MockNetwork::new();
+        served_index(&network, &["ripgrep"]);
+        let clock = MockClock::default();
+        let repo = repository(network)
+            .with_index_ttl(Duration::from_secs(600))
+            .with_clock(clock.clone());
+
+        block_on(repo.get_index()).unwrap();
+        let published = served_index(&repo.network, &["ripgrep", "fd"]);
+        clock.advance(Duration::from_secs(600));
+
+        assert_eq!(block_on(repo.get_index()).unwrap(), published);
+        // The fetch restarted the TTL.
+        assert_eq!(block_on(repo.get_index()).unwrap(), published);
+        let index_url = format!("{}/index.toml", BASE_URL);
+        assert_eq!(repo.network.request_count(&index_url), 2);
+    }
+
+    #[test]
+    fn test_stale_index_is_served_when_the_network_is_down() {
+        let network = MockNetwork::new();
+        let index = served_index(&network, &["ripgrep"]);
+        let clock = MockClock::default();
+        let repo = repository(network)
+            .with_index_ttl(Duration::from_secs(600))
+            .with_clock(clock.clone());
+
+        block_on(repo.get_index()).unwrap();
+        let index_url = format!("{}/index.toml", BASE_URL);
+        repo.network.stop_responding(&index_url);
+        clock.advance(Duration::from_secs(3600));
+
+        assert_eq!(block_on(repo.get_index()).unwrap(), index);
+        assert_eq!(repo.network.request_count(&index_url), 2);
+        // An explicit update still reports the failure.
+        assert!(block_on(repo.update_index()).is_err());
+    }
+
+    fn serve_raw_index(network: &MockNetwork, packages: &str) {
+        let index = format!("name = \"test\"\nurl = \"{}\"\n{}", BASE_URL, packages);
+        network.respond(format!("{}/index.toml", BASE_URL), index.as_bytes());
+    }
+
+    #[test]
+    fn test_index_with_duplicate_packages_is_rejected() {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\nversions = [\"1.0.0\"]\n\
+             [[packages]]\nname = \"tool\"\nversions = [\"2.0.0\"]\n",
+        );
+        let repo = repository(network);
+
+        assert!(matches!(
+            block_on(repo.get_index()),
+            Err(UhpmError::RepositoryCorrupted(message)) if message.contains("`tool` is listed twice")
+        ));
+    }
+
+    #[test]
+    fn test_index_with_invalid_versions_is_rejected() {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\nversions = [\"1.0.0\", \"latest\"]\n",
+        );
+        let repo = repository(network);
+
+        assert!(matches!(
+            block_on(repo.get_index()),
+            Err(UhpmError::RepositoryCorrupted(message)) if message.contains("`latest`")
+        ));
+    }
+
+    #[test]
+    fn test_index_checksum_fills_in_for_the_meta() {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\nversions = [\"1.0.0\"]\n\
+             [packages.checksums]\n\"1.0.0\" = \"abc123\"\n",
+        );
+        serve_package(&network, BASE_URL, "tool", None);
+        let repo = repository(network);
+        let tool = PackageReference::new("tool".to_string(), Version::new(1, 0, 0));
+
+        let package = block_on(repo.get_package(&tool)).unwrap();
+        assert_eq!(package.checksum().as_ref().unwrap().hash, "abc123");
+    }
+
+    #[test]
+    fn test_reads_a_published_directory_through_file_urls() {
+        use crate::network::FileNetwork;
+        use std::path::PathBuf;
+
+        let file_system = MemoryFileSystem::new();
+        file_system.add_file(
+            "/mnt/repo/index.toml",
+            b"name = \"usb\"\nurl = \"file:///mnt/repo\"\n\
+              [[packages]]\nname = \"tool\"\nversions = [\"1.0.0\"]\n\
+              [packages.checksums]\n\"1.0.0\" = \"abc123\"\n",
+        );
+        file_system.add_file(
+            "/mnt/repo/packages/tool-1.0.0-meta.toml",
+            b"name = \"tool\"\nversion = \"1.0.0\"\nauthor = \"tester\"\ndependencies = []\n",
+        );
+        file_system.add_file("/mnt/repo/packages/tool-1.0.0.uhp", b"archive");
+        let repo = RemotePackagesRepository::new(
+            FileNetwork::new(file_system.clone()),
+            MemoryCache::new(),
+            file_system,
+            TestPaths::new("/uhpm"),
+            Repository::Local {
+                path: PathBuf::from("/mnt/repo"),
+            },
+        )
+        .unwrap();
+        let tool = PackageReference::new("tool".to_string(), Version::new(1, 0, 0));
+
+        assert_eq!(block_on(repo.get_latest_version("tool")).unwrap(), "1.0.0");
+        let package = block_on(repo.get_package(&tool)).unwrap();
+        assert_eq!(package.checksum().as_ref().unwrap().hash, "abc123");
+        assert_eq!(
+            package.source(),
+            &PackageSource::Local {
+                path: PathBuf::from("/mnt/repo/packages/tool-1.0.0.uhp")
+            }
+        );
+        assert_eq!(block_on(repo.download_package(&tool)).unwrap(), b"archive");
+    }
+
+    #[test]
+    fn test_meta_checksum_is_used_without_index_checksums() {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\nversions = [\"1.0.0\"]\n",
+        );
+        serve_package(&network, BASE_URL, "tool", Some("def456"));
+        let repo = repository(network);
+        let tool = PackageReference::new("tool".to_string(), Version::new(1, 0, 0));
+
+        let package = block_on(repo.get_package(&tool)).unwrap();
+        assert_eq!(package.checksum().as_ref().unwrap().hash, "def456");
+        let index = block_on(repo.get_index()).unwrap();
+        assert!(index.packages[0].checksums.is_empty());
+    }
+
+    #[test]
+    fn test_meta_description_is_passed_on() {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\nversions = [\"1.0.0\"]\n",
+        );
+        serve_meta(&network, "tool", "1.0.0", "description = \"A tool\"\n");
+        let repo = repository(network);
+
+        let package = block_on(repo.get_package(&PackageReference::new(
+            "tool".to_string(),
+            Version::new(1, 0, 0),
+        )))
+        .unwrap();
+        assert_eq!(package.description(), Some("A tool"));
+    }
+
+    #[test]
+    fn test_meta_target_is_passed_on() {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\nversions = [\"1.0.0\"]\n",
+        );
+        serve_meta(&network, "tool", "1.0.0", "target_os = \"haiku\"\n");
+        let repo = repository(network);
+
+        let package = block_on(repo.get_package(&PackageReference::new(
+            "tool".to_string(),
+            Version::new(1, 0, 0),
+        )))
+        .unwrap();
+        assert_eq!(
+            package.target().os,
+            crate::OperatingSystem::Custom("haiku".to_string())
+        );
+        assert_eq!(package.target().arch, crate::Target::current().arch);
+        assert!(!package.target().matches(&crate::Target::current()));
+    }
+
+    #[test]
+    fn test_yanked_and_deprecated_flags_are_passed_on() {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\n\
+             versions = [{ version = \"1.0.0\", yanked = true, deprecated = \"use 2.x\" }, \"2.0.0\"]\n",
+        );
+        serve_meta(&network, "tool", "1.0.0", "");
+        serve_meta(&network, "tool", "2.0.0", "deprecated = \"unmaintained\"\n");
+        let repo = repository(network);
+
+        let old = block_on(repo.get_package(&PackageReference::new(
+            "tool".to_string(),
+            Version::new(1, 0, 0),
+        )))
+        .unwrap();
+        assert!(old.is_yanked());
+        assert_eq!(old.deprecation(), Some("use 2.x"));
+
+        let new = block_on(repo.get_package(&PackageReference::new(
+            "tool".to_string(),
+            Version::new(2, 0, 0),
+        )))
+        .unwrap();
+        assert!(!new.is_yanked());
+        assert_eq!(new.deprecation(), Some("unmaintained"));
+        assert_eq!(
+            block_on(repo.get_yanked_versions("tool")).unwrap(),
+            ["1.0.0"]
+        );
+    }
+
+    #[test]
+    fn test_sizes_come_from_the_meta_the_index_or_a_head_request() {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\nversions = [\"1.0.0\"]\n\
+             [[packages]]\nname = \"lib\"\nversions = [\"1.0.0\"]\n\
+             [packages.sizes]\n\"1.0.0\" = 2048\n\
+             [[packages]]\nname = \"doc\"\nversions = [\"1.0.0\"]\n",
+        );
+        serve_meta(
+            &network,
+            "tool",
+            "1.0.0",
+            "size = 1024\ninstalled_size = 4096\n",
+        );
+        serve_meta(&network, "lib", "1.0.0", "");
+        serve_meta(&network, "doc", "1.0.0", "");
+        network.respond(format!("{}/packages/doc-1.0.0.uhp", BASE_URL), b"archive");
+        let repo = repository(network);
+        let reference = |name: &str| PackageReference::new(name.to_string(), Version::new(1, 0, 0));
+
+        let tool = block_on(repo.get_package(&reference("tool"))).unwrap();
+        assert_eq!(tool.download_size(), Some(1024));
+        assert_eq!(tool.installed_size(), Some(4096));
+        let lib = block_on(repo.get_package(&reference("lib"))).unwrap();
+        assert_eq!(lib.download_size(), Some(2048));
+        assert_eq!(lib.installed_size(), None);
+
+        let doc = block_on(repo.get_package(&reference("doc"))).unwrap();
+        assert_eq!(doc.download_size(), None);
+        assert_eq!(
+            block_on(repo.download_size(&reference("doc"))).unwrap(),
+            Some(7)
+        );
+        assert_eq!(
+            block_on(repo.download_size(&reference("lib"))).unwrap(),
+            Some(2048)
+        );
+    }
+
+    fn serve_meta(network: &MockNetwork, name: &str, version: &str, extra: &str) {
+        let meta = format!(
+            "name = \"{name}\"\nversion = \"{version}\"\nauthor = \"tester\"\ndependencies = []\n{extra}"
+        );
+        network.respond(
+            format!("{}/packages/{}-{}-meta.toml", BASE_URL, name, version),
+            meta.as_bytes(),
+        );
+    }
+
+    fn requirement(name: &str, requirement: &str) -> HashSet<Dependency> {
+        HashSet::from([Dependency {
+            name: name.to_string(),
+            constraint: crate::VersionConstraint {
+                requirement: semver::VersionReq::parse(requirement).unwrap(),
+            },
+            kind: crate::DependencyKind::Required,
+            provides: None,
+            features: vec![],
+        }])
+    }
+
+    fn yanked_network() -> MockNetwork {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\nversions = [\"1.0.0\", \"1.1.0\"]\n\
+             yanked = [\"1.1.0\"]\n\
+             [[packages]]\nname = \"lib\"\nversions = [\"1.0.0\", \"1.1.0\"]\n",
+        );
+        for name in ["tool", "lib"] {
+            serve_meta(&network, name, "1.0.0", "");
+        }
+        serve_meta(&network, "tool", "1.1.0", "");
+        serve_meta(&network, "lib", "1.1.0", "yanked = true\n");
+        network
+    }
+
+    #[test]
+    fn test_yanked_latest_is_skipped() {
+        let repo = repository(yanked_network());
+
+        assert_eq!(block_on(repo.get_latest_version("tool")).unwrap(), "1.0.0");
+        for name in ["tool", "lib"] {
+            let resolved = block_on(repo.resolve_dependencies(&requirement(name, "^1"))).unwrap();
+            assert_eq!(resolved.len(), 1);
+            assert_eq!(*resolved[0].version(), Version::new(1, 0, 0), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_exact_pin_resolves_yanked_version() {
+        let repo = repository(yanked_network());
+
+        for name in ["tool", "lib"] {
+            let resolved =
+                block_on(repo.resolve_dependencies(&requirement(name, "=1.1.0"))).unwrap();
+            assert_eq!(*resolved[0].version(), Version::new(1, 1, 0), "{}", name);
+        }
+        assert!(matches!(
+            block_on(repo.resolve_dependencies(&requirement("tool", ">=1.1.0"))),
+            Err(UhpmError::ResolutionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_meta_with_newer_schema_is_rejected() {
+        let network = MockNetwork::new();
+        serve_raw_index(
+            &network,
+            "[[packages]]\nname = \"tool\"\nversions = [\"1.0.0\", \"2.0.0\"]\n",
+        );
+        serve_meta(&network, "tool", "1.0.0", "schema_version = 1\n");
+        serve_meta(&network, "tool", "2.0.0", "schema_version = 2\n");
+        let repo = repository(network);
+
+        let current = PackageReference::new("tool".to_string(), Version::new(1, 0, 0));
+        assert!(block_on(repo.get_package(&current)).is_ok());
+        let newer = PackageReference::new("tool".to_string(), Version::new(2, 0, 0));
+        assert!(matches!(
+            block_on(repo.get_package(&newer)),
+            Err(UhpmError::InvalidPackage { .. })
+        ));
+    }
+
+    fn paged_network() -> MockNetwork {
+        let network = MockNetwork::new();
+        for (page, names) in [
+            ("pages/1.toml", ["bat", "exa"]),
+            ("pages/2.toml", ["fd", "rg"]),
+        ] {
+            let data = IndexShardData {
+                packages: names
+                    .iter()
+                    .map(|name| {
+                        RepositoryPackageEntry::new(name.to_string(), vec!["1.0.0".to_string()])
+                    })
+                    .collect(),
+            };
+            network.respond(
+                format!("{}/{}", BASE_URL, page),
+                toml::to_string(&data).unwrap().as_bytes(),
+            );
+        }
+
+        let index = PagedIndex {
+            name: "test".to_string(),
+            url: BASE_URL.to_string(),
+            pages: vec!["pages/1.toml".to_string(), "pages/2.toml".to_string()],
+            package_pages: [
+                ("bat", "pages/1.toml"),
+                ("exa", "pages/1.toml"),
+                ("fd", "pages/2.toml"),
+                ("rg", "pages/2.toml"),
+            ]
+            .into_iter()
+            .map(|(name, page)| (name.to_string(), page.to_string()))
+            .collect(),
+        };
+        network.respond(
+            format!("{}/index.toml", BASE_URL),
+            toml::to_string(&index).unwrap().as_bytes(),
+        );
+        network
+    }
+
+    #[test]
+    fn test_paged_index_lookup_fetches_one_page() {
+        let repo = repository(paged_network());
+
+        assert_eq!(
+            block_on(repo.get_package_versions("fd")).unwrap(),
+            ["1.0.0"]
+        );
+        assert_eq!(
+            repo.network.requests(),
+            [
+                format!("{}/index.toml", BASE_URL),
+                format!("{}/pages/2.toml", BASE_URL),
+            ]
+        );
+        assert!(matches!(
+            block_on(repo.get_package_versions("missing")),
+            Err(UhpmError::PackageNotFound(_))
+        ));
+        assert_eq!(repo.network.requests().len(), 2);
+    }
+
+    #[test]
+    fn test_paged_index_pages_are_cached_until_the_index_changes() {
+        let network = paged_network();
+        serve_meta(&network, "fd", "1.0.0", "");
+        let repo = repository(network);
+        let fd = PackageReference::new("fd".to_string(), Version::new(1, 0, 0));
+        let page_requests = |repo: &TestRepository| {
+            repo.network
+                .requests()
+                .iter()
+                .filter(|url| url.ends_with("/pages/2.toml"))
+                .count()
+        };
+
+        block_on(repo.get_package(&fd)).unwrap();
+        block_on(repo.get_package(&fd)).unwrap();
+        assert_eq!(page_requests(&repo), 1);
+
+        block_on(repo.update_index()).unwrap();
+        block_on(repo.get_package(&fd)).unwrap();
+        assert_eq!(page_requests(&repo), 2);
+    }
+
+    #[test]
+    fn test_paged_index_is_assembled_from_every_page() {
+        let repo = repository(paged_network());
+
+        let index = block_on(repo.get_index()).unwrap();
+        assert_eq!(
+            index
+                .packages
+                .iter()
+                .map(|entry| entry.name.as_str())
+                .collect::<Vec<_>>(),
+            ["bat", "exa", "fd", "rg"]
+        );
+    }
+
+    #[test]
+    fn test_index_is_cached_under_the_index_url() {
+        let network = MockNetwork::new();
+        let index_url = format!("{}/index.toml", BASE_URL);
+        let index = RepositoryIndex {
+            name: "test".to_string(),
+            url: BASE_URL.to_string(),
+            packages: vec![],
+        };
+        network.respond(
+            index_url.clone(),
+            toml::to_string(&index).unwrap().as_bytes(),
+        );
+        let repo = repository(network);
+        block_on(repo.get_index()).unwrap();
+
+        assert_eq!(repo.get_index_url(), index_url);
+        assert!(
+            block_on(repo.cache.get_index(&index_url))
+                .unwrap()
+                .is_some()
+        );
+        assert!(block_on(repo.cache.get_index(BASE_URL)).unwrap().is_none());
+    }
+
+    const MIRROR_URL: &str = "https://mirror.example.com";
+
+    fn serve_package(network: &MockNetwork, base_url: &str, name: &str, checksum: Option<&str>) {
+        let checksum = checksum
+            .map(|hash| format!("checksum_hash = \"{}\"\n", hash))
+            .unwrap_or_default();
+        let meta = format!(
+            "name = \"{name}\"\nversion = \"1.0.0\"\nauthor = \"tester\"\ndependencies = []\n{checksum}"
+        );
+        network.respond(
+            format!("{}/packages/{}-1.0.0-meta.toml", base_url, name),
+            meta.as_bytes(),
+        );
+        network.respond(
+            format!("{}/packages/{}-1.0.0.uhp", base_url, name),
+            name.as_bytes(),
+        );
+    }
+
+    fn download_with_progress(
+        repo: &TestRepository,
+        package_ref: &PackageReference,
+    ) -> (Vec<u8>, Vec<(u64, u64)>) {
+        let progress = Arc::new(Mutex::new(Vec::new()));
+        let recorded = progress.clone();
+        let (data, _) = block_on(repo.download_package_with_progress(
+            package_ref,
+            Box::new(move |downloaded, total| recorded.lock().unwrap().push((downloaded, total))),
+        ))
+        .unwrap();
+        let progress = progress.lock().unwrap().clone();
+        (data, progress)
+    }
+
+    #[test]
+    fn test_interrupted_download_resumes() {
+        let network = MockNetwork::new();
+        let url = format!("{}/packages/tool-1.0.0.uhp", BASE_URL);
+        network.respond_with_ranges(url.clone(), b"0123456789");
+        network.interrupt_once(url, 4);
+        let repo = repository(network);
+        let tool = PackageReference::new("tool".to_string(), Version::new(1, 0, 0));
+
+        let (data, progress) = download_with_progress(&repo, &tool);
+        assert_eq!(data, b"0123456789");
+        assert_eq!(repo.network.range_offsets(), [0, 4]);
+        assert_eq!(progress, [(4, 10), (10, 10)]);
+        assert!(!block_on(
+            repo.file_system
+                .exists(Path::new("/uhpm/tmp/tool-1.0.0.uhp.part"))
+        ));
+    }
+
+    #[test]
+    fn test_interrupted_download_restarts_without_ranges() {
+        let network = MockNetwork::new();
+        let url = format!("{}/packages/tool-1.0.0.uhp", BASE_URL);
+        network.respond(url.clone(), b"0123456789");
+        network.interrupt_once(url, 4);
+        let repo = repository(network);
+        let tool = PackageReference::new("tool".to_string(), Version::new(1, 0, 0));
+
+        let (data, progress) = download_with_progress(&repo, &tool);
+        assert_eq!(data, b"0123456789");
+        assert_eq!(repo.network.range_offsets(), [0, 0]);
+        assert_eq!(progress, [(4, 10), (10, 10)]);
+    }
+
+    #[test]
+    fn test_mirror_failover() {
+        let network = MockNetwork::new();
+        let index = RepositoryIndex {
+            name: "test".to_string(),
+            url: BASE_URL.to_string(),
+            packages: vec![RepositoryPackageEntry::new(
+                "tool".to_string(),
+                vec!["1.0.0".to_string()],
+            )],
+        };
+        network.respond(
+            format!("{}/index.toml", MIRROR_URL),
+            toml::to_string(&index).unwrap().as_bytes(),
+        );
+        let checksum = compute_checksum("sha256", b"tool").unwrap();
+        serve_package(&network, MIRROR_URL, "tool", Some(&checksum));
+        serve_package(&network, MIRROR_URL, "unsigned", None);
+        let repo = repository(network).with_mirrors([MIRROR_URL]);
+        let tool = PackageReference::new("tool".to_string(), Version::new(1, 0, 0));
+
+        assert!(block_on(repo.is_available()));
+        assert_eq!(block_on(repo.get_index()).unwrap(), index);
+        let (data, source) = block_on(repo.download_package_with_source(&tool)).unwrap();
+        assert_eq!(data, b"tool");
+        assert_eq!(
+            source.as_deref(),
+            Some("https://mirror.example.com/packages/tool-1.0.0.uhp")
+        );
+
+        // Without a published checksum, mirror bytes are not trusted.
+        let unsigned = PackageReference::new("unsigned".to_string(), Version::new(1, 0, 0));
+        assert!(matches!(
+            block_on(repo.download_package(&unsigned)),
+            Err(UhpmError::ChecksumMismatch(_))
+        ));
+
+        // The primary has failed repeatedly and is now tried last.
+        let primary_requests = |repo: &TestRepository| {
+            repo.network
+                .requests()
+                .iter()
+                .filter(|url| url.starts_with(BASE_URL))
+                .count()
+        };
+        let before = primary_requests(&repo);
+        block_on(repo.update_index()).unwrap();
+        assert_eq!(primary_requests(&repo), before);
+    }
+
+    #[test]
+    fn test_single_file_index_still_supported() {
+        let network = MockNetwork::new();
+        let index = RepositoryIndex {
+            name: "test".to_string(),
+            url: BASE_URL.to_string(),
+            packages: vec![RepositoryPackageEntry::new(
+                "ripgrep".to_string(),
+                vec!["14.0.0".to_string(), "14.1.0".to_string()],
+            )],
+        };
+        network.respond(
+            format!("{}/index.toml", BASE_URL),
+            toml::to_string(&index).unwrap().as_bytes(),
+        );
+        let repo = repository(network);
+
+        assert_eq!(block_on(repo.get_index()).unwrap(), index);
+        assert_eq!(
+            block_on(repo.get_latest_version("ripgrep")).unwrap(),
+            "14.1.0"
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use crate::{
+    Installation, InstallationId, OperationRecord, Package, PackageId, UhpmError,
+    ports::StateStore, repositories::DatabaseRepository,
+};
+use async_trait::async_trait;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// [`StateStore`] backed by a [`DatabaseRepository`].
+///
+/// SQLite calls block, so each one runs on tokio's blocking thread pool with
+/// the connection behind a mutex.
+#[derive(Clone)]
+pub struct SqliteStateStore {
+    database: Arc<Mutex<DatabaseRepository>>,
+}
+
+impl SqliteStateStore {
+    pub fn new(database: DatabaseRepository) -> Self {
+        Self {
+            database: Arc::new(Mutex::new(database)),
+        }
+    }
+
+    async fn run<T, F>(&self, operation: F) -> Result<T, UhpmError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut DatabaseRepository) -> Result<T, UhpmError> + Send + 'static,
+    {
+        let database = Arc::clone(&self.database);
+        tokio::task::spawn_blocking(move || {
+            let mut database = database
+                .lock()
+                .map_err(|_| UhpmError::DatabaseError("Database lock poisoned".to_string()))?;
+            operation(&mut database)
+        })
+        .await
+        .map_err(|e| UhpmError::DatabaseError(e.to_string()))?
+    }
+}
+
+impl From<DatabaseRepository> for SqliteStateStore {
+    fn from(database: DatabaseRepository) -> Self {
+        Self::new(database)
+    }
+}
+
+#[async_trait]
+impl StateStore for SqliteStateStore {
+    async fn save_package(&self, package: &Package) -> Result<(), UhpmError> {
+        let package = package.clone();
+        self.run(move |db| db.save_package(&package)).await
+    }
+
+    async fn get_package(&self, package_id: &PackageId) -> Result<Option<Package>, UhpmError> {
+        let package_id = package_id.clone();
+        self.run(move |db| db.get_package(&package_id)).await
+    }
+
+    async fn list_installed_packages(&self) -> Result<Vec<Package>, UhpmError> {
+        self.run(|db| db.list_installed_packages()).await
+    }
+
+    async fn delete_package(&self, package_id: &PackageId) -> Result<(), UhpmError> {
+        let package_id = package_id.clone();
+        self.run(move |db| db.delete_package(&package_id)).await
+    }
+
+    async fn get_dependents(&self, package_name: &str) -> Result<Vec<PackageId>, UhpmError> {
+        let package_name = package_name.to_string();
+        self.run(move |db| db.get_dependents(&package_name)).await
+    }
+
+    async fn save_installation(&self, installation: &Installation) -> Result<(), UhpmError> {
+        let installation = installation.clone();
+        self.run(move |db| db.save_installation(&installation))
+            .await
+    }
+
+    async fn save_installs(
+        &self,
+        packages: &[Package],
+        installations: &[Installation],
+    ) -> Result<(), UhpmError> {
+        let packages = packages.to_vec();
+        let installations = installations.to_vec();
+        self.run(move |db| db.save_installs(&packages, &installations))
+            .await
+    }
+
+    async fn get_active_installation(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Option<Installation>, UhpmError> {
+        let package_id = package_id.clone();
+        self.run(move |db| db.get_active_installation(&package_id))
+            .await
+    }
+
+    async fn list_installations(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Vec<Installation>, UhpmError> {
+        let package_id = package_id.clone();
+        self.run(move |db| db.list_installations(&package_id)).await
+    }
+
+    async fn delete_installation(&self, installation_id: &InstallationId) -> Result<(), UhpmError> {
+        let installation_id = installation_id.clone();
+        self.run(move |db| db.delete_installation(&installation_id))
+            .await
+    }
+
+    async fn find_owner(&self, path: &Path) -> Result<Option<PackageId>, UhpmError> {
+        let path = path.to_path_buf();
+        self.run(move |db| db.find_owner(&path)).await
+    }
+
+    async fn record_operation(&self, record: &OperationRecord) -> Result<i64, UhpmError> {
+        let record = record.clone();
+        self.run(move |db| db.record_operation(&record)).await
+    }
+
+    async fn get_history(
+        &self,
+        limit: Option<usize>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<OperationRecord>, UhpmError> {
+        let package_name = package_name.map(str::to_string);
+        self.run(move |db| db.get_history(limit, package_name.as_deref()))
+            .await
+    }
+
+    async fn compact(&self) -> Result<(), UhpmError> {
+        self.run(|db| db.compact()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        OperationKind, PackageSource, Target, factories::InstallationFactory,
+        factories::PackageFactory, test_utils::block_on,
+    };
+    use semver::Version;
+
+    #[test]
+    fn test_state_round_trips_through_blocking_pool() {
+        let store = SqliteStateStore::new(DatabaseRepository::in_memory().unwrap());
+        let mut package = PackageFactory::create(
+            "tool".to_string(),
+            Version::new(1, 0, 0),
+            "John Doe".to_string(),
+            PackageSource::Local {
+                path: "/tmp".into(),
+            },
+            Target::current(),
+            None,
+            vec![],
+        )
+        .unwrap();
+        package.set_installed(true);
+        let mut installation = InstallationFactory::create(package.id().clone());
+        installation.activate();
+
+        block_on(async {
+            store.save_package(&package).await.unwrap();
+            store.save_installation(&installation).await.unwrap();
+            store
+                .record_operation(&OperationRecord::new(OperationKind::Install, "tool"))
+                .await
+                .unwrap();
+
+            assert_eq!(
+                store.list_installed_packages().await.unwrap(),
+                [package.clone()]
+            );
+            let active = store
+                .get_active_installation(package.id())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(active.id(), installation.id());
+            assert_eq!(
+                store.get_history(None, Some("tool")).await.unwrap().len(),
+                1
+            );
+
+            store.delete_installation(installation.id()).await.unwrap();
+            assert!(
+                store
+                    .list_installations(package.id())
+                    .await
+                    .unwrap()
+                    .is_empty()
+            );
+        });
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{DependencyConflict, Package};
+use std::collections::HashMap;
+
+/// Returns every dependency in `packages` that the selected version of its
+/// target does not satisfy.
+///
+/// Dependencies on packages outside the given set are ignored. Conflicts are
+/// ordered by dependent, then by dependency name.
+pub fn find_conflicts(packages: &[Package]) -> Vec<DependencyConflict> {
+    let selected: HashMap<&str, &Package> = packages
+        .iter()
+        .map(|package| (package.name(), package))
+        .collect();
+
+    let mut conflicts = Vec::new();
+    for package in packages {
+        let mut dependencies = package.dependencies().iter().collect::<Vec<_>>();
+        dependencies.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for dependency in dependencies {
+            let Some(target) = selected.get(dependency.name.as_str()) else {
+                continue;
+            };
+            if dependency.matches_version(target.version()) {
+                continue;
+            }
+            conflicts.push(DependencyConflict {
+                package: dependency.name.clone(),
+                required: dependency.constraint.requirement.to_string(),
+                installed: target.version().to_string(),
+                message: format!(
+                    "{} requires {} {}, but {} {} was selected",
+                    package.id().as_str(),
+                    dependency.name,
+                    dependency.constraint.requirement,
+                    dependency.name,
+                    target.version()
+                ),
+            });
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Dependency, DependencyKind, PackageSource, Target, VersionConstraint,
+        factories::PackageFactory,
+    };
+    use semver::{Version, VersionReq};
+
+    fn package(name: &str, version: &str, dependencies: &[(&str, &str)]) -> Package {
+        PackageFactory::create(
+            name.to_string(),
+            Version::parse(version).unwrap(),
+            "John Doe".to_string(),
+            PackageSource::Local {
+                path: "/tmp".into(),
+            },
+            Target::current(),
+            None,
+            dependencies
+                .iter()
+                .map(|(name, requirement)| Dependency {
+                    name: name.to_string(),
+                    constraint: VersionConstraint {
+                        requirement: VersionReq::parse(requirement).unwrap(),
+                    },
+                    kind: DependencyKind::Required,
+                    provides: None,
+                    features: vec![],
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unsatisfied_dependency_is_a_conflict() {
+        let conflicts = find_conflicts(&[
+            package("lib", "2.0.0", &[]),
+            package("old", "1.0.0", &[("lib", "^1"), ("external", "^3")]),
+            package("new", "1.0.0", &[("lib", "^2")]),
+        ]);
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].package, "lib");
+        assert_eq!(conflicts[0].required, "^1");
+        assert_eq!(conflicts[0].installed, "2.0.0");
+        assert!(conflicts[0].message.contains("old@1.0.0 requires lib ^1"));
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{Package, UhpmError};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Visiting,
+    Done,
+}
+
+/// Orders `packages` so every package comes after the packages it depends on.
+///
+/// Dependencies on packages outside the given set are ignored. The relative
+/// input order is kept wherever the graph allows it. A dependency cycle
+/// makes ordering impossible and is reported as `DependencyConflict`.
+pub fn install_order(packages: Vec<Package>) -> Result<Vec<Package>, UhpmError> {
+    let index: HashMap<&str, usize> = packages
+        .iter()
+        .enumerate()
+        .map(|(i, package)| (package.name(), i))
+        .collect();
+    let mut marks = vec![None; packages.len()];
+    let mut order = Vec::with_capacity(packages.len());
+    let mut path = Vec::new();
+
+    for i in 0..packages.len() {
+        visit(i, &packages, &index, &mut marks, &mut path, &mut order)?;
+    }
+
+    let mut slots = packages.into_iter().map(Some).collect::<Vec<_>>();
+    Ok(order.into_iter().filter_map(|i| slots[i].take()).collect())
+}
+
+fn visit(
+    i: usize,
+    packages: &[Package],
+    index: &HashMap<&str, usize>,
+    marks: &mut [Option<Mark>],
+    path: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+) -> Result<(), UhpmError> {
+    match marks[i] {
+        Some(Mark::Done) => return Ok(()),
+        Some(Mark::Visiting) => {
+            let start = path.iter().position(|&j| j == i).unwrap_or(0);
+            let cycle = path[start..]
+                .iter()
+                .chain(std::iter::once(&i))
+                .map(|&j| packages[j].name())
+                .collect::<Vec<_>>();
+            return Err(UhpmError::DependencyConflict(format!(
+                "Dependency cycle: {}",
+                cycle.join(" -> ")
+            )));
+        }
+        None => {}
+    }
+
+    marks[i] = Some(Mark::Visiting);
+    path.push(i);
+
+    let mut dependencies = packages[i]
+        .dependencies()
+        .iter()
+        .filter_map(|dependency| index.get(dependency.name.as_str()).copied())
+        .collect::<Vec<_>>();
+    dependencies.sort_unstable();
+    for j in dependencies {
+        visit(j, packages, index, marks, path, order)?;
+    }
+
+    path.pop();
+    marks[i] = Some(Mark::Done);
+    order.push(i);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        Dependency, DependencyKind, PackageSource, Target, VersionConstraint,
+        factories::PackageFactory,
+    };
+    use semver::{Version, VersionReq};
+
+    fn package(name: &str, dependencies: &[&str]) -> Package {
+        PackageFactory::create(
+            name.to_string(),
+            Version::new(1, 0, 0),
+            "John Doe".to_string(),
+            PackageSource::Local {
+                path: "/tmp".into(),
+            },
+            Target::current(),
+            None,
+            dependencies
+                .iter()
+                .map(|name| Dependency {
+                    name: name.to_string(),
+                    constraint: VersionConstraint {
+                        requirement: VersionReq::STAR,
+                    },
+                    kind: DependencyKind::Required,
+                    provides: None,
+                    features: vec![],
+                })
+                .collect(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_dependencies_come_first() {
+        let ordered = install_order(vec![
+            package("app", &["lib"]),
+            package("lib", &["base", "external"]),
+            package("base", &[]),
+        ])
+        .unwrap();
+
+        let names = ordered.iter().map(Package::name).collect::<Vec<_>>();
+        assert_eq!(names, ["base", "lib", "app"]);
+    }
+
+    #[test]
+    fn test_cycle_is_a_conflict() {
+        let err = install_order(vec![
+            package("a", &["b"]),
+            package("b", &["c"]),
+            package("c", &["a"]),
+        ])
+        .unwrap_err();
+
+        match err {
+            UhpmError::DependencyConflict(message) => {
+                assert!(message.contains("a -> b -> c -> a"), "{}", message)
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+}
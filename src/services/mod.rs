@@ -1,2 +1,10 @@
+pub mod conflicts;
+pub mod install_order;
+pub mod package_builder;
 pub mod package_service;
-pub use package_service::PackageService;
+pub mod resolution_context;
+pub use conflicts::find_conflicts;
+pub use install_order::install_order;
+pub use package_builder::{PackageBuilder, archive_checksum, inspect_package};
+pub use package_service::{PackageService, SearchResults};
+pub use resolution_context::ResolutionContext;
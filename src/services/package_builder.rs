@@ -0,0 +1,399 @@
+use crate::{
+    Checksum, FsError, PackageSource, Symlink, SymlinkType, UhpmError, compute_checksum,
+    factories::PackageFactory,
+    ports::FileSystemOperations,
+    repositories::package_files::{PREFIX_PLACEHOLDER, PackageMeta, parse_instlist},
+};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use semver::Version;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use tar::{Archive, Builder, EntryType, Header};
+
+const META_FILE: &str = "meta.toml";
+const INSTLIST_FILE: &str = "instlist";
+const MANIFEST_FILE: &str = "files.manifest";
+
+/// Builds `.uhp` archives from a package author's source directory.
+///
+/// Archives are reproducible: entries are written in sorted order with
+/// fixed timestamps and ownership, so the same input always yields the same
+/// bytes and therefore the same checksum.
+pub struct PackageBuilder<FS>
+where
+    FS: FileSystemOperations,
+{
+    file_system: FS,
+}
+
+impl<FS> PackageBuilder<FS>
+where
+    FS: FileSystemOperations,
+{
+    pub fn new(file_system: FS) -> Self {
+        Self { file_system }
+    }
+
+    /// Packs `source_dir` together with `meta` into a gzipped tar archive.
+    ///
+    /// `install_map` pairs paths relative to `source_dir` with their absolute
+    /// install targets and becomes the archive's instlist. A `files.manifest`
+    /// with the sha256 of every payload file is generated as well.
+    pub async fn build_package(
+        &self,
+        source_dir: &Path,
+        meta: PackageMeta,
+        install_map: &[(PathBuf, PathBuf)],
+    ) -> Result<Vec<u8>, UhpmError> {
+        validate_meta(&meta, source_dir)?;
+
+        let mut files = Vec::new();
+        self.collect_files(source_dir, source_dir, &mut files)
+            .await?;
+        files.sort();
+
+        if let Some(reserved) = files.iter().find(|path| {
+            [META_FILE, INSTLIST_FILE, MANIFEST_FILE]
+                .map(Path::new)
+                .contains(&path.as_path())
+        }) {
+            return Err(UhpmError::ValidationError(format!(
+                "'{}' is reserved and can't be part of the package payload",
+                reserved.display()
+            )));
+        }
+
+        let instlist = render_instlist(install_map, &files)?;
+
+        let mut payload = Vec::with_capacity(files.len());
+        let mut manifest = String::new();
+        for path in files {
+            let absolute = source_dir.join(&path);
+            let data = self.file_system.read_file(&absolute).await?;
+            let mode = self.file_system.metadata(&absolute).await?.mode();
+            manifest.push_str(&format!(
+                "{}  {}\n",
+                compute_checksum("sha256", &data)?,
+                path.display()
+            ));
+            payload.push((path, mode, data));
+        }
+
+        let meta =
+            toml::to_string(&meta).map_err(|e| UhpmError::SerializationError(e.to_string()))?;
+
+        let mut archive = Vec::new();
+        {
+            let encoder = GzEncoder::new(&mut archive, Compression::default());
+            let mut tar = Builder::new(encoder);
+
+            append(&mut tar, Path::new(META_FILE), 0o644, meta.as_bytes())?;
+            append(
+                &mut tar,
+                Path::new(INSTLIST_FILE),
+                0o644,
+                instlist.as_bytes(),
+            )?;
+            append(
+                &mut tar,
+                Path::new(MANIFEST_FILE),
+                0o644,
+                manifest.as_bytes(),
+            )?;
+            for (path, mode, data) in &payload {
+                append(&mut tar, path, *mode, data)?;
+            }
+
+            tar.into_inner()
+                .and_then(|encoder| encoder.finish())
+                .map_err(|e| UhpmError::SerializationError(e.to_string()))?;
+        }
+
+        Ok(archive)
+    }
+
+    async fn collect_files(
+        &self,
+        root: &Path,
+        dir: &Path,
+        files: &mut Vec<PathBuf>,
+    ) -> Result<(), UhpmError> {
+        for entry in self.file_system.read_dir(dir).await? {
+            if self.file_system.metadata(&entry).await?.is_directory() {
+                Box::pin(self.collect_files(root, &entry, files)).await?;
+            } else {
+                let relative = entry
+                    .strip_prefix(root)
+                    .map_err(|e| FsError::InvalidPath(e.to_string()))?;
+                files.push(relative.to_path_buf());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checksum to publish for an archive produced by `build_package`.
+pub fn archive_checksum(archive: &[u8]) -> Result<Checksum, UhpmError> {
+    Ok(Checksum {
+        algorithm: "sha256".to_string(),
+        hash: compute_checksum("sha256", archive)?,
+    })
+}
+
+/// Reads an archive's meta, instlist and payload file list in memory.
+///
+/// Instlist sources stay relative to the package root; links whose source
+/// is a directory in the archive are reported as directory links.
+pub fn inspect_package(
+    archive: &[u8],
+) -> Result<(PackageMeta, Vec<Symlink>, Vec<PathBuf>), UhpmError> {
+    let mut meta = None;
+    let mut instlist = None;
+    let mut files = Vec::new();
+
+    let mut tar = Archive::new(GzDecoder::new(archive));
+    let entries = tar
+        .entries()
+        .map_err(|e| FsError::ExtractionError(e.to_string()))?;
+    for entry in entries {
+        let mut entry = entry.map_err(|e| FsError::ExtractionError(e.to_string()))?;
+        if entry.header().entry_type() != EntryType::Regular {
+            continue;
+        }
+        let path = entry
+            .path()
+            .map_err(|e| FsError::ExtractionError(e.to_string()))?
+            .into_owned();
+
+        let target = match path.to_str() {
+            Some(META_FILE) => &mut meta,
+            Some(INSTLIST_FILE) => &mut instlist,
+            Some(MANIFEST_FILE) => continue,
+            _ => {
+                files.push(path);
+                continue;
+            }
+        };
+        let mut content = String::new();
+        entry
+            .read_to_string(&mut content)
+            .map_err(|e| UhpmError::DeserializationError(e.to_string()))?;
+        *target = Some(content);
+    }
+
+    let meta = meta.ok_or_else(|| {
+        UhpmError::ValidationError(format!("Package archive has no {}", META_FILE))
+    })?;
+    let meta = PackageMeta::from_toml(Path::new(META_FILE), &meta)?;
+
+    let symlinks = parse_instlist(
+        Path::new(INSTLIST_FILE),
+        instlist.as_deref().unwrap_or_default(),
+    )?
+    .into_iter()
+    .map(|entry| {
+        let link_type = if files
+            .iter()
+            .any(|file| file != &entry.source && file.starts_with(&entry.source))
+        {
+            SymlinkType::Directory
+        } else {
+            SymlinkType::File
+        };
+        let mut symlink = Symlink::new(entry.source, entry.target, link_type);
+        if let Some(permissions) = entry.permissions {
+            symlink.metadata = symlink.metadata.with_permissions(permissions);
+        }
+        symlink
+    })
+    .collect();
+
+    Ok((meta, symlinks, files))
+}
+
+/// Applies the `PackageFactory` rules to the meta of a package being built.
+fn validate_meta(meta: &PackageMeta, source_dir: &Path) -> Result<(), UhpmError> {
+    let version = Version::parse(&meta.version).map_err(|e| {
+        UhpmError::ValidationError(format!("Invalid version '{}': {}", meta.version, e))
+    })?;
+
+    PackageFactory::create(
+        meta.name.clone(),
+        version,
+        meta.author.clone(),
+        PackageSource::Local {
+            path: source_dir.to_path_buf(),
+        },
+        meta.target(),
+        meta.checksum(),
+        vec![],
+    )
+    .map(|_| ())
+}
+
+fn render_instlist(
+    install_map: &[(PathBuf, PathBuf)],
+    files: &[PathBuf],
+) -> Result<String, UhpmError> {
+    let mut instlist = String::new();
+
+    for (source, target) in install_map {
+        let escapes_root = source.is_absolute()
+            || source
+                .components()
+                .any(|component| matches!(component, Component::ParentDir));
+        if escapes_root {
+            return Err(UhpmError::ValidationError(format!(
+                "Install source '{}' must be relative to the package root",
+                source.display()
+            )));
+        }
+        if !target.is_absolute() && !target.starts_with(PREFIX_PLACEHOLDER) {
+            return Err(UhpmError::ValidationError(format!(
+                "Install target '{}' must be absolute or start with ${{PREFIX}}",
+                target.display()
+            )));
+        }
+        if !files.iter().any(|file| file.starts_with(source)) {
+            return Err(UhpmError::ValidationError(format!(
+                "Install source '{}' is not part of the package",
+                source.display()
+            )));
+        }
+
+        let (source, target) = (source.to_string_lossy(), target.to_string_lossy());
+        if source.contains(char::is_whitespace) || target.contains(char::is_whitespace) {
+            return Err(UhpmError::ValidationError(format!(
+                "Instlist paths can't contain whitespace: '{}' -> '{}'",
+                source, target
+            )));
+        }
+        instlist.push_str(&format!("{} {}\n", source, target));
+    }
+
+    Ok(instlist)
+}
+
+fn append<W: std::io::Write>(
+    tar: &mut Builder<W>,
+    path: &Path,
+    mode: u32,
+    data: &[u8],
+) -> Result<(), UhpmError> {
+    let mut header = Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(mode);
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    tar.append_data(&mut header, path, data)
+        .map_err(|e| UhpmError::SerializationError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::package_files::META_SCHEMA_VERSION;
+    use crate::test_utils::{MemoryFileSystem, block_on};
+
+    fn meta(name: &str) -> PackageMeta {
+        PackageMeta {
+            schema_version: META_SCHEMA_VERSION,
+            name: name.to_string(),
+            version: "1.0.0".to_string(),
+            author: "John Doe".to_string(),
+            description: None,
+            dependencies: vec![],
+            provides: None,
+            conflicts: None,
+            checksum_algorithm: None,
+            checksum_hash: None,
+            target_os: None,
+            target_arch: None,
+            extras: Default::default(),
+        }
+    }
+
+    fn source() -> MemoryFileSystem {
+        let file_system = MemoryFileSystem::new();
+        file_system.add_file("/src/bin/tool", b"#!/bin/sh\n");
+        file_system.add_file("/src/share/tool/data.txt", b"data");
+        file_system
+    }
+
+    fn install_map() -> Vec<(PathBuf, PathBuf)> {
+        vec![
+            ("bin/tool".into(), "/usr/local/bin/tool".into()),
+            ("share/tool".into(), "/usr/local/share/tool".into()),
+        ]
+    }
+
+    #[test]
+    fn test_build_is_reproducible_and_inspectable() {
+        let builder = PackageBuilder::new(source());
+
+        let (first, second) = block_on(async {
+            let first = builder
+                .build_package(Path::new("/src"), meta("tool"), &install_map())
+                .await
+                .unwrap();
+            let second = builder
+                .build_package(Path::new("/src"), meta("tool"), &install_map())
+                .await
+                .unwrap();
+            (first, second)
+        });
+        assert_eq!(first, second);
+        assert_eq!(
+            archive_checksum(&first).unwrap(),
+            archive_checksum(&second).unwrap()
+        );
+
+        let (meta, symlinks, files) = inspect_package(&first).unwrap();
+        assert_eq!(meta.name, "tool");
+        assert_eq!(
+            files,
+            [
+                PathBuf::from("bin/tool"),
+                PathBuf::from("share/tool/data.txt")
+            ]
+        );
+        assert_eq!(symlinks.len(), 2);
+        assert!(symlinks[0].is_file_link());
+        assert!(symlinks[1].is_directory_link());
+        assert_eq!(symlinks[1].target, PathBuf::from("/usr/local/share/tool"));
+    }
+
+    #[test]
+    fn test_build_rejects_invalid_input() {
+        let builder = PackageBuilder::new(source());
+
+        block_on(async {
+            let invalid_name = builder
+                .build_package(Path::new("/src"), meta("9tool"), &install_map())
+                .await;
+            assert!(matches!(invalid_name, Err(UhpmError::ValidationError(_))));
+
+            let missing_source = builder
+                .build_package(
+                    Path::new("/src"),
+                    meta("tool"),
+                    &[("bin/missing".into(), "/usr/local/bin/missing".into())],
+                )
+                .await;
+            assert!(matches!(missing_source, Err(UhpmError::ValidationError(_))));
+
+            let escaping_source = builder
+                .build_package(
+                    Path::new("/src"),
+                    meta("tool"),
+                    &[("../etc/passwd".into(), "/usr/local/passwd".into())],
+                )
+                .await;
+            assert!(matches!(
+                escaping_source,
+                Err(UhpmError::ValidationError(_))
+            ));
+        });
+    }
+}
@@ -1,5 +1,10 @@
 use crate::{Package, PackageReference, UhpmError, ports::PackageRepository};
+use semver::{Version, VersionReq};
 
+/// Looks packages up in a local and a remote repository.
+///
+/// Queries go on with whichever repository answers, so an unreachable
+/// remote doesn't make local packages unavailable.
 pub struct PackageService<LM, RM>
 where
     LM: PackageRepository,
@@ -7,6 +12,15 @@ where
 {
     local_repo: LM,
     remote_repo: RM,
+    prefer_remote_newer: bool,
+}
+
+/// Packages found by [`PackageService::search_all_packages`].
+#[derive(Debug, Clone, Default)]
+pub struct SearchResults {
+    pub packages: Vec<Package>,
+    /// Repositories that couldn't be searched, and why.
+    pub warnings: Vec<String>,
 }
 
 impl<LM, RM> PackageService<LM, RM>
@@ -18,37 +32,335 @@ where
         Self {
             local_repo,
             remote_repo,
+            prefer_remote_newer: false,
         }
     }
 
+    /// Makes `find_best_package` pick a newer remote version over a
+    /// matching local one.
+    pub fn prefer_remote_newer(mut self) -> Self {
+        self.prefer_remote_newer = true;
+        self
+    }
+
+    /// Returns the highest version of `package_name` matching `requirement`.
+    ///
+    /// A matching local version is used as is, unless the service prefers
+    /// newer remote versions and the remote has a strictly higher one. On
+    /// equal versions the local copy wins.
     pub async fn find_best_package(
         &self,
-        package_ref: &PackageReference,
+        package_name: &str,
+        requirement: &VersionReq,
     ) -> Result<Package, UhpmError> {
-        match self.local_repo.get_package(package_ref).await {
-            Ok(package) => Ok(package),
-            Err(UhpmError::PackageNotFound(_)) => self.remote_repo.get_package(package_ref).await,
-            Err(e) => Err(e),
+        let local = best_version(&self.local_repo, package_name, requirement).await;
+        if let Ok(Some(version)) = &local
+            && !self.prefer_remote_newer
+        {
+            return self
+                .local_repo
+                .get_package(&PackageReference::new(
+                    package_name.to_string(),
+                    version.clone(),
+                ))
+                .await;
+        }
+
+        let remote = best_version(&self.remote_repo, package_name, requirement).await;
+        let (repository, version): (&dyn PackageRepository, Version) = match (local, remote) {
+            (Ok(Some(local)), Ok(Some(remote))) if remote > local => (&self.remote_repo, remote),
+            (Ok(Some(local)), _) => (&self.local_repo, local),
+            (_, Ok(Some(remote))) => (&self.remote_repo, remote),
+            (Err(error), _) | (_, Err(error)) => return Err(error),
+            (Ok(None), Ok(None)) => {
+                return Err(UhpmError::PackageNotFound(format!(
+                    "{} {}",
+                    package_name, requirement
+                )));
+            }
+        };
+        repository
+            .get_package(&PackageReference::new(package_name.to_string(), version))
+            .await
+    }
+
+    /// Updates the index of both repositories, returning a warning for each
+    /// one that failed.
+    ///
+    /// Only fails when neither repository could be updated.
+    pub async fn sync_repositories(&self) -> Result<Vec<String>, UhpmError> {
+        let outcomes = [
+            ("local", self.local_repo.update_index().await.map(drop)),
+            ("remote", self.remote_repo.update_index().await.map(drop)),
+        ];
+        collect_warnings(outcomes).map(|(_, warnings)| warnings)
+    }
+
+    /// Versions of `package_name` offered by either repository, highest
+    /// first and each listed once.
+    ///
+    /// A repository that doesn't know the package or can't be reached
+    /// contributes no versions.
+    pub async fn all_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        let mut versions = Vec::new();
+        let mut first_error = None;
+        for found in [
+            self.local_repo.get_package_versions(package_name).await,
+            self.remote_repo.get_package_versions(package_name).await,
+        ] {
+            match found {
+                Ok(found) => versions.extend(found),
+                Err(UhpmError::PackageNotFound(_)) => {}
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if versions.is_empty()
+            && let Some(error) = first_error
+        {
+            return Err(error);
+        }
+
+        let mut versions = versions
+            .iter()
+            .filter_map(|version| Version::parse(version).ok())
+            .collect::<Vec<_>>();
+        versions.sort_by(|a, b| b.cmp(a));
+        versions.dedup();
+        Ok(versions.iter().map(ToString::to_string).collect())
+    }
+
+    /// Searches both repositories, returning each package version once,
+    /// ordered by name and version.
+    ///
+    /// A version found in both repositories is reported from the local one.
+    /// A repository that fails is reported in the warnings; the search only
+    /// fails when both do.
+    pub async fn search_all_packages(&self, query: &str) -> Result<SearchResults, UhpmError> {
+        let outcomes = [
+            ("local", self.local_repo.search_packages(query).await),
+            ("remote", self.remote_repo.search_packages(query).await),
+        ];
+        let (found, warnings) = collect_warnings(outcomes)?;
+
+        let mut packages = found.into_iter().flatten().collect::<Vec<_>>();
+        // The sort is stable, so local packages win over remote duplicates.
+        packages.sort_by_cached_key(PackageReference::from_package);
+        packages.dedup_by(|a, b| a.id() == b.id());
+
+        Ok(SearchResults { packages, warnings })
+    }
+}
+
+/// Highest version of `package_name` in `repository` matching `requirement`.
+async fn best_version<R: PackageRepository>(
+    repository: &R,
+    package_name: &str,
+    requirement: &VersionReq,
+) -> Result<Option<Version>, UhpmError> {
+    match repository.get_package_versions(package_name).await {
+        Ok(versions) => Ok(versions
+            .iter()
+            .filter_map(|version| Version::parse(version).ok())
+            .filter(|version| requirement.matches(version))
+            .max()),
+        Err(UhpmError::PackageNotFound(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Splits per-repository outcomes into the successful values and a warning
+/// for each failure, failing only when every repository did.
+fn collect_warnings<T>(
+    outcomes: [(&str, Result<T, UhpmError>); 2],
+) -> Result<(Vec<T>, Vec<String>), UhpmError> {
+    let mut values = Vec::new();
+    let mut warnings = Vec::new();
+    let mut first_error = None;
+    for (repository, outcome) in outcomes {
+        match outcome {
+            Ok(value) => values.push(value),
+            Err(error) => {
+                warnings.push(format!("{} repository: {}", repository, error));
+                first_error.get_or_insert(error);
+            }
         }
     }
+    match first_error {
+        Some(error) if values.is_empty() => Err(error),
+        _ => Ok((values, warnings)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MemoryRepository, UnavailableRepository, block_on};
+    use crate::{PackageSource, Target, factories::PackageFactory};
+    use std::path::PathBuf;
+
+    fn package(name: &str, version: Version, origin: &str) -> Package {
+        PackageFactory::create(
+            name.to_string(),
+            version,
+            "tester".to_string(),
+            PackageSource::Local {
+                path: PathBuf::from(origin).join(name),
+            },
+            Target::current(),
+            None,
+            vec![],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_search_all_prefers_local_duplicates() {
+        let local = MemoryRepository::new();
+        let remote = MemoryRepository::new();
+        local.add(package("tool", Version::new(1, 0, 0), "/local"), Vec::new());
+        remote.add(
+            package("tool", Version::new(1, 0, 0), "/remote"),
+            Vec::new(),
+        );
+        remote.add(
+            package("toolkit", Version::new(2, 0, 0), "/remote"),
+            Vec::new(),
+        );
+        local.add(
+            package("toolkit", Version::new(1, 0, 0), "/local"),
+            Vec::new(),
+        );
+        let service = PackageService::new(local, remote);
+
+        let found = block_on(service.search_all_packages("tool")).unwrap();
+
+        assert!(found.warnings.is_empty());
+        assert_eq!(
+            found
+                .packages
+                .iter()
+                .map(|package| (package.id().as_str(), package.source().clone()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "tool@1.0.0",
+                    PackageSource::Local {
+                        path: PathBuf::from("/local/tool")
+                    }
+                ),
+                (
+                    "toolkit@1.0.0",
+                    PackageSource::Local {
+                        path: PathBuf::from("/local/toolkit")
+                    }
+                ),
+                (
+                    "toolkit@2.0.0",
+                    PackageSource::Local {
+                        path: PathBuf::from("/remote/toolkit")
+                    }
+                ),
+            ]
+        );
+    }
 
-    pub async fn sync_repositories(&self) -> Result<(), UhpmError> {
-        let _local_index = self.local_repo.update_index().await?;
-        let _remote_index = self.remote_repo.update_index().await?;
+    #[test]
+    fn test_all_versions_merges_both_repositories() {
+        let local = MemoryRepository::new();
+        let remote = MemoryRepository::new();
+        local.add(package("tool", Version::new(1, 0, 0), "/local"), Vec::new());
+        remote.add(
+            package("tool", Version::new(1, 0, 0), "/remote"),
+            Vec::new(),
+        );
+        remote.add(
+            package("tool", Version::new(1, 1, 0), "/remote"),
+            Vec::new(),
+        );
+        let service = PackageService::new(local, remote);
 
-        Ok(())
+        assert_eq!(
+            block_on(service.all_versions("tool")).unwrap(),
+            ["1.1.0", "1.0.0"]
+        );
+        assert!(
+            block_on(service.all_versions("missing"))
+                .unwrap()
+                .is_empty()
+        );
     }
 
-    pub async fn search_all_packages(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
-        let local_results = self.local_repo.search_packages(query).await?;
-        let remote_results = self.remote_repo.search_packages(query).await?;
+    #[test]
+    fn test_unavailable_remote_leaves_local_packages_usable() {
+        let local = MemoryRepository::new();
+        local.add(package("tool", Version::new(1, 0, 0), "/local"), Vec::new());
+        let service = PackageService::new(local, UnavailableRepository::new());
+
+        let found = block_on(service.search_all_packages("tool")).unwrap();
+        assert_eq!(found.packages.len(), 1);
+        assert_eq!(found.warnings.len(), 1);
+        assert!(found.warnings[0].starts_with("remote repository"));
+
+        assert_eq!(block_on(service.all_versions("tool")).unwrap(), ["1.0.0"]);
+        let any = VersionReq::STAR;
+        let best = block_on(service.find_best_package("tool", &any)).unwrap();
+        assert_eq!(best.id().as_str(), "tool@1.0.0");
+        assert_eq!(block_on(service.sync_repositories()).unwrap().len(), 1);
+
+        // Nothing local to fall back on: the remote failure is reported.
+        assert!(matches!(
+            block_on(service.find_best_package("missing", &any)),
+            Err(UhpmError::RepositoryUnavailable(_))
+        ));
+        let service =
+            PackageService::new(UnavailableRepository::new(), UnavailableRepository::new());
+        assert!(matches!(
+            block_on(service.search_all_packages("tool")),
+            Err(UhpmError::RepositoryUnavailable(_))
+        ));
+    }
 
-        let mut all_results = local_results;
-        all_results.extend(remote_results);
+    #[test]
+    fn test_find_best_package_prefers_local_unless_remote_is_newer() {
+        let repositories = || {
+            let local = MemoryRepository::new();
+            let remote = MemoryRepository::new();
+            local.add(package("tool", Version::new(1, 0, 0), "/local"), Vec::new());
+            remote.add(
+                package("tool", Version::new(1, 0, 0), "/remote"),
+                Vec::new(),
+            );
+            remote.add(
+                package("tool", Version::new(1, 2, 0), "/remote"),
+                Vec::new(),
+            );
+            (local, remote)
+        };
+        let origin = |package: Package| match package.source() {
+            PackageSource::Local { path } => (package.version().to_string(), path.clone()),
+            source => panic!("unexpected source {:?}", source),
+        };
 
-        all_results.sort_by(|a, b| a.name().cmp(b.name()));
-        all_results.dedup_by(|a, b| a.id() == b.id());
+        let (local, remote) = repositories();
+        let service = PackageService::new(local, remote);
+        let any = VersionReq::STAR;
+        assert_eq!(
+            origin(block_on(service.find_best_package("tool", &any)).unwrap()),
+            ("1.0.0".to_string(), PathBuf::from("/local/tool"))
+        );
 
-        Ok(all_results)
+        let (local, remote) = repositories();
+        let service = PackageService::new(local, remote).prefer_remote_newer();
+        assert_eq!(
+            origin(block_on(service.find_best_package("tool", &any)).unwrap()),
+            ("1.2.0".to_string(), PathBuf::from("/remote/tool"))
+        );
+        let exact = VersionReq::parse("=1.0.0").unwrap();
+        assert_eq!(
+            origin(block_on(service.find_best_package("tool", &exact)).unwrap()),
+            ("1.0.0".to_string(), PathBuf::from("/local/tool"))
+        );
     }
 }
@@ -0,0 +1,150 @@
+use crate::{Dependency, Package, PackageReference, UhpmError, ports::PackageRepository};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Repository lookups memoized for the length of one operation.
+///
+/// Resolving a dependency graph asks for the same packages and version
+/// lists over and over, and each lookup may be a network round trip. The
+/// context answers repeated `get_package` and `get_package_versions` calls
+/// from memory and remembers the packages `resolve_dependencies` returns,
+/// so the resolver and the installer work on the very same `Package`s.
+///
+/// It is meant to be created at the start of an operation and dropped at
+/// its end, so no state carries over into the next one. Once `capacity`
+/// entries are held, further results are passed through uncached.
+pub struct ResolutionContext<'a, R>
+where
+    R: PackageRepository + ?Sized,
+{
+    repository: &'a R,
+    capacity: usize,
+    packages: Mutex<HashMap<PackageReference, Package>>,
+    versions: Mutex<HashMap<String, Vec<String>>>,
+}
+
+impl<'a, R> ResolutionContext<'a, R>
+where
+    R: PackageRepository + ?Sized,
+{
+    pub fn new(repository: &'a R) -> Self {
+        Self::with_capacity(repository, DEFAULT_CAPACITY)
+    }
+
+    /// Caches at most `capacity` packages and as many version lists.
+    pub fn with_capacity(repository: &'a R, capacity: usize) -> Self {
+        Self {
+            repository,
+            capacity,
+            packages: Mutex::default(),
+            versions: Mutex::default(),
+        }
+    }
+
+    pub async fn get_package(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
+        if let Some(package) = self.packages.lock().unwrap().get(package_ref) {
+            return Ok(package.clone());
+        }
+        let package = self.repository.get_package(package_ref).await?;
+        self.remember(&package);
+        Ok(package)
+    }
+
+    pub async fn get_package_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        if let Some(versions) = self.versions.lock().unwrap().get(package_name) {
+            return Ok(versions.clone());
+        }
+        let versions = self.repository.get_package_versions(package_name).await?;
+        let mut cached = self.versions.lock().unwrap();
+        if cached.len() < self.capacity {
+            cached.insert(package_name.to_string(), versions.clone());
+        }
+        Ok(versions)
+    }
+
+    /// Asks the repository every time; yanked versions are only looked up
+    /// when several dependents share a package.
+    pub async fn get_yanked_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        self.repository.get_yanked_versions(package_name).await
+    }
+
+    /// Resolves through the repository and remembers the packages it picked.
+    pub async fn resolve_dependencies(
+        &self,
+        dependencies: &HashSet<Dependency>,
+    ) -> Result<Vec<Package>, UhpmError> {
+        let packages = self.repository.resolve_dependencies(dependencies).await?;
+        for package in &packages {
+            self.remember(package);
+        }
+        Ok(packages)
+    }
+
+    fn remember(&self, package: &Package) {
+        let mut packages = self.packages.lock().unwrap();
+        if packages.len() < self.capacity {
+            packages
+                .entry(PackageReference::from_package(package))
+                .or_insert_with(|| package.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{MemoryRepository, block_on};
+    use crate::{PackageSource, Target, factories::PackageFactory};
+    use semver::Version;
+    use std::path::PathBuf;
+
+    fn add(repository: &MemoryRepository, name: &str) -> PackageReference {
+        let package = PackageFactory::create(
+            name.to_string(),
+            Version::new(1, 0, 0),
+            "tester".to_string(),
+            PackageSource::Local {
+                path: PathBuf::from("/memory").join(name),
+            },
+            Target::current(),
+            None,
+            vec![],
+        )
+        .unwrap();
+        repository.add(package, Vec::new());
+        PackageReference::new(name.to_string(), Version::new(1, 0, 0))
+    }
+
+    #[test]
+    fn test_repeated_lookups_hit_the_repository_once() {
+        let repository = MemoryRepository::new();
+        let tool = add(&repository, "tool");
+        let context = ResolutionContext::new(&repository);
+
+        block_on(async {
+            for _ in 0..3 {
+                context.get_package(&tool).await.unwrap();
+            }
+        });
+        assert_eq!(repository.get_package_calls(&tool), 1);
+    }
+
+    #[test]
+    fn test_capacity_bounds_the_cache() {
+        let repository = MemoryRepository::new();
+        let first = add(&repository, "first");
+        let second = add(&repository, "second");
+        let context = ResolutionContext::with_capacity(&repository, 1);
+
+        block_on(async {
+            for _ in 0..2 {
+                context.get_package(&first).await.unwrap();
+                context.get_package(&second).await.unwrap();
+            }
+        });
+        assert_eq!(repository.get_package_calls(&first), 1);
+        assert_eq!(repository.get_package_calls(&second), 2);
+    }
+}
@@ -0,0 +1,1205 @@
+//! In-memory port implementations shared by the unit tests.
+
+use crate::{
+    CacheValidators, ConditionalFetch, Dependency, EventEnvelope, EventReceiver, FileMetadata,
+    FileType, FsError, HttpHeadResult, Installation, InstallationId, OperationRecord,
+    OverflowPolicy, Package, PackageEvent, PackageId, PackageReference, RangeResponse, Repository,
+    RepositoryIndex, RepositoryPackageEntry, ResolutionFailure, Symlink, UhpmError,
+    paths::UhpmPaths,
+    ports::{
+        CacheManager, EventCallback, EventPublisher, FileSystemOperations, NetworkOperations,
+        PackageRepository, StateStore,
+    },
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::{StreamExt, stream};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use url::Url;
+
+/// Runs a future to completion on a fresh single-threaded runtime.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+#[derive(Debug, Clone)]
+pub struct TestPaths {
+    pub base: PathBuf,
+}
+
+impl TestPaths {
+    pub fn new<P: Into<PathBuf>>(base: P) -> Self {
+        Self { base: base.into() }
+    }
+}
+
+impl UhpmPaths for TestPaths {
+    fn base_dir(&self) -> PathBuf {
+        self.base.clone()
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.base.join("config.toml")
+    }
+
+    fn cache_dir(&self) -> PathBuf {
+        self.base.join("cache")
+    }
+
+    fn temp_dir(&self) -> PathBuf {
+        self.base.join("tmp")
+    }
+}
+
+#[derive(Default)]
+struct MemoryFileSystemState {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    directories: BTreeSet<PathBuf>,
+    symlinks: BTreeMap<PathBuf, PathBuf>,
+    permissions: HashMap<PathBuf, u32>,
+    reads: Vec<PathBuf>,
+}
+
+/// File system kept entirely in memory. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct MemoryFileSystem {
+    state: Arc<Mutex<MemoryFileSystemState>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes a file, creating its parent directories.
+    pub fn add_file<P: Into<PathBuf>>(&self, path: P, data: &[u8]) {
+        let path = path.into();
+        let mut state = self.state.lock().unwrap();
+        for ancestor in path.ancestors().skip(1) {
+            state.directories.insert(ancestor.to_path_buf());
+        }
+        state.files.insert(path, data.to_vec());
+    }
+
+    pub fn file(&self, path: &Path) -> Option<Vec<u8>> {
+        self.state.lock().unwrap().files.get(path).cloned()
+    }
+
+    pub fn permissions(&self, path: &Path) -> Option<u32> {
+        self.state.lock().unwrap().permissions.get(path).copied()
+    }
+
+    pub fn symlink_target(&self, path: &Path) -> Option<PathBuf> {
+        self.state.lock().unwrap().symlinks.get(path).cloned()
+    }
+
+    /// Returns the files read since the last call, in order.
+    pub fn take_reads(&self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.state.lock().unwrap().reads)
+    }
+
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let state = self.state.lock().unwrap();
+        state
+            .files
+            .keys()
+            .chain(state.symlinks.keys())
+            .cloned()
+            .collect()
+    }
+}
+
+fn not_found(path: &Path) -> UhpmError {
+    FsError::NotFound(path.display().to_string()).into()
+}
+
+#[async_trait]
+impl FileSystemOperations for MemoryFileSystem {
+    async fn read_file(&self, path: &Path) -> Result<Vec<u8>, UhpmError> {
+        let mut state = self.state.lock().unwrap();
+        state.reads.push(path.to_path_buf());
+        let resolved = state
+            .symlinks
+            .get(path)
+            .map(PathBuf::as_path)
+            .unwrap_or(path);
+        state
+            .files
+            .get(resolved)
+            .cloned()
+            .ok_or_else(|| not_found(path))
+    }
+
+    async fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), UhpmError> {
+        self.add_file(path, data);
+        Ok(())
+    }
+
+    async fn create_dir(&self, path: &Path) -> Result<(), UhpmError> {
+        self.state
+            .lock()
+            .unwrap()
+            .directories
+            .insert(path.to_path_buf());
+        Ok(())
+    }
+
+    async fn create_dir_all(&self, path: &Path) -> Result<(), UhpmError> {
+        let mut state = self.state.lock().unwrap();
+        for ancestor in path.ancestors() {
+            state.directories.insert(ancestor.to_path_buf());
+        }
+        Ok(())
+    }
+
+    async fn remove(&self, path: &Path) -> Result<(), UhpmError> {
+        let mut state = self.state.lock().unwrap();
+        let removed = state.files.remove(path).is_some()
+            || state.symlinks.remove(path).is_some()
+            || state.directories.remove(path);
+        state.permissions.remove(path);
+        if removed {
+            Ok(())
+        } else {
+            Err(not_found(path))
+        }
+    }
+
+    async fn remove_dir_all(&self, path: &Path) -> Result<(), UhpmError> {
+        let mut state = self.state.lock().unwrap();
+        state.files.retain(|file, _| !file.starts_with(path));
+        state.symlinks.retain(|link, _| !link.starts_with(path));
+        state.directories.retain(|dir| !dir.starts_with(path));
+        state.permissions.retain(|file, _| !file.starts_with(path));
+        Ok(())
+    }
+
+    async fn copy_file(&self, from: &Path, to: &Path) -> Result<(), UhpmError> {
+        let data = self.read_file(from).await?;
+        self.add_file(to, &data);
+        Ok(())
+    }
+
+    async fn move_file(&self, from: &Path, to: &Path) -> Result<(), UhpmError> {
+        let data = self.read_file(from).await?;
+        self.remove(from).await?;
+        self.add_file(to, &data);
+        Ok(())
+    }
+
+    async fn exists(&self, path: &Path) -> bool {
+        let state = self.state.lock().unwrap();
+        state.files.contains_key(path)
+            || state.directories.contains(path)
+            || state.symlinks.contains_key(path)
+    }
+
+    async fn metadata(&self, path: &Path) -> Result<FileMetadata, UhpmError> {
+        let state = self.state.lock().unwrap();
+        if let Some(data) = state.files.get(path) {
+            let mut metadata = FileMetadata::new(path.to_path_buf(), data.len() as u64);
+            if let Some(mode) = state.permissions.get(path) {
+                metadata = metadata.with_mode(*mode);
+            }
+            Ok(metadata)
+        } else if state.directories.contains(path) {
+            let mut metadata =
+                FileMetadata::new(path.to_path_buf(), 0).with_file_type(FileType::Directory);
+            if let Some(mode) = state.permissions.get(path) {
+                metadata = metadata.with_mode(*mode);
+            }
+            Ok(metadata)
+        } else if state.symlinks.contains_key(path) {
+            Ok(FileMetadata::new(path.to_path_buf(), 0).with_file_type(FileType::Symlink))
+        } else {
+            Err(not_found(path))
+        }
+    }
+
+    async fn read_dir(&self, path: &Path) -> Result<Vec<PathBuf>, UhpmError> {
+        let state = self.state.lock().unwrap();
+        if !state.directories.contains(path) {
+            return Err(not_found(path));
+        }
+
+        let children: BTreeSet<PathBuf> = state
+            .files
+            .keys()
+            .chain(state.symlinks.keys())
+            .chain(state.directories.iter())
+            .filter(|entry| entry.parent() == Some(path))
+            .cloned()
+            .collect();
+        Ok(children.into_iter().collect())
+    }
+
+    async fn create_symlink(&self, symlink: &Symlink) -> Result<(), UhpmError> {
+        let mut state = self.state.lock().unwrap();
+        if state.symlinks.contains_key(&symlink.target) || state.files.contains_key(&symlink.target)
+        {
+            return Err(FsError::Io(format!("{} already exists", symlink.target.display())).into());
+        }
+        state
+            .symlinks
+            .insert(symlink.target.clone(), symlink.source.clone());
+        Ok(())
+    }
+
+    async fn remove_symlink(&self, path: &Path) -> Result<(), UhpmError> {
+        match self.state.lock().unwrap().symlinks.remove(path) {
+            Some(_) => Ok(()),
+            None => Err(not_found(path)),
+        }
+    }
+
+    async fn read_symlink(&self, path: &Path) -> Result<PathBuf, UhpmError> {
+        self.symlink_target(path).ok_or_else(|| not_found(path))
+    }
+
+    async fn is_symlink(&self, path: &Path) -> bool {
+        self.state.lock().unwrap().symlinks.contains_key(path)
+    }
+
+    async fn set_permissions(&self, path: &Path, permissions: u32) -> Result<(), UhpmError> {
+        let mut state = self.state.lock().unwrap();
+        if !state.files.contains_key(path) && !state.directories.contains(path) {
+            return Err(not_found(path));
+        }
+        state.permissions.insert(path.to_path_buf(), permissions);
+        Ok(())
+    }
+
+    async fn create_hard_link(&self, from: &Path, to: &Path) -> Result<(), UhpmError> {
+        let mut state = self.state.lock().unwrap();
+        let data = state
+            .files
+            .get(from)
+            .cloned()
+            .ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_path_buf(), data);
+        Ok(())
+    }
+}
+
+/// Network serving canned responses and recording every requested URL.
+#[derive(Default)]
+pub struct MockNetwork {
+    responses: Mutex<HashMap<String, Vec<u8>>>,
+    etags: Mutex<HashMap<String, String>>,
+    requests: Mutex<Vec<String>>,
+    ranged: Mutex<HashSet<String>>,
+    interruptions: Mutex<HashMap<String, usize>>,
+    range_offsets: Mutex<Vec<u64>>,
+}
+
+impl MockNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn respond<S: Into<String>>(&self, url: S, data: &[u8]) {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(url.into(), data.to_vec());
+    }
+
+    /// Serves `data` with an `ETag`, answering conditional requests that
+    /// carry the same tag with `304 Not Modified`.
+    pub fn respond_with_etag<S: Into<String>>(&self, url: S, data: &[u8], etag: &str) {
+        let url = url.into();
+        self.etags
+            .lock()
+            .unwrap()
+            .insert(url.clone(), etag.to_string());
+        self.respond(url, data);
+    }
+
+    /// Fails every further request for `url`, as if the server went down.
+    pub fn stop_responding(&self, url: &str) {
+        self.responses.lock().unwrap().remove(url);
+        self.etags.lock().unwrap().remove(url);
+    }
+
+    /// Serves `data` with `Accept-Ranges: bytes`, honouring range requests.
+    pub fn respond_with_ranges<S: Into<String>>(&self, url: S, data: &[u8]) {
+        let url = url.into();
+        self.ranged.lock().unwrap().insert(url.clone());
+        self.respond(url, data);
+    }
+
+    /// Makes the next range request for `url` fail after `after` bytes.
+    pub fn interrupt_once<S: Into<String>>(&self, url: S, after: usize) {
+        self.interruptions.lock().unwrap().insert(url.into(), after);
+    }
+
+    /// Offsets of every range request, in order.
+    pub fn range_offsets(&self) -> Vec<u64> {
+        self.range_offsets.lock().unwrap().clone()
+    }
+
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn request_count(&self, url: &str) -> usize {
+        self.requests
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|requested| requested.as_str() == url)
+            .count()
+    }
+}
+
+#[async_trait]
+impl NetworkOperations for MockNetwork {
+    async fn get(&self, url: &str) -> Result<Vec<u8>, UhpmError> {
+        self.requests.lock().unwrap().push(url.to_string());
+        self.responses
+            .lock()
+            .unwrap()
+            .get(url)
+            .cloned()
+            .ok_or_else(|| UhpmError::network(format!("No response for {}", url)))
+    }
+
+    async fn get_with_progress(
+        &self,
+        url: &str,
+        _on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<Vec<u8>, UhpmError> {
+        self.get(url).await
+    }
+
+    async fn head(&self, url: &str) -> Result<HttpHeadResult, UhpmError> {
+        let content_length = self
+            .responses
+            .lock()
+            .unwrap()
+            .get(url)
+            .map(|data| data.len() as u64);
+        Ok(HttpHeadResult {
+            status: if content_length.is_some() { 200 } else { 404 },
+            content_length,
+            validators: CacheValidators {
+                etag: self.etags.lock().unwrap().get(url).cloned(),
+                last_modified: None,
+            },
+            accepts_ranges: self.ranged.lock().unwrap().contains(url),
+        })
+    }
+
+    async fn get_conditional(
+        &self,
+        url: &str,
+        validators: &CacheValidators,
+    ) -> Result<ConditionalFetch, UhpmError> {
+        let etag = self.etags.lock().unwrap().get(url).cloned();
+        if etag.is_some() && etag == validators.etag {
+            self.requests.lock().unwrap().push(url.to_string());
+            return Ok(ConditionalFetch::NotModified);
+        }
+        Ok(ConditionalFetch::Modified {
+            data: self.get(url).await?,
+            validators: CacheValidators {
+                etag,
+                last_modified: None,
+            },
+        })
+    }
+
+    async fn get_range(&self, url: &str, offset: u64) -> Result<RangeResponse, UhpmError> {
+        self.range_offsets.lock().unwrap().push(offset);
+        let data = self.get(url).await?;
+        let total = data.len() as u64;
+        let partial = offset > 0 && self.ranged.lock().unwrap().contains(url);
+        let mut body = if partial {
+            data[offset as usize..].to_vec()
+        } else {
+            data
+        };
+
+        let chunks = match self.interruptions.lock().unwrap().remove(url) {
+            Some(after) => {
+                body.truncate(after);
+                vec![
+                    Ok(body),
+                    Err(UhpmError::network(format!("Connection reset for {}", url))),
+                ]
+            }
+            None => vec![Ok(body)],
+        };
+        Ok(RangeResponse {
+            partial,
+            total: Some(total),
+            body: stream::iter(chunks).boxed(),
+        })
+    }
+
+    async fn is_url_available(&self, url: &str) -> bool {
+        self.responses.lock().unwrap().contains_key(url)
+    }
+
+    async fn download_with_checksum(
+        &self,
+        url: &str,
+        _expected_checksum: Option<(&str, &str)>,
+        _on_progress: Option<Box<dyn Fn(u64, u64) + Send + Sync>>,
+    ) -> Result<Vec<u8>, UhpmError> {
+        self.get(url).await
+    }
+
+    fn parse_url(&self, url: &str) -> Result<Url, UhpmError> {
+        Url::parse(url).map_err(|e| UhpmError::network(e.to_string()))
+    }
+}
+
+/// Cache kept in memory.
+#[derive(Default)]
+pub struct MemoryCache {
+    packages: Mutex<HashMap<PackageReference, Vec<u8>>>,
+    indexes: Mutex<HashMap<String, Vec<u8>>>,
+    path: PathBuf,
+}
+
+impl MemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn index_keys(&self) -> Vec<String> {
+        self.indexes.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+#[async_trait]
+impl CacheManager for MemoryCache {
+    async fn get_package(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<Vec<u8>>, UhpmError> {
+        Ok(self.packages.lock().unwrap().get(package_ref).cloned())
+    }
+
+    async fn put_package(
+        &self,
+        package_ref: &PackageReference,
+        data: &[u8],
+    ) -> Result<(), UhpmError> {
+        self.packages
+            .lock()
+            .unwrap()
+            .insert(package_ref.clone(), data.to_vec());
+        Ok(())
+    }
+
+    async fn remove_package(&self, package_ref: &PackageReference) -> Result<(), UhpmError> {
+        self.packages.lock().unwrap().remove(package_ref);
+        Ok(())
+    }
+
+    async fn clear_packages(&self) -> Result<(), UhpmError> {
+        self.packages.lock().unwrap().clear();
+        Ok(())
+    }
+
+    async fn list_packages(&self) -> Result<Vec<PackageReference>, UhpmError> {
+        Ok(self.packages.lock().unwrap().keys().cloned().collect())
+    }
+
+    async fn get_index(&self, repository_url: &str) -> Result<Option<Vec<u8>>, UhpmError> {
+        Ok(self.indexes.lock().unwrap().get(repository_url).cloned())
+    }
+
+    async fn put_index(&self, repository_url: &str, data: &[u8]) -> Result<(), UhpmError> {
+        self.indexes
+            .lock()
+            .unwrap()
+            .insert(repository_url.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    async fn invalidate_index(&self, repository_url: &str) -> Result<(), UhpmError> {
+        self.indexes.lock().unwrap().remove(repository_url);
+        Ok(())
+    }
+
+    async fn get_cache_size(&self) -> Result<u64, UhpmError> {
+        let packages: usize = self.packages.lock().unwrap().values().map(Vec::len).sum();
+        let indexes: usize = self.indexes.lock().unwrap().values().map(Vec::len).sum();
+        Ok((packages + indexes) as u64)
+    }
+
+    async fn cleanup_old_entries(&self, _max_age: Duration) -> Result<(), UhpmError> {
+        Ok(())
+    }
+
+    fn get_cache_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    async fn has_package(&self, package_ref: &PackageReference) -> bool {
+        self.packages.lock().unwrap().contains_key(package_ref)
+    }
+
+    fn pin_package(&self, _package_ref: &PackageReference) {}
+
+    fn unpin_package(&self, _package_ref: &PackageReference) {}
+
+    async fn evict_to_fit(&self) -> Result<u64, UhpmError> {
+        Ok(0)
+    }
+}
+
+/// Builds a `tar.gz` package archive from `(path, contents)` pairs.
+pub fn package_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut data = Vec::new();
+    {
+        let encoder = flate2::write::GzEncoder::new(&mut data, flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        for (path, contents) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o755);
+            header.set_cksum();
+            builder.append_data(&mut header, path, *contents).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+    data
+}
+
+type PackageVersions = BTreeMap<semver::Version, (Package, Vec<u8>)>;
+
+/// Repository serving packages and archives registered by the test.
+///
+/// Downloads yield to the executor once so concurrent downloads overlap and
+/// the peak number in flight can be asserted.
+pub struct MemoryRepository {
+    packages: Mutex<BTreeMap<String, PackageVersions>>,
+    repository: Repository,
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    get_package_calls: Mutex<HashMap<PackageReference, usize>>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self {
+            packages: Mutex::default(),
+            repository: Repository::Local {
+                path: PathBuf::from("/memory"),
+            },
+            in_flight: AtomicUsize::new(0),
+            max_in_flight: AtomicUsize::new(0),
+            get_package_calls: Mutex::default(),
+        }
+    }
+
+    /// How many times `get_package` was asked for `package_ref`.
+    pub fn get_package_calls(&self, package_ref: &PackageReference) -> usize {
+        self.get_package_calls
+            .lock()
+            .unwrap()
+            .get(package_ref)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Highest number of downloads that were running at the same time.
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn add(&self, package: Package, archive: Vec<u8>) {
+        self.packages
+            .lock()
+            .unwrap()
+            .entry(package.name().to_string())
+            .or_default()
+            .insert(package.version().clone(), (package, archive));
+    }
+
+    fn entry(&self, package_ref: &PackageReference) -> Result<(Package, Vec<u8>), UhpmError> {
+        self.packages
+            .lock()
+            .unwrap()
+            .get(&package_ref.name)
+            .and_then(|versions| versions.get(&package_ref.version))
+            .cloned()
+            .ok_or_else(|| UhpmError::PackageNotFound(package_ref.to_string()))
+    }
+}
+
+#[async_trait]
+impl PackageRepository for MemoryRepository {
+    async fn get_package(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
+        *self
+            .get_package_calls
+            .lock()
+            .unwrap()
+            .entry(package_ref.clone())
+            .or_default() += 1;
+        self.entry(package_ref).map(|(package, _)| package)
+    }
+
+    async fn search_packages(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
+        Ok(self
+            .packages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| name.contains(query))
+            .filter_map(|(_, versions)| versions.values().last())
+            .map(|(package, _)| package.clone())
+            .collect())
+    }
+
+    async fn get_package_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        Ok(self
+            .packages
+            .lock()
+            .unwrap()
+            .get(package_name)
+            .map(|versions| versions.keys().map(ToString::to_string).collect())
+            .unwrap_or_default())
+    }
+
+    async fn get_yanked_versions(&self, package_name: &str) -> Result<Vec<String>, UhpmError> {
+        Ok(self
+            .packages
+            .lock()
+            .unwrap()
+            .get(package_name)
+            .map(|versions| {
+                versions
+                    .iter()
+                    .filter(|(_, (package, _))| package.is_yanked())
+                    .map(|(version, _)| version.to_string())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn get_latest_version(&self, package_name: &str) -> Result<String, UhpmError> {
+        self.get_package_versions(package_name)
+            .await?
+            .pop()
+            .ok_or_else(|| UhpmError::PackageNotFound(package_name.to_string()))
+    }
+
+    async fn resolve_dependencies(
+        &self,
+        dependencies: &HashSet<Dependency>,
+    ) -> Result<Vec<Package>, UhpmError> {
+        let packages = self.packages.lock().unwrap();
+        dependencies
+            .iter()
+            .map(|dependency| {
+                packages
+                    .get(&dependency.name)
+                    .and_then(|versions| {
+                        versions
+                            .iter()
+                            .rev()
+                            .filter(|(version, (package, _))| {
+                                !package.is_yanked() || dependency.pins_exactly(version)
+                            })
+                            .find(|(version, _)| dependency.matches_version(version))
+                    })
+                    .map(|(_, (package, _))| package.clone())
+                    .ok_or_else(|| {
+                        let versions = packages.get(&dependency.name);
+                        let listed = |yanked_only: bool| {
+                            versions
+                                .into_iter()
+                                .flatten()
+                                .filter(|(_, (package, _))| !yanked_only || package.is_yanked())
+                                .map(|(version, _)| version.to_string())
+                                .collect::<Vec<_>>()
+                        };
+                        UhpmError::ResolutionError(Box::new(ResolutionFailure::new(
+                            dependency,
+                            &listed(false),
+                            &listed(true),
+                        )))
+                    })
+            })
+            .collect()
+    }
+
+    async fn download_package(&self, package_ref: &PackageReference) -> Result<Vec<u8>, UhpmError> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        tokio::task::yield_now().await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+        self.entry(package_ref).map(|(_, archive)| archive)
+    }
+
+    async fn download_size(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<Option<u64>, UhpmError> {
+        self.entry(package_ref)
+            .map(|(_, archive)| Some(archive.len() as u64))
+    }
+
+    async fn get_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        let packages = self
+            .packages
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, versions)| {
+                RepositoryPackageEntry::new(
+                    name.clone(),
+                    versions.keys().map(ToString::to_string).collect(),
+                )
+            })
+            .collect();
+
+        Ok(RepositoryIndex {
+            name: "memory".to_string(),
+            url: "/memory".to_string(),
+            packages,
+        })
+    }
+
+    async fn update_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        self.get_index().await
+    }
+
+    async fn is_available(&self) -> bool {
+        true
+    }
+
+    fn get_repository(&self) -> &Repository {
+        &self.repository
+    }
+}
+
+/// Repository whose every request fails as if its server were down.
+pub struct UnavailableRepository {
+    repository: Repository,
+}
+
+impl UnavailableRepository {
+    pub fn new() -> Self {
+        Self {
+            repository: Repository::Http {
+                index_url: "https://down.example.com".to_string(),
+            },
+        }
+    }
+
+    fn error(&self) -> UhpmError {
+        UhpmError::RepositoryUnavailable("https://down.example.com".to_string())
+    }
+}
+
+#[async_trait]
+impl PackageRepository for UnavailableRepository {
+    async fn get_package(&self, _package_ref: &PackageReference) -> Result<Package, UhpmError> {
+        Err(self.error())
+    }
+
+    async fn search_packages(&self, _query: &str) -> Result<Vec<Package>, UhpmError> {
+        Err(self.error())
+    }
+
+    async fn get_package_versions(&self, _package_name: &str) -> Result<Vec<String>, UhpmError> {
+        Err(self.error())
+    }
+
+    async fn get_latest_version(&self, _package_name: &str) -> Result<String, UhpmError> {
+        Err(self.error())
+    }
+
+    async fn resolve_dependencies(
+        &self,
+        _dependencies: &HashSet<Dependency>,
+    ) -> Result<Vec<Package>, UhpmError> {
+        Err(self.error())
+    }
+
+    async fn download_package(
+        &self,
+        _package_ref: &PackageReference,
+    ) -> Result<Vec<u8>, UhpmError> {
+        Err(self.error())
+    }
+
+    async fn get_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        Err(self.error())
+    }
+
+    async fn update_index(&self) -> Result<RepositoryIndex, UhpmError> {
+        Err(self.error())
+    }
+
+    async fn is_available(&self) -> bool {
+        false
+    }
+
+    fn get_repository(&self) -> &Repository {
+        &self.repository
+    }
+}
+
+type PublishHook = Box<dyn Fn(&PackageEvent) + Send + Sync>;
+
+/// Event publisher that only records what was published.
+#[derive(Default)]
+pub struct RecordingEventPublisher {
+    envelopes: Mutex<Vec<EventEnvelope>>,
+    hook: Mutex<Option<PublishHook>>,
+}
+
+impl RecordingEventPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `hook` with every event as it is published.
+    pub fn on_publish(&self, hook: impl Fn(&PackageEvent) + Send + Sync + 'static) {
+        *self.hook.lock().unwrap() = Some(Box::new(hook));
+    }
+
+    pub fn events(&self) -> Vec<PackageEvent> {
+        self.envelopes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|envelope| envelope.event.clone())
+            .collect()
+    }
+}
+
+#[async_trait]
+impl EventPublisher for RecordingEventPublisher {
+    async fn publish(&self, event: PackageEvent) -> Result<(), UhpmError> {
+        if let Some(hook) = self.hook.lock().unwrap().as_ref() {
+            hook(&event);
+        }
+        let mut envelopes = self.envelopes.lock().unwrap();
+        let sequence = envelopes.last().map_or(0, |envelope| envelope.sequence) + 1;
+        envelopes.push(EventEnvelope::new(sequence, Utc::now(), event));
+        Ok(())
+    }
+
+    async fn subscribe(&self, _callback: EventCallback) -> Result<String, UhpmError> {
+        Ok(uuid::Uuid::new_v4().to_string())
+    }
+
+    async fn subscribe_channel(
+        &self,
+        capacity: usize,
+        overflow: OverflowPolicy,
+    ) -> Result<EventReceiver, UhpmError> {
+        Ok(crate::event_channel(capacity, overflow).1)
+    }
+
+    async fn unsubscribe(&self, _subscription_id: &str) -> Result<(), UhpmError> {
+        Ok(())
+    }
+
+    async fn get_event_history(
+        &self,
+        limit: Option<usize>,
+        since: Option<DateTime<Utc>>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<EventEnvelope>, UhpmError> {
+        let envelopes = self
+            .envelopes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|envelope| envelope.matches(since, package_name))
+            .cloned()
+            .collect::<Vec<_>>();
+        let skip = limit.map_or(0, |limit| envelopes.len().saturating_sub(limit));
+        Ok(envelopes.into_iter().skip(skip).collect())
+    }
+
+    async fn clear_event_history(&self) -> Result<(), UhpmError> {
+        self.envelopes.lock().unwrap().clear();
+        Ok(())
+    }
+}
+
+/// State store kept in memory.
+#[derive(Default)]
+pub struct InMemoryStateStore {
+    packages: Mutex<HashMap<PackageId, Package>>,
+    installations: Mutex<Vec<Installation>>,
+    operations: Mutex<Vec<OperationRecord>>,
+    fail_install_records: AtomicBool,
+}
+
+impl InMemoryStateStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes every further `save_installs` fail, as if the database went
+    /// away right when an install is recorded.
+    pub fn fail_install_records(&self) {
+        self.fail_install_records.store(true, Ordering::SeqCst);
+    }
+
+    /// Package owning `path` exactly, or below one of its directory links.
+    fn owner(&self, path: &Path, below_directory_link: bool) -> Option<PackageId> {
+        let installations = self.installations.lock().unwrap();
+        let mut owners = installations
+            .iter()
+            .filter(|installation| {
+                if below_directory_link {
+                    installation
+                        .symlinks()
+                        .iter()
+                        .any(|symlink| symlink.is_directory_link() && symlink.target == path)
+                } else {
+                    installation.installed_files().contains_key(path)
+                        || installation
+                            .symlinks()
+                            .iter()
+                            .any(|symlink| symlink.target == path)
+                }
+            })
+            .collect::<Vec<_>>();
+        owners.sort_by_key(|installation| !installation.is_active());
+        owners
+            .first()
+            .map(|installation| installation.package_id().clone())
+    }
+
+    /// Replaces an installation, deactivating other versions of its package
+    /// when it is the active one.
+    fn insert_installation(&self, installation: &Installation) {
+        let mut installations = self.installations.lock().unwrap();
+        installations.retain(|existing| existing.id() != installation.id());
+        if installation.is_active() {
+            let package_id = installation.package_id();
+            for other in installations.iter_mut() {
+                if other.package_id() != package_id
+                    && other.package_id().name() == package_id.name()
+                {
+                    other.deactivate();
+                }
+            }
+            for package in self.packages.lock().unwrap().values_mut() {
+                if package.name() == package_id.name() && package.id() != package_id {
+                    package.set_active(false);
+                }
+            }
+        }
+        installations.push(installation.clone());
+    }
+}
+
+#[async_trait]
+impl StateStore for InMemoryStateStore {
+    async fn save_package(&self, package: &Package) -> Result<(), UhpmError> {
+        self.packages
+            .lock()
+            .unwrap()
+            .insert(package.id().clone(), package.clone());
+        Ok(())
+    }
+
+    async fn get_package(&self, package_id: &PackageId) -> Result<Option<Package>, UhpmError> {
+        Ok(self.packages.lock().unwrap().get(package_id).cloned())
+    }
+
+    async fn list_installed_packages(&self) -> Result<Vec<Package>, UhpmError> {
+        let mut installed = self
+            .packages
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|package| package.is_installed())
+            .cloned()
+            .collect::<Vec<_>>();
+        installed.sort_by(|a, b| a.id().as_str().cmp(b.id().as_str()));
+        Ok(installed)
+    }
+
+    async fn delete_package(&self, package_id: &PackageId) -> Result<(), UhpmError> {
+        self.packages.lock().unwrap().remove(package_id);
+        Ok(())
+    }
+
+    async fn get_dependents(&self, package_name: &str) -> Result<Vec<PackageId>, UhpmError> {
+        Ok(self
+            .list_installed_packages()
+            .await?
+            .into_iter()
+            .filter(|package| {
+                package.name() != package_name
+                    && package
+                        .dependencies()
+                        .iter()
+                        .any(|dependency| dependency.name == package_name)
+            })
+            .map(|package| package.id().clone())
+            .collect())
+    }
+
+    async fn save_installation(&self, installation: &Installation) -> Result<(), UhpmError> {
+        self.insert_installation(installation);
+        Ok(())
+    }
+
+    async fn save_installs(
+        &self,
+        packages: &[Package],
+        installations: &[Installation],
+    ) -> Result<(), UhpmError> {
+        // Fail before touching anything, like a rolled back transaction.
+        if self.fail_install_records.load(Ordering::SeqCst) {
+            return Err(UhpmError::DatabaseError("database is locked".to_string()));
+        }
+        for package in packages {
+            self.save_package(package).await?;
+        }
+        for installation in installations {
+            self.insert_installation(installation);
+        }
+        Ok(())
+    }
+
+    async fn get_active_installation(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Option<Installation>, UhpmError> {
+        Ok(self
+            .installations
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|installation| {
+                installation.package_id() == package_id && installation.is_active()
+            })
+            .cloned())
+    }
+
+    async fn list_installations(
+        &self,
+        package_id: &PackageId,
+    ) -> Result<Vec<Installation>, UhpmError> {
+        let mut installations = self
+            .installations
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|installation| installation.package_id() == package_id)
+            .cloned()
+            .collect::<Vec<_>>();
+        installations.sort_by_key(|installation| *installation.installed_at());
+        Ok(installations)
+    }
+
+    async fn delete_installation(&self, installation_id: &InstallationId) -> Result<(), UhpmError> {
+        self.installations
+            .lock()
+            .unwrap()
+            .retain(|installation| installation.id() != installation_id);
+        Ok(())
+    }
+
+    async fn find_owner(&self, path: &Path) -> Result<Option<PackageId>, UhpmError> {
+        Ok(self.owner(path, false).or_else(|| {
+            path.ancestors()
+                .skip(1)
+                .find_map(|ancestor| self.owner(ancestor, true))
+        }))
+    }
+
+    async fn record_operation(&self, record: &OperationRecord) -> Result<i64, UhpmError> {
+        let mut operations = self.operations.lock().unwrap();
+        let id = operations.len() as i64 + 1;
+        operations.push(OperationRecord {
+            id: Some(id),
+            ..record.clone()
+        });
+        Ok(id)
+    }
+
+    async fn get_history(
+        &self,
+        limit: Option<usize>,
+        package_name: Option<&str>,
+    ) -> Result<Vec<OperationRecord>, UhpmError> {
+        Ok(self
+            .operations
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .filter(|record| package_name.is_none_or(|name| record.package_name == name))
+            .take(limit.unwrap_or(usize::MAX))
+            .cloned()
+            .collect())
+    }
+
+    async fn compact(&self) -> Result<(), UhpmError> {
+        Ok(())
+    }
+}
+
+/// Tracing subscriber that records the names of created spans and the
+/// levels of emitted events.
+#[derive(Clone, Default)]
+pub struct RecordingSubscriber {
+    spans: Arc<Mutex<Vec<String>>>,
+    events: Arc<Mutex<Vec<tracing::Level>>>,
+}
+
+impl RecordingSubscriber {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spans(&self) -> Vec<String> {
+        self.spans.lock().unwrap().clone()
+    }
+
+    pub fn events(&self) -> Vec<tracing::Level> {
+        self.events.lock().unwrap().clone()
+    }
+}
+
+impl tracing::Subscriber for RecordingSubscriber {
+    fn enabled(&self, _metadata: &tracing::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let mut spans = self.spans.lock().unwrap();
+        spans.push(span.metadata().name().to_string());
+        tracing::span::Id::from_u64(spans.len() as u64)
+    }
+
+    fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {}
+
+    fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+    fn event(&self, event: &tracing::Event<'_>) {
+        self.events.lock().unwrap().push(*event.metadata().level());
+    }
+
+    fn enter(&self, _span: &tracing::span::Id) {}
+
+    fn exit(&self, _span: &tracing::span::Id) {}
+}
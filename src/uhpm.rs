@@ -0,0 +1,508 @@
+use crate::{
+    InstallResult, Package, PackageReference, RemovalResult, Repository, RepositoryConfig,
+    SwitchResult, TargetPolicy, UhpmConfig, UhpmError, UpdatePolicy,
+    application::package_manager::DynPackageManager,
+    cache::FileSystemCache,
+    events::InMemoryEventPublisher,
+    fs::TokioFileSystem,
+    lock::LockFile,
+    network::{FileNetwork, ReqwestNetwork},
+    paths::{UhpmPaths, create_directories},
+    ports::{EventCallback, EventPublisher},
+    repositories::{
+        CompositeRepository, DatabaseRepository, GitCli, GitPackagesRepository,
+        LocalPackagesRepository, PackageFilesRepository, RemotePackagesRepository,
+        SqliteStateStore,
+    },
+};
+use semver::Version;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Package manager wired with the default implementations.
+///
+/// Files are managed with [`TokioFileSystem`], downloads go through
+/// [`ReqwestNetwork`] into a [`FileSystemCache`], events are delivered by an
+/// [`InMemoryEventPublisher`] and the installed state lives in SQLite at
+/// `paths.db_path()`. Use [`PackageManager`](crate::application::package_manager::PackageManager)
+/// directly to swap any of these; either way the manager can be taken out as
+/// a [`DynPackageManager`].
+///
+/// ```no_run
+/// # async fn example(config: uhpm_core::UhpmConfig) -> Result<(), uhpm_core::UhpmError> {
+/// let uhpm = uhpm_core::Uhpm::new(config, uhpm_core::paths::XdgPaths::from_env()?).await?;
+/// for package in uhpm.search("tool").await? {
+///     println!("{}", package.summary());
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Uhpm {
+    manager: DynPackageManager,
+}
+
+impl Uhpm {
+    /// Creates the uhpm directories and opens the cache and database in them.
+    ///
+    /// Enabled repositories of `config` are consulted in ascending `priority`
+    /// order: `http(s)://` URLs are served as remote repositories, git URLs
+    /// from their release tags, and `file://` URLs or plain paths as
+    /// directories: ones with an `index.toml` are read like a remote
+    /// repository, others are laid out like the packages directory.
+    pub async fn new(config: UhpmConfig, paths: impl UhpmPaths) -> Result<Self, UhpmError> {
+        let file_system = TokioFileSystem::new();
+        create_directories(&paths, &file_system).await?;
+        let paths = ResolvedPaths::from(&paths);
+
+        let network = ReqwestNetwork::new();
+        let cache = Arc::new(
+            FileSystemCache::open(
+                file_system.clone(),
+                paths.cache_dir(),
+                config.max_cache_size,
+            )
+            .await?,
+        );
+        let repository = composite_repository(&config, &paths, &file_system, &network, &cache)?;
+
+        let home = std::env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| paths.base_dir());
+        let policy = TargetPolicy::from_config(&config, home, &paths.base_dir());
+        let default_prefix = config
+            .default_prefix
+            .as_ref()
+            .map(|prefix| policy.expand_home(Path::new(prefix)));
+
+        let (mut database, recovered) = DatabaseRepository::open_or_recover(&paths.db_path())?;
+        if let Some(moved) = recovered {
+            let mut files = PackageFilesRepository::new(file_system.clone(), paths.packages_dir())
+                .with_target_policy(policy.clone());
+            if let Some(prefix) = &default_prefix {
+                files = files.with_prefix(prefix.clone());
+            }
+            let rebuilt = database.rebuild_from_store(&repository, &files).await?;
+            warn!(
+                corrupt = %moved.display(),
+                packages = rebuilt.len(),
+                "rebuilt the corrupted database from the package store"
+            );
+        }
+        let store = SqliteStateStore::new(database);
+        let mut manager = DynPackageManager::from_ports(
+            file_system,
+            network,
+            repository,
+            cache,
+            InMemoryEventPublisher::new(),
+            store,
+            paths.packages_dir(),
+        )
+        .with_install_mode(config.default_install_mode)
+        .with_lock(LockFile::new(paths.lock_path()))
+        .with_journal(paths.journal_path())
+        .with_trust_threshold(config.trust_threshold)
+        .with_update_policy(config.update_policy)
+        .with_glob_directories(config.glob_directories);
+        for repository in &config.repositories {
+            manager = manager.with_repository_trust(&repository.name, repository.trust_level);
+        }
+        if let Some(prefix) = default_prefix {
+            manager = manager.with_default_prefix(prefix);
+        }
+        let manager = manager.with_target_policy(policy);
+
+        let recovered = manager.recover().await?;
+        if !recovered.rolled_back.is_empty() {
+            warn!(
+                packages = recovered.rolled_back.len(),
+                files = recovered.removed_files,
+                "rolled back an interrupted install"
+            );
+        }
+
+        Ok(Self { manager })
+    }
+
+    /// The manager behind the facade, for operations it doesn't cover.
+    pub fn manager(&self) -> &DynPackageManager {
+        &self.manager
+    }
+
+    pub fn into_manager(self) -> DynPackageManager {
+        self.manager
+    }
+
+    pub async fn install(
+        &self,
+        package_ref: &PackageReference,
+    ) -> Result<InstallResult, UhpmError> {
+        self.manager.install(package_ref).await
+    }
+
+    pub async fn remove(&self, package_ref: &PackageReference) -> Result<RemovalResult, UhpmError> {
+        self.manager.remove(package_ref).await
+    }
+
+    pub async fn switch(
+        &self,
+        package_name: &str,
+        target_version: &Version,
+        allow_pinned: bool,
+    ) -> Result<SwitchResult, UhpmError> {
+        self.manager
+            .switch(package_name, target_version, allow_pinned)
+            .await
+    }
+
+    pub async fn update(&self, package_name: &str) -> Result<SwitchResult, UhpmError> {
+        self.manager.update(package_name).await
+    }
+
+    pub async fn update_to(
+        &self,
+        package_name: &str,
+        version: &Version,
+    ) -> Result<SwitchResult, UhpmError> {
+        self.manager.update_to(package_name, version).await
+    }
+
+    pub async fn check_updates(&self) -> Result<Vec<PackageReference>, UhpmError> {
+        self.manager.check_updates().await
+    }
+
+    pub async fn set_update_policy(
+        &self,
+        package_name: &str,
+        update_policy: Option<UpdatePolicy>,
+    ) -> Result<(), UhpmError> {
+        self.manager
+            .set_update_policy(package_name, update_policy)
+            .await
+    }
+
+    pub async fn pin(&self, package_name: &str, version: &Version) -> Result<(), UhpmError> {
+        self.manager.pin(package_name, version).await
+    }
+
+    pub async fn unpin(&self, package_name: &str) -> Result<(), UhpmError> {
+        self.manager.unpin(package_name).await
+    }
+
+    pub async fn search(&self, query: &str) -> Result<Vec<Package>, UhpmError> {
+        self.manager.search(query).await
+    }
+
+    pub async fn info(&self, package_ref: &PackageReference) -> Result<Package, UhpmError> {
+        self.manager.info(package_ref).await
+    }
+
+    pub async fn list_installed(&self) -> Result<Vec<Package>, UhpmError> {
+        self.manager.list_installed(false).await
+    }
+
+    /// Calls `callback` for every event published from now on, returning the
+    /// subscription id.
+    pub async fn subscribe_events(&self, callback: EventCallback) -> Result<String, UhpmError> {
+        self.manager.event_publisher().subscribe(callback).await
+    }
+
+    pub async fn unsubscribe_events(&self, subscription_id: &str) -> Result<(), UhpmError> {
+        self.manager
+            .event_publisher()
+            .unsubscribe(subscription_id)
+            .await
+    }
+}
+
+/// A snapshot of the directories of some [`UhpmPaths`], shared by the
+/// repositories.
+#[derive(Debug, Clone)]
+struct ResolvedPaths {
+    base_dir: PathBuf,
+    packages_dir: PathBuf,
+    db_path: PathBuf,
+    config_path: PathBuf,
+    cache_dir: PathBuf,
+    temp_dir: PathBuf,
+    log_dir: PathBuf,
+    lock_path: PathBuf,
+    journal_path: PathBuf,
+}
+
+impl ResolvedPaths {
+    fn from<P: UhpmPaths>(paths: &P) -> Self {
+        Self {
+            base_dir: paths.base_dir(),
+            packages_dir: paths.packages_dir(),
+            db_path: paths.db_path(),
+            config_path: paths.config_path(),
+            cache_dir: paths.cache_dir(),
+            temp_dir: paths.temp_dir(),
+            log_dir: paths.log_dir(),
+            lock_path: paths.lock_path(),
+            journal_path: paths.journal_path(),
+        }
+    }
+
+    /// Paths whose packages directory is the local repository at `root`.
+    fn for_local_repository(&self, root: PathBuf) -> Self {
+        Self {
+            packages_dir: root,
+            ..self.clone()
+        }
+    }
+}
+
+impl UhpmPaths for ResolvedPaths {
+    fn base_dir(&self) -> PathBuf {
+        self.base_dir.clone()
+    }
+
+    fn packages_dir(&self) -> PathBuf {
+        self.packages_dir.clone()
+    }
+
+    fn db_path(&self) -> PathBuf {
+        self.db_path.clone()
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.config_path.clone()
+    }
+
+    fn cache_dir(&self) -> PathBuf {
+        self.cache_dir.clone()
+    }
+
+    fn temp_dir(&self) -> PathBuf {
+        self.temp_dir.clone()
+    }
+
+    fn log_dir(&self) -> PathBuf {
+        self.log_dir.clone()
+    }
+
+    fn lock_path(&self) -> PathBuf {
+        self.lock_path.clone()
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.journal_path.clone()
+    }
+}
+
+fn is_git_url(url: &str) -> bool {
+    url.starts_with("git://") || url.starts_with("git@") || url.ends_with(".git")
+}
+
+fn composite_repository(
+    config: &UhpmConfig,
+    paths: &ResolvedPaths,
+    file_system: &TokioFileSystem,
+    network: &ReqwestNetwork,
+    cache: &Arc<FileSystemCache<TokioFileSystem>>,
+) -> Result<CompositeRepository, UhpmError> {
+    let mut repositories: Vec<&RepositoryConfig> = config
+        .repositories
+        .iter()
+        .filter(|repository| repository.enabled)
+        .collect();
+    repositories.sort_by_key(|repository| repository.priority);
+
+    let mut composite = CompositeRepository::new();
+    for repository in repositories {
+        composite = if is_git_url(&repository.url) {
+            composite.with_named_repository(
+                &repository.name,
+                GitPackagesRepository::new(
+                    GitCli::new(),
+                    paths.clone(),
+                    Repository::Git {
+                        url: repository.url.clone(),
+                        release: None,
+                    },
+                )?,
+            )
+        } else if repository.is_remote() {
+            composite.with_named_repository(
+                &repository.name,
+                RemotePackagesRepository::new(
+                    network.clone(),
+                    cache.clone(),
+                    file_system.clone(),
+                    paths.clone(),
+                    Repository::Http {
+                        index_url: repository.url.clone(),
+                    },
+                )?
+                .with_mirrors(repository.mirrors.iter().cloned())
+                .with_download_options(config.downloads.clone()),
+            )
+        } else if let Some(root) = repository.local_path()
+            && root.join("index.toml").is_file()
+        {
+            composite.with_named_repository(
+                &repository.name,
+                RemotePackagesRepository::new(
+                    FileNetwork::new(file_system.clone()),
+                    cache.clone(),
+                    file_system.clone(),
+                    paths.clone(),
+                    Repository::Local { path: root },
+                )?
+                .with_download_options(config.downloads.clone()),
+            )
+        } else if let Some(root) = repository.local_path() {
+            composite.with_named_repository(
+                &repository.name,
+                LocalPackagesRepository::new(
+                    file_system.clone(),
+                    paths.for_local_repository(root.clone()),
+                    Repository::Local { path: root },
+                )?,
+            )
+        } else {
+            return Err(UhpmError::ConfigError(format!(
+                "Unsupported URL for repository {}: {}",
+                repository.name, repository.url
+            )));
+        };
+    }
+    Ok(composite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{TestPaths, block_on};
+    use crate::{
+        DownloadOptions, GlobDirectories, InstallMode, RepositoryType, TrustLevel, UpdatePolicy,
+    };
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_installs_from_local_repository() {
+        let dir = std::env::temp_dir().join(format!("uhpm-facade-{}", uuid::Uuid::new_v4()));
+        let prefix = dir.join("prefix");
+        let package_dir = dir.join("repo").join("hello").join("1.0.0");
+        std::fs::create_dir_all(package_dir.join("bin")).unwrap();
+        std::fs::write(
+            package_dir.join("meta.toml"),
+            "name = \"hello\"\nversion = \"1.0.0\"\nauthor = \"test\"\ndependencies = []\n",
+        )
+        .unwrap();
+        std::fs::write(
+            package_dir.join("instlist"),
+            format!("bin/hello {}\n", prefix.join("bin/hello").display()),
+        )
+        .unwrap();
+        std::fs::write(package_dir.join("bin/hello"), "#!/bin/sh\necho hello\n").unwrap();
+
+        let config = UhpmConfig {
+            update_source: String::new(),
+            default_install_mode: InstallMode::Direct,
+            repositories: vec![RepositoryConfig::new(
+                "fixture".to_string(),
+                format!("file://{}", dir.join("repo").display()),
+                RepositoryType::Binary,
+            )],
+            max_cache_size: None,
+            install_prefixes: vec![prefix.display().to_string()],
+            default_prefix: None,
+            downloads: DownloadOptions::default(),
+            trust_threshold: TrustLevel::default(),
+            update_policy: UpdatePolicy::default(),
+            glob_directories: GlobDirectories::default(),
+        };
+
+        block_on(async {
+            let uhpm = Uhpm::new(config, TestPaths::new(dir.join("uhpm")))
+                .await
+                .unwrap();
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let recorded = events.clone();
+            uhpm.subscribe_events(Box::new(move |envelope| {
+                recorded.lock().unwrap().push(envelope.sequence)
+            }))
+            .await
+            .unwrap();
+
+            assert_eq!(uhpm.search("hel").await.unwrap().len(), 1);
+            let package_ref = PackageReference::new("hello".to_string(), Version::new(1, 0, 0));
+            let result = uhpm.install(&package_ref).await.unwrap();
+
+            assert_eq!(result.package_id.as_str(), "hello@1.0.0");
+            assert_eq!(
+                std::fs::read_to_string(prefix.join("bin/hello")).unwrap(),
+                "#!/bin/sh\necho hello\n"
+            );
+            assert!(!events.lock().unwrap().is_empty());
+
+            uhpm.manager().deactivate(&package_ref).await.unwrap();
+            uhpm.remove(&package_ref).await.unwrap();
+            assert!(!prefix.join("bin/hello").exists());
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_installs_from_published_directory() {
+        let dir = std::env::temp_dir().join(format!("uhpm-facade-{}", uuid::Uuid::new_v4()));
+        let prefix = dir.join("prefix");
+        let repo = dir.join("repo");
+        std::fs::create_dir_all(repo.join("packages")).unwrap();
+        let meta = "name = \"hello\"\nversion = \"1.0.0\"\nauthor = \"test\"\ndependencies = []\n";
+        std::fs::write(repo.join("packages/hello-1.0.0-meta.toml"), meta).unwrap();
+        let instlist = format!("bin/hello {}\n", prefix.join("bin/hello").display());
+        let archive = crate::test_utils::package_archive(&[
+            ("meta.toml", meta.as_bytes()),
+            ("instlist", instlist.as_bytes()),
+            ("bin/hello", b"#!/bin/sh\necho hello\n"),
+        ]);
+        std::fs::write(repo.join("packages/hello-1.0.0.uhp"), &archive).unwrap();
+        std::fs::write(
+            repo.join("index.toml"),
+            format!(
+                "name = \"usb\"\nurl = \"\"\n[[packages]]\nname = \"hello\"\nversions = [\"1.0.0\"]\n\
+                 [packages.checksums]\n\"1.0.0\" = \"{}\"\n",
+                crate::compute_checksum("sha256", &archive).unwrap()
+            ),
+        )
+        .unwrap();
+
+        let config = UhpmConfig {
+            update_source: String::new(),
+            default_install_mode: InstallMode::Direct,
+            repositories: vec![RepositoryConfig::new(
+                "usb".to_string(),
+                format!("file://{}", repo.display()),
+                RepositoryType::Binary,
+            )],
+            max_cache_size: None,
+            install_prefixes: vec![prefix.display().to_string()],
+            default_prefix: None,
+            downloads: DownloadOptions::default(),
+            trust_threshold: TrustLevel::default(),
+            update_policy: UpdatePolicy::default(),
+            glob_directories: GlobDirectories::default(),
+        };
+
+        block_on(async {
+            let uhpm = Uhpm::new(config, TestPaths::new(dir.join("uhpm")))
+                .await
+                .unwrap();
+            let package_ref = PackageReference::new("hello".to_string(), Version::new(1, 0, 0));
+            uhpm.install(&package_ref).await.unwrap();
+
+            assert_eq!(
+                std::fs::read_to_string(prefix.join("bin/hello")).unwrap(),
+                "#!/bin/sh\necho hello\n"
+            );
+        });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+//! Validation rules shared by everything that accepts package data.
+//!
+//! [`crate::factories::PackageFactory`] applies these when creating packages;
+//! config import and user input should use the same functions so the rules
+//! can't drift apart.
+
+use crate::{PackageSource, UhpmError};
+
+/// Longest package name accepted by the default [`NamePolicy`].
+pub const MAX_PACKAGE_NAME_LEN: usize = 50;
+
+/// Rules a package name has to follow.
+///
+/// Names always consist of ASCII letters, digits, hyphens and underscores
+/// plus the policy's extra characters, and must start with a letter (or a
+/// digit when allowed). The default policy allows no extra characters, no
+/// leading digit and at most [`MAX_PACKAGE_NAME_LEN`] characters.
+///
+/// # Examples
+/// ```
+/// use uhpm_core::validation::NamePolicy;
+///
+/// let relaxed = NamePolicy::default()
+///     .allow_leading_digit()
+///     .with_extra_chars(['.', '/']);
+/// assert!(relaxed.validate("7zip").is_ok());
+/// assert!(relaxed.validate("org/tool.cli").is_ok());
+/// assert!(NamePolicy::default().validate("7zip").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamePolicy {
+    pub max_len: usize,
+    pub allow_leading_digit: bool,
+    /// Characters allowed besides letters, digits, `-` and `_`, e.g. `.` or
+    /// `/` for scoped names. They are never accepted as the first character,
+    /// and with `/` allowed no path segment may be empty, `.` or `..`.
+    pub extra_chars: Vec<char>,
+}
+
+impl Default for NamePolicy {
+    fn default() -> Self {
+        Self {
+            max_len: MAX_PACKAGE_NAME_LEN,
+            allow_leading_digit: false,
+            extra_chars: Vec::new(),
+        }
+    }
+}
+
+impl NamePolicy {
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+
+    pub fn allow_leading_digit(mut self) -> Self {
+        self.allow_leading_digit = true;
+        self
+    }
+
+    pub fn with_extra_chars<I: IntoIterator<Item = char>>(mut self, chars: I) -> Self {
+        self.extra_chars.extend(chars);
+        self
+    }
+
+    /// Checks `name` against the policy, naming the first rule it breaks.
+    pub fn validate(&self, name: &str) -> Result<(), UhpmError> {
+        if name.trim().is_empty() {
+            return Err(UhpmError::ValidationError(
+                "Package name cannot be empty or whitespace".to_string(),
+            ));
+        }
+
+        if name.len() > self.max_len {
+            return Err(UhpmError::ValidationError(format!(
+                "Invalid package name '{}'. Must be at most {} characters long",
+                name, self.max_len
+            )));
+        }
+
+        let starts_validly = name.starts_with(|c: char| {
+            c.is_ascii_alphabetic() || (self.allow_leading_digit && c.is_ascii_digit())
+        });
+        if !starts_validly {
+            return Err(UhpmError::ValidationError(format!(
+                "Invalid package name '{}'. Must start with a {}",
+                name,
+                if self.allow_leading_digit {
+                    "letter or digit"
+                } else {
+                    "letter"
+                }
+            )));
+        }
+
+        if !name.chars().all(|c| {
+            c.is_ascii_alphanumeric() || c == '-' || c == '_' || self.extra_chars.contains(&c)
+        }) {
+            let extra = if self.extra_chars.is_empty() {
+                String::new()
+            } else {
+                format!(
+                    " or one of \"{}\"",
+                    self.extra_chars.iter().collect::<String>()
+                )
+            };
+            return Err(UhpmError::ValidationError(format!(
+                "Invalid package name '{}'. Must contain only alphanumeric characters, hyphens, and underscores{}",
+                name, extra
+            )));
+        }
+
+        // Names become directory names, so scoped names must not be able to
+        // climb out of or collapse into the packages directory.
+        if self.extra_chars.contains(&'/')
+            && name
+                .split('/')
+                .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        {
+            return Err(UhpmError::ValidationError(format!(
+                "Invalid package name '{}'. Must not contain empty, '.' or '..' path segments",
+                name
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks `name` against the default [`NamePolicy`]: it starts with an ASCII
+/// letter, contains only ASCII letters, digits, hyphens and underscores, and
+/// is at most [`MAX_PACKAGE_NAME_LEN`] characters long.
+pub fn validate_package_name(name: &str) -> Result<(), UhpmError> {
+    NamePolicy::default().validate(name)
+}
+
+/// Checks that a package source points somewhere usable: Git URLs must be
+/// http(s), file or `git@` URLs, HTTP URLs must be http(s) and local paths
+/// must not be empty.
+pub fn validate_source(source: &PackageSource) -> Result<(), UhpmError> {
+    match source {
+        PackageSource::Git { url, release: _ } => {
+            if url.trim().is_empty() {
+                return Err(UhpmError::ValidationError(
+                    "Git URL cannot be empty".to_string(),
+                ));
+            }
+            // Basic URL validation
+            if !url.starts_with("http://")
+                && !url.starts_with("https://")
+                && !url.starts_with("git@")
+                && !url.starts_with("file://")
+            {
+                return Err(UhpmError::ValidationError(
+                    "Git URL must be http, https, file, or git@ format".to_string(),
+                ));
+            }
+        }
+        PackageSource::Http { url } => {
+            if url.trim().is_empty() {
+                return Err(UhpmError::ValidationError(
+                    "HTTP URL cannot be empty".to_string(),
+                ));
+            }
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(UhpmError::ValidationError(
+                    "HTTP URL must start with http:// or https://".to_string(),
+                ));
+            }
+        }
+        PackageSource::Local { path } => {
+            if path.as_os_str().is_empty() {
+                return Err(UhpmError::ValidationError(
+                    "Local path cannot be empty".to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_names() {
+        for name in ["tool", "my-package", "lib_2", "a"] {
+            assert!(validate_package_name(name).is_ok(), "{}", name);
+        }
+
+        let err = validate_package_name("2fast").unwrap_err();
+        assert!(err.to_string().contains("start with a letter"), "{}", err);
+        let err = validate_package_name(&"a".repeat(MAX_PACKAGE_NAME_LEN + 1)).unwrap_err();
+        assert!(err.to_string().contains("at most 50"), "{}", err);
+        assert!(validate_package_name(&"a".repeat(MAX_PACKAGE_NAME_LEN)).is_ok());
+        for name in ["", "  ", "-tool", "my tool", "tool@1", "tööl"] {
+            assert!(validate_package_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_relaxed_name_policy() {
+        let relaxed = NamePolicy::default()
+            .allow_leading_digit()
+            .with_extra_chars(['.', '/'])
+            .with_max_len(10);
+
+        assert!(validate_package_name("7zip").is_err());
+        for name in ["7zip", "org/tool", "tool.cli", "a-b_c"] {
+            assert!(relaxed.validate(name).is_ok(), "{}", name);
+        }
+        let err = relaxed.validate("tool@1").unwrap_err();
+        assert!(err.to_string().contains("or one of \"./\""), "{}", err);
+        for name in [".tool", "/tool", "-tool", "much-too-long"] {
+            assert!(relaxed.validate(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_scoped_names_cannot_form_traversing_paths() {
+        let relaxed = NamePolicy::default().with_extra_chars(['.', '/']);
+
+        for name in ["a/../../x", "a/./b", "a//b", "org/", "a/.."] {
+            let err = relaxed.validate(name).unwrap_err();
+            assert!(
+                err.to_string().contains("path segments"),
+                "{}: {}",
+                name,
+                err
+            );
+        }
+        for name in ["org/tool", "org/tool.cli", "a..b", "org/v1.2"] {
+            assert!(relaxed.validate(name).is_ok(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_git_sources() {
+        let git = |url: &str| PackageSource::Git {
+            url: url.to_string(),
+            release: None,
+        };
+
+        for url in [
+            "https://example.com/tool.git",
+            "http://example.com/tool.git",
+            "git@example.com:tool.git",
+            "file:///srv/tool.git",
+        ] {
+            assert!(validate_source(&git(url)).is_ok(), "{}", url);
+        }
+        assert!(validate_source(&git(" ")).is_err());
+        assert!(validate_source(&git("ftp://example.com/tool.git")).is_err());
+    }
+
+    #[test]
+    fn test_http_sources() {
+        let http = |url: &str| PackageSource::Http {
+            url: url.to_string(),
+        };
+
+        assert!(validate_source(&http("https://example.com/tool.uhp")).is_ok());
+        assert!(validate_source(&http("")).is_err());
+        assert!(validate_source(&http("example.com/tool.uhp")).is_err());
+    }
+
+    #[test]
+    fn test_local_sources() {
+        assert!(
+            validate_source(&PackageSource::Local {
+                path: "/tmp".into()
+            })
+            .is_ok()
+        );
+        assert!(validate_source(&PackageSource::Local { path: "".into() }).is_err());
+    }
+}